@@ -1,12 +1,20 @@
-use std::fmt::Display;
+use std::{collections::HashMap, io::Write};
 
-use crate::{bus::{Bus, RAM_END, RAM_BASE}, stats::Stats, mem::B64, isa::{Rv32i, Extension, Rv64i}, exception::Exception};
+use crate::{bus::Bus, stats::{Stats, InsMix, Profile}, isa::{Extension, DEP_REG_COUNT}, decode::DecodeCache, exception::Exception, soc::{SoC, Config, ExecObserver, Hart, Datapath, fetch, DepNode}, csr::Csr};
 
 /*
 An out-of-order, infinite-fetch, infinite-issue single-stage processor
+
+Hazard model: a read waits for every earlier write of the same register or
+address that has not issued in an earlier cycle (RAW). Without renaming, a
+write also waits for earlier writes of its register that have not issued in
+an earlier cycle (WAW), and for earlier reads of it that are still waiting
+(WAR). A read and a later write issuing in the same cycle is fine, as
+operands are read at issue.
 */
 
 struct HistItem {
+    pc: u64,
     src_regs: Vec<u64>,
     src_mem: Option<u64>,
     dst_reg: Option<u64>,
@@ -16,66 +24,150 @@ struct HistItem {
 
 pub struct AtlasSoC {
     pub regs: [u64; 32],
+    /// Float registers, holding NaN-boxed singles
+    pub fregs: [u64; 32],
     pub pc: u64,
     pub bus: Bus,
+    pub csr: Csr,
     pub stats: Stats,
-    hist: Vec<HistItem>
+    /// Executed instructions by mnemonic
+    pub mix: InsMix,
+    /// Executions of each pc
+    pub profile: Profile,
+    pub config: Config,
+    pub trace_out: Box<dyn Write>,
+    pub observer: Option<Box<dyn ExecObserver>>,
+    /// Assume unlimited register renaming, which removes WAR and WAW hazards
+    pub rename: bool,
+    /// Instructions already decoded, so that loops skip the decoder
+    decoded: DecodeCache,
+    hist: Vec<HistItem>,
+    /// Issue cycle of each history item, filled in by calc_stats
+    issued: Vec<usize>
 }
 
 type Result = std::result::Result<(), Exception>;
 
 impl AtlasSoC {
-    pub fn new(bin: Vec<u8>) -> Self {
-        let mut regs = [0_u64; 32];
-        regs[2] = RAM_END;
-        let pc = RAM_BASE;
-        let bus = Bus::new(bin);
-        let stats = Stats::new();
-        let hist = Vec::new();
-        Self { regs, pc, bus, stats, hist }
+    fn hart(&mut self) -> Hart<'_> {
+        Hart {
+            regs: &mut self.regs, fregs: &mut self.fregs, pc: &mut self.pc, bus: &mut self.bus, csr: &mut self.csr,
+            stats: &mut self.stats, mix: &mut self.mix, profile: &mut self.profile, config: &self.config,
+            trace_out: &mut self.trace_out, observer: &mut self.observer
+        }
     }
 
-    pub fn pipeline(&mut self) -> Result {
-        let ins = self.bus.load(self.pc, B64)? as u32;
-        if let Ok(ins) = Rv32i::id(ins) {
-            self.datapath(ins)
-        } else if let Ok(ins) = Rv64i::id(ins) {
-            self.datapath(ins)
-        } else {
-            Err(Exception::IllegalInstruction(ins as u64))
-        }
+    fn intersect<'a, T: PartialEq>(a: &'a [T], b: &'a [T]) -> Vec<&'a T> {
+        a.iter()
+            .filter(|item| b.contains(&item))
+            .collect()
     }
+}
 
-    pub fn datapath<O: Extension + Display>(&mut self, i: O) -> Result {
+impl Datapath for AtlasSoC {
+    fn datapath<O: Extension>(&mut self, word: u32, i: O) -> Result {
+        let pc = self.pc;
         let record = HistItem { 
+            pc,
             src_regs: i.src_regs(),
             src_mem: i.src_mem_addr(),
             dst_reg: i.dst_reg(), 
             dst_mem: i.dst_mem_addr(),
             blocking: i.is_br() || i.is_jmp()
         };
-        let ins_ex = i.ex(&self.regs);
-        if ins_ex.is_ld() || ins_ex.is_st() {
-            self.stats.mem_ops += 1;
-        } else {
-            self.stats.alu_ops += 1;
-        }
-        self.regs[0] = 0;
-        self.pc = ins_ex.wr(self.pc, &mut self.regs, &mut self.bus)?;
-        self.regs[0] = 0;
+        self.hart().commit(word, i)?;
         self.hist.push(record);
         Ok(())
     }
+}
 
-    fn intersect<'a, T: PartialEq>(a: &'a [T], b: &'a [T]) -> Vec<&'a T> {
-        a.iter()
-            .filter(|item| b.contains(&item))
-            .collect()
+impl SoC for AtlasSoC {
+    fn with_entry(bin: Vec<u8>, entry: u64) -> Self {
+        let bus = Bus::new(bin);
+        let mut regs = [0_u64; 32];
+        regs[2] = bus.base + bus.size - 1;
+        let pc = entry;
+        let csr = Csr::new();
+        let stats = Stats::new();
+        let mix = InsMix::new();
+        let profile = Profile::new();
+        let hist = Vec::new();
+        Self { regs, fregs: [0; 32], pc, bus, csr, stats, mix, profile, config: Config { entry, ..Config::default() }, trace_out: Box::new(std::io::stdout()), observer: None, decoded: DecodeCache::new(), rename: false, hist, issued: Vec::new() }
+    }
+
+    fn pipeline(&mut self) -> Result {
+        let (word, ins) = fetch(&mut self.bus, &self.csr, self.pc, self.config.enabled_exts, Some(&mut self.decoded))?;
+        self.dispatch(word, ins)
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn pc_mut(&mut self) -> &mut u64 {
+        &mut self.pc
+    }
+
+    fn regfile(&self) -> &[u64; 32] {
+        &self.regs
+    }
+
+    fn regfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.regs
+    }
+
+    fn fregfile(&self) -> &[u64; 32] {
+        &self.fregs
+    }
+
+    fn fregfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.fregs
+    }
+
+    fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    fn csr(&mut self) -> &mut Csr {
+        &mut self.csr
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    fn mix(&self) -> &InsMix {
+        &self.mix
+    }
+
+    fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    fn reset(&mut self, bin: Vec<u8>) {
+        self.hart().reset(&bin);
+        self.decoded.clear();
+        self.hist.clear();
+        self.issued.clear();
     }
 
     fn calc_stats(&mut self) {
+        // only instructions that completed are recorded in the history
+        self.stats.retired = self.hist.len();
         let mut cycles = 0;
-        let mut stalls = 0;
+        let (mut branch_stalls, mut raw_stalls) = (0, 0);
         // 1. starting from the top of the hist:
         // 2. an instruction is executed if all src regs are available
         // 3. the instructions's dst regs are then added to the occupied list
@@ -83,18 +175,29 @@ impl AtlasSoC {
         // 5. if we encounter the end of the list or a branch, we stop
         // 6. increment cycles and go to 1
         let mut executed = vec![false; self.hist.len()];
+        let mut issued = vec![0; self.hist.len()];
         'cycle: loop {
             cycles += 1;
             let mut occupied_regs = Vec::new();
             let mut occupied_addrs = Vec::new();
+            // sources of earlier ops that are still waiting to issue
+            let mut pending_reads = Vec::new();
             let iter = executed.iter_mut().enumerate()
                 .filter(|(_, done)| !**done);
             for (i, done) in iter {
                 let ins = &self.hist[i];
-                if Self::intersect(&ins.src_regs, &occupied_regs).is_empty()
-                    && ins.src_mem.map(|a| !occupied_addrs.contains(&a)).unwrap_or(true) {
+                let false_dep = !self.rename && ins.dst_reg
+                    .filter(|dst| *dst != 0)
+                    .is_some_and(|dst| occupied_regs.contains(&dst) || pending_reads.contains(&dst));
+                let raw = !Self::intersect(&ins.src_regs, &occupied_regs).is_empty();
+                if !raw
+                    && ins.src_mem.map(|a| !occupied_addrs.contains(&a)).unwrap_or(true)
+                    && !false_dep {
                     // we can execute this op
                     *done = true;
+                    issued[i] = cycles;
+                } else {
+                    pending_reads.extend(&ins.src_regs);
                 }
                 if let Some(dst) = ins.dst_reg {
                     occupied_regs.push(dst);
@@ -103,29 +206,83 @@ impl AtlasSoC {
                     occupied_addrs.push(addr);
                 }
                 if self.hist[i].blocking {
-                    stalls += 1;
+                    // only branches and jumps close the window
+                    if raw {
+                        raw_stalls += 1;
+                    } else {
+                        branch_stalls += 1;
+                    }
                     continue 'cycle;
                 }
             }
             if executed.iter().all(|e| *e) {
-                self.stats.cycles = cycles;
-                self.stats.stalls = stalls;
+                self.stats.cycles = cycles + self.stats.page_walk_cycles;
+                self.stats.stalls = branch_stalls + raw_stalls;
+                self.stats.branch_stalls = branch_stalls;
+                self.stats.raw_stalls = raw_stalls;
+                self.issued = issued;
                 break;
             }
         }
     }
 
-    pub fn execute(&mut self) -> Exception {
-        loop {
-            // execute instruction, add dst registers to dependents
-            // don't execute beyond branch
-            match self.pipeline() {
-                Ok(_) => {},
-                Err(ex) => if ex.is_fatal() {
-                    self.calc_stats();
-                    return ex
-                },
+    fn dep_graph(&self) -> Option<Vec<DepNode>> {
+        // the most recent writer of each register and address
+        let mut writer: [Option<usize>; DEP_REG_COUNT] = [None; DEP_REG_COUNT];
+        let mut mem_writer: HashMap<u64, usize> = HashMap::new();
+        let nodes = self.hist.iter().zip(&self.issued).enumerate().map(|(i, (item, cycle))| {
+            let mut deps: Vec<usize> = item.src_regs.iter()
+                .filter_map(|r| writer[*r as usize])
+                .chain(item.src_mem.and_then(|a| mem_writer.get(&a).copied()))
+                .collect();
+            deps.sort();
+            deps.dedup();
+            if let Some(dst) = item.dst_reg.filter(|r| *r != 0) {
+                writer[dst as usize] = Some(i);
             }
-        }
+            if let Some(addr) = item.dst_mem {
+                mem_writer.insert(addr, i);
+            }
+            DepNode { pc: item.pc, cycle: *cycle, deps }
+        });
+        Some(nodes.collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::to_bin, soc::SoC};
+    use super::AtlasSoC;
+
+    #[test]
+    fn waw_hazard() {
+        let bin = to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00a505b3, // add a1, a0, a0
+            0x00500593, // addi a1, zero, 5
+        ]);
+        // with renaming the second write to a1 issues alongside the first op
+        let mut renamed = AtlasSoC::new(bin.clone());
+        renamed.rename = true;
+        renamed.execute();
+        assert_eq!(renamed.stats.cycles, 2);
+
+        // without it, it has to wait for the add to write a1 first
+        let mut atlas = AtlasSoC::new(bin);
+        atlas.execute();
+        assert_eq!(atlas.stats.cycles, 3);
+        assert_eq!(atlas.regs[11], 5);
+    }
+    #[test]
+    fn memory_dep_edge() {
+        let mut cpu = AtlasSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x00100513, // addi a0, zero, 1
+            0x10a2a023, // sw a0, 256(t0)
+            0x1002a583, // lw a1, 256(t0)
+        ]));
+        cpu.execute();
+        let deps: Vec<_> = cpu.dep_graph().unwrap().into_iter().map(|n| n.deps).collect();
+        assert_eq!(deps, [vec![], vec![], vec![0, 1], vec![0, 2]]);
     }
 }
\ No newline at end of file
@@ -0,0 +1,59 @@
+use std::{fmt::Display, time::{Duration, Instant}};
+
+use crate::{bus::Bus, soc::{SoC, Exit, StepOutcome}};
+
+/// Host-side emulation speed, independent of the simulated cycle count
+#[derive(Copy, Clone)]
+pub struct BenchReport {
+    pub wall: Duration,
+    pub instructions: usize,
+}
+
+impl BenchReport {
+    /// Emulated millions of instructions per host second
+    pub fn mips(&self) -> f64 {
+        // guard against a zero duration on very short runs
+        let secs = self.wall.as_secs_f64().max(f64::MIN_POSITIVE);
+        self.instructions as f64 / secs / 1e6
+    }
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Host time:    {:.3?}", self.wall)?;
+        writeln!(f, "Instructions: {}", self.instructions)?;
+        writeln!(f, "MIPS:         {:.2}", self.mips())
+    }
+}
+
+/// Runs the SoC to completion and measures the host wall-clock time
+pub fn bench(cpu: &mut dyn SoC) -> (Exit, BenchReport) {
+    bench_with(cpu, &mut |_, _| {})
+}
+
+/// As bench, passing each retired instruction to `on_retire`
+pub fn bench_with(cpu: &mut dyn SoC, on_retire: &mut dyn FnMut(&Bus, StepOutcome)) -> (Exit, BenchReport) {
+    let start = Instant::now();
+    let exit = cpu.execute_with(on_retire);
+    let wall = start.elapsed();
+    let instructions = cpu.stats().retired;
+    (exit, BenchReport { wall, instructions })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dart::DartSoC, isa::to_bin, soc::{SoC, Exit}};
+    use super::bench;
+
+    #[test]
+    fn fixed_length_program() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x0000006f, // jal zero, 0
+        ]));
+        cpu.config.timeout_cycles = Some(10_000);
+        let (exit, report) = bench(&mut cpu);
+        assert!(matches!(exit, Exit::Timeout));
+        assert_eq!(report.instructions, 10_000);
+        assert!(report.mips() > 0.0);
+    }
+}
\ No newline at end of file
@@ -1,31 +1,789 @@
-use crate::{mem::{Mem, Bits}, exception::Exception};
+use std::{collections::VecDeque, fmt::Display, path::PathBuf};
+
+use crate::{mem::{Mem, Bits, B8, B16, B32, B64}, rvc::is_compressed, exception::Exception, device::{Device, Halt, Uart, Assert}, csr::{parse_u64, Privilege}};
 
 pub const RAM_BASE: u64 = 0x8000_0000;
 pub const RAM_SIZE: u64 = 1024 * 1024 * 128;
-pub const RAM_END: u64 = RAM_SIZE + RAM_BASE - 1;
+
+/// 8250-style UART, at the same address as QEMU's virt machine
+pub const UART_BASE: u64 = 0x1000_0000;
+pub const UART_SIZE: u64 = 0x100;
+
+/// Assertion device for self-checking test programs, clear of the finisher
+pub const ASSERT_BASE: u64 = 0x0011_0000;
+pub const ASSERT_SIZE: u64 = 0x10;
+
+/// SiFive test finisher, at the same address as QEMU's virt machine. It is
+/// only mapped on request, a store there otherwise faults.
+pub const FINISHER_BASE: u64 = 0x0010_0000;
+pub const FINISHER_SIZE: u64 = 0x1000;
+
+/// Kind of memory access, which decides the permission a page needs and the
+/// exception raised when it is missing
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl Access {
+    fn page_fault(self, addr: u64) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstructionPageFault(addr),
+            Access::Load => Exception::LoadPageFault(addr),
+            Access::Store => Exception::StoreAMOPageFault(addr),
+        }
+    }
+
+    fn access_fault(self, addr: u64) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstructionAccessFault(addr),
+            Access::Load => Exception::LoadAccessFault(addr),
+            Access::Store => Exception::StoreAMOAccessFault(addr),
+        }
+    }
+}
+
+/// satp.MODE value selecting Sv39
+pub const SATP_SV39: u64 = 8;
+const SATP_PPN: u64 = (1 << 44) - 1;
+const PAGE_SIZE: u64 = 4096;
+
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+
+/// Translations the TLB holds before evicting the oldest
+pub const TLB_ENTRIES: usize = 16;
+
+/// A cached leaf PTE, covering one page or superpage
+#[derive(Debug, Copy, Clone)]
+struct TlbEntry {
+    /// Virtual page number at the granularity of `level`
+    vpn: u64,
+    /// 0 for a 4 KiB page, 1 or 2 for a superpage
+    level: u64,
+    pte: u64,
+}
+
+impl TlbEntry {
+    fn covers(&self, vaddr: u64) -> bool {
+        vaddr >> (12 + 9 * self.level) == self.vpn
+    }
+
+    /// The physical address of `vaddr` if the page permits `access` at
+    /// `privilege`. `sum` is sstatus.SUM.
+    fn check(&self, vaddr: u64, access: Access, privilege: Privilege, sum: bool) -> Result<u64, Exception> {
+        let permitted = match access {
+            Access::Fetch => self.pte & PTE_X,
+            Access::Load => self.pte & PTE_R,
+            Access::Store => self.pte & PTE_W,
+        } != 0;
+        // U-mode only reaches user pages, S-mode never runs code from them
+        // and only loads and stores to them with SUM set
+        let user = self.pte & PTE_U != 0;
+        let owned = match privilege {
+            Privilege::U => user,
+            _ => !user || (sum && access != Access::Fetch),
+        };
+        if !permitted || !owned || self.pte & PTE_A == 0 || (access == Access::Store && self.pte & PTE_D == 0) {
+            return Err(access.page_fault(vaddr))
+        }
+        Ok(self.phys(vaddr))
+    }
+
+    /// The physical address of `vaddr`, whatever the page permits
+    fn phys(&self, vaddr: u64) -> u64 {
+        let ppn = (self.pte >> 10) & SATP_PPN;
+        let offset = vaddr & ((PAGE_SIZE << (9 * self.level)) - 1);
+        (ppn * PAGE_SIZE) | offset
+    }
+}
+
+struct Mapping {
+    base: u64,
+    end: u64,
+    dev: Box<dyn Device>
+}
+
+impl Mapping {
+    /// Whether every byte of the access falls inside this mapping
+    fn contains(&self, addr: u64, bits: &Bits) -> bool {
+        addr >= self.base && addr.checked_add(bits.size() - 1).is_some_and(|end| end <= self.end)
+    }
+}
+
+/// A store that touched a watched range
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WatchHit {
+    pub pc: u64,
+    pub addr: u64,
+    pub old: u64,
+    pub new: u64,
+}
+
+impl Display for WatchHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010x}: store to {:#010x} changed {:#x} -> {:#x}", self.pc, self.addr, self.old, self.new)
+    }
+}
+
+/// Parses a watchpoint argument of the form `<addr>[:<size>]`, where the
+/// size defaults to a doubleword
+pub fn parse_watch_arg(arg: &str) -> Result<(u64, u64), String> {
+    let (addr, size) = arg.split_once(':').unwrap_or((arg, "8"));
+    let addr = parse_u64(addr).ok_or(format!("invalid watch address {}", addr))?;
+    let size = parse_u64(size).filter(|s| *s > 0).ok_or(format!("invalid watch size {}", size))?;
+    Ok((addr, size))
+}
+
+/// Parses a memory dump argument of the form `<file>:<addr>:<len>`
+pub fn parse_dump_arg(arg: &str) -> Result<(PathBuf, u64, u64), String> {
+    let mut fields = arg.rsplitn(3, ':');
+    let (Some(len), Some(addr), Some(path)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(format!("expected <file>:<addr>:<len>, got {}", arg))
+    };
+    let addr = parse_u64(addr).ok_or(format!("invalid dump address {}", addr))?;
+    let len = parse_u64(len).ok_or(format!("invalid dump length {}", len))?;
+    Ok((PathBuf::from(path), addr, len))
+}
+
+pub fn parse_addr_arg(arg: &str) -> Result<u64, String> {
+    parse_u64(arg).ok_or(format!("invalid address {}", arg))
+}
+
+/// Parses an entry point, which must lie within RAM
+pub fn parse_entry_arg(arg: &str) -> Result<u64, String> {
+    let addr = parse_addr_arg(arg)?;
+    if !(RAM_BASE..RAM_BASE + RAM_SIZE).contains(&addr) {
+        return Err(format!("entry {:#010x} is outside RAM ({:#010x}..{:#010x})", addr, RAM_BASE, RAM_BASE + RAM_SIZE))
+    }
+    Ok(addr)
+}
+
+/// What a store into the loaded program does
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum TextCheck {
+    #[default]
+    Off,
+    /// Record the store in text_writes and carry on
+    Warn,
+    /// Fail the store with a store access fault
+    Strict,
+}
+
+pub fn parse_text_check_arg(arg: &str) -> Result<TextCheck, String> {
+    match arg {
+        "off" => Ok(TextCheck::Off),
+        "warn" => Ok(TextCheck::Warn),
+        "strict" => Ok(TextCheck::Strict),
+        _ => Err(format!("expected off, warn or strict, got {}", arg))
+    }
+}
 
 pub struct Bus {
-    pub mem: Mem
+    devices: Vec<Mapping>,
+    /// Separate instruction memory in a Harvard layout, fetches look here
+    /// before the devices. None for the default Von Neumann layout, where
+    /// code and data share RAM.
+    imem: Option<Mapping>,
+    /// Address the program is loaded at
+    pub base: u64,
+    /// Size of RAM in bytes
+    pub size: u64,
+    /// Raise misaligned exceptions for accesses not aligned to their size
+    pub strict_align: bool,
+    /// Address reserved by the last lr, checked and cleared by sc
+    pub reservation: Option<u64>,
+    /// Watched [start, end] ranges, checked on every store
+    watchpoints: Vec<(u64, u64)>,
+    /// Stores that touched a watchpoint, oldest first
+    pub watch_hits: Vec<WatchHit>,
+    /// Pc recorded with watchpoint hits, set by the SoC before each instruction
+    pub watch_pc: u64,
+    /// The [start, end] range the program image was loaded into. The image
+    /// is flat, so this covers any data it carries as well as its code.
+    pub text: Option<(u64, u64)>,
+    pub text_check: TextCheck,
+    /// Pc and address of stores into text under TextCheck::Warn, oldest first
+    pub text_writes: Vec<(u64, u64)>,
+    /// Copy of the satp CSR, set by the SoC before each instruction. Loads,
+    /// stores and fetches are translated through Sv39 page tables when its
+    /// mode is SATP_SV39, and go straight to physical memory otherwise.
+    pub satp: u64,
+    /// Privilege translated accesses are made at and sstatus.SUM, set by
+    /// the SoC along with satp
+    pub privilege: Privilege,
+    pub sum: bool,
+    /// Fully associative, oldest entry first
+    tlb: VecDeque<TlbEntry>,
+    pub tlb_hits: usize,
+    pub tlb_misses: usize,
+    /// Page table levels read by walks on a TLB miss, one cycle each
+    pub page_walk_cycles: usize,
+    /// Stores that have completed, so callers can tell whether memory
+    /// changed between two points in a run
+    pub stores: usize,
+    /// Physical address, size and value of the latest store
+    pub last_store: Option<(u64, u64, u64)>
 }
 
 impl Bus {
     pub fn new(program: Vec<u8>) -> Bus {
-        let mut mem = vec![0; RAM_SIZE as usize];
+        Self::with_strict_align(program, false)
+    }
+
+    pub fn with_strict_align(program: Vec<u8>, strict_align: bool) -> Bus {
+        let mut bus = Self::with_layout(program, RAM_BASE, RAM_SIZE);
+        bus.strict_align = strict_align;
+        bus
+    }
+
+    /// A bus with `size` bytes of RAM at `base`, holding the program
+    pub fn with_layout(program: Vec<u8>, base: u64, size: u64) -> Bus {
+        let text = (!program.is_empty()).then(|| (base, base + program.len() as u64 - 1));
+        let mut mem = vec![0; size as usize];
         mem.splice(..program.len(), program.into_iter());
-        Self { mem: Mem::new(mem) }
+        let mut bus = Self {
+            devices: Vec::new(),
+            imem: None,
+            base,
+            size,
+            strict_align: false,
+            reservation: None,
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            watch_pc: 0,
+            text,
+            text_check: TextCheck::Off,
+            text_writes: Vec::new(),
+            satp: 0,
+            privilege: Privilege::M,
+            sum: false,
+            tlb: VecDeque::with_capacity(TLB_ENTRIES),
+            tlb_hits: 0,
+            tlb_misses: 0,
+            page_walk_cycles: 0,
+            stores: 0,
+            last_store: None,
+        };
+        bus.map(ASSERT_BASE, ASSERT_SIZE, Box::new(Assert::default()));
+        bus.map(UART_BASE, UART_SIZE, Box::new(Uart::new(Box::new(std::io::stdout()))));
+        bus.map(base, size, Box::new(Mem::new(mem)));
+        bus
     }
 
-    pub fn load(&self, addr: u64, bits: Bits) -> Result<u64, Exception> {
-        match addr {
-            RAM_BASE..=RAM_END => Ok(self.mem.load(addr - RAM_BASE, bits)),
-            _ => Err(Exception::LoadAccessFault(addr))
+    /// A Harvard bus, which fetches instructions from `imem` and sends loads
+    /// and stores to `dmem`. Both are RAM_SIZE bytes at RAM_BASE, so a store
+    /// never changes the instruction fetched from the same address.
+    pub fn harvard(imem: Vec<u8>, dmem: Vec<u8>) -> Bus {
+        let mut bus = Self::with_layout(dmem, RAM_BASE, RAM_SIZE);
+        let mut mem = vec![0; RAM_SIZE as usize];
+        mem.splice(..imem.len(), imem);
+        bus.imem = Some(Mapping { base: RAM_BASE, end: RAM_BASE + RAM_SIZE - 1, dev: Box::new(Mem::new(mem)) });
+        // no store can reach the program
+        bus.text = None;
+        bus
+    }
+
+    /// Maps a device at [base, base + size). Later mappings shadow earlier
+    /// ones, so this can also replace a default device.
+    pub fn map(&mut self, base: u64, size: u64, dev: Box<dyn Device>) {
+        self.devices.push(Mapping { base, end: base + size - 1, dev });
+    }
+
+    /// Records every store that overlaps [addr, addr + size) in watch_hits
+    pub fn add_watchpoint(&mut self, addr: u64, size: u64) {
+        self.watchpoints.push((addr, addr + size - 1));
+    }
+
+    /// Drops every cached translation, as sfence.vma requires
+    pub fn flush_tlb(&mut self) {
+        self.tlb.clear();
+    }
+
+    /// Translates a virtual address through the Sv39 page tables rooted at
+    /// satp, or returns it unchanged when translation is off. Leaf entries
+    /// are cached in the TLB until the next flush_tlb. Accessed and dirty
+    /// bits are not updated: a page without A, or a store to a page without
+    /// D, faults so that software can set them.
+    pub fn translate(&mut self, vaddr: u64, access: Access) -> Result<u64, Exception> {
+        if self.satp >> 60 != SATP_SV39 {
+            return Ok(vaddr)
+        }
+        if let Some(entry) = self.tlb.iter().find(|e| e.covers(vaddr)) {
+            self.tlb_hits += 1;
+            return entry.check(vaddr, access, self.privilege, self.sum)
+        }
+        self.tlb_misses += 1;
+        let entry = self.walk(vaddr, access)?;
+        self.page_walk_cycles += 3 - entry.level as usize;
+        if self.tlb.len() == TLB_ENTRIES {
+            self.tlb.pop_front();
+        }
+        self.tlb.push_back(entry);
+        entry.check(vaddr, access, self.privilege, self.sum)
+    }
+
+    /// Translates without touching the TLB or its counters, for debuggers
+    /// and reports
+    pub fn peek_translate(&self, vaddr: u64, access: Access) -> Result<u64, Exception> {
+        if self.satp >> 60 != SATP_SV39 {
+            return Ok(vaddr)
+        }
+        self.walk(vaddr, access)?.check(vaddr, access, self.privilege, self.sum)
+    }
+
+    /// Reads the page tables for the leaf entry mapping `vaddr`
+    fn walk(&self, vaddr: u64, access: Access) -> Result<TlbEntry, Exception> {
+        // bits 63:39 have to be copies of bit 38
+        if ((vaddr as i64) << 25 >> 25) as u64 != vaddr {
+            return Err(access.page_fault(vaddr))
+        }
+        let mut table = (self.satp & SATP_PPN) * PAGE_SIZE;
+        for level in (0..3).rev() {
+            let vpn = (vaddr >> (12 + 9 * level)) & 0x1ff;
+            let pte = self.load_phys(table + vpn * 8, B64).map_err(|_| access.access_fault(vaddr))?;
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(access.page_fault(vaddr))
+            }
+            let ppn = (pte >> 10) & SATP_PPN;
+            if pte & (PTE_R | PTE_X) == 0 {
+                // pointer to the next level
+                table = ppn * PAGE_SIZE;
+                continue
+            }
+            // a superpage has to be aligned to its own size
+            let superpage = (1 << (9 * level)) - 1;
+            if ppn & superpage != 0 {
+                return Err(access.page_fault(vaddr))
+            }
+            return Ok(TlbEntry { vpn: vaddr >> (12 + 9 * level), level, pte })
+        }
+        // the last level held another pointer
+        Err(access.page_fault(vaddr))
+    }
+
+    pub fn load(&mut self, addr: u64, bits: Bits) -> Result<u64, Exception> {
+        let addr = self.translate(addr, Access::Load)?;
+        self.load_phys(addr, bits)
+    }
+
+    /// Reads instruction memory like a fetch would, without touching the
+    /// TLB, for reports
+    pub fn peek_fetch(&self, addr: u64, bits: Bits) -> Result<u64, Exception> {
+        let addr = self.peek_translate(addr, Access::Fetch)?;
+        self.fetch_phys(addr, bits).ok_or(Exception::InstructionAccessFault(addr))
+    }
+
+    /// The physical address a debugger access to `vaddr` reaches. Page
+    /// permissions are ignored so that read-only code can still be patched.
+    fn debug_translate(&self, vaddr: u64) -> Option<u64> {
+        if self.satp >> 60 != SATP_SV39 {
+            return Some(vaddr)
+        }
+        self.walk(vaddr, Access::Load).ok().map(|entry| entry.phys(vaddr))
+    }
+
+    /// Debugger backdoor that reads memory without raising exceptions or
+    /// checking alignment. None if the address is not mapped.
+    pub fn peek(&self, addr: u64, bits: Bits) -> Option<u64> {
+        let addr = self.debug_translate(addr)?;
+        self.devices.iter().rev()
+            .find(|m| m.contains(addr, &bits))
+            .map(|m| m.dev.load(addr - m.base, bits))
+    }
+
+    /// Debugger backdoor that writes memory without raising exceptions,
+    /// checking alignment or recording watchpoint and text hits. False if
+    /// the address is not mapped.
+    pub fn poke(&mut self, addr: u64, bits: Bits, value: u64) -> bool {
+        let Some(addr) = self.debug_translate(addr) else {
+            return false
+        };
+        match self.devices.iter_mut().rev().find(|m| m.contains(addr, &bits)) {
+            Some(m) => {
+                m.dev.store(addr - m.base, bits, value);
+                true
+            },
+            None => false
         }
     }
 
     pub fn store(&mut self, addr: u64, bits: Bits, value: u64) -> Result<(), Exception> {
-        match addr {
-            RAM_BASE..=RAM_END => Ok(self.mem.store(addr - RAM_BASE, bits, value)),
-            _ => Err(Exception::StoreAMOAccessFault(addr))
+        let addr = self.translate(addr, Access::Store)?;
+        self.store_phys(addr, bits, value)
+    }
+
+    /// Loads from a physical address, bypassing translation
+    pub fn load_phys(&self, addr: u64, bits: Bits) -> Result<u64, Exception> {
+        if self.strict_align && !addr.is_multiple_of(bits.size()) {
+            return Err(Exception::LoadAccessMisaligned(addr))
+        }
+        match self.devices.iter().rev().find(|m| m.contains(addr, &bits)) {
+            Some(m) => Ok(m.dev.load(addr - m.base, bits)),
+            None => Err(Exception::LoadAccessFault(addr))
+        }
+    }
+
+    /// Reads the 32-bit instruction word at virtual address `addr`. Fetch
+    /// alignment is enforced by the jumps rather than strict_align, and a
+    /// word outside every device is an instruction access fault.
+    pub fn fetch(&mut self, addr: u64) -> Result<u32, Exception> {
+        let pc = self.translate(addr, Access::Fetch)?;
+        if let Some(word) = self.fetch_phys(pc, B32) {
+            return Ok(word as u32)
+        }
+        // a compressed instruction in the last halfword of a device has no
+        // upper half to read
+        match self.fetch_phys(pc, B16).map(|half| half as u32) {
+            Some(word) if is_compressed(word) => Ok(word),
+            _ => Err(Exception::InstructionAccessFault(addr))
+        }
+    }
+
+    /// Reads instruction memory at a physical address, which is RAM unless
+    /// the bus is Harvard
+    fn fetch_phys(&self, addr: u64, bits: Bits) -> Option<u64> {
+        self.imem.iter().chain(self.devices.iter().rev())
+            .find(|m| m.contains(addr, &bits))
+            .map(|m| m.dev.load(addr - m.base, bits))
+    }
+
+    /// Stores to a physical address, bypassing translation
+    pub fn store_phys(&mut self, addr: u64, bits: Bits, value: u64) -> Result<(), Exception> {
+        if self.strict_align && !addr.is_multiple_of(bits.size()) {
+            return Err(Exception::StoreAMOAddrMisaligned(addr))
+        }
+        let end = addr.saturating_add(bits.size() - 1);
+        if self.text_check != TextCheck::Off && self.text.is_some_and(|(start, stop)| addr <= stop && start <= end) {
+            if self.text_check == TextCheck::Strict {
+                return Err(Exception::StoreAMOAccessFault(addr))
+            }
+            self.text_writes.push((self.watch_pc, addr));
         }
+        match self.devices.iter_mut().rev().find(|m| m.contains(addr, &bits)) {
+            Some(m) => {
+                if !self.watchpoints.is_empty() && self.watchpoints.iter().any(|&(start, stop)| addr <= stop && start <= end) {
+                    let old = m.dev.load(addr - m.base, bits);
+                    self.watch_hits.push(WatchHit { pc: self.watch_pc, addr, old, new: value });
+                }
+                m.dev.store(addr - m.base, bits, value);
+                self.stores += 1;
+                self.last_store = Some((addr, bits.size(), value));
+                Ok(())
+            },
+            None => Err(Exception::StoreAMOAccessFault(addr))
+        }
+    }
+
+    /// Memory in [start, end) as 32-bit little-endian words, one hex word per
+    /// line, the format riscv-arch-test expects for signatures
+    pub fn dump_words(&self, start: u64, end: u64) -> Result<String, Exception> {
+        (start..end).step_by(4)
+            .map(|addr| self.load_phys(addr, B32).map(|word| format!("{:08x}\n", word)))
+            .collect()
+    }
+
+    /// Copies [addr, addr + len) out of the bus, failing on the first byte
+    /// that is not mapped
+    pub fn read_bytes(&self, addr: u64, len: u64) -> Result<Vec<u8>, Exception> {
+        if len > 0 && addr.checked_add(len - 1).is_none() {
+            return Err(Exception::LoadAccessFault(addr))
+        }
+        (addr..addr + len)
+            .map(|a| self.load_phys(a, B8).map(|b| b as u8))
+            .collect()
+    }
+
+    /// The RAM holding the program, unless something else has been mapped
+    /// over it
+    pub fn ram(&self) -> Option<&[u8]> {
+        self.devices.iter().rev().find(|m| m.base == self.base).and_then(|m| m.dev.ram())
+    }
+
+    pub fn ram_mut(&mut self) -> Option<&mut [u8]> {
+        let base = self.base;
+        self.devices.iter_mut().rev().find(|m| m.base == base).and_then(|m| m.dev.ram_mut())
+    }
+
+    /// Replaces the contents of RAM with program followed by zeroes, in
+    /// place, and forgets the reservation and watch hits of the last run. A
+    /// Harvard bus loads the program into instruction memory and clears its
+    /// data memory.
+    pub fn reload(&mut self, program: &[u8]) {
+        if let Some(ram) = self.ram_mut() {
+            ram.fill(0);
+        }
+        let ram = if self.imem.is_some() {
+            self.imem.as_mut().and_then(|m| m.dev.ram_mut())
+        } else {
+            self.ram_mut()
+        };
+        if let Some(ram) = ram {
+            ram.fill(0);
+            ram[..program.len()].copy_from_slice(program);
+        }
+        self.text = (!program.is_empty() && self.imem.is_none()).then(|| (self.base, self.base + program.len() as u64 - 1));
+        self.reservation = None;
+        self.watch_hits.clear();
+        self.text_writes.clear();
+        self.flush_tlb();
+        self.tlb_hits = 0;
+        self.tlb_misses = 0;
+        self.page_walk_cycles = 0;
+        self.stores = 0;
+        self.last_store = None;
+    }
+
+    /// The first stop request raised by any device
+    pub fn halt(&self) -> Option<Halt> {
+        self.devices.iter().find_map(|m| m.dev.halt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mem::{B8, B32, B64}, exception::Exception, dart::DartSoC, isa::to_bin, soc::{SoC, Exit}, csr::{Privilege, SATP}};
+    use super::{Bus, Access, WatchHit, TextCheck, SATP_SV39, RAM_BASE, RAM_SIZE, parse_dump_arg, parse_entry_arg};
+
+    #[test]
+    fn layout_at_zero() {
+        let mut bus = Bus::with_layout(vec![0x13, 0x05, 0x10, 0x00], 0x0, 64 * 1024);
+        assert_eq!(bus.load(0x0, B32).unwrap(), 0x00100513);
+        assert_eq!(bus.load(0xffff, B8).unwrap(), 0);
+        assert!(matches!(bus.load(0x1_0000, B8), Err(Exception::LoadAccessFault(0x1_0000))));
+    }
+
+    #[test]
+    fn watchpoint_hit() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x02a00313, // addi t1, zero, 42
+            0x0e62ae23, // sw t1, 252(t0)
+            0x1062a023, // sw t1, 256(t0)
+        ]));
+        cpu.bus.store(RAM_BASE + 256, B32, 7).unwrap();
+        cpu.bus.add_watchpoint(RAM_BASE + 256, 4);
+        cpu.execute();
+        assert_eq!(cpu.bus.watch_hits, [
+            WatchHit { pc: RAM_BASE + 12, addr: RAM_BASE + 256, old: 7, new: 42 },
+        ]);
+    }
+
+    #[test]
+    fn signature_dump() {
+        let mut bus = Bus::new(vec![]);
+        bus.store(RAM_BASE + 0x10, B32, 0xdeadbeef).unwrap();
+        bus.store(RAM_BASE + 0x14, B8, 0x2a).unwrap();
+        assert_eq!(bus.dump_words(RAM_BASE + 0x10, RAM_BASE + 0x1c).unwrap(), "deadbeef\n0000002a\n00000000\n");
+    }
+
+    #[test]
+    fn memory_dump() {
+        let mut bus = Bus::new(vec![]);
+        for i in 0..16 {
+            bus.store(RAM_BASE + 0x100 + i, B8, 0xa0 + i).unwrap();
+        }
+        let (path, addr, len) = parse_dump_arg(&format!("{}:0x80000100:16", std::env::temp_dir().join("mur-dump.bin").display())).unwrap();
+        std::fs::write(&path, bus.read_bytes(addr, len).unwrap()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data, (0xa0..0xb0).collect::<Vec<u8>>());
+
+        assert!(matches!(bus.read_bytes(RAM_BASE + RAM_SIZE - 2, 4), Err(Exception::LoadAccessFault(a)) if a == RAM_BASE + RAM_SIZE));
+        assert!(matches!(bus.read_bytes(u64::MAX, 2), Err(Exception::LoadAccessFault(u64::MAX))));
+        assert!(parse_dump_arg("out.bin:0x80000000").is_err());
+    }
+
+    #[test]
+    fn entry_within_ram() {
+        assert_eq!(parse_entry_arg("0x80000004"), Ok(RAM_BASE + 4));
+        assert!(parse_entry_arg("0x1000").is_err());
+        assert!(parse_entry_arg(&format!("{:#x}", RAM_BASE + RAM_SIZE)).is_err());
+    }
+
+    #[test]
+    fn store_into_text() {
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x00100313, // addi t1, zero, 1
+            0x0062a023, // sw t1, 0(t0)
+            0xfe612823, // sw t1, -16(sp)
+        ]);
+        let mut cpu = DartSoC::new(bin.clone());
+        assert_eq!(cpu.bus.text, Some((RAM_BASE, RAM_BASE + 15)));
+        cpu.bus.text_check = TextCheck::Warn;
+        cpu.execute();
+        // only the store over the first instruction, not the one to the stack
+        assert_eq!(cpu.bus.text_writes, [(RAM_BASE + 8, RAM_BASE)]);
+        assert_eq!(cpu.bus.load(RAM_BASE, B32).unwrap(), 1);
+
+        let mut cpu = DartSoC::new(bin);
+        cpu.bus.text_check = TextCheck::Strict;
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::StoreAMOAccessFault(RAM_BASE))));
+        assert_eq!(cpu.bus.load(RAM_BASE, B32).unwrap(), 0x00000297);
+    }
+
+    #[test]
+    fn sv39_translation() {
+        let mut bus = Bus::with_layout(vec![], RAM_BASE, 0x10000);
+        let (root, mid, leaf, page) = (RAM_BASE + 0x1000, RAM_BASE + 0x2000, RAM_BASE + 0x3000, RAM_BASE + 0x5000);
+        let pointer = |table: u64| ((table >> 12) << 10) | 1;
+        // V, R, A, no W
+        let read_only = |page: u64| ((page >> 12) << 10) | 0b0100_0011;
+        // vaddr 0x4020_3000 has vpn[2] = 1, vpn[1] = 1, vpn[0] = 3
+        bus.store(root + 8, B64, pointer(mid)).unwrap();
+        bus.store(mid + 8, B64, pointer(leaf)).unwrap();
+        bus.store(leaf + 3 * 8, B64, read_only(page)).unwrap();
+        bus.store(page + 0x10, B8, 0xab).unwrap();
+        bus.satp = (SATP_SV39 << 60) | (root >> 12);
+
+        assert_eq!(bus.translate(0x4020_3010, Access::Load).unwrap(), page + 0x10);
+        assert_eq!(bus.load(0x4020_3010, B8).unwrap(), 0xab);
+        assert!(matches!(bus.load(0x4020_4010, B8), Err(Exception::LoadPageFault(0x4020_4010))));
+        assert!(matches!(bus.store(0x4020_3010, B8, 0), Err(Exception::StoreAMOPageFault(0x4020_3010))));
+        assert!(matches!(bus.translate(0x4020_3010, Access::Fetch), Err(Exception::InstructionPageFault(0x4020_3010))));
+        // not sign-extended from bit 38
+        assert!(matches!(bus.load(0x80_4020_3010, B8), Err(Exception::LoadPageFault(_))));
+        // physical accesses and bare mode are untouched
+        assert_eq!(bus.load_phys(page + 0x10, B8).unwrap(), 0xab);
+        bus.satp = 0;
+        assert_eq!(bus.load(page + 0x10, B8).unwrap(), 0xab);
+    }
+
+    #[test]
+    fn sv39_user_pages() {
+        let mut bus = Bus::with_layout(vec![], RAM_BASE, 0x10000);
+        let (root, mid, leaf) = (RAM_BASE + 0x1000, RAM_BASE + 0x2000, RAM_BASE + 0x3000);
+        let pointer = |table: u64| ((table >> 12) << 10) | 1;
+        // V, R, W, X, A, D, with U for the user page
+        let leaf_pte = |page: u64, user: u64| ((page >> 12) << 10) | 0b1100_1111 | user << 4;
+        bus.store(root + 8, B64, pointer(mid)).unwrap();
+        bus.store(mid + 8, B64, pointer(leaf)).unwrap();
+        bus.store(leaf + 3 * 8, B64, leaf_pte(RAM_BASE + 0x5000, 0)).unwrap();
+        bus.store(leaf + 4 * 8, B64, leaf_pte(RAM_BASE + 0x6000, 1)).unwrap();
+        bus.satp = (SATP_SV39 << 60) | (root >> 12);
+        let (kernel, user) = (0x4020_3000, 0x4020_4000);
+
+        // the first access walks, the rest hit the TLB
+        bus.privilege = Privilege::S;
+        assert_eq!(bus.translate(kernel, Access::Load).unwrap(), RAM_BASE + 0x5000);
+        bus.privilege = Privilege::U;
+        assert!(matches!(bus.load(kernel, B8), Err(Exception::LoadPageFault(0x4020_3000))));
+        assert!(matches!(bus.store(kernel, B8, 0), Err(Exception::StoreAMOPageFault(0x4020_3000))));
+        assert!(matches!(bus.translate(kernel, Access::Fetch), Err(Exception::InstructionPageFault(0x4020_3000))));
+        assert_eq!(bus.tlb_hits, 3);
+        assert_eq!(bus.translate(user, Access::Fetch).unwrap(), RAM_BASE + 0x6000);
+
+        // S-mode needs SUM for user data and can never run user code
+        bus.privilege = Privilege::S;
+        assert!(matches!(bus.load(user, B8), Err(Exception::LoadPageFault(0x4020_4000))));
+        assert!(matches!(bus.store(user, B8, 0), Err(Exception::StoreAMOPageFault(0x4020_4000))));
+        bus.sum = true;
+        assert_eq!(bus.load(user, B8).unwrap(), 0);
+        bus.store(user, B8, 1).unwrap();
+        assert!(matches!(bus.translate(user, Access::Fetch), Err(Exception::InstructionPageFault(0x4020_4000))));
+    }
+
+    #[test]
+    fn peek_poke_backdoor() {
+        let mut bus = Bus::with_strict_align(vec![], true);
+        bus.add_watchpoint(RAM_BASE, 16);
+        assert!(bus.poke(RAM_BASE + 3, B32, 0xdead_beef));
+        assert_eq!(bus.peek(RAM_BASE + 3, B32), Some(0xdead_beef));
+        // no alignment fault and no watchpoint hit
+        assert!(bus.watch_hits.is_empty());
+        assert!(matches!(bus.load(RAM_BASE + 3, B32), Err(Exception::LoadAccessMisaligned(_))));
+
+        assert_eq!(bus.peek(RAM_BASE + RAM_SIZE, B8), None);
+        assert_eq!(bus.peek(RAM_BASE + RAM_SIZE - 2, B32), None);
+        assert!(!bus.poke(0x10, B64, 1));
+    }
+
+    #[test]
+    fn harvard_store_leaves_code() {
+        let program = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x0002a423, // sw zero, 8(t0)
+            0x00200593, // addi a1, zero, 2
+        ]);
+        let mut bus = Bus::harvard(program.clone(), vec![]);
+        // data memory does not hold the program
+        assert_eq!(bus.load(RAM_BASE, B32).unwrap(), 0);
+        assert_eq!(bus.fetch(RAM_BASE).unwrap(), 0x00000297);
+        bus.store(RAM_BASE, B32, 0x13).unwrap();
+        assert_eq!(bus.fetch(RAM_BASE).unwrap(), 0x00000297);
+
+        // the store over the addi only reaches data memory
+        let mut cpu = DartSoC::new(vec![]);
+        cpu.bus = Bus::harvard(program.clone(), vec![]);
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.regs[11], 2);
+        assert_eq!(cpu.pc, RAM_BASE + 12);
+
+        // von Neumann by default, where the store overwrites it
+        let mut cpu = DartSoC::new(program);
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.regs[11], 0);
+        assert_eq!(cpu.pc, RAM_BASE + 8);
+    }
+
+    #[test]
+    fn tlb_hits_and_misses() {
+        let mut bus = Bus::with_layout(vec![], RAM_BASE, 0x10000);
+        let (root, mid, leaf, pages) = (RAM_BASE + 0x1000, RAM_BASE + 0x2000, RAM_BASE + 0x3000, RAM_BASE + 0x5000);
+        let pointer = |table: u64| ((table >> 12) << 10) | 1;
+        // V, R, A
+        let readable = |page: u64| ((page >> 12) << 10) | 0b0100_0011;
+        bus.store(root + 8, B64, pointer(mid)).unwrap();
+        bus.store(mid + 8, B64, pointer(leaf)).unwrap();
+        for i in 0..4 {
+            bus.store(leaf + i * 8, B64, readable(pages + i * 0x1000)).unwrap();
+        }
+        bus.satp = (SATP_SV39 << 60) | (root >> 12);
+
+        // sequential loads within one page walk once
+        for offset in (0..0x100).step_by(8) {
+            bus.load(0x4020_0000 + offset, B64).unwrap();
+        }
+        assert_eq!((bus.tlb_hits, bus.tlb_misses), (31, 1));
+        assert_eq!(bus.page_walk_cycles, 3);
+
+        // striding across pages misses on each new page
+        for i in 1..4 {
+            bus.load(0x4020_0000 + i * 0x1000, B64).unwrap();
+        }
+        assert_eq!((bus.tlb_hits, bus.tlb_misses), (31, 4));
+        assert_eq!(bus.page_walk_cycles, 4 * 3);
+
+        // debugger reads leave the counters alone
+        bus.peek(0x4020_0000, B64).unwrap();
+        assert_eq!((bus.tlb_hits, bus.tlb_misses), (31, 4));
+
+        bus.flush_tlb();
+        bus.load(0x4020_0000, B64).unwrap();
+        assert_eq!(bus.tlb_misses, 5);
+
+        // the walks cost the hart cycles, here one for the gigapage the code
+        // runs from and three for the load
+        bus.store_phys(root + 2 * 8, B64, ((RAM_BASE >> 12) << 10) | 0b0100_1011).unwrap();
+        bus.store_phys(RAM_BASE, B32, 0x40200537).unwrap(); // lui a0, 0x40200
+        bus.store_phys(RAM_BASE + 4, B32, 0x00053583).unwrap(); // ld a1, 0(a0)
+        let mut cpu = DartSoC::new(vec![]);
+        cpu.bus = bus;
+        cpu.bus.flush_tlb();
+        cpu.bus.page_walk_cycles = 0;
+        cpu.csr.store(SATP, cpu.bus.satp);
+        cpu.csr.privilege = Privilege::S;
+        cpu.execute();
+        assert_eq!(cpu.stats.page_walk_cycles, 1 + 3);
+        assert_eq!(cpu.stats.cycles, 3 + 1 + 3);
     }
 }
\ No newline at end of file
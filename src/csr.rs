@@ -0,0 +1,268 @@
+use tabled::{builder::Builder, settings::Style};
+
+/// Float exception flags and rounding mode, both also fields of fcsr
+pub const FFLAGS: u64 = 0x001;
+pub const FRM: u64 = 0x002;
+pub const FCSR: u64 = 0x003;
+pub const SSTATUS: u64 = 0x100;
+pub const SIE: u64 = 0x104;
+pub const STVEC: u64 = 0x105;
+pub const SSCRATCH: u64 = 0x140;
+pub const SEPC: u64 = 0x141;
+pub const SCAUSE: u64 = 0x142;
+pub const STVAL: u64 = 0x143;
+pub const SIP: u64 = 0x144;
+pub const SATP: u64 = 0x180;
+pub const MSTATUS: u64 = 0x300;
+pub const MISA: u64 = 0x301;
+pub const MEDELEG: u64 = 0x302;
+pub const MIDELEG: u64 = 0x303;
+pub const MIE: u64 = 0x304;
+pub const MTVEC: u64 = 0x305;
+pub const MSCRATCH: u64 = 0x340;
+pub const MEPC: u64 = 0x341;
+pub const MCAUSE: u64 = 0x342;
+pub const MTVAL: u64 = 0x343;
+pub const MIP: u64 = 0x344;
+pub const MHARTID: u64 = 0xf14;
+/// Unprivileged counters, read by rdcycle, rdtime and rdinstret
+pub const CYCLE: u64 = 0xc00;
+pub const TIME: u64 = 0xc01;
+pub const INSTRET: u64 = 0xc02;
+
+/// Size of the CSR address space
+pub const CSR_COUNT: u64 = 4096;
+
+pub const CSR_NAMES: [(&str, u64); 27] = [
+    ("fflags", FFLAGS), ("frm", FRM), ("fcsr", FCSR),
+    ("sstatus", SSTATUS), ("sie", SIE), ("stvec", STVEC), ("sscratch", SSCRATCH),
+    ("sepc", SEPC), ("scause", SCAUSE), ("stval", STVAL), ("sip", SIP), ("satp", SATP),
+    ("mstatus", MSTATUS), ("misa", MISA), ("medeleg", MEDELEG), ("mideleg", MIDELEG),
+    ("mie", MIE), ("mtvec", MTVEC), ("mscratch", MSCRATCH), ("mepc", MEPC),
+    ("mcause", MCAUSE), ("mtval", MTVAL), ("mip", MIP), ("mhartid", MHARTID),
+    ("cycle", CYCLE), ("time", TIME), ("instret", INSTRET),
+];
+
+/// misa extension bits, one per letter
+pub const MISA_A: u64 = 1 << 0;
+pub const MISA_C: u64 = 1 << 2;
+pub const MISA_F: u64 = 1 << 5;
+pub const MISA_I: u64 = 1 << 8;
+pub const MISA_M: u64 = 1 << 12;
+
+/// mstatus.MPP, the privilege a machine-mode trap was taken from
+pub const MSTATUS_MPP: u64 = 0b11 << 11;
+/// mstatus.MIE, the global M-mode interrupt enable
+pub const MSTATUS_MIE: u64 = 1 << 3;
+/// mstatus.MPIE, MIE as it was before the last trap
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+/// sstatus.SIE, sstatus.SPIE and sstatus.SPP, the S-mode equivalents
+pub const SSTATUS_SIE: u64 = 1 << 1;
+pub const SSTATUS_SPIE: u64 = 1 << 5;
+pub const SSTATUS_SPP: u64 = 1 << 8;
+/// sstatus.SUM, lets S-mode load and store to user pages
+pub const SSTATUS_SUM: u64 = 1 << 18;
+/// The mstatus bits visible through sstatus: SIE, SPIE, UBE, SPP, VS, FS,
+/// XS, SUM, MXR, UXL and SD. The rest read as 0 and ignore writes.
+pub const SSTATUS_MASK: u64 = 0x8000_0003_000d_e762;
+/// fflags bits: invalid operation, divide by zero, overflow, underflow and
+/// inexact
+pub const FFLAGS_NV: u64 = 1 << 4;
+pub const FFLAGS_DZ: u64 = 1 << 3;
+pub const FFLAGS_OF: u64 = 1 << 2;
+pub const FFLAGS_UF: u64 = 1 << 1;
+pub const FFLAGS_NX: u64 = 1 << 0;
+/// Interrupt causes, which are also their bit positions in mip and mie
+pub const IRQ_M_SOFT: u64 = 3;
+pub const IRQ_M_TIMER: u64 = 7;
+pub const IRQ_M_EXT: u64 = 11;
+
+/// Privilege level the hart executes at, numbered as in mstatus.MPP
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Privilege {
+    U = 0,
+    S = 1,
+    M = 3
+}
+
+impl Privilege {
+    /// Decodes a two-bit privilege field, the reserved value 2 reads as U
+    pub fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            3 => Self::M,
+            1 => Self::S,
+            _ => Self::U
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Csr {
+    csrs: Vec<u64>,
+    /// Current privilege level, harts come out of reset in M-mode
+    pub privilege: Privilege,
+    /// Interrupts raised from outside the hart, as mip bits. Reads of mip
+    /// include them.
+    pub pending_interrupts: u64
+}
+
+impl Default for Csr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Csr {
+    pub fn new() -> Self {
+        let mut csr = Self { csrs: vec![0; CSR_COUNT as usize], privilege: Privilege::M, pending_interrupts: 0 };
+        csr.store(MISA, MISA_A | MISA_C | MISA_F | MISA_I | MISA_M);
+        csr.set_xlen(64);
+        csr
+    }
+
+    /// Register width, taken from the MXL field of misa
+    pub fn xlen(&self) -> u32 {
+        let misa = self.load(MISA);
+        if misa >> 62 == 0 && (misa >> 30) & 0b11 == 1 { 32 } else { 64 }
+    }
+
+    pub fn set_xlen(&mut self, xlen: u32) {
+        let extensions = self.load(MISA) & 0x3ff_ffff;
+        let mxl = if xlen == 32 { 1 << 30 } else { 2 << 62 };
+        self.store(MISA, mxl | extensions);
+    }
+
+    pub fn load(&self, addr: u64) -> u64 {
+        // fflags and frm are views of fcsr
+        match addr & 0xfff {
+            MIP => self.csrs[MIP as usize] | self.pending_interrupts,
+            // sstatus is a restricted view of mstatus
+            SSTATUS => self.csrs[MSTATUS as usize] & SSTATUS_MASK,
+            FFLAGS => self.csrs[FCSR as usize] & 0x1f,
+            FRM => (self.csrs[FCSR as usize] >> 5) & 0b111,
+            addr => self.csrs[addr as usize]
+        }
+    }
+
+    pub fn store(&mut self, addr: u64, value: u64) {
+        let fcsr = self.csrs[FCSR as usize];
+        match addr & 0xfff {
+            FFLAGS => self.csrs[FCSR as usize] = (fcsr & !0x1f) | (value & 0x1f),
+            FRM => self.csrs[FCSR as usize] = (fcsr & !0xe0) | ((value & 0b111) << 5),
+            FCSR => self.csrs[FCSR as usize] = value & 0xff,
+            SSTATUS => {
+                let mstatus = self.csrs[MSTATUS as usize];
+                self.csrs[MSTATUS as usize] = (mstatus & !SSTATUS_MASK) | (value & SSTATUS_MASK);
+            },
+            // the pending bits read back from mip are not the hart's to set
+            MIP => self.csrs[MIP as usize] = value & !self.pending_interrupts,
+            addr => self.csrs[addr as usize] = value
+        }
+    }
+
+    /// The value stored for a CSR, without the views and pending interrupts
+    /// that load adds
+    pub fn load_raw(&self, addr: u64) -> u64 {
+        self.csrs[(addr & 0xfff) as usize]
+    }
+
+    pub fn store_raw(&mut self, addr: u64, value: u64) {
+        self.csrs[(addr & 0xfff) as usize] = value;
+    }
+
+    /// Bits 9:8 of a CSR address give the lowest privilege that may access it
+    pub fn accessible(&self, addr: u64) -> bool {
+        (addr >> 8) & 0b11 <= self.privilege as u64
+    }
+}
+
+/// CSRs with bits 11:10 of their address set, such as cycle and mhartid,
+/// cannot be written by instructions
+pub fn read_only(addr: u64) -> bool {
+    (addr >> 10) & 0b11 == 0b11
+}
+
+/// Looks up a CSR address by name, or parses a raw 12-bit address
+pub fn parse_csr(name: &str) -> Option<u64> {
+    CSR_NAMES.iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, addr)| *addr)
+        .or_else(|| parse_u64(name).filter(|addr| *addr < 4096))
+}
+
+/// Name of a CSR, or its address in hex if it is not in CSR_NAMES
+pub fn csr_name(addr: u64) -> String {
+    CSR_NAMES.iter()
+        .find(|(_, a)| *a == addr)
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_else(|| format!("{:#x}", addr))
+}
+
+/// Parses a `<csr>=<value>` command line assignment
+pub fn parse_csr_arg(arg: &str) -> Result<(u64, u64), String> {
+    let (name, value) = arg.split_once('=')
+        .ok_or(format!("expected <csr>=<value>, got {}", arg))?;
+    let addr = parse_csr(name).ok_or(format!("unknown CSR {}", name))?;
+    let value = parse_u64(value).ok_or(format!("invalid CSR value {}", value))?;
+    Ok((addr, value))
+}
+
+pub fn parse_u64(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
+
+pub fn csr_table(csr: &Csr) -> String {
+    let mut builder = Builder::new();
+    builder.set_header(["CSR", "Decimal", "Hex"]);
+    CSR_NAMES.iter().for_each(|(name, addr)| {
+        let value = csr.load(*addr);
+        builder.push_record([
+            name.to_string(),
+            format!("{}", value),
+            format!("{:#01x}", value),
+        ]);
+    });
+    builder.build()
+        .with(Style::ascii_rounded())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dart::DartSoC, isa::to_bin, soc::SoC};
+    use super::{Csr, parse_csr_arg, MSCRATCH, MSTATUS, MSTATUS_MIE, MSTATUS_MPP, SSTATUS, SSTATUS_SIE, SSTATUS_SPP};
+
+    #[test]
+    fn csr_arg() {
+        assert_eq!(parse_csr_arg("mtvec=0x80001000"), Ok((0x305, 0x8000_1000)));
+        assert_eq!(parse_csr_arg("0x340=42"), Ok((MSCRATCH, 42)));
+        assert!(parse_csr_arg("mscratch").is_err());
+        assert!(parse_csr_arg("bogus=1").is_err());
+    }
+
+    #[test]
+    fn csrr_reads_preset() {
+        let bin = to_bin(&[
+            0x34002573, // csrrs a0, mscratch, zero
+        ]);
+        let mut cpu = DartSoC::new(bin);
+        let (addr, value) = parse_csr_arg("mscratch=0x1234").unwrap();
+        cpu.csr.store(addr, value);
+        cpu.execute();
+        assert_eq!(cpu.regs[10], 0x1234);
+    }
+
+    #[test]
+    fn sstatus_aliases_mstatus() {
+        let mut csr = Csr::new();
+        csr.store(MSTATUS, MSTATUS_MPP | MSTATUS_MIE | SSTATUS_SIE);
+        // the M-mode fields are hidden from sstatus
+        assert_eq!(csr.load(SSTATUS), SSTATUS_SIE);
+        csr.store(SSTATUS, SSTATUS_SPP | MSTATUS_MPP);
+        assert_eq!(csr.load(MSTATUS), MSTATUS_MPP | MSTATUS_MIE | SSTATUS_SPP);
+        assert_eq!(csr.load(SSTATUS), SSTATUS_SPP);
+    }
+}
\ No newline at end of file
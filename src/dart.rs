@@ -1,57 +1,489 @@
-use crate::{bus::{Bus, RAM_END, RAM_BASE}, stats::Stats, mem::B64, isa::{Rv32i, Extension, Rv64i}, exception::Exception};
+use std::io::Write;
+
+use crate::{bus::Bus, stats::{Stats, InsMix, Profile}, isa::Extension, exception::Exception, soc::{SoC, Config, ExecObserver, Hart, Datapath, fetch}, csr::Csr};
 
 pub struct DartSoC {
     pub regs: [u64; 32],
+    /// Float registers, holding NaN-boxed singles
+    pub fregs: [u64; 32],
     pub pc: u64,
     pub bus: Bus,
-    pub stats: Stats
+    pub csr: Csr,
+    pub stats: Stats,
+    /// Executed instructions by mnemonic
+    pub mix: InsMix,
+    /// Executions of each pc
+    pub profile: Profile,
+    pub config: Config,
+    pub trace_out: Box<dyn Write>,
+    pub observer: Option<Box<dyn ExecObserver>>
 }
 
 type Result = std::result::Result<(), Exception>;
 
 impl DartSoC {
-    pub fn new(bin: Vec<u8>) -> Self {
-        let mut regs = [0_u64; 32];
-        regs[2] = RAM_END;
-        let pc = RAM_BASE;
+    fn hart(&mut self) -> Hart<'_> {
+        Hart {
+            regs: &mut self.regs, fregs: &mut self.fregs, pc: &mut self.pc, bus: &mut self.bus, csr: &mut self.csr,
+            stats: &mut self.stats, mix: &mut self.mix, profile: &mut self.profile, config: &self.config,
+            trace_out: &mut self.trace_out, observer: &mut self.observer
+        }
+    }
+}
+
+impl Datapath for DartSoC {
+    fn datapath<O: Extension>(&mut self, word: u32, i: O) -> Result {
+        let mem = i.is_ld() || i.is_st();
+        self.hart().commit(word, i)?;
+        // an access that faults never waits on memory
+        if mem {
+            self.stats.cycles += self.config.mem_latency;
+        }
+        self.stats.retired += 1;
+        Ok(())
+    }
+}
+
+impl SoC for DartSoC {
+    fn with_entry(bin: Vec<u8>, entry: u64) -> Self {
         let bus = Bus::new(bin);
+        let mut regs = [0_u64; 32];
+        regs[2] = bus.base + bus.size - 1;
+        let pc = entry;
+        let csr = Csr::new();
         let stats = Stats::new();
-        Self { regs, pc, bus, stats }
+        let mix = InsMix::new();
+        let profile = Profile::new();
+        Self { regs, fregs: [0; 32], pc, bus, csr, stats, mix, profile, config: Config { entry, ..Config::default() }, trace_out: Box::new(std::io::stdout()), observer: None }
     }
 
-    pub fn pipeline(&mut self) -> Result {
-        let ins = self.bus.load(self.pc, B64)? as u32;
-        if let Ok(ins) = Rv32i::id(ins) {
-            self.datapath(ins)
-        } else if let Ok(ins) = Rv64i::id(ins) {
-            self.datapath(ins)
-        } else {
-            Err(Exception::IllegalInstruction(ins as u64))
-        }
+    fn pipeline(&mut self) -> Result {
+        let (word, ins) = fetch(&mut self.bus, &self.csr, self.pc, self.config.enabled_exts, None)?;
+        self.dispatch(word, ins)
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn pc_mut(&mut self) -> &mut u64 {
+        &mut self.pc
+    }
+
+    fn regfile(&self) -> &[u64; 32] {
+        &self.regs
+    }
+
+    fn regfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.regs
+    }
+
+    fn fregfile(&self) -> &[u64; 32] {
+        &self.fregs
+    }
+
+    fn fregfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.fregs
+    }
+
+    fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    fn csr(&mut self) -> &mut Csr {
+        &mut self.csr
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    fn mix(&self) -> &InsMix {
+        &self.mix
+    }
+
+    fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    fn reset(&mut self, bin: Vec<u8>) {
+        self.hart().reset(&bin);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::{to_bin, disasm, parse_march_arg, Exts}, soc::{SoC, Exit}, exception::Exception, bus::{Bus, RAM_BASE, RAM_SIZE}, mem::{B16, B32}, kronos::KronosSoC, atlas::AtlasSoC};
+    use super::DartSoC;
+
+    #[test]
+    fn jump_to_top_of_address_space() {
+        let bin = to_bin(&[
+            0xffc00067, // jalr zero, -4(zero)
+        ]);
+        let mut cpu = DartSoC::new(bin);
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::InstructionAccessFault(0xffff_ffff_ffff_fffc))));
+        assert_eq!(cpu.pc, 0xffff_ffff_ffff_fffc);
     }
 
-    pub fn datapath<O: Extension>(&mut self, i: O) -> Result {
-        let ins_ex = i.ex(&self.regs);
-        if ins_ex.is_ld() || ins_ex.is_st() {
-            self.stats.mem_ops += 1;
-        } else {
-            self.stats.alu_ops += 1;
+    #[test]
+    fn far_call_above_4gib() {
+        let base = 0x1_0000_0000;
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x010280e7, // jalr ra, 16(t0)
+            0x00100593, // addi a1, zero, 1
+            0x00000073, // ecall
+            // callee
+            0x02a00513, // addi a0, zero, 42
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+        let mut cpu = DartSoC::new(vec![]);
+        cpu.bus = Bus::with_layout(bin, base, 64 * 1024);
+        cpu.pc = base;
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::EnvironmentCallFromMMode(pc)) if pc == base + 12));
+        assert_eq!(cpu.regs[10], 42);
+        assert_eq!(cpu.regs[11], 1);
+        assert_eq!(cpu.regs[1], base + 8);
+    }
+
+    #[test]
+    fn fetch_past_ram_end() {
+        let bin = to_bin(&[
+            0x08000297, // auipc t0, 0x8000
+            0x00028067, // jalr zero, 0(t0)
+        ]);
+        let mut cpu = DartSoC::new(bin);
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::InstructionAccessFault(pc)) if pc == RAM_BASE + RAM_SIZE));
+    }
+
+    #[test]
+    fn fetch_at_ram_end() {
+        let end = RAM_BASE + RAM_SIZE;
+        let cpus: [Box<dyn SoC>; 3] = [
+            Box::new(DartSoC::new(vec![])),
+            Box::new(KronosSoC::new(vec![])),
+            Box::new(AtlasSoC::new(vec![])),
+        ];
+        for mut cpu in cpus {
+            // the last word of RAM, which an 8-byte fetch would overrun
+            cpu.bus_mut().store(end - 4, B32, 0x02a00513).unwrap(); // addi a0, zero, 42
+            *cpu.pc_mut() = end - 4;
+            assert!(matches!(cpu.execute(), Exit::Exception(Exception::InstructionAccessFault(pc)) if pc == end));
+            assert_eq!(cpu.regfile()[10], 42);
         }
-        self.regs[0] = 0;
-        self.pc = ins_ex.wr(self.pc, &mut self.regs, &mut self.bus)?;
-        self.regs[0] = 0;
-        Ok(())
+
+        // a compressed instruction in the last halfword
+        let mut cpu = DartSoC::new(vec![]);
+        cpu.bus.store(end - 2, B16, 0x4515).unwrap(); // c.li a0, 5
+        cpu.pc = end - 2;
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::InstructionAccessFault(pc)) if pc == end));
+        assert_eq!(cpu.regs[10], 5);
+    }
+
+    #[test]
+    fn record_overflow() {
+        let bin = to_bin(&[
+            0x00150513, // addi a0, a0, 1
+            0x00b58633, // add a2, a1, a1
+            0x40b006b3, // sub a3, zero, a1
+        ]);
+        let mut cpu = DartSoC::new(bin.clone());
+        cpu.config.record_overflow = true;
+        cpu.regs[10] = i64::MAX as u64;
+        cpu.regs[11] = 5;
+        cpu.execute();
+        assert_eq!(cpu.regs[10], i64::MIN as u64);
+        assert_eq!(cpu.stats.overflows, vec![RAM_BASE]);
+
+        // off by default
+        let mut cpu = DartSoC::new(bin);
+        cpu.regs[10] = i64::MAX as u64;
+        cpu.execute();
+        assert!(cpu.stats.overflows.is_empty());
+    }
+
+    #[test]
+    fn mem_latency() {
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x0002b503, // ld a0, 0(t0)
+            0x0082b583, // ld a1, 8(t0)
+            0x0102a603, // lw a2, 16(t0)
+            0x00002683, // lw a3, 0(zero)
+        ]);
+        let mut fast = DartSoC::new(bin.clone());
+        fast.execute();
+        let mut slow = DartSoC::new(bin);
+        slow.config.mem_latency = 10;
+        assert!(matches!(slow.execute(), Exit::Exception(Exception::LoadAccessFault(0))));
+        assert_eq!(slow.regs, fast.regs);
+        assert_eq!(fast.stats.mem_ops, 4);
+        // the faulting lw is not charged
+        assert_eq!(slow.stats.cycles, fast.stats.cycles + 3 * 10);
+    }
+
+    #[test]
+    fn march_disables_extensions() {
+        let bin = to_bin(&[
+            0x00300513, // addi a0, zero, 3
+            0x02a50533, // mul a0, a0, a0
+        ]);
+        let (_, rv64i) = parse_march_arg("rv64i").unwrap();
+        let mut cpu = DartSoC::new(bin.clone());
+        cpu.config.enabled_exts = rv64i;
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0x02a50533))));
+        assert_eq!(cpu.regs[10], 3);
+
+        let (_, rv64im) = parse_march_arg("rv64im").unwrap();
+        let mut cpu = DartSoC::new(bin);
+        cpu.config.enabled_exts = rv64im;
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.regs[10], 9);
+
+        assert_eq!(parse_march_arg("RV32IMAFC_zbb_zicsr"), Ok((32, Exts::ALL)));
+        assert_eq!(parse_march_arg("rv64gc"), Ok((64, Exts::M.with(Exts::A).with(Exts::F).with(Exts::C))));
+        assert!(parse_march_arg("rv64id").is_err());
+        assert!(parse_march_arg("x86").is_err());
+    }
+
+    #[test]
+    fn mixed_compressed_stream() {
+        let bin = vec![
+            0x15, 0x45, // c.li a0, 5
+            0x93, 0x05, 0x15, 0x00, // addi a1, a0, 1
+            0x05, 0x05, // c.addi a0, 1
+        ];
+        let mut cpu = DartSoC::new(bin);
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.regs[10], 6);
+        assert_eq!(cpu.regs[11], 6);
+        assert_eq!(cpu.pc, RAM_BASE + 8);
+    }
+
+    #[test]
+    fn xlen32() {
+        let bin = to_bin(&[
+            0xfff00513, // addi a0, zero, -1
+            0x800005b7, // lui a1, 0x80000
+            0x4045d613, // srai a2, a1, 4
+            0x0005a6b3, // slt a3, a1, zero
+            0x00455713, // srli a4, a0, 4
+            0x00003783, // ld a5, 0(zero)
+        ]);
+        let mut rv64 = DartSoC::new(bin.clone());
+        let ex = rv64.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::LoadAccessFault(0))));
+        assert_eq!(rv64.regs[10], 0xffff_ffff_ffff_ffff);
+        assert_eq!(rv64.regs[11], 0xffff_ffff_8000_0000);
+        assert_eq!(rv64.regs[12], 0xffff_ffff_f800_0000);
+        assert_eq!(rv64.regs[13], 1);
+        assert_eq!(rv64.regs[14], 0x0fff_ffff_ffff_ffff);
+
+        let mut rv32 = DartSoC::new(bin);
+        rv32.csr.set_xlen(32);
+        let ex = rv32.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0x00003783))));
+        assert_eq!(rv32.regs[10], 0xffff_ffff);
+        assert_eq!(rv32.regs[11], 0x8000_0000);
+        assert_eq!(rv32.regs[12], 0xf800_0000);
+        assert_eq!(rv32.regs[13], 1);
+        assert_eq!(rv32.regs[14], 0x0fff_ffff);
+    }
+
+    #[test]
+    fn xlen32_rvc_and_shifts() {
+        let mut bin = vec![
+            0x11, 0x20, // c.jal 4
+            0x01, 0x00, // c.nop
+        ];
+        bin.extend(to_bin(&[
+            0x02151513, // slli a0, a0, 33
+        ]));
+        let mut rv32 = DartSoC::new(bin);
+        rv32.csr.set_xlen(32);
+        let ex = rv32.execute();
+        // shamt[5] is reserved in RV32
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0x02151513))));
+        assert_eq!(rv32.regs[1], RAM_BASE + 2);
+        assert_eq!(rv32.pc, RAM_BASE + 4);
+    }
+
+    #[test]
+    fn fence_between_stores() {
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x00100513, // addi a0, zero, 1
+            0x00200593, // addi a1, zero, 2
+            0x10a2a023, // sw a0, 256(t0)
+            0x0ff0000f, // fence
+            0x0000100f, // fence.i
+            0x10b2a223, // sw a1, 260(t0)
+        ]);
+        assert_eq!(disasm(&bin[16..24], RAM_BASE, true), "80000000: fence iorw, iorw\n80000004: fence.i\n");
+        let mut cpu = DartSoC::new(bin);
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.pc, RAM_BASE + 28);
+        assert_eq!(cpu.bus.load(RAM_BASE + 256, B32).unwrap(), 1);
+        assert_eq!(cpu.bus.load(RAM_BASE + 260, B32).unwrap(), 2);
+    }
+
+    #[test]
+    fn instruction_mix() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000513, // addi a0, zero, 0
+            0x00500593, // addi a1, zero, 5
+            0x00b50533, // add a0, a0, a1
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ce3, // bne a1, zero, -8
+        ]));
+        cpu.execute();
+        // two addis before the loop, then one per iteration
+        assert_eq!(cpu.mix.count("addi"), 2 + 5);
+        assert_eq!(cpu.mix.count("add"), 5);
+        assert_eq!(cpu.mix.count("bne"), 5);
+        assert_eq!(cpu.mix.count("sub"), 0);
+    }
+
+    #[test]
+    fn wfi_is_a_hint() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x10500073, // wfi
+            0x00200593, // addi a1, zero, 2
+        ]));
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.regs[11], 2);
+        assert_eq!(cpu.pc, RAM_BASE + 12);
+        assert_eq!(disasm(&to_bin(&[0x10500073]), RAM_BASE, true), "80000000: wfi\n");
+    }
+
+    #[test]
+    fn entry_skips_first_instruction() {
+        let mut cpu = DartSoC::with_entry(to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00200593, // addi a1, zero, 2
+        ]), RAM_BASE + 4);
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.regs[10], 0);
+        assert_eq!(cpu.regs[11], 2);
+        assert_eq!(cpu.stats.retired, 1);
     }
 
-    pub fn execute(&mut self) -> Exception {
-        loop {
-            self.stats.cycles += 1;
-            match self.pipeline() {
-                Ok(_) => {},
-                Err(ex) => if ex.is_fatal() {
-                    return ex
-                },
-            }
+    #[test]
+    fn loop_body_is_hottest() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000513, // addi a0, zero, 0
+            0x00500593, // addi a1, zero, 5
+            0x00b50533, // add a0, a0, a1
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ce3, // bne a1, zero, -8
+        ]));
+        cpu.execute();
+        assert_eq!(cpu.profile.hottest(4), [(RAM_BASE + 8, 5), (RAM_BASE + 12, 5), (RAM_BASE + 16, 5), (RAM_BASE, 1)]);
+        assert!(cpu.hot_spots(1).contains("| 0x80000008 | 5     | 00b50533 | add a0, a0, a1 |"));
+    }
+
+    #[test]
+    fn reset_reuses_ram() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00000297, // auipc t0, 0
+            0x10a2a023, // sw a0, 256(t0)
+        ]));
+        cpu.execute();
+        assert_eq!(cpu.bus.load(RAM_BASE + 260, B32).unwrap(), 1);
+        let ram = cpu.bus.ram().unwrap().as_ptr();
+
+        cpu.reset(to_bin(&[
+            0x00200593, // addi a1, zero, 2
+        ]));
+        assert_eq!(cpu.stats.retired, 0);
+        assert_eq!(cpu.mix.count("addi"), 0);
+        cpu.execute();
+        assert_eq!(cpu.regs[10], 0);
+        assert_eq!(cpu.regs[11], 2);
+        assert_eq!(cpu.stats.retired, 1);
+        assert_eq!(cpu.bus.load(RAM_BASE + 260, B32).unwrap(), 0);
+        assert_eq!(cpu.bus.ram().unwrap().as_ptr(), ram);
+    }
+
+    #[test]
+    fn reset_keeps_entry_and_xlen() {
+        let bin = to_bin(&[
+            0xfff00513, // addi a0, zero, -1
+            0xfff00593, // addi a1, zero, -1
+        ]);
+        let mut cpu = DartSoC::with_entry(bin.clone(), RAM_BASE + 4);
+        cpu.csr.set_xlen(32);
+        cpu.reset(bin);
+        assert_eq!(cpu.pc, RAM_BASE + 4);
+        assert_eq!(cpu.csr.xlen(), 32);
+        cpu.execute();
+        assert_eq!(cpu.regs[10], 0);
+        assert_eq!(cpu.regs[11], 0xffff_ffff);
+    }
+
+    #[test]
+    fn sltiu_unsigned_immediate() {
+        let bin = to_bin(&[
+            0xfff03093, // sltiu ra, zero, -1
+            0x00003113, // sltiu sp, zero, 0
+            0xfff00293, // addi t0, zero, -1
+            0x0012b193, // sltiu gp, t0, 1 (seqz)
+            0x00103213, // sltiu tp, zero, 1 (seqz)
+            0xfff2b313, // sltiu t1, t0, -1
+        ]);
+        for xlen in [32, 64] {
+            let mut cpu = DartSoC::new(bin.clone());
+            cpu.csr.set_xlen(xlen);
+            cpu.execute();
+            // -1 is the largest unsigned value at either width
+            assert_eq!(cpu.regs[1], 1);
+            assert_eq!(cpu.regs[2], 0);
+            assert_eq!(cpu.regs[3], 0);
+            assert_eq!(cpu.regs[4], 1);
+            assert_eq!(cpu.regs[6], 0);
         }
     }
+
+    #[test]
+    fn lui_upper_immediate() {
+        let bin = to_bin(&[
+            0xabcde0b7, // lui ra, 0xabcde
+            0x12345137, // lui sp, 0x12345
+        ]);
+        let mut rv64 = DartSoC::new(bin.clone());
+        rv64.execute();
+        // bit 31 of the immediate is sign-extended into the upper word
+        assert_eq!(rv64.regs[1], 0xffff_ffff_abcd_e000);
+        assert_eq!(rv64.regs[2], 0x1234_5000);
+
+        let mut rv32 = DartSoC::new(bin.clone());
+        rv32.csr.set_xlen(32);
+        rv32.execute();
+        assert_eq!(rv32.regs[1], 0xabcd_e000);
+
+        let mut kronos = KronosSoC::new(bin);
+        kronos.execute();
+        assert_eq!(kronos.regs, rv64.regs);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,126 @@
+use crate::{isa::{Rv32i, Extension, Exts, Rv64i, Zicsr, Zifencei, System}, rvc::{is_compressed, expand}, rvm::{Rv32m, Rv64m}, rva::{Rv32a, Rv64a}, rvb::{Zbb, Zbb64}, rvf::Rv32f, exception::Exception};
+
+/// Entries in a DecodeCache, enough for the hot loops of most programs
+pub const DECODE_CACHE_ENTRIES: usize = 4096;
+
+/// An instruction decoded by whichever extension recognised it
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Decoded {
+    Rv32i(Rv32i),
+    Rv64i(Rv64i),
+    Zbb(Zbb),
+    Zbb64(Zbb64),
+    Rv32m(Rv32m),
+    Rv64m(Rv64m),
+    Rv32a(Rv32a),
+    Rv64a(Rv64a),
+    Rv32f(Rv32f),
+    Zicsr(Zicsr),
+    Zifencei(Zifencei),
+    System(System),
+}
+
+/// Decodes an instruction word, the low half only if it is compressed. The
+/// pipelines and the disassembler all decode through here, so they agree
+/// on which extension claims a word. The RV64-only encodings are illegal
+/// unless rv64 is set.
+pub fn decode(word: u32, rv64: bool, exts: Exts) -> Result<Decoded, Exception> {
+    let illegal = Exception::IllegalInstruction(word as u64);
+    let ins = if is_compressed(word) && exts.has(Exts::C) {
+        expand(word as u16, rv64)?
+    } else if is_compressed(word) {
+        return Err(illegal)
+    } else {
+        word
+    };
+    // slli, srli and srai with shamt[5] set only exist in RV64
+    if !rv64 && ins & 0x7f == 0b0010011 && (ins >> 12) & 0b11 == 0b01 && (ins >> 25) & 1 == 1 {
+        return Err(illegal)
+    }
+    if let Ok(ins) = Rv32i::id(ins) {
+        Ok(Decoded::Rv32i(ins))
+    } else if let Some(ins) = Rv64i::id(ins).ok().filter(|_| rv64) {
+        Ok(Decoded::Rv64i(ins))
+    } else if let Some(ins) = Zbb::id(ins).ok().filter(|_| exts.has(Exts::ZBB)) {
+        Ok(Decoded::Zbb(ins))
+    } else if let Some(ins) = Zbb64::id(ins).ok().filter(|_| rv64 && exts.has(Exts::ZBB)) {
+        Ok(Decoded::Zbb64(ins))
+    } else if let Some(ins) = Rv32m::id(ins).ok().filter(|_| exts.has(Exts::M)) {
+        Ok(Decoded::Rv32m(ins))
+    } else if let Some(ins) = Rv64m::id(ins).ok().filter(|_| rv64 && exts.has(Exts::M)) {
+        Ok(Decoded::Rv64m(ins))
+    } else if let Some(ins) = Rv32a::id(ins).ok().filter(|_| exts.has(Exts::A)) {
+        Ok(Decoded::Rv32a(ins))
+    } else if let Some(ins) = Rv64a::id(ins).ok().filter(|_| rv64 && exts.has(Exts::A)) {
+        Ok(Decoded::Rv64a(ins))
+    } else if let Some(ins) = Rv32f::id(ins).ok().filter(|_| exts.has(Exts::F)) {
+        Ok(Decoded::Rv32f(ins))
+    } else if let Ok(ins) = Zicsr::id(ins) {
+        Ok(Decoded::Zicsr(ins))
+    } else if let Ok(ins) = Zifencei::id(ins) {
+        Ok(Decoded::Zifencei(ins))
+    } else if let Ok(ins) = System::id(ins) {
+        Ok(Decoded::System(ins))
+    } else {
+        Err(illegal)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    pc: u64,
+    word: u32,
+    rv64: bool,
+    exts: Exts,
+    ins: Decoded
+}
+
+/// Direct-mapped cache of decoded instructions keyed by pc. An entry is
+/// only used while the word at its pc and the decode mode are unchanged,
+/// so a store into the program or a change of mapping decodes afresh.
+pub struct DecodeCache {
+    entries: Vec<Option<Entry>>,
+    pub hits: usize,
+    pub misses: usize
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self { entries: vec![None; DECODE_CACHE_ENTRIES], hits: 0, misses: 0 }
+    }
+
+    /// Decodes the instruction fetched from pc, returning its word with the
+    /// upper half dropped if it is compressed
+    pub fn decode(&mut self, pc: u64, fetched: u32, rv64: bool, exts: Exts) -> Result<(u32, Decoded), Exception> {
+        let word = if is_compressed(fetched) { fetched & 0xffff } else { fetched };
+        // instructions are at least two-byte aligned
+        let slot = &mut self.entries[(pc >> 1) as usize % DECODE_CACHE_ENTRIES];
+        if let Some(entry) = slot.filter(|e| e.pc == pc && e.word == word && e.rv64 == rv64 && e.exts == exts) {
+            self.hits += 1;
+            return Ok((word, entry.ins))
+        }
+        self.misses += 1;
+        let ins = decode(word, rv64, exts)?;
+        *slot = Some(Entry { pc, word, rv64, exts, ins });
+        Ok((word, ins))
+    }
+
+    /// The decoded instruction cached for pc, if any
+    pub fn get(&self, pc: u64) -> Option<Decoded> {
+        self.entries[(pc >> 1) as usize % DECODE_CACHE_ENTRIES]
+            .filter(|e| e.pc == pc)
+            .map(|e| e.ins)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
\ No newline at end of file
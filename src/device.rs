@@ -0,0 +1,298 @@
+use std::io::Write;
+
+use crate::mem::{Mem, Bits};
+
+/// Request from a device to stop the run
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Halt {
+    Done,
+    AssertFailed(u64),
+    /// The guest asked the host to exit with a status code
+    Exit(u64),
+}
+
+/// A memory-mapped device. Offsets are relative to the address the
+/// device is mapped at, and are guaranteed to lie inside the mapping.
+pub trait Device {
+    fn load(&self, offset: u64, bits: Bits) -> u64;
+    fn store(&mut self, offset: u64, bits: Bits, value: u64);
+    /// Polled after every instruction
+    fn halt(&self) -> Option<Halt> {
+        None
+    }
+    /// Backing storage for devices that are plain memory
+    fn ram(&self) -> Option<&[u8]> {
+        None
+    }
+    fn ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+impl Device for Mem {
+    fn load(&self, offset: u64, bits: Bits) -> u64 {
+        Mem::load(self, offset, bits)
+    }
+
+    fn store(&mut self, offset: u64, bits: Bits, value: u64) {
+        Mem::store(self, offset, bits, value)
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        Some(self.bytes())
+    }
+
+    fn ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(self.bytes_mut())
+    }
+}
+
+/*
+Minimal 8250-style UART, output only:
+
+| offset | name | on load                  | on store           |
+|--------|------|--------------------------|--------------------|
+| 0x0    | THR  | 0 (no input)             | write low byte out |
+| 0x5    | LSR  | transmitter always empty | ignored            |
+*/
+
+pub const UART_THR: u64 = 0x0;
+pub const UART_LSR: u64 = 0x5;
+const LSR_THRE: u64 = 1 << 5;
+const LSR_TEMT: u64 = 1 << 6;
+
+pub struct Uart {
+    out: Box<dyn Write>
+}
+
+impl Uart {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self { out }
+    }
+}
+
+impl Device for Uart {
+    fn load(&self, offset: u64, _bits: Bits) -> u64 {
+        match offset {
+            UART_LSR => LSR_THRE | LSR_TEMT,
+            _ => 0
+        }
+    }
+
+    fn store(&mut self, offset: u64, _bits: Bits, value: u64) {
+        if offset == UART_THR {
+            // guest output is best effort and never stops the run
+            let _ = self.out.write_all(&[value as u8]);
+            let _ = self.out.flush();
+        }
+    }
+}
+
+/*
+Assertion device for self-checking test programs. Both registers are
+write-only and read as zero:
+
+| offset | name | on store                                      |
+|--------|------|-----------------------------------------------|
+| 0x0    | FAIL | halt with an assertion failure carrying value |
+| 0x8    | DONE | halt successfully, the value is ignored       |
+*/
+
+pub const ASSERT_FAIL: u64 = 0x0;
+pub const ASSERT_DONE: u64 = 0x8;
+
+#[derive(Default)]
+pub struct Assert {
+    halt: Option<Halt>
+}
+
+impl Device for Assert {
+    fn load(&self, _offset: u64, _bits: Bits) -> u64 {
+        0
+    }
+
+    fn store(&mut self, offset: u64, _bits: Bits, value: u64) {
+        match offset {
+            ASSERT_FAIL => self.halt = Some(Halt::AssertFailed(value)),
+            ASSERT_DONE => self.halt = Some(Halt::Done),
+            _ => {}
+        }
+    }
+
+    fn halt(&self) -> Option<Halt> {
+        self.halt
+    }
+}
+
+/*
+SiFive test finisher, as on QEMU's virt machine. The low half of a word
+stored at offset 0 selects the action:
+
+| value              | meaning                    |
+|--------------------|----------------------------|
+| 0x5555             | pass, exit(0)              |
+| 0x3333 \| code << 16 | fail, exit with code       |
+
+Other values are ignored.
+*/
+
+const FINISHER_PASS: u64 = 0x5555;
+const FINISHER_FAIL: u64 = 0x3333;
+
+#[derive(Default)]
+pub struct Finisher {
+    halt: Option<Halt>
+}
+
+impl Device for Finisher {
+    fn load(&self, _offset: u64, _bits: Bits) -> u64 {
+        0
+    }
+
+    fn store(&mut self, offset: u64, _bits: Bits, value: u64) {
+        if offset != 0 {
+            return
+        }
+        match value & 0xffff {
+            FINISHER_PASS => self.halt = Some(Halt::Done),
+            FINISHER_FAIL => self.halt = Some(Halt::Exit((value >> 16) & 0xffff)),
+            _ => {}
+        }
+    }
+
+    fn halt(&self) -> Option<Halt> {
+        self.halt
+    }
+}
+
+/*
+HTIF tohost register used by riscv-tests. The doubleword written to it is
+interpreted as:
+
+| value                      | meaning                          |
+|----------------------------|----------------------------------|
+| 1 << 56 \| 1 << 48 \| char | console putchar                  |
+| 1                          | test passed                      |
+| other odd value            | exit with status value >> 1      |
+
+Other device commands are ignored. fromhost is left to ordinary memory.
+*/
+
+pub const HTIF_SIZE: u64 = 0x8;
+
+const HTIF_DEV_CONSOLE: u64 = 1;
+const HTIF_CMD_PUTCHAR: u64 = 1;
+
+pub struct Htif {
+    tohost: u64,
+    out: Box<dyn Write>,
+    halt: Option<Halt>
+}
+
+impl Htif {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self { tohost: 0, out, halt: None }
+    }
+}
+
+impl Device for Htif {
+    fn load(&self, offset: u64, _bits: Bits) -> u64 {
+        self.tohost >> (offset * 8)
+    }
+
+    fn store(&mut self, offset: u64, bits: Bits, value: u64) {
+        // RV32 programs write the doubleword as two words
+        let mask = u64::MAX >> (64 - bits.size() * 8);
+        let shift = offset * 8;
+        self.tohost = (self.tohost & !(mask << shift)) | ((value & mask) << shift);
+        let (dev, cmd) = (self.tohost >> 56, (self.tohost >> 48) & 0xff);
+        if dev == HTIF_DEV_CONSOLE && cmd == HTIF_CMD_PUTCHAR {
+            // guest output is best effort and never stops the run
+            let _ = self.out.write_all(&[self.tohost as u8]);
+            let _ = self.out.flush();
+            self.tohost = 0;
+        } else if self.tohost == 1 {
+            self.halt = Some(Halt::Done);
+        } else if dev == 0 && self.tohost & 1 == 1 {
+            self.halt = Some(Halt::Exit((self.tohost & 0xffff_ffff_ffff) >> 1));
+        }
+    }
+
+    fn halt(&self) -> Option<Halt> {
+        self.halt
+    }
+}
+
+/// Writer that can be handed to a device while the test keeps a handle
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub struct SharedBuf(pub std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl SharedBuf {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dart::DartSoC, isa::to_bin, soc::{SoC, Exit}, bus::{UART_BASE, UART_SIZE, RAM_BASE}};
+    use super::{Uart, SharedBuf, Htif, HTIF_SIZE};
+
+    #[test]
+    fn uart_output() {
+        let buf = SharedBuf::default();
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x100002b7, // lui t0, 0x10000
+            0x04800313, // addi t1, zero, 72
+            0x00628023, // sb t1, 0(t0)
+            0x06900313, // addi t1, zero, 105
+            0x00628023, // sb t1, 0(t0)
+        ]));
+        cpu.bus.map(UART_BASE, UART_SIZE, Box::new(Uart::new(Box::new(buf.clone()))));
+        cpu.execute();
+        assert_eq!(buf.contents(), "Hi");
+    }
+
+    #[test]
+    fn htif_tohost() {
+        let buf = SharedBuf::default();
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x04100313, // addi t1, zero, 65
+            0x10100393, // addi t2, zero, 257
+            0x03039393, // slli t2, t2, 48
+            0x00736333, // or t1, t1, t2
+            0x1062b023, // sd t1, 256(t0)
+            0x00100313, // addi t1, zero, 1
+            0x1062b023, // sd t1, 256(t0)
+        ]);
+        let mut cpu = DartSoC::new(bin.clone());
+        cpu.bus.map(RAM_BASE + 256, HTIF_SIZE, Box::new(Htif::new(Box::new(buf.clone()))));
+        let exit = cpu.execute();
+        assert!(matches!(exit, Exit::Done));
+        assert_eq!(exit.code(), 0);
+        assert_eq!(buf.contents(), "A");
+
+        // a failing test reports its number as the exit status
+        let mut bin = bin;
+        bin[24..28].copy_from_slice(&0x00700313_u32.to_le_bytes()); // addi t1, zero, 7
+        let mut cpu = DartSoC::new(bin);
+        cpu.bus.map(RAM_BASE + 256, HTIF_SIZE, Box::new(Htif::new(Box::new(SharedBuf::default()))));
+        let exit = cpu.execute();
+        assert!(matches!(exit, Exit::Status(3)));
+        assert_eq!(exit.code(), 3);
+    }
+}
\ No newline at end of file
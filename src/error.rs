@@ -0,0 +1,49 @@
+use std::{fmt::Display, io};
+
+/// Why a run of the simulator could not be carried out, as opposed to the
+/// Exit of a program that ran
+#[derive(Debug)]
+pub enum MurError {
+    /// Reading the program or writing an output file failed
+    Io(io::Error),
+    /// --soc named a model that does not exist
+    UnknownSoc(String),
+    /// A snapshot or replay log could not be decoded
+    Decode(String),
+    /// The options cannot be honoured, e.g. a dump of unmapped memory
+    Config(String),
+}
+
+impl MurError {
+    /// Process exit status for the error, kept apart from the statuses a
+    /// guest program can produce on a clean run
+    pub fn exit_code(&self) -> i32 {
+        2
+    }
+}
+
+impl Display for MurError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MurError::Io(e) => write!(f, "{}", e),
+            MurError::UnknownSoc(soc) => write!(f, "Unknown SoC type {}", soc),
+            MurError::Decode(msg) => write!(f, "{}", msg),
+            MurError::Config(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MurError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MurError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for MurError {
+    fn from(e: io::Error) -> Self {
+        MurError::Io(e)
+    }
+}
\ No newline at end of file
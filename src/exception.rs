@@ -57,6 +57,25 @@ impl Exception {
         }
     }
 
+    pub fn name(&self) -> &'static str {
+        match self {
+            Exception::InstructionAddrMisaligned(_) => "InstructionAddrMisaligned",
+            Exception::InstructionAccessFault(_) => "InstructionAccessFault",
+            Exception::IllegalInstruction(_) => "IllegalInstruction",
+            Exception::Breakpoint(_) => "Breakpoint",
+            Exception::LoadAccessMisaligned(_) => "LoadAccessMisaligned",
+            Exception::LoadAccessFault(_) => "LoadAccessFault",
+            Exception::StoreAMOAddrMisaligned(_) => "StoreAMOAddrMisaligned",
+            Exception::StoreAMOAccessFault(_) => "StoreAMOAccessFault",
+            Exception::EnvironmentCallFromUMode(_) => "EnvironmentCallFromUMode",
+            Exception::EnvironmentCallFromSMode(_) => "EnvironmentCallFromSMode",
+            Exception::EnvironmentCallFromMMode(_) => "EnvironmentCallFromMMode",
+            Exception::InstructionPageFault(_) => "InstructionPageFault",
+            Exception::LoadPageFault(_) => "LoadPageFault",
+            Exception::StoreAMOPageFault(_) => "StoreAMOPageFault",
+        }
+    }
+
     pub fn is_fatal(&self) -> bool {
         match self {
             Exception::InstructionAddrMisaligned(_)
@@ -0,0 +1,316 @@
+use std::{collections::HashSet, io::{self, BufReader, Read, Write}, net::{TcpListener, TcpStream}};
+
+use crate::{soc::{SoC, Exit}, mem::B8, exception::Exception};
+
+/*
+A GDB remote serial protocol stub. Supports the register, memory, software
+breakpoint, step and continue packets, which is enough for `target remote`
+*/
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// gdb numbers the pc after the 32 integer registers
+const PC_REG: u64 = 32;
+
+/// Steps between checks for a ^C from the debugger while continuing
+const INTERRUPT_POLL: usize = 4096;
+
+/// Opens the port a debugger connects to, on localhost only
+pub fn listen(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind(("127.0.0.1", port))
+}
+
+/// Waits for a debugger on `listener` and serves it until it detaches
+pub fn serve(listener: &TcpListener, cpu: &mut dyn SoC) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    Stub { cpu, breakpoints: HashSet::new() }.run(stream)
+}
+
+struct Stub<'a> {
+    cpu: &'a mut dyn SoC,
+    breakpoints: HashSet<u64>,
+}
+
+impl Stub<'_> {
+    fn run(&mut self, stream: TcpStream) -> io::Result<()> {
+        // packets are tiny and strictly request/reply
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        while let Some(packet) = read_packet(&mut reader)? {
+            writer.write_all(b"+")?;
+            match packet.as_str() {
+                "k" => return Ok(()),
+                "D" => return write_packet(&mut writer, "OK"),
+                _ => {
+                    let reply = self.handle(&packet, &writer);
+                    write_packet(&mut writer, &reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str, stream: &TcpStream) -> String {
+        let Some(cmd) = packet.chars().next() else {
+            return String::new()
+        };
+        let args = &packet[1..];
+        let reply = match cmd {
+            '?' => Some(format!("S{:02x}", SIGTRAP)),
+            'g' => Some(self.read_regs()),
+            'G' => self.write_regs(args),
+            'p' => self.read_reg(args),
+            'P' => self.write_reg(args),
+            'm' => self.read_mem(args),
+            'M' => self.write_mem(args),
+            'Z' | 'z' => self.breakpoint(cmd == 'Z', args),
+            's' | 'c' => {
+                if let Some(addr) = parse_hex(args) {
+                    *self.cpu.pc_mut() = addr;
+                }
+                Some(self.resume(cmd == 'c', stream))
+            },
+            'H' => Some("OK".to_string()),
+            'q' if args.starts_with("Supported") => Some("PacketSize=4000".to_string()),
+            'q' if args == "Attached" => Some("1".to_string()),
+            // an empty reply tells gdb the packet is not supported
+            _ => Some(String::new()),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    /// Register width in bytes
+    fn reg_bytes(&mut self) -> usize {
+        self.cpu.csr().xlen() as usize / 8
+    }
+
+    fn read_regs(&mut self) -> String {
+        let bytes = self.reg_bytes();
+        let pc = *self.cpu.pc_mut();
+        self.cpu.regfile().iter()
+            .chain(std::iter::once(&pc))
+            .map(|r| reg_hex(*r, bytes))
+            .collect()
+    }
+
+    fn write_regs(&mut self, args: &str) -> Option<String> {
+        let width = self.reg_bytes() * 2;
+        for (i, chunk) in args.as_bytes().chunks(width).enumerate().take(PC_REG as usize + 1) {
+            let value = parse_reg(std::str::from_utf8(chunk).ok()?)?;
+            self.set_reg(i as u64, value)?;
+        }
+        Some("OK".to_string())
+    }
+
+    fn read_reg(&mut self, args: &str) -> Option<String> {
+        let bytes = self.reg_bytes();
+        let value = match parse_hex(args)? {
+            PC_REG => *self.cpu.pc_mut(),
+            reg if reg < PC_REG => self.cpu.regfile()[reg as usize],
+            _ => return None,
+        };
+        Some(reg_hex(value, bytes))
+    }
+
+    fn write_reg(&mut self, args: &str) -> Option<String> {
+        let (reg, value) = args.split_once('=')?;
+        self.set_reg(parse_hex(reg)?, parse_reg(value)?)?;
+        Some("OK".to_string())
+    }
+
+    fn set_reg(&mut self, reg: u64, value: u64) -> Option<()> {
+        match reg {
+            PC_REG => *self.cpu.pc_mut() = value,
+            // x0 is hardwired to zero
+            0 => {},
+            reg if reg < PC_REG => self.cpu.regfile_mut()[reg as usize] = value,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn read_mem(&mut self, args: &str) -> Option<String> {
+        let (addr, len) = args.split_once(',')?;
+        let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+        // stop at the first unmapped byte, gdb accepts a short read
+        let data: String = (0..len)
+            .map_while(|i| self.cpu.bus().peek(addr.wrapping_add(i), B8))
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        if data.is_empty() && len != 0 { None } else { Some(data) }
+    }
+
+    fn write_mem(&mut self, args: &str) -> Option<String> {
+        let (addr, data) = args.split_once(':')?;
+        let addr = parse_hex(addr.split_once(',')?.0)?;
+        for (i, byte) in data.as_bytes().chunks(2).enumerate() {
+            let byte = parse_hex(std::str::from_utf8(byte).ok()?)?;
+            self.cpu.bus_mut().poke(addr.wrapping_add(i as u64), B8, byte).then_some(())?;
+        }
+        Some("OK".to_string())
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        // only software breakpoints, which never patch guest memory here
+        if fields.next()? != "0" {
+            return Some(String::new())
+        }
+        let addr = parse_hex(fields.next()?)?;
+        if insert {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+        Some("OK".to_string())
+    }
+
+    /// Runs one instruction, or until a breakpoint or stop when continuing,
+    /// and returns the stop reply
+    fn resume(&mut self, cont: bool, stream: &TcpStream) -> String {
+        let mut steps = 0;
+        loop {
+            match self.cpu.step() {
+                Ok(_) => {},
+                Err(Exit::Exception(ex)) => return format!("S{:02x}", signal(ex)),
+                Err(Exit::Done) => return "W00".to_string(),
+                Err(Exit::AssertFailed(_)) => return "W01".to_string(),
+                Err(Exit::Status(code)) => return format!("W{:02x}", code as u8),
+                Err(Exit::Timeout | Exit::InsnLimit | Exit::Livelock(_)) => return format!("S{:02x}", SIGTRAP),
+            }
+            if !cont || self.breakpoints.contains(self.cpu.pc_mut()) {
+                return format!("S{:02x}", SIGTRAP)
+            }
+            steps += 1;
+            if steps % INTERRUPT_POLL == 0 && interrupted(stream) {
+                return format!("S{:02x}", SIGINT)
+            }
+        }
+    }
+}
+
+fn signal(ex: Exception) -> u8 {
+    match ex {
+        Exception::IllegalInstruction(_) => SIGILL,
+        Exception::InstructionAddrMisaligned(_)
+        | Exception::InstructionAccessFault(_)
+        | Exception::LoadAccessMisaligned(_)
+        | Exception::LoadAccessFault(_)
+        | Exception::StoreAMOAddrMisaligned(_)
+        | Exception::StoreAMOAccessFault(_)
+        | Exception::InstructionPageFault(_)
+        | Exception::LoadPageFault(_)
+        | Exception::StoreAMOPageFault(_) => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+/// Whether gdb has sent a ^C, without consuming anything else
+fn interrupted(stream: &TcpStream) -> bool {
+    let mut byte = [0];
+    if stream.set_nonblocking(true).is_err() {
+        return false
+    }
+    let hit = matches!(stream.peek(&mut byte), Ok(1) if byte[0] == 0x03);
+    let _ = stream.set_nonblocking(false);
+    hit
+}
+
+/// Reads the next `$data#cs` packet, skipping acks and interrupts
+fn read_packet(reader: &mut impl Read) -> io::Result<Option<String>> {
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None)
+        }
+        if byte[0] == b'$' {
+            break
+        }
+    }
+    let mut data = Vec::new();
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None)
+        }
+        if byte[0] == b'#' {
+            break
+        }
+        data.push(byte[0]);
+    }
+    // the transport is TCP, so the checksum is not verified
+    let mut checksum = [0; 2];
+    reader.read_exact(&mut checksum)?;
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+fn write_packet(writer: &mut impl Write, data: &str) -> io::Result<()> {
+    let checksum = data.bytes().fold(0_u8, |sum, b| sum.wrapping_add(b));
+    write!(writer, "${}#{:02x}", data, checksum)?;
+    writer.flush()
+}
+
+fn parse_hex(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex, 16).ok()
+}
+
+/// Registers are sent as target-endian (little-endian) byte strings
+fn reg_hex(value: u64, bytes: usize) -> String {
+    value.to_le_bytes()[..bytes].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_reg(hex: &str) -> Option<u64> {
+    hex.as_bytes().chunks(2).rev().try_fold(0, |value, byte| {
+        let byte = parse_hex(std::str::from_utf8(byte).ok()?)?;
+        Some(value << 8 | byte)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, thread};
+
+    use crate::{dart::DartSoC, isa::to_bin, soc::SoC};
+    use super::{read_packet, write_packet, serve};
+
+    fn exchange(stream: &mut TcpStream, packet: &str) -> String {
+        write_packet(stream, packet).unwrap();
+        let mut ack = [0];
+        stream.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+        let reply = read_packet(stream).unwrap().unwrap();
+        stream.write_all(b"+").unwrap();
+        reply
+    }
+
+    #[test]
+    fn rsp_session() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x02a00513, // addi a0, zero, 42
+            0x00150593, // addi a1, a0, 1
+            0x0000006f, // jal zero, 0
+        ]));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // the SoC is not Send, so the client gets the thread
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.set_nodelay(true).unwrap();
+            let packets = [
+                "?", "s", "p0a", "p20", "Z0,80000008,4", "c", "p20", "m80000000,4",
+                "M80000100,2:beef", "m80000100,2", "P0b=0100000000000000", "p0b", "D",
+            ];
+            packets.iter().map(|p| exchange(&mut stream, p)).collect::<Vec<_>>()
+        });
+        serve(&listener, &mut cpu).unwrap();
+        let replies = client.join().unwrap();
+        assert_eq!(replies, [
+            "S05", "S05", "2a00000000000000", "0400008000000000", "OK", "S05", "0800008000000000",
+            "1305a002", "OK", "beef", "OK", "0100000000000000", "OK",
+        ]);
+        assert_eq!(cpu.regs[11], 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,255 @@
+use std::io::Write;
+
+use crate::{bus::Bus, stats::{Stats, InsMix, Profile}, isa::Extension, rvc::ins_len, exception::Exception, soc::{SoC, Config, ExecObserver, Hart, Datapath, fetch}, csr::Csr};
+
+/*
+An in-order, dual-issue single-stage processor
+
+Two instructions are fetched each cycle and both issue unless the second
+reads the register the first writes, or both need the single load/store
+port or the single branch unit, or the first is a taken branch or jump
+so the second was fetched from its target. Otherwise only the first
+issues and the second is paired with the instruction after it.
+*/
+
+struct HistItem {
+    src_regs: Vec<u64>,
+    dst_reg: Option<u64>,
+    is_mem: bool,
+    is_ctrl: bool,
+    /// The next instruction did not follow this one in memory
+    taken: bool
+}
+
+pub struct GeminiSoC {
+    pub regs: [u64; 32],
+    /// Float registers, holding NaN-boxed singles
+    pub fregs: [u64; 32],
+    pub pc: u64,
+    pub bus: Bus,
+    pub csr: Csr,
+    pub stats: Stats,
+    /// Executed instructions by mnemonic
+    pub mix: InsMix,
+    /// Executions of each pc
+    pub profile: Profile,
+    pub config: Config,
+    pub trace_out: Box<dyn Write>,
+    pub observer: Option<Box<dyn ExecObserver>>,
+    hist: Vec<HistItem>
+}
+
+type Result = std::result::Result<(), Exception>;
+
+impl GeminiSoC {
+    fn hart(&mut self) -> Hart<'_> {
+        Hart {
+            regs: &mut self.regs, fregs: &mut self.fregs, pc: &mut self.pc, bus: &mut self.bus, csr: &mut self.csr,
+            stats: &mut self.stats, mix: &mut self.mix, profile: &mut self.profile, config: &self.config,
+            trace_out: &mut self.trace_out, observer: &mut self.observer
+        }
+    }
+
+    /// Whether `second` can issue in the same cycle as `first`
+    fn can_pair(first: &HistItem, second: &HistItem) -> bool {
+        let raw = first.dst_reg
+            .is_some_and(|dst| dst != 0 && second.src_regs.contains(&dst));
+        let port = first.is_mem && second.is_mem;
+        let branch = first.is_ctrl && second.is_ctrl;
+        !raw && !port && !branch && !first.taken
+    }
+}
+
+impl Datapath for GeminiSoC {
+    fn datapath<O: Extension>(&mut self, word: u32, i: O) -> Result {
+        let pc = self.pc;
+        let mut record = HistItem { 
+            src_regs: i.src_regs(), 
+            dst_reg: i.dst_reg(), 
+            is_mem: i.is_ld() || i.is_st(),
+            is_ctrl: i.is_br() || i.is_jmp(),
+            taken: false
+        };
+        self.hart().commit(word, i)?;
+        record.taken = self.pc != pc.wrapping_add(ins_len(word));
+        self.hist.push(record);
+        Ok(())
+    }
+}
+
+impl SoC for GeminiSoC {
+    fn with_entry(bin: Vec<u8>, entry: u64) -> Self {
+        let bus = Bus::new(bin);
+        let mut regs = [0_u64; 32];
+        regs[2] = bus.base + bus.size - 1;
+        let pc = entry;
+        let csr = Csr::new();
+        let stats = Stats::new();
+        let mix = InsMix::new();
+        let profile = Profile::new();
+        let hist = Vec::new();
+        Self { regs, fregs: [0; 32], pc, bus, csr, stats, mix, profile, config: Config { entry, ..Config::default() }, trace_out: Box::new(std::io::stdout()), observer: None, hist }
+    }
+
+    fn pipeline(&mut self) -> Result {
+        let (word, ins) = fetch(&mut self.bus, &self.csr, self.pc, self.config.enabled_exts, None)?;
+        self.dispatch(word, ins)
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn pc_mut(&mut self) -> &mut u64 {
+        &mut self.pc
+    }
+
+    fn regfile(&self) -> &[u64; 32] {
+        &self.regs
+    }
+
+    fn regfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.regs
+    }
+
+    fn fregfile(&self) -> &[u64; 32] {
+        &self.fregs
+    }
+
+    fn fregfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.fregs
+    }
+
+    fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    fn csr(&mut self) -> &mut Csr {
+        &mut self.csr
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    fn mix(&self) -> &InsMix {
+        &self.mix
+    }
+
+    fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    fn reset(&mut self, bin: Vec<u8>) {
+        self.hart().reset(&bin);
+        self.hist.clear();
+    }
+
+    fn calc_stats(&mut self) {
+        // only instructions that completed are recorded in the history
+        self.stats.retired = self.hist.len();
+        let (mut dual, mut single) = (0, 0);
+        let mut i = 0;
+        while i < self.hist.len() {
+            if i + 1 < self.hist.len() && Self::can_pair(&self.hist[i], &self.hist[i + 1]) {
+                dual += 1;
+                i += 2;
+            } else {
+                single += 1;
+                i += 1;
+            }
+        }
+        self.stats.dual_issue_cycles = dual;
+        self.stats.single_issue_cycles = single;
+        self.stats.cycles = dual + single + self.stats.page_walk_cycles;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dart::DartSoC, isa::to_bin, soc::SoC};
+    use super::GeminiSoC;
+
+    #[test]
+    fn dual_issue_against_dart() {
+        let bin = to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00200593, // addi a1, zero, 2
+            0x00b50633, // add a2, a0, a1
+            0x00c606b3, // add a3, a2, a2
+            0x00002703, // lw a4, 0(zero)
+        ]);
+        let mut gemini = GeminiSoC::new(bin.clone());
+        gemini.execute();
+        // the adds are dependent, so only the addis pair up
+        assert_eq!(gemini.stats.retired, 4);
+        assert_eq!(gemini.stats.dual_issue_cycles, 1);
+        assert_eq!(gemini.stats.single_issue_cycles, 2);
+        assert_eq!(gemini.stats.cycles, 3);
+
+        let mut dart = DartSoC::new(bin);
+        dart.execute();
+        assert_eq!(gemini.regs, dart.regs);
+        assert!(gemini.stats.cycles < dart.stats.cycles);
+    }
+
+    #[test]
+    fn single_load_port() {
+        let mut cpu = GeminiSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x0002a503, // lw a0, 0(t0)
+            0x0042a583, // lw a1, 4(t0)
+            0x00000613, // addi a2, zero, 0
+        ]));
+        cpu.execute();
+        // auipc/lw pair is a RAW hazard, the loads share a port
+        assert_eq!(cpu.stats.dual_issue_cycles, 1);
+        assert_eq!(cpu.stats.single_issue_cycles, 2);
+    }
+
+    #[test]
+    fn float_raw_hazard() {
+        let mut cpu = GeminiSoC::new(to_bin(&[
+            0x00107153, // fadd.s ft2, ft0, ft1
+            0x102171d3, // fmul.s ft3, ft2, ft2
+        ]));
+        cpu.execute();
+        assert_eq!(cpu.stats.retired, 2);
+        assert_eq!(cpu.stats.dual_issue_cycles, 0);
+
+        let mut cpu = GeminiSoC::new(to_bin(&[
+            0x00107153, // fadd.s ft2, ft0, ft1
+            0x101071d3, // fmul.s ft3, ft0, ft1
+        ]));
+        cpu.execute();
+        assert_eq!(cpu.stats.dual_issue_cycles, 1);
+    }
+
+    #[test]
+    fn no_pairing_across_taken_branch() {
+        let mut cpu = GeminiSoC::new(to_bin(&[
+            0x00300593, // addi a1, zero, 3
+            0xfff58593, // addi a1, a1, -1
+            0x00160613, // addi a2, a2, 1
+            0xfe059ce3, // bne a1, zero, -8
+        ]));
+        cpu.execute();
+        // each trip pairs its two addis, the bne issues alone
+        assert_eq!(cpu.stats.retired, 10);
+        assert_eq!(cpu.stats.dual_issue_cycles, 3);
+        assert_eq!(cpu.stats.single_issue_cycles, 4);
+        assert_eq!(cpu.stats.cycles, 7);
+    }
+}
\ No newline at end of file
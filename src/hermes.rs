@@ -0,0 +1,278 @@
+use std::io::Write;
+
+use crate::{bus::Bus, stats::{Stats, InsMix, Profile}, isa::{Extension, DEP_REG_COUNT}, exception::Exception, soc::{SoC, Config, ExecObserver, Hart, Datapath, fetch}, csr::Csr};
+
+/*
+An out-of-order processor with register renaming and a reorder buffer.
+
+Each cycle, up to `width` instructions commit in order from the head of the
+ROB, any ROB entry whose operands are ready issues, and up to `width`
+instructions are renamed into the ROB. Renaming stops when the ROB is full
+or an instruction needs a destination and no physical register is free; a
+physical register is freed when the next writer of its architectural
+register commits. Memory operations issue in program order amongst
+themselves and branches are predicted perfectly.
+*/
+
+struct HistItem {
+    src_regs: Vec<u64>,
+    dst_reg: Option<u64>,
+    is_mem: bool,
+    latency: usize
+}
+
+pub struct HermesSoC {
+    pub regs: [u64; 32],
+    /// Float registers, holding NaN-boxed singles
+    pub fregs: [u64; 32],
+    pub pc: u64,
+    pub bus: Bus,
+    pub csr: Csr,
+    pub stats: Stats,
+    /// Executed instructions by mnemonic
+    pub mix: InsMix,
+    /// Executions of each pc
+    pub profile: Profile,
+    pub config: Config,
+    pub trace_out: Box<dyn Write>,
+    pub observer: Option<Box<dyn ExecObserver>>,
+    /// Reorder buffer entries
+    pub rob_size: usize,
+    /// Physical registers, including the 32 holding the committed state
+    pub phys_regs: usize,
+    /// Instructions renamed and committed per cycle
+    pub width: usize,
+    hist: Vec<HistItem>
+}
+
+type Result = std::result::Result<(), Exception>;
+
+impl HermesSoC {
+    fn hart(&mut self) -> Hart<'_> {
+        Hart {
+            regs: &mut self.regs, fregs: &mut self.fregs, pc: &mut self.pc, bus: &mut self.bus, csr: &mut self.csr,
+            stats: &mut self.stats, mix: &mut self.mix, profile: &mut self.profile, config: &self.config,
+            trace_out: &mut self.trace_out, observer: &mut self.observer
+        }
+    }
+}
+
+impl Datapath for HermesSoC {
+    fn datapath<O: Extension>(&mut self, word: u32, i: O) -> Result {
+        let record = HistItem { 
+            src_regs: i.src_regs(), 
+            dst_reg: i.dst_reg(), 
+            is_mem: i.is_ld() || i.is_st(),
+            latency: i.latency()
+        };
+        self.hart().commit(word, i)?;
+        self.hist.push(record);
+        Ok(())
+    }
+}
+
+impl SoC for HermesSoC {
+    fn with_entry(bin: Vec<u8>, entry: u64) -> Self {
+        let bus = Bus::new(bin);
+        let mut regs = [0_u64; 32];
+        regs[2] = bus.base + bus.size - 1;
+        let pc = entry;
+        let csr = Csr::new();
+        let stats = Stats::new();
+        let mix = InsMix::new();
+        let profile = Profile::new();
+        let hist = Vec::new();
+        Self {
+            regs, fregs: [0; 32], pc, bus, csr, stats, mix, profile,
+            config: Config { entry, ..Config::default() },
+            trace_out: Box::new(std::io::stdout()),
+            observer: None,
+            rob_size: 32,
+            phys_regs: 64,
+            width: 4,
+            hist
+        }
+    }
+
+    fn pipeline(&mut self) -> Result {
+        let (word, ins) = fetch(&mut self.bus, &self.csr, self.pc, self.config.enabled_exts, None)?;
+        self.dispatch(word, ins)
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn pc_mut(&mut self) -> &mut u64 {
+        &mut self.pc
+    }
+
+    fn regfile(&self) -> &[u64; 32] {
+        &self.regs
+    }
+
+    fn regfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.regs
+    }
+
+    fn fregfile(&self) -> &[u64; 32] {
+        &self.fregs
+    }
+
+    fn fregfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.fregs
+    }
+
+    fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    fn csr(&mut self) -> &mut Csr {
+        &mut self.csr
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    fn mix(&self) -> &InsMix {
+        &self.mix
+    }
+
+    fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    fn reset(&mut self, bin: Vec<u8>) {
+        self.hart().reset(&bin);
+        self.hist.clear();
+    }
+
+    fn calc_stats(&mut self) {
+        // only instructions that completed are recorded in the history
+        self.stats.retired = self.hist.len();
+        let width = self.width.max(1);
+        let rob_size = self.rob_size.max(1);
+        let mut free_regs = self.phys_regs.saturating_sub(32).max(1);
+        // the rob entry (history index) that will produce each register
+        let mut rename_map: [Option<usize>; DEP_REG_COUNT] = [None; DEP_REG_COUNT];
+        // per history index: the producers of its operands, and the cycle
+        // its result is available once it has issued
+        let mut srcs: Vec<Vec<usize>> = vec![Vec::new(); self.hist.len()];
+        let mut done_at: Vec<Option<usize>> = vec![None; self.hist.len()];
+        // [head, tail) of the history is in the rob
+        let (mut head, mut tail) = (0, 0);
+        let mut cycles = 0;
+        let (mut rob_full_stalls, mut rename_stalls) = (0, 0);
+        while head < self.hist.len() {
+            cycles += 1;
+
+            // commit in order
+            let mut committed = 0;
+            while head < tail && committed < width && done_at[head].is_some_and(|c| c <= cycles) {
+                if self.hist[head].dst_reg.is_some_and(|r| r != 0) {
+                    free_regs += 1;
+                }
+                head += 1;
+                committed += 1;
+            }
+
+            // issue every ready entry, keeping memory ops in order
+            let mut mem_blocked = false;
+            for i in head..tail {
+                if done_at[i].is_some() {
+                    continue
+                }
+                let item = &self.hist[i];
+                let ready = srcs[i].iter().all(|p| done_at[*p].is_some_and(|c| c <= cycles));
+                if ready && !(item.is_mem && mem_blocked) {
+                    done_at[i] = Some(cycles + item.latency);
+                } else if item.is_mem {
+                    mem_blocked = true;
+                }
+            }
+
+            // rename into the rob
+            for _ in 0..width {
+                if tail == self.hist.len() {
+                    break
+                }
+                let item = &self.hist[tail];
+                let dst = item.dst_reg.filter(|r| *r != 0);
+                if tail - head == rob_size {
+                    rob_full_stalls += 1;
+                    break
+                }
+                if dst.is_some() && free_regs == 0 {
+                    rename_stalls += 1;
+                    break
+                }
+                srcs[tail] = item.src_regs.iter()
+                    .filter_map(|r| rename_map[*r as usize])
+                    .collect();
+                if let Some(dst) = dst {
+                    free_regs -= 1;
+                    rename_map[dst as usize] = Some(tail);
+                }
+                tail += 1;
+            }
+        }
+        self.stats.cycles = cycles + self.stats.page_walk_cycles;
+        self.stats.rob_full_stalls = rob_full_stalls;
+        self.stats.rename_stalls = rename_stalls;
+        self.stats.stalls = rob_full_stalls + rename_stalls;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::to_bin, soc::SoC};
+    use super::HermesSoC;
+
+    // a chain of dependent divides followed by independent adds
+    const DIV_CHAIN: [u32; 8] = [
+        0x02b54533, // div a0, a0, a1
+        0x02b54533, // div a0, a0, a1
+        0x02b54533, // div a0, a0, a1
+        0x02b54533, // div a0, a0, a1
+        0x00c60633, // add a2, a2, a2
+        0x00d686b3, // add a3, a3, a3
+        0x00e70733, // add a4, a4, a4
+        0x00f787b3, // add a5, a5, a5
+    ];
+
+    #[test]
+    fn rob_fills_behind_chain() {
+        let mut small = HermesSoC::new(to_bin(&DIV_CHAIN));
+        small.rob_size = 4;
+        small.execute();
+        assert!(small.stats.rob_full_stalls > 0);
+
+        let mut large = HermesSoC::new(to_bin(&DIV_CHAIN));
+        large.execute();
+        assert_eq!(large.stats.rob_full_stalls, 0);
+        assert_eq!(large.stats.retired, 8);
+        assert!(large.stats.cycles < small.stats.cycles);
+    }
+
+    #[test]
+    fn rename_stalls_without_free_registers() {
+        let mut cpu = HermesSoC::new(to_bin(&DIV_CHAIN));
+        cpu.phys_regs = 33;
+        cpu.execute();
+        assert!(cpu.stats.rename_stalls > 0);
+        assert_eq!(cpu.stats.rob_full_stalls, 0);
+    }
+}
\ No newline at end of file
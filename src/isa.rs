@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use tabled::{builder::Builder, settings::Style};
 
-use crate::{exception::Exception, bus::Bus, mem::{B8, B16, B32, B64}};
+use crate::{exception::Exception, bus::Bus, csr::{Csr, Privilege, csr_name, read_only, parse_u64, MEPC, MISA, MISA_A, MISA_C, MISA_F, MISA_M, MSTATUS, MSTATUS_MPP, MSTATUS_MIE, MSTATUS_MPIE, SEPC, SSTATUS, SSTATUS_SIE, SSTATUS_SPIE, SSTATUS_SPP}, mem::{B8, B16, B32, B64}, rvf::freg_name, rvc::{ins_len, is_compressed, expand}, decode::{decode, Decoded}};
 
 const RVABI: [&str; 32] = [
     "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", 
@@ -11,18 +11,147 @@ const RVABI: [&str; 32] = [
     "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
 ];
 
+/// Optional extensions the decoder accepts, all of them by default. The
+/// base ISA, Zicsr and Zifencei are always decoded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Exts(u32);
+
+impl Exts {
+    pub const M: Exts = Exts(1 << 0);
+    pub const A: Exts = Exts(1 << 1);
+    pub const C: Exts = Exts(1 << 2);
+    pub const ZBB: Exts = Exts(1 << 3);
+    pub const F: Exts = Exts(1 << 4);
+    pub const NONE: Exts = Exts(0);
+    pub const ALL: Exts = Exts(0b11111);
+
+    pub fn has(self, ext: Exts) -> bool {
+        self.0 & ext.0 == ext.0
+    }
+
+    pub fn with(self, ext: Exts) -> Exts {
+        Exts(self.0 | ext.0)
+    }
+
+    /// The misa bits of the single-letter extensions in the set
+    pub fn misa(self) -> u64 {
+        [(Exts::M, MISA_M), (Exts::A, MISA_A), (Exts::F, MISA_F), (Exts::C, MISA_C)].iter()
+            .filter(|(ext, _)| self.has(*ext))
+            .fold(0, |misa, (_, bit)| misa | bit)
+    }
+}
+
+/// Every extension but C, for words that are already 32 bits wide
+const EXTS_32: Exts = Exts(Exts::ALL.0 & !Exts::C.0);
+
+impl Default for Exts {
+    fn default() -> Self {
+        Exts::ALL
+    }
+}
+
+/// Parses an ISA string such as rv64imac_zbb into its XLEN and the
+/// extensions it enables. g stands for imaf here, D is not modelled.
+pub fn parse_march_arg(arg: &str) -> Result<(u32, Exts), String> {
+    let arg = arg.to_ascii_lowercase();
+    let (xlen, rest) = if let Some(rest) = arg.strip_prefix("rv64") {
+        (64, rest)
+    } else if let Some(rest) = arg.strip_prefix("rv32") {
+        (32, rest)
+    } else {
+        return Err(format!("expected rv32 or rv64, got {}", arg))
+    };
+    let mut parts = rest.split('_');
+    let mut letters = parts.next().unwrap_or_default().chars();
+    let mut exts = match letters.next() {
+        Some('i') => Exts::NONE,
+        Some('g') => Exts::M.with(Exts::A).with(Exts::F),
+        _ => return Err(format!("expected i or g after rv{}, got {}", xlen, arg))
+    };
+    for letter in letters {
+        exts = exts.with(match letter {
+            'm' => Exts::M,
+            'a' => Exts::A,
+            'f' => Exts::F,
+            'c' => Exts::C,
+            _ => return Err(format!("unsupported extension {} in {}", letter, arg))
+        });
+    }
+    for name in parts {
+        exts = match name {
+            "zbb" => exts.with(Exts::ZBB),
+            "zicsr" | "zifencei" => exts,
+            _ => return Err(format!("unsupported extension {} in {}", name, arg))
+        };
+    }
+    Ok((xlen, exts))
+}
+
 pub trait Extension {
     fn id(ins: u32) -> Result<Self, Exception> where Self: Sized;
-    fn ex(self, regs: &[u64; 32]) -> Self;
-    fn wr(self, pc: u64, regs: &mut [u64; 32], bus: &mut Bus) -> Result<u64, Exception>;
+    fn ex(self, regs: &[u64; 32], fregs: &[u64; 32]) -> Self;
+    /// Writes back the instruction and returns the next pc. PC arithmetic
+    /// wraps around the address space; fetching from an address that is not
+    /// backed by the bus then raises an access fault. len is the encoded
+    /// size in bytes (2 for compressed instructions), used for the
+    /// fall-through pc and link addresses. fregs is the float register
+    /// file, which only the F extension uses.
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], fregs: &mut [u64; 32], csr: &mut Csr, bus: &mut Bus) -> Result<u64, Exception>;
+    /// Registers the instruction reads. Float registers are numbered from
+    /// FREG_BASE, after the integer ones.
     fn src_regs(&self) -> Vec<u64>;
+    /// Register the instruction writes, numbered as in src_regs
     fn dst_reg(&self) -> Option<u64>;
+    /// The sign-extended immediate, for instructions that encode one. Shift
+    /// amounts count as immediates; U-type immediates are already shifted.
+    fn imm(&self) -> Option<i64> {
+        None
+    }
+    /// Whether the executed instruction is an add, sub or addi whose result
+    /// leaves the range of a signed XLEN value. RISC-V has no flags, the
+    /// SoCs log these under Config::record_overflow for teaching.
+    fn overflows(&self, _csr: &Csr) -> bool {
+        false
+    }
     fn src_mem_addr(&self) -> Option<u64>;
     fn dst_mem_addr(&self) -> Option<u64>;
     fn is_ld(&self) -> bool;
     fn is_st(&self) -> bool;
     fn is_br(&self) -> bool;
     fn is_jmp(&self) -> bool;
+    /// Assembler syntax for this decoded instruction located at pc
+    fn asm(&self, pc: u64) -> String;
+    /// Assembler mnemonic, used to key the instruction mix
+    fn mnemonic(&self) -> &'static str;
+    /// Number of cycles before the result of this instruction can be
+    /// consumed by a dependent instruction.
+    fn latency(&self) -> usize {
+        if self.is_ld() { 2 } else { 1 }
+    }
+    /// Functional unit the instruction issues to
+    fn func_unit(&self) -> FuncUnit {
+        if self.is_ld() || self.is_st() {
+            FuncUnit::Mem
+        } else if self.is_br() || self.is_jmp() {
+            FuncUnit::Branch
+        } else {
+            FuncUnit::Alu
+        }
+    }
+}
+
+/// Functional units, for modelling issue port contention
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FuncUnit {
+    Alu,
+    Mul,
+    Div,
+    Mem,
+    Branch,
+}
+
+impl FuncUnit {
+    pub const COUNT: usize = 5;
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -63,10 +192,11 @@ pub enum Rv32i {
     Srl { rd: u64, rs1: u64, rs2: u64 },
     Sra { rd: u64, rs1: u64, rs2: u64 },
     Or { rd: u64, rs1: u64, rs2: u64 },
-    And { rd: u64, rs1: u64, rs2: u64 }
+    And { rd: u64, rs1: u64, rs2: u64 },
+    Fence { pred: u32, succ: u32 }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Rv64i {
     Lwu { rd: u64, rs1: u64, imm: u64 },
     Ld { rd: u64, rs1: u64, imm: u64 },
@@ -82,6 +212,33 @@ pub enum Rv64i {
     Sraw { rd: u64, rs1: u64, rs2: u64 },
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Zicsr {
+    Csrrw { rd: u64, rs1: u64, csr: u64 },
+    Csrrs { rd: u64, rs1: u64, csr: u64 },
+    Csrrc { rd: u64, rs1: u64, csr: u64 },
+    Csrrwi { rd: u64, uimm: u64, csr: u64 },
+    Csrrsi { rd: u64, uimm: u64, csr: u64 },
+    Csrrci { rd: u64, uimm: u64, csr: u64 },
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Zifencei {
+    FenceI,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum System {
+    Ecall,
+    Ebreak,
+    Mret,
+    Sret,
+    Wfi,
+    /// Orders page table updates before later translations. The registers
+    /// select an address and ASID to flush, which is all of them here.
+    SfenceVma { rs1: u64, rs2: u64 },
+}
+
 impl Extension for Rv32i {
     fn id(ins: u32) -> Result<Self, Exception> {
         let opcode = opcode(ins);
@@ -123,9 +280,10 @@ impl Extension for Rv32i {
             (_, 0b100, 0b0010011) => Ok(Self::Xori { rd, rs1, imm: i_imm }),
             (_, 0b110, 0b0010011) => Ok(Self::Ori { rd, rs1, imm: i_imm }),
             (_, 0b111, 0b0010011) => Ok(Self::Andi { rd, rs1, imm: i_imm }),
-            (0b0000000, 0b001, 0b0010011) => Ok(Self::Slli { rd, rs1, shamt: (i_imm as u32) & 0xf }),
-            (0b0000000, 0b101, 0b0010011) => Ok(Self::Srli { rd, rs1, shamt: (i_imm as u32) & 0xf }),
-            (0b0100000, 0b101, 0b0010011) => Ok(Self::Srai { rd, rs1, shamt: (i_imm as u32) & 0xf }),
+            // RV64 widens shamt to 6 bits, taking the low bit of funct7
+            (f, 0b001, 0b0010011) if f >> 1 == 0 => Ok(Self::Slli { rd, rs1, shamt: (i_imm as u32) & 0x3f }),
+            (f, 0b101, 0b0010011) if f >> 1 == 0 => Ok(Self::Srli { rd, rs1, shamt: (i_imm as u32) & 0x3f }),
+            (f, 0b101, 0b0010011) if f >> 1 == 0b010000 => Ok(Self::Srai { rd, rs1, shamt: (i_imm as u32) & 0x3f }),
             (0b0000000, 0b000, 0b0110011) => Ok(Self::Add { rd, rs1, rs2 }),
             (0b0100000, 0b000, 0b0110011) => Ok(Self::Sub { rd, rs1, rs2 }),
             (0b0000000, 0b001, 0b0110011) => Ok(Self::Sll { rd, rs1, rs2 }),
@@ -136,11 +294,13 @@ impl Extension for Rv32i {
             (0b0100000, 0b101, 0b0110011) => Ok(Self::Sra { rd, rs1, rs2 }),
             (0b0000000, 0b110, 0b0110011) => Ok(Self::Or { rd, rs1, rs2 }),
             (0b0000000, 0b111, 0b0110011) => Ok(Self::And { rd, rs1, rs2 }),
+            // memory is always coherent in these models, so fm is ignored
+            (_, 0b000, 0b0001111) => Ok(Self::Fence { pred: (ins >> 24) & 0xf, succ: (ins >> 20) & 0xf }),
             _ => Err(Exception::IllegalInstruction(ins as u64))
         }
     }
 
-    fn ex(self, regs: &[u64; 32]) -> Self {
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
         match self {
             Rv32i::Lui { rd, imm } => Self::Lui { rd, imm },
             Rv32i::Auipc { rd, imm } => Self::Auipc { rd, imm },
@@ -166,9 +326,9 @@ impl Extension for Rv32i {
             Rv32i::Xori { rd, rs1, imm } => Self::Xori { rd, rs1: regs[rs1 as usize], imm },
             Rv32i::Ori { rd, rs1, imm } => Self::Ori { rd, rs1: regs[rs1 as usize], imm },
             Rv32i::Andi { rd, rs1, imm } => Self::Andi { rd, rs1: regs[rs1 as usize], imm },
-            Rv32i::Slli { rd, rs1, shamt } => Self::Slli { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x1f },
-            Rv32i::Srli { rd, rs1, shamt } => Self::Srli { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x1f },
-            Rv32i::Srai { rd, rs1, shamt } => Self::Srai { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x1f },
+            Rv32i::Slli { rd, rs1, shamt } => Self::Slli { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x3f },
+            Rv32i::Srli { rd, rs1, shamt } => Self::Srli { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x3f },
+            Rv32i::Srai { rd, rs1, shamt } => Self::Srai { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x3f },
             Rv32i::Add { rd, rs1, rs2 } => Self::Add { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
             Rv32i::Sub { rd, rs1, rs2 } => Self::Sub { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
             Rv32i::Sll { rd, rs1, rs2 } => Self::Sll { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
@@ -179,161 +339,165 @@ impl Extension for Rv32i {
             Rv32i::Sra { rd, rs1, rs2 } => Self::Sra { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
             Rv32i::Or { rd, rs1, rs2 } => Self::Or { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
             Rv32i::And { rd, rs1, rs2 } => Self::And { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32i::Fence { pred, succ } => Self::Fence { pred, succ },
         }
     }
 
-    fn wr(self, pc: u64, regs: &mut [u64; 32], bus: &mut Bus) -> Result<u64, Exception> {
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], csr: &mut Csr, bus: &mut Bus) -> Result<u64, Exception> {
         match self {
             Rv32i::Lui { rd, imm } => {
-                regs[rd as usize] = imm;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, imm);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Auipc { rd, imm } => {
-                regs[rd as usize] = pc.wrapping_add(imm);
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, pc.wrapping_add(imm));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Jal { rd, imm } => {
-                regs[rd as usize] = pc.wrapping_add(4);
-                Ok(pc.wrapping_add(imm) as u64)
+                let target = jump_target(pc.wrapping_add(imm), csr)?;
+                write_reg(regs, rd, pc.wrapping_add(len));
+                Ok(target)
             },
             Rv32i::Jalr { rd, rs1, imm } => {
-                regs[rd as usize] = pc.wrapping_add(4);
-                Ok((rs1.wrapping_add(imm) as u64) & !1)
+                let target = jump_target(rs1.wrapping_add(imm) & !1, csr)?;
+                write_reg(regs, rd, pc.wrapping_add(len));
+                Ok(target)
             },
             Rv32i::Beq { rs1, rs2, imm } => {
-                Ok(if rs1 == rs2 { pc.wrapping_add(imm) as u64 } else { pc.wrapping_add(4) })
+                if rs1 == rs2 { jump_target(pc.wrapping_add(imm), csr) } else { Ok(pc.wrapping_add(len)) }
             },
             Rv32i::Bne { rs1, rs2, imm } => {
-                Ok(if rs1 != rs2 { pc.wrapping_add(imm ) as u64 } else { pc.wrapping_add(4) })
+                if rs1 != rs2 { jump_target(pc.wrapping_add(imm), csr) } else { Ok(pc.wrapping_add(len)) }
             },
             Rv32i::Blt { rs1, rs2, imm } => {
-                Ok(if (rs1 as i64) < (rs2 as i64) { (pc as i64).wrapping_add(imm as i64) as u64 } else { pc.wrapping_add(4) })
+                if signed(rs1, csr) < signed(rs2, csr) { jump_target(pc.wrapping_add(imm), csr) } else { Ok(pc.wrapping_add(len)) }
             },
             Rv32i::Bge { rs1, rs2, imm } => {
-                Ok(if (rs1 as i64) >= (rs2 as i64) { (pc as i64).wrapping_add(imm as i64) as u64 } else { pc.wrapping_add(4) })
+                if signed(rs1, csr) >= signed(rs2, csr) { jump_target(pc.wrapping_add(imm), csr) } else { Ok(pc.wrapping_add(len)) }
             },
             Rv32i::Bltu { rs1, rs2, imm } => {
-                Ok(if rs1 < rs2 { pc.wrapping_add(imm) as u64 } else { pc.wrapping_add(4) })
+                if rs1 < rs2 { jump_target(pc.wrapping_add(imm), csr) } else { Ok(pc.wrapping_add(len)) }
             },
             Rv32i::Bgeu { rs1, rs2, imm } => {
-                Ok(if rs1 >= rs2 { pc.wrapping_add(imm) as u64 } else { pc.wrapping_add(4)})
+                if rs1 >= rs2 { jump_target(pc.wrapping_add(imm), csr) } else { Ok(pc.wrapping_add(len)) }
             },
             Rv32i::Lb { rd, rs1, imm } => {
                 let addr = rs1.wrapping_add(imm);
-                regs[rd as usize] = bus.load(addr as u64, B8)? as i8 as i64 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, bus.load(addr, B8)? as i8 as i64 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Lh { rd, rs1, imm } => {
                 let addr = rs1.wrapping_add(imm);
-                regs[rd as usize] = bus.load(addr as u64, B16)? as i16 as i64 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, bus.load(addr, B16)? as i16 as i64 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Lw { rd, rs1, imm } => {
                 let addr = rs1.wrapping_add(imm);
-                regs[rd as usize] = bus.load(addr as u64, B32)? as i32 as i64 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, bus.load(addr, B32)? as i32 as i64 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Lbu { rd, rs1, imm } => {
                 let addr = rs1.wrapping_add(imm);
-                regs[rd as usize] = bus.load(addr as u64, B8)?;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, bus.load(addr, B8)?);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Lhu { rd, rs1, imm } => {
                 let addr = rs1.wrapping_add(imm);
-                regs[rd as usize] = bus.load(addr as u64, B16)?;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, bus.load(addr, B16)?);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Sb { rs1, rs2, imm } => {
                 let addr = rs1.wrapping_add(imm);
                 bus.store(addr as u64, B8, rs2 & 0xff)?;
-                Ok(pc.wrapping_add(4))
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Sh { rs1, rs2, imm } => {
                 let addr = rs1.wrapping_add(imm);
                 bus.store(addr as u64, B16, rs2 & 0xffff)?;
-                Ok(pc.wrapping_add(4))
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Sw { rs1, rs2, imm } => {
                 let addr = rs1.wrapping_add(imm);
                 bus.store(addr as u64, B32, rs2 & 0xffffffff)?;
-                Ok(pc.wrapping_add(4))
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Addi { rd, rs1, imm } => {
-                regs[rd as usize] = rs1.wrapping_add(imm);
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_add(imm));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Slti { rd, rs1, imm } => {
-                regs[rd as usize] = if (rs1 as i64) < (imm as i64) { 1 } else { 0 };
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, set_less_than(rs1, imm, false, csr));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Sltiu { rd, rs1, imm } => {
-                regs[rd as usize] = if rs1 < imm { 1 } else { 0 };
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, set_less_than(rs1, imm, true, csr));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Xori { rd, rs1, imm } => {
-                regs[rd as usize] = rs1 ^ imm;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1 ^ imm);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Ori { rd, rs1, imm } => {
-                regs[rd as usize] = rs1 | imm;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1 | imm);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Andi { rd, rs1, imm } => {
-                regs[rd as usize] = rs1 & imm;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1 & imm);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Slli { rd, rs1, shamt } => {
-                regs[rd as usize] = rs1.wrapping_shl(shamt);
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_shl(shamt));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Srli { rd, rs1, shamt } => {
-                regs[rd as usize] = rs1.wrapping_shr(shamt);
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_shr(shamt));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Srai { rd, rs1, shamt } => {
-                regs[rd as usize] = ((rs1 as i64).wrapping_shr(shamt)) as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, (signed(rs1, csr).wrapping_shr(shamt)) as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Add { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1.wrapping_add(rs2);
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_add(rs2));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Sub { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1.wrapping_sub(rs2);
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_sub(rs2));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Sll { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1.wrapping_shl(rs2 as u32);
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_shl(shamt(rs2, csr)));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Slt { rd, rs1, rs2 } => {
-                regs[rd as usize] = if (rs1 as i64) < (rs2 as i64) { 1 } else { 0 };
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, set_less_than(rs1, rs2, false, csr));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Sltu { rd, rs1, rs2 } => {
-                regs[rd as usize] = if rs1 < rs2 { 1 } else { 0 };
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, set_less_than(rs1, rs2, true, csr));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Xor { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1 ^ rs2;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1 ^ rs2);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Srl { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1.wrapping_shr(rs2 as u32);
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_shr(shamt(rs2, csr)));
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Sra { rd, rs1, rs2 } => {
-                regs[rd as usize] = ((rs1 as i64).wrapping_shr(rs2 as u32)) as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, (signed(rs1, csr).wrapping_shr(shamt(rs2, csr))) as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::Or { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1 | rs2;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1 | rs2);
+                Ok(pc.wrapping_add(len))
             },
             Rv32i::And { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1 & rs2;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1 & rs2);
+                Ok(pc.wrapping_add(len))
             },
+            Rv32i::Fence { .. } => Ok(pc.wrapping_add(len)),
         }
     }
 
@@ -376,6 +540,7 @@ impl Extension for Rv32i {
             Rv32i::Sra { rs1, rs2, .. } => vec![*rs1, *rs2],
             Rv32i::Or { rs1, rs2, .. } => vec![*rs1, *rs2],
             Rv32i::And { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32i::Fence { .. } => vec![],
         }
     }
 
@@ -418,6 +583,49 @@ impl Extension for Rv32i {
             Rv32i::Sra { rd, .. } => Some(*rd),
             Rv32i::Or { rd, .. } => Some(*rd),
             Rv32i::And { rd, .. } => Some(*rd),
+            Rv32i::Fence { .. } => None,
+        }
+    }
+
+    fn overflows(&self, csr: &Csr) -> bool {
+        match *self {
+            Rv32i::Addi { rs1, imm, .. } => signed_overflow(rs1, imm, false, csr),
+            Rv32i::Add { rs1, rs2, .. } => signed_overflow(rs1, rs2, false, csr),
+            Rv32i::Sub { rs1, rs2, .. } => signed_overflow(rs1, rs2, true, csr),
+            _ => false,
+        }
+    }
+
+    fn imm(&self) -> Option<i64> {
+        match self {
+            Rv32i::Lui { imm, .. } |
+            Rv32i::Auipc { imm, .. } |
+            Rv32i::Jal { imm, .. } |
+            Rv32i::Jalr { imm, .. } |
+            Rv32i::Beq { imm, .. } |
+            Rv32i::Bne { imm, .. } |
+            Rv32i::Blt { imm, .. } |
+            Rv32i::Bge { imm, .. } |
+            Rv32i::Bltu { imm, .. } |
+            Rv32i::Bgeu { imm, .. } |
+            Rv32i::Lb { imm, .. } |
+            Rv32i::Lh { imm, .. } |
+            Rv32i::Lw { imm, .. } |
+            Rv32i::Lbu { imm, .. } |
+            Rv32i::Lhu { imm, .. } |
+            Rv32i::Sb { imm, .. } |
+            Rv32i::Sh { imm, .. } |
+            Rv32i::Sw { imm, .. } |
+            Rv32i::Addi { imm, .. } |
+            Rv32i::Slti { imm, .. } |
+            Rv32i::Sltiu { imm, .. } |
+            Rv32i::Xori { imm, .. } |
+            Rv32i::Ori { imm, .. } |
+            Rv32i::Andi { imm, .. } => Some(*imm as i64),
+            Rv32i::Slli { shamt, .. } |
+            Rv32i::Srli { shamt, .. } |
+            Rv32i::Srai { shamt, .. } => Some(*shamt as i64),
+            _ => None
         }
     }
 
@@ -496,6 +704,92 @@ impl Extension for Rv32i {
             _ => false
         }
     }
+
+    fn asm(&self, pc: u64) -> String {
+        match self {
+            Rv32i::Lui { rd, imm } => format!("lui {}, {:#x}", reg_name(*rd), (imm >> 12) & 0xfffff),
+            Rv32i::Auipc { rd, imm } => format!("auipc {}, {:#x}", reg_name(*rd), (imm >> 12) & 0xfffff),
+            Rv32i::Jal { rd, imm } => format!("jal {}, {:#x}", reg_name(*rd), pc.wrapping_add(*imm)),
+            Rv32i::Jalr { rd, rs1, imm } => format!("jalr {}, {}({})", reg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv32i::Beq { rs1, rs2, imm } => format!("beq {}, {}, {:#x}", reg_name(*rs1), reg_name(*rs2), pc.wrapping_add(*imm)),
+            Rv32i::Bne { rs1, rs2, imm } => format!("bne {}, {}, {:#x}", reg_name(*rs1), reg_name(*rs2), pc.wrapping_add(*imm)),
+            Rv32i::Blt { rs1, rs2, imm } => format!("blt {}, {}, {:#x}", reg_name(*rs1), reg_name(*rs2), pc.wrapping_add(*imm)),
+            Rv32i::Bge { rs1, rs2, imm } => format!("bge {}, {}, {:#x}", reg_name(*rs1), reg_name(*rs2), pc.wrapping_add(*imm)),
+            Rv32i::Bltu { rs1, rs2, imm } => format!("bltu {}, {}, {:#x}", reg_name(*rs1), reg_name(*rs2), pc.wrapping_add(*imm)),
+            Rv32i::Bgeu { rs1, rs2, imm } => format!("bgeu {}, {}, {:#x}", reg_name(*rs1), reg_name(*rs2), pc.wrapping_add(*imm)),
+            Rv32i::Lb { rd, rs1, imm } => format!("lb {}, {}({})", reg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv32i::Lh { rd, rs1, imm } => format!("lh {}, {}({})", reg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv32i::Lw { rd, rs1, imm } => format!("lw {}, {}({})", reg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv32i::Lbu { rd, rs1, imm } => format!("lbu {}, {}({})", reg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv32i::Lhu { rd, rs1, imm } => format!("lhu {}, {}({})", reg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv32i::Sb { rs1, rs2, imm } => format!("sb {}, {}({})", reg_name(*rs2), *imm as i64, reg_name(*rs1)),
+            Rv32i::Sh { rs1, rs2, imm } => format!("sh {}, {}({})", reg_name(*rs2), *imm as i64, reg_name(*rs1)),
+            Rv32i::Sw { rs1, rs2, imm } => format!("sw {}, {}({})", reg_name(*rs2), *imm as i64, reg_name(*rs1)),
+            Rv32i::Addi { rd, rs1, imm } => format!("addi {}, {}, {}", reg_name(*rd), reg_name(*rs1), *imm as i64),
+            Rv32i::Slti { rd, rs1, imm } => format!("slti {}, {}, {}", reg_name(*rd), reg_name(*rs1), *imm as i64),
+            Rv32i::Sltiu { rd, rs1, imm } => format!("sltiu {}, {}, {}", reg_name(*rd), reg_name(*rs1), *imm as i64),
+            Rv32i::Xori { rd, rs1, imm } => format!("xori {}, {}, {}", reg_name(*rd), reg_name(*rs1), *imm as i64),
+            Rv32i::Ori { rd, rs1, imm } => format!("ori {}, {}, {}", reg_name(*rd), reg_name(*rs1), *imm as i64),
+            Rv32i::Andi { rd, rs1, imm } => format!("andi {}, {}, {}", reg_name(*rd), reg_name(*rs1), *imm as i64),
+            Rv32i::Slli { rd, rs1, shamt } => format!("slli {}, {}, {}", reg_name(*rd), reg_name(*rs1), shamt),
+            Rv32i::Srli { rd, rs1, shamt } => format!("srli {}, {}, {}", reg_name(*rd), reg_name(*rs1), shamt),
+            Rv32i::Srai { rd, rs1, shamt } => format!("srai {}, {}, {}", reg_name(*rd), reg_name(*rs1), shamt),
+            Rv32i::Add { rd, rs1, rs2 } => format!("add {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Sub { rd, rs1, rs2 } => format!("sub {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Sll { rd, rs1, rs2 } => format!("sll {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Slt { rd, rs1, rs2 } => format!("slt {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Sltu { rd, rs1, rs2 } => format!("sltu {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Xor { rd, rs1, rs2 } => format!("xor {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Srl { rd, rs1, rs2 } => format!("srl {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Sra { rd, rs1, rs2 } => format!("sra {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Or { rd, rs1, rs2 } => format!("or {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::And { rd, rs1, rs2 } => format!("and {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32i::Fence { pred, succ } => format!("fence {}, {}", fence_set(*pred), fence_set(*succ)),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Rv32i::Lui { .. } => "lui",
+            Rv32i::Auipc { .. } => "auipc",
+            Rv32i::Jal { .. } => "jal",
+            Rv32i::Jalr { .. } => "jalr",
+            Rv32i::Beq { .. } => "beq",
+            Rv32i::Bne { .. } => "bne",
+            Rv32i::Blt { .. } => "blt",
+            Rv32i::Bge { .. } => "bge",
+            Rv32i::Bltu { .. } => "bltu",
+            Rv32i::Bgeu { .. } => "bgeu",
+            Rv32i::Lb { .. } => "lb",
+            Rv32i::Lh { .. } => "lh",
+            Rv32i::Lw { .. } => "lw",
+            Rv32i::Lbu { .. } => "lbu",
+            Rv32i::Lhu { .. } => "lhu",
+            Rv32i::Sb { .. } => "sb",
+            Rv32i::Sh { .. } => "sh",
+            Rv32i::Sw { .. } => "sw",
+            Rv32i::Addi { .. } => "addi",
+            Rv32i::Slti { .. } => "slti",
+            Rv32i::Sltiu { .. } => "sltiu",
+            Rv32i::Xori { .. } => "xori",
+            Rv32i::Ori { .. } => "ori",
+            Rv32i::Andi { .. } => "andi",
+            Rv32i::Slli { .. } => "slli",
+            Rv32i::Srli { .. } => "srli",
+            Rv32i::Srai { .. } => "srai",
+            Rv32i::Add { .. } => "add",
+            Rv32i::Sub { .. } => "sub",
+            Rv32i::Sll { .. } => "sll",
+            Rv32i::Slt { .. } => "slt",
+            Rv32i::Sltu { .. } => "sltu",
+            Rv32i::Xor { .. } => "xor",
+            Rv32i::Srl { .. } => "srl",
+            Rv32i::Sra { .. } => "sra",
+            Rv32i::Or { .. } => "or",
+            Rv32i::And { .. } => "and",
+            Rv32i::Fence { .. } => "fence",
+        }
+    }
 }
 
 impl Extension for Rv64i {
@@ -516,9 +810,9 @@ impl Extension for Rv64i {
             (_, 0b011, 0b0000011) => Ok(Self::Ld { rd, rs1, imm: i_imm }),
             (_, 0b011, 0b0100011) => Ok(Self::Sd { rs1, rs2, imm: s_imm }),
             (_, 0b000, 0b0011011) => Ok(Self::Addiw { rd, rs1, imm: i_imm }),
-            (0b0000000, 0b001, 0b0011011) => Ok(Self::Slliw { rd, rs1, shamt: (i_imm as u32) & 0xf }),
-            (0b0000000, 0b101, 0b0011011) => Ok(Self::Srliw { rd, rs1, shamt: (i_imm as u32) & 0xf }),
-            (0b0100000, 0b101, 0b0011011) => Ok(Self::Sraiw { rd, rs1, shamt: (i_imm as u32) & 0xf }),
+            (0b0000000, 0b001, 0b0011011) => Ok(Self::Slliw { rd, rs1, shamt: (i_imm as u32) & 0x1f }),
+            (0b0000000, 0b101, 0b0011011) => Ok(Self::Srliw { rd, rs1, shamt: (i_imm as u32) & 0x1f }),
+            (0b0100000, 0b101, 0b0011011) => Ok(Self::Sraiw { rd, rs1, shamt: (i_imm as u32) & 0x1f }),
             (0b0000000, 0b000, 0b0111011) => Ok(Self::Addw { rd, rs1, rs2 }),
             (0b0100000, 0b000, 0b0111011) => Ok(Self::Subw { rd, rs1, rs2 }),
             (0b0000000, 0b001, 0b0111011) => Ok(Self::Sllw { rd, rs1, rs2 }),
@@ -528,15 +822,15 @@ impl Extension for Rv64i {
         }
     }
 
-    fn ex(self, regs: &[u64; 32]) -> Self {
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
         match self {
             Rv64i::Lwu { rd, rs1, imm } => Self::Lwu { rd, rs1: regs[rs1 as usize], imm },
             Rv64i::Ld { rd, rs1, imm } => Self::Ld { rd, rs1: regs[rs1 as usize], imm },
             Rv64i::Sd { rs1, rs2, imm } => Self::Sd { rs1: regs[rs1 as usize], rs2: regs[rs2 as usize], imm },
             Rv64i::Addiw { rd, rs1, imm } => Self::Addiw { rd, rs1: regs[rs1 as usize], imm },
-            Rv64i::Slliw { rd, rs1, shamt } => Self::Slliw { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x3f },
-            Rv64i::Srliw { rd, rs1, shamt } => Self::Srliw { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x3f },
-            Rv64i::Sraiw { rd, rs1, shamt } => Self::Sraiw { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x3f },
+            Rv64i::Slliw { rd, rs1, shamt } => Self::Slliw { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x1f },
+            Rv64i::Srliw { rd, rs1, shamt } => Self::Srliw { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x1f },
+            Rv64i::Sraiw { rd, rs1, shamt } => Self::Sraiw { rd, rs1: regs[rs1 as usize], shamt: shamt & 0x1f },
             Rv64i::Addw { rd, rs1, rs2 } => Self::Addw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
             Rv64i::Subw { rd, rs1, rs2 } => Self::Subw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
             Rv64i::Sllw { rd, rs1, rs2 } => Self::Sllw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
@@ -545,58 +839,58 @@ impl Extension for Rv64i {
         }
     }
 
-    fn wr(self, pc: u64, regs: &mut [u64; 32], bus: &mut Bus) -> Result<u64, Exception> {
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], _csr: &mut Csr, bus: &mut Bus) -> Result<u64, Exception> {
         match self {
             Rv64i::Lwu { rd, rs1, imm } => {
                 let addr = rs1.wrapping_add(imm);
-                regs[rd as usize] = bus.load(addr, B64)?;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, bus.load(addr, B32)?);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Ld { rd, rs1, imm } => {
                 let addr = rs1.wrapping_add(imm);
-                regs[rd as usize] = bus.load(addr as u64, B64)?;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, bus.load(addr, B64)?);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Sd { rs1, rs2, imm } => {
                 let addr = rs1.wrapping_add(imm);
                 bus.store(addr as u64, B64, rs2)?;
-                Ok(pc.wrapping_add(4))
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Addiw { rd, rs1, imm } => {
-                regs[rd as usize] = rs1.wrapping_add(imm) as i32 as i64 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_add(imm) as i32 as i64 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Slliw { rd, rs1, shamt } => {
-                regs[rd as usize] = rs1.wrapping_shl(shamt) as i32 as i64 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_shl(shamt) as i32 as i64 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Srliw { rd, rs1, shamt } => {
-                regs[rd as usize] = (rs1 as u32).wrapping_shr(shamt) as i32 as i64 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, (rs1 as u32).wrapping_shr(shamt) as i32 as i64 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Sraiw { rd, rs1, shamt } => {
-                regs[rd as usize] = ((rs1 as i32).wrapping_shr(shamt)) as i64 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, ((rs1 as i32).wrapping_shr(shamt)) as i64 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Addw { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1.wrapping_add(rs2) as i32 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_add(rs2) as i32 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Subw { rd, rs1, rs2 } => {
-                regs[rd as usize] = rs1.wrapping_sub(rs2) as i32 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, rs1.wrapping_sub(rs2) as i32 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Sllw { rd, rs1, rs2 } => {
-                regs[rd as usize] = (rs1 as u32).wrapping_shl(rs2 as u32) as i32 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, (rs1 as u32).wrapping_shl(rs2 as u32) as i32 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Srlw { rd, rs1, rs2 } => {
-                regs[rd as usize] = (rs1 as u32).wrapping_shr(rs2 as u32) as i32 as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, (rs1 as u32).wrapping_shr(rs2 as u32) as i32 as u64);
+                Ok(pc.wrapping_add(len))
             },
             Rv64i::Sraw { rd, rs1, rs2 } => {
-                regs[rd as usize] = (rs1 as i32).wrapping_shr(rs2 as u32) as u64;
-                Ok(pc.wrapping_add(4))
+                write_reg(regs, rd, (rs1 as i32).wrapping_shr(rs2 as u32) as u64);
+                Ok(pc.wrapping_add(len))
             },
         }
     }
@@ -635,6 +929,19 @@ impl Extension for Rv64i {
         }
     }
 
+    fn imm(&self) -> Option<i64> {
+        match self {
+            Rv64i::Lwu { imm, .. } |
+            Rv64i::Ld { imm, .. } |
+            Rv64i::Sd { imm, .. } |
+            Rv64i::Addiw { imm, .. } => Some(*imm as i64),
+            Rv64i::Slliw { shamt, .. } |
+            Rv64i::Srliw { shamt, .. } |
+            Rv64i::Sraiw { shamt, .. } => Some(*shamt as i64),
+            _ => None
+        }
+    }
+
     fn src_mem_addr(&self) -> Option<u64> {
         match self {
             Rv64i::Lwu { rs1, imm, .. } => {
@@ -678,6 +985,377 @@ impl Extension for Rv64i {
     fn is_jmp(&self) -> bool {
         false
     }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Rv64i::Lwu { rd, rs1, imm } => format!("lwu {}, {}({})", reg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv64i::Ld { rd, rs1, imm } => format!("ld {}, {}({})", reg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv64i::Sd { rs1, rs2, imm } => format!("sd {}, {}({})", reg_name(*rs2), *imm as i64, reg_name(*rs1)),
+            Rv64i::Addiw { rd, rs1, imm } => format!("addiw {}, {}, {}", reg_name(*rd), reg_name(*rs1), *imm as i64),
+            Rv64i::Slliw { rd, rs1, shamt } => format!("slliw {}, {}, {}", reg_name(*rd), reg_name(*rs1), shamt),
+            Rv64i::Srliw { rd, rs1, shamt } => format!("srliw {}, {}, {}", reg_name(*rd), reg_name(*rs1), shamt),
+            Rv64i::Sraiw { rd, rs1, shamt } => format!("sraiw {}, {}, {}", reg_name(*rd), reg_name(*rs1), shamt),
+            Rv64i::Addw { rd, rs1, rs2 } => format!("addw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv64i::Subw { rd, rs1, rs2 } => format!("subw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv64i::Sllw { rd, rs1, rs2 } => format!("sllw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv64i::Srlw { rd, rs1, rs2 } => format!("srlw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv64i::Sraw { rd, rs1, rs2 } => format!("sraw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Rv64i::Lwu { .. } => "lwu",
+            Rv64i::Ld { .. } => "ld",
+            Rv64i::Sd { .. } => "sd",
+            Rv64i::Addiw { .. } => "addiw",
+            Rv64i::Slliw { .. } => "slliw",
+            Rv64i::Srliw { .. } => "srliw",
+            Rv64i::Sraiw { .. } => "sraiw",
+            Rv64i::Addw { .. } => "addw",
+            Rv64i::Subw { .. } => "subw",
+            Rv64i::Sllw { .. } => "sllw",
+            Rv64i::Srlw { .. } => "srlw",
+            Rv64i::Sraw { .. } => "sraw",
+        }
+    }
+}
+
+impl Extension for Zicsr {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        let opcode = opcode(ins);
+        let funct3 = funct3(ins);
+
+        let rd = rd(ins) as u64;
+        let rs1 = rs1(ins) as u64;
+        let csr = (ins >> 20) as u64;
+
+        match (funct3, opcode) {
+            (0b001, 0b1110011) => Ok(Self::Csrrw { rd, rs1, csr }),
+            (0b010, 0b1110011) => Ok(Self::Csrrs { rd, rs1, csr }),
+            (0b011, 0b1110011) => Ok(Self::Csrrc { rd, rs1, csr }),
+            (0b101, 0b1110011) => Ok(Self::Csrrwi { rd, uimm: rs1, csr }),
+            (0b110, 0b1110011) => Ok(Self::Csrrsi { rd, uimm: rs1, csr }),
+            (0b111, 0b1110011) => Ok(Self::Csrrci { rd, uimm: rs1, csr }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        match self {
+            Zicsr::Csrrw { rd, rs1, csr } => Self::Csrrw { rd, rs1: regs[rs1 as usize], csr },
+            // with x0 as the source these only read the CSR, as the
+            // immediate forms do with a zero uimm
+            Zicsr::Csrrs { rd, rs1: 0, csr } => Self::Csrrsi { rd, uimm: 0, csr },
+            Zicsr::Csrrc { rd, rs1: 0, csr } => Self::Csrrci { rd, uimm: 0, csr },
+            Zicsr::Csrrs { rd, rs1, csr } => Self::Csrrs { rd, rs1: regs[rs1 as usize], csr },
+            Zicsr::Csrrc { rd, rs1, csr } => Self::Csrrc { rd, rs1: regs[rs1 as usize], csr },
+            Zicsr::Csrrwi { rd, uimm, csr } => Self::Csrrwi { rd, uimm, csr },
+            Zicsr::Csrrsi { rd, uimm, csr } => Self::Csrrsi { rd, uimm, csr },
+            Zicsr::Csrrci { rd, uimm, csr } => Self::Csrrci { rd, uimm, csr },
+        }
+    }
+
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], csr: &mut Csr, _bus: &mut Bus) -> Result<u64, Exception> {
+        match self {
+            Zicsr::Csrrw { rd, rs1, csr: addr } => {
+                csr_access(csr, addr, true)?;
+                let old = csr.load(addr);
+                csr.store(addr, rs1);
+                write_reg(regs, rd, old);
+                Ok(pc.wrapping_add(len))
+            },
+            // ex turned the forms with x0 as the source into immediates
+            Zicsr::Csrrs { rd, rs1, csr: addr } => {
+                csr_access(csr, addr, true)?;
+                let old = csr.load(addr);
+                csr.store(addr, old | rs1);
+                write_reg(regs, rd, old);
+                Ok(pc.wrapping_add(len))
+            },
+            Zicsr::Csrrc { rd, rs1, csr: addr } => {
+                csr_access(csr, addr, true)?;
+                let old = csr.load(addr);
+                csr.store(addr, old & !rs1);
+                write_reg(regs, rd, old);
+                Ok(pc.wrapping_add(len))
+            },
+            Zicsr::Csrrwi { rd, uimm, csr: addr } => {
+                csr_access(csr, addr, true)?;
+                let old = csr.load(addr);
+                csr.store(addr, uimm);
+                write_reg(regs, rd, old);
+                Ok(pc.wrapping_add(len))
+            },
+            Zicsr::Csrrsi { rd, uimm, csr: addr } => {
+                csr_access(csr, addr, uimm != 0)?;
+                let old = csr.load(addr);
+                if uimm != 0 {
+                    csr.store(addr, old | uimm);
+                }
+                write_reg(regs, rd, old);
+                Ok(pc.wrapping_add(len))
+            },
+            Zicsr::Csrrci { rd, uimm, csr: addr } => {
+                csr_access(csr, addr, uimm != 0)?;
+                let old = csr.load(addr);
+                if uimm != 0 {
+                    csr.store(addr, old & !uimm);
+                }
+                write_reg(regs, rd, old);
+                Ok(pc.wrapping_add(len))
+            },
+        }
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            Zicsr::Csrrw { rs1, .. } => vec![*rs1],
+            Zicsr::Csrrs { rs1, .. } => vec![*rs1],
+            Zicsr::Csrrc { rs1, .. } => vec![*rs1],
+            Zicsr::Csrrwi { .. } => vec![],
+            Zicsr::Csrrsi { .. } => vec![],
+            Zicsr::Csrrci { .. } => vec![],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        match self {
+            Zicsr::Csrrw { rd, .. } => Some(*rd),
+            Zicsr::Csrrs { rd, .. } => Some(*rd),
+            Zicsr::Csrrc { rd, .. } => Some(*rd),
+            Zicsr::Csrrwi { rd, .. } => Some(*rd),
+            Zicsr::Csrrsi { rd, .. } => Some(*rd),
+            Zicsr::Csrrci { rd, .. } => Some(*rd),
+        }
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_ld(&self) -> bool {
+        false
+    }
+
+    fn is_st(&self) -> bool {
+        false
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Zicsr::Csrrw { rd, rs1, csr } => format!("csrrw {}, {}, {}", reg_name(*rd), csr_name(*csr), reg_name(*rs1)),
+            Zicsr::Csrrs { rd, rs1, csr } => format!("csrrs {}, {}, {}", reg_name(*rd), csr_name(*csr), reg_name(*rs1)),
+            Zicsr::Csrrc { rd, rs1, csr } => format!("csrrc {}, {}, {}", reg_name(*rd), csr_name(*csr), reg_name(*rs1)),
+            Zicsr::Csrrwi { rd, uimm, csr } => format!("csrrwi {}, {}, {}", reg_name(*rd), csr_name(*csr), uimm),
+            Zicsr::Csrrsi { rd, uimm, csr } => format!("csrrsi {}, {}, {}", reg_name(*rd), csr_name(*csr), uimm),
+            Zicsr::Csrrci { rd, uimm, csr } => format!("csrrci {}, {}, {}", reg_name(*rd), csr_name(*csr), uimm),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Zicsr::Csrrw { .. } => "csrrw",
+            Zicsr::Csrrs { .. } => "csrrs",
+            Zicsr::Csrrc { .. } => "csrrc",
+            Zicsr::Csrrwi { .. } => "csrrwi",
+            Zicsr::Csrrsi { .. } => "csrrsi",
+            Zicsr::Csrrci { .. } => "csrrci",
+        }
+    }
+}
+
+impl Extension for Zifencei {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        match (funct3(ins), opcode(ins)) {
+            (0b001, 0b0001111) => Ok(Self::FenceI),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, _regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        self
+    }
+
+    fn wr(self, pc: u64, len: u64, _regs: &mut [u64; 32], _fregs: &mut [u64; 32], _csr: &mut Csr, _bus: &mut Bus) -> Result<u64, Exception> {
+        // instructions are fetched straight from the bus, so there is no
+        // stale instruction stream to synchronise
+        Ok(pc.wrapping_add(len))
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        vec![]
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        None
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_ld(&self) -> bool {
+        false
+    }
+
+    fn is_st(&self) -> bool {
+        false
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        self.to_string()
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Zifencei::FenceI => "fence.i",
+        }
+    }
+}
+
+impl Extension for System {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        match ins {
+            0x00000073 => Ok(Self::Ecall),
+            0x00100073 => Ok(Self::Ebreak),
+            0x30200073 => Ok(Self::Mret),
+            0x10200073 => Ok(Self::Sret),
+            0x10500073 => Ok(Self::Wfi),
+            _ if ins & 0xfe007fff == 0x12000073 => Ok(Self::SfenceVma { rs1: rs1(ins) as u64, rs2: rs2(ins) as u64 }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, _regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        self
+    }
+
+    fn wr(self, pc: u64, len: u64, _regs: &mut [u64; 32], _fregs: &mut [u64; 32], csr: &mut Csr, bus: &mut Bus) -> Result<u64, Exception> {
+        match self {
+            System::Ecall => Err(match csr.privilege {
+                Privilege::U => Exception::EnvironmentCallFromUMode(pc),
+                Privilege::S => Exception::EnvironmentCallFromSMode(pc),
+                Privilege::M => Exception::EnvironmentCallFromMMode(pc),
+            }),
+            System::Ebreak => Err(Exception::Breakpoint(pc)),
+            System::Mret => {
+                if csr.privilege < Privilege::M {
+                    return Err(Exception::IllegalInstruction(0x30200073))
+                }
+                // return to the privilege the trap was taken from, leaving
+                // MPP at the lowest level as the spec requires, and pop the
+                // interrupt enable stack
+                let mstatus = csr.load(MSTATUS);
+                csr.privilege = Privilege::from_bits((mstatus & MSTATUS_MPP) >> 11);
+                let mie = if mstatus & MSTATUS_MPIE != 0 { MSTATUS_MIE } else { 0 };
+                csr.store(MSTATUS, (mstatus & !(MSTATUS_MPP | MSTATUS_MIE)) | mie | MSTATUS_MPIE);
+                Ok(csr.load(MEPC))
+            },
+            System::Sret => {
+                if csr.privilege < Privilege::S {
+                    return Err(Exception::IllegalInstruction(0x10200073))
+                }
+                let sstatus = csr.load(SSTATUS);
+                csr.privilege = if sstatus & SSTATUS_SPP != 0 { Privilege::S } else { Privilege::U };
+                let sie = if sstatus & SSTATUS_SPIE != 0 { SSTATUS_SIE } else { 0 };
+                csr.store(SSTATUS, (sstatus & !(SSTATUS_SPP | SSTATUS_SIE)) | sie | SSTATUS_SPIE);
+                Ok(csr.load(SEPC))
+            },
+            // interrupts are only raised from outside the run loop, so there
+            // is never one to wait for and wfi is the no-op the spec permits
+            System::Wfi => Ok(pc.wrapping_add(len)),
+            System::SfenceVma { rs1, rs2 } => {
+                if csr.privilege < Privilege::S {
+                    return Err(Exception::IllegalInstruction(0x12000073 | rs2 << 20 | rs1 << 15))
+                }
+                bus.flush_tlb();
+                Ok(pc.wrapping_add(len))
+            },
+        }
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            System::SfenceVma { rs1, rs2 } => vec![*rs1, *rs2],
+            _ => vec![],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        None
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_ld(&self) -> bool {
+        false
+    }
+
+    fn is_st(&self) -> bool {
+        false
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        matches!(self, System::Mret | System::Sret)
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        self.to_string()
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            System::Ecall => "ecall",
+            System::Ebreak => "ebreak",
+            System::Mret => "mret",
+            System::Sret => "sret",
+            System::Wfi => "wfi",
+            System::SfenceVma { .. } => "sfence.vma",
+        }
+    }
+}
+
+impl Display for Zifencei {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Zifencei::FenceI => write!(f, "fence.i"),
+        }
+    }
 }
 
 impl Display for Rv32i {
@@ -720,6 +1398,7 @@ impl Display for Rv32i {
             Rv32i::Sra { rd, rs1, rs2 } => write!(f, "sra rd={}, rs1={}, rs2={}", rd, rs1, rs2),
             Rv32i::Or { rd, rs1, rs2 } => write!(f, "or rd={}, rs1={}, rs2={}", rd, rs1, rs2),
             Rv32i::And { rd, rs1, rs2 } => write!(f, "and rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32i::Fence { pred, succ } => write!(f, "fence pred={}, succ={}", fence_set(*pred), fence_set(*succ)),
         }
     }
 }
@@ -730,10 +1409,10 @@ impl Display for Rv64i {
             Rv64i::Lwu { rd, rs1, imm } => write!(f, "lwu rd={}, offset(rs1)={}({})", rd, imm, rs1),
             Rv64i::Ld { rd, rs1, imm } => write!(f, "ld rd={}, offset(rs1)={}({})", rd, imm, rs1),
             Rv64i::Sd { rs1, rs2, imm } => write!(f, "sd rs2={}, offset(rs1)={}({})", rs2, imm, rs1),
-            Rv64i::Addiw { rd, rs1, imm } => write!(f, "add rd={}, rs1={}, imm={}", rd, rs1, imm),
-            Rv64i::Slliw { rd, rs1, shamt } => write!(f, "add rd={}, rs1={}, shamt={}", rd, rs1, shamt),
-            Rv64i::Srliw { rd, rs1, shamt } => write!(f, "add rd={}, rs1={}, shamt={}", rd, rs1, shamt),
-            Rv64i::Sraiw { rd, rs1, shamt } => write!(f, "add rd={}, rs1={}, shamt={}", rd, rs1, shamt),
+            Rv64i::Addiw { rd, rs1, imm } => write!(f, "addiw rd={}, rs1={}, imm={}", rd, rs1, imm),
+            Rv64i::Slliw { rd, rs1, shamt } => write!(f, "slliw rd={}, rs1={}, shamt={}", rd, rs1, shamt),
+            Rv64i::Srliw { rd, rs1, shamt } => write!(f, "srliw rd={}, rs1={}, shamt={}", rd, rs1, shamt),
+            Rv64i::Sraiw { rd, rs1, shamt } => write!(f, "sraiw rd={}, rs1={}, shamt={}", rd, rs1, shamt),
             Rv64i::Addw { rd, rs1, rs2 } => write!(f, "addw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
             Rv64i::Subw { rd, rs1, rs2 } => write!(f, "subw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
             Rv64i::Sllw { rd, rs1, rs2 } => write!(f, "sllw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
@@ -743,6 +1422,32 @@ impl Display for Rv64i {
     }
 }
 
+impl Display for Zicsr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Zicsr::Csrrw { rd, rs1, csr } => write!(f, "csrrw rd={}, csr={}, rs1={}", rd, csr, rs1),
+            Zicsr::Csrrs { rd, rs1, csr } => write!(f, "csrrs rd={}, csr={}, rs1={}", rd, csr, rs1),
+            Zicsr::Csrrc { rd, rs1, csr } => write!(f, "csrrc rd={}, csr={}, rs1={}", rd, csr, rs1),
+            Zicsr::Csrrwi { rd, uimm, csr } => write!(f, "csrrwi rd={}, csr={}, uimm={}", rd, csr, uimm),
+            Zicsr::Csrrsi { rd, uimm, csr } => write!(f, "csrrsi rd={}, csr={}, uimm={}", rd, csr, uimm),
+            Zicsr::Csrrci { rd, uimm, csr } => write!(f, "csrrci rd={}, csr={}, uimm={}", rd, csr, uimm),
+        }
+    }
+}
+
+impl Display for System {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            System::Ecall => write!(f, "ecall"),
+            System::Ebreak => write!(f, "ebreak"),
+            System::Mret => write!(f, "mret"),
+            System::Sret => write!(f, "sret"),
+            System::Wfi => write!(f, "wfi"),
+            System::SfenceVma { rs1, rs2 } => write!(f, "sfence.vma {}, {}", reg_name(*rs1), reg_name(*rs2)),
+        }
+    }
+}
+
 pub fn opcode(ins: u32) -> u32 {
     ins & 0x7f
 }
@@ -793,7 +1498,260 @@ pub fn j_imm(ins: u32) -> u64 {
         | ((ins as u64 >> 20) & 0x7fe)
 }
 
-pub fn print_register_table(regs: &[u64; 32]) {
+// Encoders for each instruction format, the inverse of the field
+// decoders above. Immediates are given as the decoders return them, so
+// encode_u takes the value already shifted into bits 31:12 and the
+// branch and jump offsets are in bytes.
+
+#[cfg(test)]
+pub fn encode_r(opcode: u32, rd: usize, funct3: u32, rs1: usize, rs2: usize, funct7: u32) -> u32 {
+    funct7 << 25 | (rs2 as u32) << 20 | (rs1 as u32) << 15 | funct3 << 12 | (rd as u32) << 7 | opcode
+}
+
+#[cfg(test)]
+pub fn encode_i(opcode: u32, rd: usize, funct3: u32, rs1: usize, imm: i64) -> u32 {
+    (imm as u32) << 20 | (rs1 as u32) << 15 | funct3 << 12 | (rd as u32) << 7 | opcode
+}
+
+#[cfg(test)]
+pub fn encode_s(opcode: u32, funct3: u32, rs1: usize, rs2: usize, imm: i64) -> u32 {
+    let imm = imm as u32;
+    (imm >> 5 & 0x7f) << 25 | (rs2 as u32) << 20 | (rs1 as u32) << 15 | funct3 << 12 | (imm & 0x1f) << 7 | opcode
+}
+
+#[cfg(test)]
+pub fn encode_b(opcode: u32, funct3: u32, rs1: usize, rs2: usize, imm: i64) -> u32 {
+    let imm = imm as u32;
+    (imm >> 12 & 1) << 31 | (imm >> 5 & 0x3f) << 25 | (rs2 as u32) << 20 | (rs1 as u32) << 15
+        | funct3 << 12 | (imm >> 1 & 0xf) << 8 | (imm >> 11 & 1) << 7 | opcode
+}
+
+#[cfg(test)]
+pub fn encode_u(opcode: u32, rd: usize, imm: i64) -> u32 {
+    (imm as u32 & 0xfffff000) | (rd as u32) << 7 | opcode
+}
+
+#[cfg(test)]
+pub fn encode_j(opcode: u32, rd: usize, imm: i64) -> u32 {
+    let imm = imm as u32;
+    (imm >> 20 & 1) << 31 | (imm >> 1 & 0x3ff) << 21 | (imm >> 11 & 1) << 20 | (imm & 0xff000)
+        | (rd as u32) << 7 | opcode
+}
+
+/// Every field of an instruction word, whether or not the format it belongs
+/// to applies, for working out why a word failed to decode
+#[derive(Debug, PartialEq)]
+pub struct DecodeFields {
+    pub opcode: u32,
+    pub funct3: u32,
+    pub funct7: u32,
+    pub rd: usize,
+    pub rs1: usize,
+    pub rs2: usize,
+    pub i_imm: i64,
+    pub s_imm: i64,
+    pub b_imm: i64,
+    pub u_imm: i64,
+    pub j_imm: i64,
+}
+
+pub fn decode_fields(ins: u32) -> DecodeFields {
+    DecodeFields {
+        opcode: opcode(ins),
+        funct3: funct3(ins),
+        funct7: funct7(ins),
+        rd: rd(ins),
+        rs1: rs1(ins),
+        rs2: rs2(ins),
+        i_imm: i_imm(ins) as i64,
+        s_imm: s_imm(ins) as i64,
+        b_imm: b_imm(ins) as i64,
+        u_imm: u_imm(ins) as i64,
+        j_imm: j_imm(ins) as i64,
+    }
+}
+
+impl DecodeFields {
+    pub fn json(&self) -> String {
+        format!(
+            "{{\"opcode\":{},\"funct3\":{},\"funct7\":{},\"rd\":{},\"rs1\":{},\"rs2\":{},\"i_imm\":{},\"s_imm\":{},\"b_imm\":{},\"u_imm\":{},\"j_imm\":{}}}",
+            self.opcode, self.funct3, self.funct7, self.rd, self.rs1, self.rs2,
+            self.i_imm, self.s_imm, self.b_imm, self.u_imm, self.j_imm
+        )
+    }
+}
+
+/// One decoded instruction as JSON, see decode_json
+fn ins_json<O: Extension>(i: O, fields: &DecodeFields) -> String {
+    let rd = i.dst_reg().map_or("null".to_string(), |rd| format!("\"{}\"", dep_reg_name(rd)));
+    let rs: Vec<String> = i.src_regs().iter().map(|rs| format!("\"{}\"", dep_reg_name(*rs))).collect();
+    let imm = i.imm().map_or("null".to_string(), |imm| imm.to_string());
+    format!(
+        "{{\"mnemonic\":\"{}\",\"rd\":{},\"rs\":[{}],\"imm\":{},\"fields\":{}}}",
+        i.mnemonic(), rd, rs.join(","), imm, fields.json()
+    )
+}
+
+/// Decodes a word to a JSON object holding the mnemonic, the ABI names of
+/// the registers it writes and reads, its immediate (null for formats that
+/// do not report one) and the raw fields. A word that does not decode gives
+/// `{"illegal":true,"fields":{...}}` so the fields can still be inspected.
+pub fn decode_json(ins: u32) -> String {
+    let fields = decode_fields(ins);
+    match decode(ins, true, EXTS_32) {
+        Ok(Decoded::Rv32i(i)) => ins_json(i, &fields),
+        Ok(Decoded::Rv64i(i)) => ins_json(i, &fields),
+        Ok(Decoded::Zbb(i)) => ins_json(i, &fields),
+        Ok(Decoded::Zbb64(i)) => ins_json(i, &fields),
+        Ok(Decoded::Rv32m(i)) => ins_json(i, &fields),
+        Ok(Decoded::Rv64m(i)) => ins_json(i, &fields),
+        Ok(Decoded::Rv32a(i)) => ins_json(i, &fields),
+        Ok(Decoded::Rv64a(i)) => ins_json(i, &fields),
+        Ok(Decoded::Rv32f(i)) => ins_json(i, &fields),
+        Ok(Decoded::Zicsr(i)) => ins_json(i, &fields),
+        Ok(Decoded::Zifencei(i)) => ins_json(i, &fields),
+        Ok(Decoded::System(i)) => ins_json(i, &fields),
+        Err(_) => format!("{{\"illegal\":true,\"fields\":{}}}", fields.json()),
+    }
+}
+
+impl Display for DecodeFields {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "opcode {:#09b} funct3 {:#05b} funct7 {:#09b} rd {} rs1 {} rs2 {}",
+            self.opcode, self.funct3, self.funct7, self.rd, self.rs1, self.rs2)?;
+        write!(f, "i_imm {} s_imm {} b_imm {} u_imm {} j_imm {}",
+            self.i_imm, self.s_imm, self.b_imm, self.u_imm, self.j_imm)
+    }
+}
+
+/// Writes an instruction's result to rd. x0 is hardwired to zero, so a
+/// write to it is dropped here and nothing has to clear it afterwards.
+pub fn write_reg(regs: &mut [u64; 32], rd: u64, value: u64) {
+    if rd != 0 {
+        regs[rd as usize] = value;
+    }
+}
+
+/// Interprets a register value as signed at the current XLEN
+pub fn signed(value: u64, csr: &Csr) -> i64 {
+    if csr.xlen() == 32 { value as i32 as i64 } else { value as i64 }
+}
+
+/// 1 if a < b at the current XLEN, else 0, for the slt family. Both sides
+/// are sign-extended from XLEN first: I-type immediates always arrive
+/// sign-extended to 64 bits, and sign extension keeps the unsigned order
+/// of two XLEN-bit values, so one rule serves all four instructions.
+pub fn set_less_than(a: u64, b: u64, unsigned: bool, csr: &Csr) -> u64 {
+    let (a, b) = (signed(a, csr), signed(b, csr));
+    let less = if unsigned { (a as u64) < (b as u64) } else { a < b };
+    less as u64
+}
+
+/// Whether `a + b`, or `a - b` for `sub`, leaves the range of a signed
+/// XLEN value
+fn signed_overflow(a: u64, b: u64, sub: bool, csr: &Csr) -> bool {
+    let (a, b) = (signed(a, csr) as i128, signed(b, csr) as i128);
+    let result = if sub { a - b } else { a + b };
+    let limit = 1_i128 << (csr.xlen() - 1);
+    result < -limit || result >= limit
+}
+
+/// Checks a taken branch or jump target against the instruction alignment,
+/// which is 2 bytes with the C extension enabled and 4 bytes without
+fn jump_target(target: u64, csr: &Csr) -> Result<u64, Exception> {
+    let align = if csr.load(MISA) & MISA_C != 0 { 2 } else { 4 };
+    if !target.is_multiple_of(align) {
+        return Err(Exception::InstructionAddrMisaligned(target))
+    }
+    Ok(target)
+}
+
+/// Rejects a CSR access from below the privilege its address requires, and
+/// a write to a read-only CSR. The instruction bits are gone by writeback,
+/// so mtval is left 0 as the spec permits.
+fn csr_access(csr: &Csr, addr: u64, write: bool) -> Result<(), Exception> {
+    if !csr.accessible(addr) || (write && read_only(addr)) {
+        return Err(Exception::IllegalInstruction(0))
+    }
+    Ok(())
+}
+
+/// Register shift amounts only use the low log2(XLEN) bits
+pub fn shamt(value: u64, csr: &Csr) -> u32 {
+    (value & (csr.xlen() as u64 - 1)) as u32
+}
+
+/// The i/o/r/w letters of a fence predecessor or successor set
+fn fence_set(set: u32) -> String {
+    "iorw".chars().enumerate()
+        .filter(|(i, _)| set & (0b1000 >> i) != 0)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+pub fn reg_name(reg: u64) -> &'static str {
+    RVABI[reg as usize]
+}
+
+/// Number of the first float register in src_regs and dst_reg
+pub const FREG_BASE: u64 = 32;
+/// Registers src_regs and dst_reg can name, integer then float
+pub const DEP_REG_COUNT: usize = 64;
+
+/// ABI name of a register numbered as in src_regs and dst_reg
+pub fn dep_reg_name(reg: u64) -> &'static str {
+    if reg >= FREG_BASE { freg_name(reg - FREG_BASE) } else { reg_name(reg) }
+}
+
+/// Decodes one instruction word into its assembler syntax
+pub fn disasm_ins(ins: u32, pc: u64) -> Option<String> {
+    let asm = match decode(ins, true, EXTS_32).ok()? {
+        Decoded::Rv32i(ins) => ins.asm(pc),
+        Decoded::Rv64i(ins) => ins.asm(pc),
+        Decoded::Zbb(ins) => ins.asm(pc),
+        Decoded::Zbb64(ins) => ins.asm(pc),
+        Decoded::Rv32m(ins) => ins.asm(pc),
+        Decoded::Rv64m(ins) => ins.asm(pc),
+        Decoded::Rv32a(ins) => ins.asm(pc),
+        Decoded::Rv64a(ins) => ins.asm(pc),
+        Decoded::Rv32f(ins) => ins.asm(pc),
+        Decoded::Zicsr(ins) => ins.asm(pc),
+        Decoded::Zifencei(ins) => ins.asm(pc),
+        Decoded::System(ins) => ins.asm(pc),
+    };
+    Some(asm)
+}
+
+/// Disassembles a binary loaded at base, one instruction per line.
+/// Compressed instructions are shown as their 32-bit expansion, which
+/// depends on rv64. Stops at the first word that does not decode.
+pub fn disasm(bin: &[u8], base: u64, rv64: bool) -> String {
+    let mut out = String::new();
+    let mut offset = 0;
+    while offset + 2 <= bin.len() {
+        let pc = base.wrapping_add(offset as u64);
+        let half = u16::from_le_bytes([bin[offset], bin[offset + 1]]) as u32;
+        let (word, ins) = if is_compressed(half) {
+            (half, expand(half as u16, rv64).ok())
+        } else if offset + 4 <= bin.len() {
+            let word = u32::from_le_bytes([bin[offset], bin[offset + 1], bin[offset + 2], bin[offset + 3]]);
+            (word, Some(word))
+        } else {
+            break
+        };
+        match ins.and_then(|ins| disasm_ins(ins, pc)) {
+            Some(asm) => out += &format!("{:08x}: {}\n", pc, asm),
+            None => {
+                out += &format!("{:08x}: undecodable word {:#010x} at offset {:#x}\n", pc, word, offset);
+                break;
+            }
+        }
+        offset += ins_len(word) as usize;
+    }
+    out
+}
+
+pub fn register_table(regs: &[u64; 32], xlen: u32) -> String {
     let mut builder = Builder::new();
         builder.set_header(["Register", "Decimal", "Hex"]);
         regs
@@ -801,25 +1759,79 @@ pub fn print_register_table(regs: &[u64; 32]) {
             .enumerate()
             .map(|(i, r)| [
                 format!("{}", RVABI[i]),
-                format!("{}", r),
-                format!("{:#01x}", r),
+                if xlen == 32 { format!("{}", *r as u32) } else { format!("{}", r) },
+                if xlen == 32 { format!("{:#010x}", *r as u32) } else { format!("{:#01x}", r) },
                 //format!("{:#01b}", r),
             ]).for_each(|line| {
                 builder.push_record(line);
             });
-        let table = builder.build()
+        builder.build()
             .with(Style::ascii_rounded())
-            .to_string();
-        println!("{}", table);
+            .to_string()
+}
+
+/// The registers that differ between two register files, as the index and
+/// the value in each
+pub fn diff_regs(a: &[u64; 32], b: &[u64; 32]) -> Vec<(usize, u64, u64)> {
+    a.iter().zip(b).enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(i, (a, b))| (i, *a, *b))
+        .collect()
+}
+
+/// Formats the output of diff_regs with ABI register names, headed by the
+/// names of the two sides
+pub fn diff_table(diffs: &[(usize, u64, u64)], left: &str, right: &str) -> String {
+    let mut builder = Builder::new();
+    builder.set_header(["Register", left, right]);
+    for (i, a, b) in diffs {
+        builder.push_record([RVABI[*i].to_string(), format!("{:#x}", a), format!("{:#x}", b)]);
+    }
+    builder.build()
+        .with(Style::ascii_rounded())
+        .to_string()
+}
+
+/// Writes a register file as one `name value` line per register, e.g.
+/// `a0 0x2a`
+pub fn format_regs(regs: &[u64; 32]) -> String {
+    regs.iter().enumerate()
+        .map(|(i, r)| format!("{} {:#x}\n", RVABI[i], r))
+        .collect()
+}
+
+/// Parses a register file written by format_regs
+pub fn parse_regs(text: &str) -> Result<[u64; 32], String> {
+    let mut regs = [0; 32];
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    for (i, reg) in regs.iter_mut().enumerate() {
+        let line = lines.next().ok_or(format!("expected 32 registers, found {}", i))?;
+        *reg = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [name, value] if name == RVABI[i] => parse_u64(value),
+            _ => None
+        }.ok_or(format!("line {}: expected \"{} value\", found \"{}\"", i + 1, RVABI[i], line))?;
+    }
+    match lines.next() {
+        Some(line) => Err(format!("unexpected \"{}\" after the registers", line)),
+        None => Ok(regs)
+    }
+}
+
+/// Packs instruction words into a little-endian program image
+pub fn to_bin(ins: &[u32]) -> Vec<u8> {
+    ins.iter().flat_map(|i| i.to_le_bytes()).collect()
 }
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "clang-tests")]
     use std::{process::Command, fs::File, io::{Write, Read}};
-    use crate::{isa::{Rv32i, Extension}, bus::Bus};
+    use crate::{isa::{Rv32i, Rv64i, System, Zicsr, Extension, disasm, disasm_ins, to_bin, s_imm, i_imm, b_imm, u_imm, j_imm, rd, rs1, rs2, funct3, funct7, opcode, decode_fields, decode_json, DecodeFields, diff_regs, diff_table, format_regs, parse_regs, encode_r, encode_i, encode_s, encode_b, encode_u, encode_j}, bus::{Bus, RAM_BASE}, csr::{Csr, Privilege, MISA, MISA_C, SEPC, SSTATUS, SSTATUS_SIE, SSTATUS_SPIE}, exception::Exception, rvm::Rv32m};
 
+    #[cfg(feature = "clang-tests")]
     type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+    #[cfg(feature = "clang-tests")]
     #[allow(dead_code)]
     fn clang_compile_c(c_src: &str) -> Result<()> {
         let cc = "clang";
@@ -839,6 +1851,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "clang-tests")]
     fn clang_compile_asm(asm_path: &str, ex_path: &str) -> Result<()> {
         let cc = "clang";
         let out = Command::new(cc).arg("-Wl,-Ttext=0x0")
@@ -859,6 +1872,7 @@ mod tests {
         }
     } 
 
+    #[cfg(feature = "clang-tests")]
     fn llvm_copy_obj(ex_path: &str, bin_path: &str) -> Result<()> {
         let objcopy = "llvm-objcopy";
         let out = Command::new(objcopy).arg("-O")
@@ -874,6 +1888,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "clang-tests")]
     fn asm(name: &str, code: &str) -> Result<Vec<u8>> {
         let asm_path = "./target/test/".to_string() + name + ".s";
         let ex_path = "./target/test/".to_string() + name;
@@ -889,6 +1904,7 @@ mod tests {
         Ok(code)
     }
 
+    #[cfg(feature = "clang-tests")]
     fn if32(bin: &[u8], i: usize) -> Option<u32> {
         assert!(bin.len() >= (i * 4) + 4);
         bin.iter().skip(i * 4).enumerate()
@@ -897,6 +1913,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "clang-tests")]
     fn addi() {
         let addi = asm("addi", "addi x31, x0, 42");
         assert!(addi.is_ok(), "Failed to compile: {}", addi.err().unwrap());
@@ -907,12 +1924,394 @@ mod tests {
         assert_eq!(t.as_ref().unwrap(), &Rv32i::Addi { rd: 31, rs1: 0, imm: 42 });
         let mut regs = [0_u64; 32];
         regs[31] = 5;
-        let t = t.unwrap().ex(&regs);
+        let t = t.unwrap().ex(&regs, &[0; 32]);
         assert_eq!(&t, &Rv32i::Addi { rd: 31, rs1: 0, imm: 42 });
-        let res = t.wr(0, &mut regs, &mut Bus::new(vec![]));
+        let res = t.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![]));
         assert!(res.is_ok(), "Execution failed: {:?}", res.err().unwrap());
         let res = res.unwrap();
         assert_eq!(res, 4);
         assert_eq!(regs[31], 42);
     }
+
+    #[test]
+    fn addi_encoded() {
+        let ins = encode_i(0b0010011, 31, 0b000, 0, 42); // addi x31, x0, 42
+        assert_eq!(ins, 0x02a00f93);
+        let t = Rv32i::id(ins).unwrap();
+        assert_eq!(t, Rv32i::Addi { rd: 31, rs1: 0, imm: 42 });
+        let mut regs = [0_u64; 32];
+        regs[31] = 5;
+        let res = t.ex(&regs, &[0; 32]).wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![]));
+        assert_eq!(res.unwrap(), 4);
+        assert_eq!(regs[31], 42);
+    }
+
+    #[test]
+    fn rv64i_word_display() {
+        assert!(Rv64i::Addiw { rd: 10, rs1: 11, imm: 1 }.to_string().starts_with("addiw "));
+        assert_eq!(Rv64i::Slliw { rd: 1, rs1: 2, shamt: 3 }.to_string(), "slliw rd=1, rs1=2, shamt=3");
+        assert_eq!(Rv64i::Srliw { rd: 1, rs1: 2, shamt: 3 }.to_string(), "srliw rd=1, rs1=2, shamt=3");
+        assert_eq!(Rv64i::Sraiw { rd: 1, rs1: 2, shamt: 3 }.to_string(), "sraiw rd=1, rs1=2, shamt=3");
+        // stores list the data register first, as in sd rs2, imm(rs1)
+        assert_eq!(Rv64i::Sd { rs1: 2, rs2: 8, imm: 16 }.to_string(), "sd rs2=8, offset(rs1)=16(2)");
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        assert_eq!(Rv32i::id(encode_r(0b0110011, 5, 0b000, 6, 7, 0b0100000)).unwrap(), Rv32i::Sub { rd: 5, rs1: 6, rs2: 7 });
+        assert_eq!(Rv32i::id(encode_i(0b0010011, 1, 0b000, 2, -1)).unwrap(), Rv32i::Addi { rd: 1, rs1: 2, imm: -1_i64 as u64 });
+        assert_eq!(Rv32i::id(encode_s(0b0100011, 0b010, 2, 8, -16)).unwrap(), Rv32i::Sw { rs1: 2, rs2: 8, imm: -16_i64 as u64 });
+        assert_eq!(Rv32i::id(encode_b(0b1100011, 0b001, 10, 11, -12)).unwrap(), Rv32i::Bne { rs1: 10, rs2: 11, imm: -12_i64 as u64 });
+        assert_eq!(Rv32i::id(encode_u(0b0110111, 5, 0x100000)).unwrap(), Rv32i::Lui { rd: 5, imm: 0x100000 });
+        assert_eq!(Rv32i::id(encode_j(0b1101111, 1, 2048)).unwrap(), Rv32i::Jal { rd: 1, imm: 2048 });
+        assert_eq!(Rv64i::id(encode_s(0b0100011, 0b011, 2, 8, 2040)).unwrap(), Rv64i::Sd { rs1: 2, rs2: 8, imm: 2040 });
+
+        // the extremes of every immediate survive the trip
+        for imm in [-2048, -1, 0, 1, 42, 2047] {
+            assert_eq!(i_imm(encode_i(0b0010011, 0, 0, 0, imm)), imm as u64);
+            assert_eq!(s_imm(encode_s(0b0100011, 0, 0, 0, imm)), imm as u64);
+        }
+        for imm in [-4096, -2, 0, 2, 2048, 4094] {
+            assert_eq!(b_imm(encode_b(0b1100011, 0, 0, 0, imm)), imm as u64);
+        }
+        for imm in [-0x10_0000, -2, 0, 2, 0x800, 0xf_fffe] {
+            assert_eq!(j_imm(encode_j(0b1101111, 0, imm)), imm as u64);
+        }
+        for imm in [-0x8000_0000, -0x1000, 0, 0x1000, 0x7fff_f000] {
+            assert_eq!(u_imm(encode_u(0b0110111, 0, imm)), imm as u64);
+        }
+        let ins = encode_r(0b0110011, 31, 0b111, 30, 29, 0b1111111);
+        assert_eq!((opcode(ins), rd(ins), funct3(ins), rs1(ins), rs2(ins), funct7(ins)), (0b0110011, 31, 0b111, 30, 29, 0b1111111));
+    }
+
+    #[test]
+    fn diff_regs_reports_mismatches() {
+        let a = [7_u64; 32];
+        let mut b = a;
+        b[1] = 0x8000_0010;
+        b[10] = 0;
+        b[31] = u64::MAX;
+        assert_eq!(diff_regs(&a, &b), vec![(1, 7, 0x8000_0010), (10, 7, 0), (31, 7, u64::MAX)]);
+        assert!(diff_regs(&a, &a).is_empty());
+        let table = diff_table(&diff_regs(&a, &b), "Reference", "Dart");
+        assert!(table.contains("| ra "), "{}", table);
+        assert!(table.contains("| a0 "), "{}", table);
+        assert!(!table.contains("| sp "), "{}", table);
+    }
+
+    #[test]
+    fn regs_round_trip() {
+        let mut regs = [0; 32];
+        regs[2] = 0x8000_fff0;
+        regs[31] = u64::MAX;
+        let text = format_regs(&regs);
+        assert!(text.starts_with("zero 0x0\nra 0x0\nsp 0x8000fff0\n"));
+        assert_eq!(parse_regs(&text), Ok(regs));
+        assert!(parse_regs("zero 0x0\n").is_err());
+        assert!(parse_regs(&text.replace("sp ", "gp ")).is_err());
+        assert!(parse_regs(&(text + "pc 0x0\n")).is_err());
+    }
+
+    #[test]
+    fn jal_json() {
+        let json = decode_json(0x001000ef); // jal ra, 2048
+        assert!(json.starts_with("{\"mnemonic\":\"jal\",\"rd\":\"ra\",\"rs\":[],\"imm\":2048,"), "{}", json);
+        assert!(json.contains("\"j_imm\":2048}"), "{}", json);
+        assert!(json.contains("\"opcode\":111,"), "{}", json);
+
+        let json = decode_json(0x00b50533); // add a0, a0, a1
+        assert!(json.starts_with("{\"mnemonic\":\"add\",\"rd\":\"a0\",\"rs\":[\"a0\",\"a1\"],\"imm\":null,"), "{}", json);
+
+        let json = decode_json(0xffffffff);
+        assert!(json.starts_with("{\"illegal\":true,\"fields\":{\"opcode\":127,"), "{}", json);
+    }
+
+    #[test]
+    fn sfence_vma() {
+        let ins = 0x12b50073; // sfence.vma a0, a1
+        let t = System::id(ins).unwrap();
+        assert_eq!(t, System::SfenceVma { rs1: 10, rs2: 11 });
+        assert_eq!(disasm_ins(ins, 0).unwrap(), "sfence.vma a0, a1");
+        let mut csr = Csr::new();
+        let res = t.wr(0x100, 4, &mut [0; 32], &mut [0; 32], &mut csr, &mut Bus::new(vec![]));
+        assert_eq!(res.unwrap(), 0x104);
+        csr.privilege = Privilege::S;
+        let res = t.wr(0x100, 4, &mut [0; 32], &mut [0; 32], &mut csr, &mut Bus::new(vec![]));
+        assert_eq!(res.unwrap(), 0x104);
+        // supervisor only
+        csr.privilege = Privilege::U;
+        let res = t.wr(0x100, 4, &mut [0; 32], &mut [0; 32], &mut csr, &mut Bus::new(vec![]));
+        assert!(matches!(res, Err(Exception::IllegalInstruction(0x12b50073))));
+    }
+
+    #[test]
+    fn x0_writes_dropped() {
+        let mut regs = [0_u64; 32];
+        regs[10] = 3;
+        let (mut csr, mut bus) = (Csr::new(), Bus::new(vec![]));
+        for word in [
+            0x00500013, // addi zero, zero, 5
+            0x12345037, // lui zero, 0x12345
+            0x0000006f, // jal zero, 0
+            0x00a50033, // add zero, a0, a0
+        ] {
+            Rv32i::id(word).unwrap().ex(&regs, &[0; 32]).wr(RAM_BASE, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+            assert_eq!(regs[0], 0);
+        }
+        Rv32m::Mul { rd: 0, rs1: 3, rs2: 3 }.wr(RAM_BASE, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[0], 0);
+        // csrrs zero, misa, zero
+        Zicsr::id(0x30102073).unwrap().ex(&regs, &[0; 32]).wr(RAM_BASE, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[0], 0);
+        assert_eq!(regs[10], 3);
+    }
+
+    #[test]
+    fn sret_returns_to_spp() {
+        let t = System::id(0x10200073).unwrap();
+        assert_eq!(t, System::Sret);
+        assert_eq!(disasm_ins(0x10200073, 0).unwrap(), "sret");
+        let mut csr = Csr::new();
+        csr.privilege = Privilege::S;
+        csr.store(SEPC, 0x200);
+        csr.store(SSTATUS, SSTATUS_SPIE);
+        let res = t.wr(0x100, 4, &mut [0; 32], &mut [0; 32], &mut csr, &mut Bus::new(vec![]));
+        assert_eq!(res.unwrap(), 0x200);
+        // SPP was clear, so the hart drops to U-mode with SIE restored
+        assert_eq!(csr.privilege, Privilege::U);
+        assert_eq!(csr.load(SSTATUS), SSTATUS_SIE | SSTATUS_SPIE);
+        // not available from U-mode
+        let res = t.wr(0x100, 4, &mut [0; 32], &mut [0; 32], &mut csr, &mut Bus::new(vec![]));
+        assert!(matches!(res, Err(Exception::IllegalInstruction(0x10200073))));
+    }
+
+    #[test]
+    fn disasm_sequence() {
+        let bin = to_bin(&[
+            0x02a00f93, // addi t6, zero, 42
+            0x001002b7, // lui t0, 0x100
+            0x0062b423, // sd t1, 8(t0)
+            0xfeb51ae3, // bne a0, a1, -12
+            0xff813503, // ld a0, -8(sp)
+            0x34002573, // csrrs a0, mscratch, zero
+            0x00359593, // slli a1, a1, 3
+            0x02b54633, // div a2, a0, a1
+            0x00000000,
+            0x02a00f93, // addi t6, zero, 42
+        ]);
+        assert_eq!(disasm(&bin, RAM_BASE, true), "\
+80000000: addi t6, zero, 42
+80000004: lui t0, 0x100
+80000008: sd t1, 8(t0)
+8000000c: bne a0, a1, 0x80000000
+80000010: ld a0, -8(sp)
+80000014: csrrs a0, mscratch, zero
+80000018: slli a1, a1, 3
+8000001c: div a2, a0, a1
+80000020: undecodable word 0x00000000 at offset 0x20
+");
+    }
+
+    #[test]
+    fn jalr_rd_eq_rs1() {
+        // jalr ra, 0(ra)
+        let t = Rv32i::id(0x000080e7);
+        assert!(t.is_ok(), "Failed to parse instruction: {:?}", t.err().unwrap());
+        assert_eq!(t.as_ref().unwrap(), &Rv32i::Jalr { rd: 1, rs1: 1, imm: 0 });
+        let mut regs = [0_u64; 32];
+        regs[1] = 0x8000_0100;
+        let t = t.unwrap().ex(&regs, &[0; 32]);
+        let res = t.wr(0x8000_0040, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![]));
+        assert!(res.is_ok(), "Execution failed: {:?}", res.err().unwrap());
+        // the target uses the base captured before the link register is written
+        assert_eq!(res.unwrap(), 0x8000_0100);
+        assert_eq!(regs[1], 0x8000_0044);
+    }
+
+    #[test]
+    fn lw_misaligned() {
+        let lw = Rv32i::Lw { rd: 1, rs1: RAM_BASE, imm: 1 };
+        let mut regs = [0_u64; 32];
+        let res = lw.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::with_strict_align(vec![], true));
+        assert!(matches!(res, Err(Exception::LoadAccessMisaligned(addr)) if addr == RAM_BASE + 1));
+        let res = lw.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::with_strict_align(vec![], false));
+        assert!(res.is_ok(), "Execution failed: {:?}", res.err().unwrap());
+    }
+
+    #[test]
+    fn lwu_zero_extends_a_word() {
+        let mut bus = Bus::with_strict_align(vec![], true);
+        bus.store(RAM_BASE, crate::mem::B64, 0x1122_3344_8765_4321).unwrap();
+        let mut regs = [0_u64; 32];
+        Rv64i::Lwu { rd: 1, rs1: RAM_BASE, imm: 0 }.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut bus).unwrap();
+        assert_eq!(regs[1], 0x8765_4321);
+        // word aligned is enough, and only the word is read
+        Rv64i::Lwu { rd: 1, rs1: RAM_BASE, imm: 4 }.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut bus).unwrap();
+        assert_eq!(regs[1], 0x1122_3344);
+    }
+
+    #[test]
+    fn sw_misaligned() {
+        let sw = Rv32i::Sw { rs1: RAM_BASE, rs2: 42, imm: 2 };
+        let mut regs = [0_u64; 32];
+        let res = sw.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::with_strict_align(vec![], true));
+        assert!(matches!(res, Err(Exception::StoreAMOAddrMisaligned(addr)) if addr == RAM_BASE + 2));
+        let res = sw.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::with_strict_align(vec![], false));
+        assert!(res.is_ok(), "Execution failed: {:?}", res.err().unwrap());
+    }
+
+    #[test]
+    fn pc_wraps() {
+        // jal ra, 8 from the last word of the address space
+        let jal = Rv32i::Jal { rd: 1, imm: 8 };
+        let mut regs = [0_u64; 32];
+        let res = jal.wr(u64::MAX - 3, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![]));
+        assert_eq!(res.unwrap(), 4);
+        assert_eq!(regs[1], 0);
+    }
+
+    #[test]
+    fn s_imm_max_offset() {
+        assert_eq!(s_imm(0x7e112fa3), 0x7ff); // sw ra, 2047(sp)
+        assert_eq!(s_imm(0x80112023), -2048_i64 as u64); // sw ra, -2048(sp)
+        // the same offset encoded as a load immediate
+        assert_eq!(s_imm(0x7e112fa3), i_imm(0x7ff12083)); // lw ra, 2047(sp)
+    }
+
+    #[test]
+    fn i_imm_sign_extends_to_64() {
+        assert_eq!(i_imm(0xfff00093), u64::MAX); // addi ra, zero, -1
+        assert_eq!(i_imm(0x7ff00093), 0x7ff); // addi ra, zero, 2047
+        assert_eq!(i_imm(0x80000093), -2048_i64 as u64); // addi ra, zero, -2048
+        let mut regs = [0_u64; 32];
+        let addi = Rv32i::id(0xfff00093).unwrap().ex(&regs, &[0; 32]);
+        addi.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![])).unwrap();
+        assert_eq!(regs[1], u64::MAX);
+    }
+
+    #[test]
+    fn srai_negative() {
+        let mut regs = [0_u64; 32];
+        regs[2] = -40_i64 as u64;
+        let srai = Rv32i::id(0x40315093).unwrap().ex(&regs, &[0; 32]); // srai ra, sp, 3
+        srai.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![])).unwrap();
+        assert_eq!(regs[1], -5_i64 as u64);
+
+        regs[2] = 0x8000_0000_0000_0000;
+        let srai = Rv32i::id(0x42315093).unwrap().ex(&regs, &[0; 32]); // srai ra, sp, 35
+        srai.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![])).unwrap();
+        assert_eq!(regs[1], 0xffff_ffff_f000_0000);
+    }
+
+    #[test]
+    fn wide_shift_amounts() {
+        let mut regs = [0_u64; 32];
+        regs[2] = 1;
+        let slli = Rv32i::id(0x02811093).unwrap().ex(&regs, &[0; 32]); // slli ra, sp, 40
+        slli.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![])).unwrap();
+        assert_eq!(regs[1], 1 << 40);
+        let slli = Rv32i::id(0x03f11093).unwrap().ex(&regs, &[0; 32]); // slli ra, sp, 63
+        slli.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![])).unwrap();
+        assert_eq!(regs[1], 1 << 63);
+
+        regs[2] = u64::MAX;
+        let srli = Rv32i::id(0x03f15093).unwrap().ex(&regs, &[0; 32]); // srli ra, sp, 63
+        srli.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![])).unwrap();
+        assert_eq!(regs[1], 1);
+
+        // register shifts use the low 6 bits of rs2
+        regs[2] = 1;
+        regs[3] = 64 + 40;
+        let sll = Rv32i::id(0x003110b3).unwrap().ex(&regs, &[0; 32]); // sll ra, sp, gp
+        sll.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![])).unwrap();
+        assert_eq!(regs[1], 1 << 40);
+
+        let slliw = Rv64i::id(0x01f1109b).unwrap().ex(&regs, &[0; 32]); // slliw ra, sp, 31
+        slliw.wr(0, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![])).unwrap();
+        assert_eq!(regs[1], 0xffff_ffff_8000_0000);
+    }
+
+    #[test]
+    fn misaligned_jump() {
+        let mut rv64i = Csr::new();
+        rv64i.store(MISA, rv64i.load(MISA) & !MISA_C);
+        let mut regs = [0_u64; 32];
+        let res = Rv32i::Jalr { rd: 1, rs1: RAM_BASE, imm: 2 }.wr(RAM_BASE + 4, 4, &mut regs, &mut [0; 32], &mut rv64i, &mut Bus::new(vec![]));
+        assert!(matches!(res, Err(Exception::InstructionAddrMisaligned(addr)) if addr == RAM_BASE + 2));
+        // the link register is not written when the jump faults
+        assert_eq!(regs[1], 0);
+        let res = Rv32i::Beq { rs1: 0, rs2: 0, imm: 6 }.wr(RAM_BASE, 4, &mut regs, &mut [0; 32], &mut rv64i, &mut Bus::new(vec![]));
+        assert!(matches!(res, Err(Exception::InstructionAddrMisaligned(addr)) if addr == RAM_BASE + 6));
+
+        // with C enabled, 2-byte aligned targets are legal
+        let res = Rv32i::Jalr { rd: 1, rs1: RAM_BASE, imm: 2 }.wr(RAM_BASE + 4, 4, &mut regs, &mut [0; 32], &mut Csr::new(), &mut Bus::new(vec![]));
+        assert_eq!(res.unwrap(), RAM_BASE + 2);
+        assert_eq!(regs[1], RAM_BASE + 8);
+    }
+
+    #[test]
+    fn addi_fields() {
+        // addi a0, a1, -3
+        assert_eq!(decode_fields(0xffd58513), DecodeFields {
+            opcode: 0b0010011,
+            funct3: 0,
+            funct7: 0b1111111,
+            rd: 10,
+            rs1: 11,
+            rs2: 29,
+            i_imm: -3,
+            s_imm: -22,
+            b_imm: -2070,
+            u_imm: -0x2a8000,
+            j_imm: -0xa7004,
+        });
+    }
+
+    #[test]
+    fn immediates() {
+        // addi a0, a1, -3
+        assert_eq!(Rv32i::id(0xffd58513).unwrap().imm(), Some(-3));
+        // lw a0, 8(sp)
+        assert_eq!(Rv32i::id(0x00812503).unwrap().imm(), Some(8));
+        // lui a0, 0xfffff
+        assert_eq!(Rv32i::id(0xfffff537).unwrap().imm(), Some(-4096));
+        // add a0, a0, a1
+        assert_eq!(Rv32i::id(0x00b50533).unwrap().imm(), None);
+        // sd a1, -8(sp)
+        assert_eq!(Rv64i::id(0xfeb13c23).unwrap().imm(), Some(-8));
+        // addw a0, a0, a1
+        assert_eq!(Rv64i::id(0x00b5053b).unwrap().imm(), None);
+    }
+
+    #[test]
+    fn slt_matrix() {
+        let values: [i64; 11] = [0, 1, -1, 2047, -2048, i32::MAX as i64, i32::MIN as i64, 0xffff_ffff, i64::MAX, i64::MIN, 0x1_0000_0000];
+        let imms: [i64; 6] = [0, 1, -1, 2047, -2048, -2];
+        let mut bus = Bus::with_layout(vec![], RAM_BASE, 64);
+        for xlen in [32, 64] {
+            let mut csr = Csr::new();
+            csr.set_xlen(xlen);
+            // registers only ever hold XLEN bits
+            let reg = |v: i64| if xlen == 32 { v as u64 & 0xffff_ffff } else { v as u64 };
+            let lt = |a: i64, b: i64| if xlen == 32 { (a as i32) < (b as i32) } else { a < b };
+            let ltu = |a: i64, b: i64| if xlen == 32 { (a as u32) < (b as u32) } else { (a as u64) < (b as u64) };
+            let mut run = |ins: Rv32i, a: i64, b: i64| {
+                let mut regs = [0_u64; 32];
+                regs[1] = reg(a);
+                regs[2] = reg(b);
+                ins.ex(&regs, &[0; 32]).wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+                regs[3]
+            };
+            for a in values {
+                for b in values {
+                    assert_eq!(run(Rv32i::Slt { rd: 3, rs1: 1, rs2: 2 }, a, b), lt(a, b) as u64, "slt {} {} at {}", a, b, xlen);
+                    assert_eq!(run(Rv32i::Sltu { rd: 3, rs1: 1, rs2: 2 }, a, b), ltu(a, b) as u64, "sltu {} {} at {}", a, b, xlen);
+                }
+                for imm in imms {
+                    assert_eq!(run(Rv32i::Slti { rd: 3, rs1: 1, imm: imm as u64 }, a, 0), lt(a, imm) as u64, "slti {} {} at {}", a, imm, xlen);
+                    assert_eq!(run(Rv32i::Sltiu { rd: 3, rs1: 1, imm: imm as u64 }, a, 0), ltu(a, imm) as u64, "sltiu {} {} at {}", a, imm, xlen);
+                }
+            }
+        }
+    }
 }
\ No newline at end of file
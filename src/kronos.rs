@@ -1,121 +1,344 @@
-use std::fmt::Display;
+use std::io::Write;
 
-use crate::{bus::{Bus, RAM_END, RAM_BASE}, stats::Stats, mem::B64, isa::{Rv32i, Extension, Rv64i}, exception::Exception};
+use crate::{bus::Bus, stats::{Stats, InsMix, Profile}, isa::{Extension, FuncUnit, DEP_REG_COUNT}, decode::DecodeCache, exception::Exception, soc::{SoC, Config, ExecObserver, Hart, Datapath, fetch, DepNode}, csr::Csr};
 
 /*
 An out-of-order, infinite-fetch, infinite-issue single-stage processor
 */
 
 struct HistItem {
+    pc: u64,
     src_regs: Vec<u64>,
     dst_reg: Option<u64>,
-    blocking: bool
+    blocking: bool,
+    latency: usize,
+    unit: FuncUnit
 }
 
 pub struct KronosSoC {
     pub regs: [u64; 32],
+    /// Float registers, holding NaN-boxed singles
+    pub fregs: [u64; 32],
     pub pc: u64,
     pub bus: Bus,
+    pub csr: Csr,
     pub stats: Stats,
-    hist: Vec<HistItem>
+    /// Executed instructions by mnemonic
+    pub mix: InsMix,
+    /// Executions of each pc
+    pub profile: Profile,
+    pub config: Config,
+    pub trace_out: Box<dyn Write>,
+    pub observer: Option<Box<dyn ExecObserver>>,
+    /// Issue ports per functional unit, indexed by FuncUnit. Only the
+    /// multiplier and divider are limited by default.
+    pub ports: [usize; FuncUnit::COUNT],
+    /// Instructions already decoded, so that loops skip the decoder
+    decoded: DecodeCache,
+    hist: Vec<HistItem>,
+    /// Issue cycle of each history item, filled in by calc_stats
+    issued: Vec<usize>
 }
 
 type Result = std::result::Result<(), Exception>;
 
 impl KronosSoC {
-    pub fn new(bin: Vec<u8>) -> Self {
-        let mut regs = [0_u64; 32];
-        regs[2] = RAM_END;
-        let pc = RAM_BASE;
-        let bus = Bus::new(bin);
-        let stats = Stats::new();
-        let hist = Vec::new();
-        Self { regs, pc, bus, stats, hist }
+    fn hart(&mut self) -> Hart<'_> {
+        Hart {
+            regs: &mut self.regs, fregs: &mut self.fregs, pc: &mut self.pc, bus: &mut self.bus, csr: &mut self.csr,
+            stats: &mut self.stats, mix: &mut self.mix, profile: &mut self.profile, config: &self.config,
+            trace_out: &mut self.trace_out, observer: &mut self.observer
+        }
     }
 
-    pub fn pipeline(&mut self) -> Result {
-        let ins = self.bus.load(self.pc, B64)? as u32;
-        if let Ok(ins) = Rv32i::id(ins) {
-            self.datapath(ins)
-        } else if let Ok(ins) = Rv64i::id(ins) {
-            self.datapath(ins)
-        } else {
-            Err(Exception::IllegalInstruction(ins as u64))
-        }
+    fn intersect<'a, T: PartialEq>(a: &'a [T], b: &'a [T]) -> Vec<&'a T> {
+        a.iter()
+            .filter(|item| b.contains(&item))
+            .collect()
     }
+}
 
-    pub fn datapath<O: Extension + Display>(&mut self, i: O) -> Result {
+impl Datapath for KronosSoC {
+    fn datapath<O: Extension>(&mut self, word: u32, i: O) -> Result {
+        let pc = self.pc;
         let record = HistItem { 
+            pc,
             src_regs: i.src_regs(), 
             dst_reg: i.dst_reg(), 
-            blocking: i.is_ld() || i.is_st()
+            blocking: i.is_ld() || i.is_st(),
+            latency: i.latency(),
+            unit: i.func_unit()
         };
-        let ins_ex = i.ex(&self.regs);
-        if ins_ex.is_ld() || ins_ex.is_st() {
-            self.stats.mem_ops += 1;
-        } else {
-            self.stats.alu_ops += 1;
-        }
-        self.regs[0] = 0;
-        self.pc = ins_ex.wr(self.pc, &mut self.regs, &mut self.bus)?;
-        self.regs[0] = 0;
+        self.hart().commit(word, i)?;
         self.hist.push(record);
         Ok(())
     }
+}
 
-    fn intersect<'a, T: PartialEq>(a: &'a [T], b: &'a [T]) -> Vec<&'a T> {
-        a.iter()
-            .filter(|item| b.contains(&item))
-            .collect()
+impl SoC for KronosSoC {
+    fn with_entry(bin: Vec<u8>, entry: u64) -> Self {
+        let bus = Bus::new(bin);
+        let mut regs = [0_u64; 32];
+        regs[2] = bus.base + bus.size - 1;
+        let pc = entry;
+        let csr = Csr::new();
+        let stats = Stats::new();
+        let mix = InsMix::new();
+        let profile = Profile::new();
+        let mut ports = [usize::MAX; FuncUnit::COUNT];
+        ports[FuncUnit::Mul as usize] = 1;
+        ports[FuncUnit::Div as usize] = 1;
+        let hist = Vec::new();
+        Self { regs, fregs: [0; 32], pc, bus, csr, stats, mix, profile, config: Config { entry, ..Config::default() }, trace_out: Box::new(std::io::stdout()), observer: None, decoded: DecodeCache::new(), ports, hist, issued: Vec::new() }
+    }
+
+    fn pipeline(&mut self) -> Result {
+        let (word, ins) = fetch(&mut self.bus, &self.csr, self.pc, self.config.enabled_exts, Some(&mut self.decoded))?;
+        self.dispatch(word, ins)
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn pc_mut(&mut self) -> &mut u64 {
+        &mut self.pc
+    }
+
+    fn regfile(&self) -> &[u64; 32] {
+        &self.regs
+    }
+
+    fn regfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.regs
+    }
+
+    fn fregfile(&self) -> &[u64; 32] {
+        &self.fregs
+    }
+
+    fn fregfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.fregs
+    }
+
+    fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    fn csr(&mut self) -> &mut Csr {
+        &mut self.csr
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    fn mix(&self) -> &InsMix {
+        &self.mix
+    }
+
+    fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    fn reset(&mut self, bin: Vec<u8>) {
+        self.hart().reset(&bin);
+        self.decoded.clear();
+        self.hist.clear();
+        self.issued.clear();
     }
 
     fn calc_stats(&mut self) {
+        // only instructions that completed are recorded in the history
+        self.stats.retired = self.hist.len();
         let mut cycles = 0;
-        let mut stalls = 0;
+        let (mut mem_stalls, mut raw_stalls, mut structural_stalls) = (0, 0, 0);
         // 1. starting from the top of the hist:
         // 2. an instruction is executed if all src regs are available
+        //    and the producing instruction's latency has elapsed
+        //    and a port of its functional unit is free this cycle
         // 3. the instructions's dst regs are then added to the occupied list
         // 4. the instruction is removed from the history
         // 5. if we encounter the end of the list or a branch, we stop
         // 6. increment cycles and go to 1
         let mut executed = vec![false; self.hist.len()];
+        let mut issued = vec![0; self.hist.len()];
+        let mut ready = [0_usize; DEP_REG_COUNT];
         'cycle: loop {
             cycles += 1;
             let mut occupied_regs = Vec::new();
+            let mut busy = [0_usize; FuncUnit::COUNT];
             let iter = executed.iter_mut().enumerate()
                 .filter(|(_, done)| !**done);
             for (i, done) in iter {
-                if Self::intersect(&self.hist[i].src_regs, &occupied_regs).is_empty() {
-                    // we can execute this op
-                    *done = true;
+                let item = &self.hist[i];
+                let raw = !Self::intersect(&item.src_regs, &occupied_regs).is_empty()
+                    || item.src_regs.iter().any(|r| ready[*r as usize] > cycles);
+                if !raw {
+                    if busy[item.unit as usize] < self.ports[item.unit as usize] {
+                        // we can execute this op
+                        busy[item.unit as usize] += 1;
+                        *done = true;
+                        issued[i] = cycles;
+                        if let Some(dst) = item.dst_reg {
+                            ready[dst as usize] = cycles + item.latency;
+                        }
+                    } else {
+                        // ready, but every port of its unit is taken
+                        structural_stalls += 1;
+                    }
                 }
                 if let Some(dst) = self.hist[i].dst_reg {
                     occupied_regs.push(dst);
                 }
                 if self.hist[i].blocking {
-                    stalls += 1;
+                    // only loads and stores close the window
+                    if raw {
+                        raw_stalls += 1;
+                    } else {
+                        mem_stalls += 1;
+                    }
                     continue 'cycle;
                 }
             }
             if executed.iter().all(|e| *e) {
-                self.stats.cycles = cycles;
-                self.stats.stalls = stalls;
+                self.stats.cycles = cycles + self.stats.page_walk_cycles;
+                self.stats.stalls = mem_stalls + raw_stalls;
+                self.stats.mem_stalls = mem_stalls;
+                self.stats.raw_stalls = raw_stalls;
+                self.stats.structural_stalls = structural_stalls;
+                self.issued = issued;
                 break;
             }
         }
     }
 
-    pub fn execute(&mut self) -> Exception {
-        loop {
-            // execute instruction, add dst registers to dependents
-            // don't execute beyond branch
-            match self.pipeline() {
-                Ok(_) => {},
-                Err(ex) => if ex.is_fatal() {
-                    self.calc_stats();
-                    return ex
-                },
+    fn dep_graph(&self) -> Option<Vec<DepNode>> {
+        // the most recent writer of each register
+        let mut writer: [Option<usize>; DEP_REG_COUNT] = [None; DEP_REG_COUNT];
+        let nodes = self.hist.iter().zip(&self.issued).enumerate().map(|(i, (item, cycle))| {
+            let mut deps: Vec<usize> = item.src_regs.iter()
+                .filter_map(|r| writer[*r as usize])
+                .collect();
+            deps.sort();
+            deps.dedup();
+            if let Some(dst) = item.dst_reg.filter(|r| *r != 0) {
+                writer[dst as usize] = Some(i);
             }
+            DepNode { pc: item.pc, cycle: *cycle, deps }
+        });
+        Some(nodes.collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::{to_bin, FuncUnit, Exts}, soc::{SoC, DepNode, dep_dot}, rvm::DIV_LATENCY, bus::RAM_BASE, decode::decode, mem::B32};
+    use super::KronosSoC;
+
+    #[test]
+    fn decode_cache_sees_stores() {
+        let mut cpu = KronosSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x00150513, // addi a0, a0, 1
+            0x01c2a303, // lw t1, 28(t0)
+            0x0062a223, // sw t1, 4(t0)
+            0x0015c593, // xori a1, a1, 1
+            0xfe0598e3, // bne a1, zero, -16
+            0x00000073, // ecall
+            0x06450513, // addi a0, a0, 100
+        ]));
+        cpu.execute();
+        // the second trip round runs the patched addi, not the cached one
+        assert_eq!(cpu.regs[10], 101);
+        assert_eq!(cpu.decoded.hits, 4);
+        for pc in (RAM_BASE..RAM_BASE + 28).step_by(4) {
+            let word = cpu.bus.load(pc, B32).unwrap() as u32;
+            assert_eq!(cpu.decoded.get(pc), decode(word, true, Exts::ALL).ok());
         }
     }
+
+    fn cycles(prog: &[u32]) -> usize {
+        let mut cpu = KronosSoC::new(to_bin(prog));
+        cpu.execute();
+        cpu.stats.cycles
+    }
+
+    #[test]
+    fn div_latency() {
+        let independent = cycles(&[
+            0x02b54633, // div a2, a0, a1
+            0x00a506b3, // add a3, a0, a0
+        ]);
+        let dependent = cycles(&[
+            0x02b54633, // div a2, a0, a1
+            0x00c606b3, // add a3, a2, a2
+        ]);
+        assert_eq!(independent, 1);
+        assert_eq!(dependent, 1 + DIV_LATENCY);
+    }
+    #[test]
+    fn raw_chain_edges() {
+        let mut cpu = KronosSoC::new(to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00a505b3, // add a1, a0, a0
+            0x00a58633, // add a2, a1, a0
+        ]));
+        cpu.execute();
+        let nodes = cpu.dep_graph().unwrap();
+        assert_eq!(nodes, [
+            DepNode { pc: RAM_BASE, cycle: 1, deps: vec![] },
+            DepNode { pc: RAM_BASE + 4, cycle: 2, deps: vec![0] },
+            DepNode { pc: RAM_BASE + 8, cycle: 3, deps: vec![0, 1] },
+        ]);
+        let dot = dep_dot(&nodes);
+        assert!(dot.starts_with("digraph deps {\n    n0 [label=\"0: 0x80000000\\ncycle 1\"];\n"));
+        assert!(dot.contains("    n0 -> n2;\n    n1 -> n2;\n"));
+    }
+    #[test]
+    fn stall_breakdown() {
+        let mut cpu = KronosSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x0402b503, // ld a0, 64(t0)
+            0x04a2b423, // sd a0, 72(t0)
+            0x00100593, // addi a1, zero, 1
+            0x04b2b823, // sd a1, 80(t0)
+        ]));
+        cpu.execute();
+        // ld and both sds each wait a cycle on their base or data register,
+        // then close the window once more when they issue
+        let stats = &cpu.stats;
+        assert_eq!(stats.stalls, 6);
+        assert_eq!((stats.mem_stalls, stats.raw_stalls, stats.branch_stalls), (3, 3, 0));
+    }
+
+    #[test]
+    fn mul_port_contention() {
+        let bin = to_bin(&[
+            0x02b50633, // mul a2, a0, a1
+            0x02d706b3, // mul a3, a4, a3
+        ]);
+        let mut cpu = KronosSoC::new(bin.clone());
+        cpu.execute();
+        assert_eq!(cpu.stats.cycles, 2);
+        assert_eq!(cpu.stats.structural_stalls, 1);
+
+        let mut cpu = KronosSoC::new(bin);
+        cpu.ports[FuncUnit::Mul as usize] = 2;
+        cpu.execute();
+        assert_eq!(cpu.stats.cycles, 1);
+        assert_eq!(cpu.stats.structural_stalls, 0);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,57 @@
+//! RISC-V models at several levels of microarchitectural detail, from the
+//! functional Dart to the dual-issue Gemini, sharing one decoder and bus.
+//!
+//! ```
+//! use mur::{DartSoC, SoC, Exit, Exception};
+//!
+//! let bin: Vec<u8> = [
+//!     0x02a00513_u32, // addi a0, zero, 42
+//!     0x00a50593,     // addi a1, a0, 10
+//! ].iter().flat_map(|i| i.to_le_bytes()).collect();
+//! let mut cpu = DartSoC::new(bin);
+//! // the zeroed RAM after the program decodes as an illegal instruction
+//! assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0))));
+//! assert_eq!(cpu.regs[11], 52);
+//! assert_eq!(cpu.stats.retired, 2);
+//! ```
+
+pub mod mem;
+pub mod bus;
+pub mod device;
+pub mod isa;
+pub mod decode;
+pub mod exception;
+pub mod dart;
+pub mod zeus;
+pub mod kronos;
+pub mod atlas;
+pub mod stats;
+pub mod soc;
+pub mod csr;
+pub mod rvm;
+pub mod rva;
+pub mod rvb;
+pub mod rvf;
+pub mod rvc;
+pub mod bench;
+pub mod gdbstub;
+pub mod hermes;
+pub mod gemini;
+pub mod reference;
+pub mod replay;
+pub mod error;
+
+pub use dart::DartSoC;
+pub use zeus::ZeusSoC;
+pub use kronos::KronosSoC;
+pub use atlas::AtlasSoC;
+pub use hermes::HermesSoC;
+pub use gemini::GeminiSoC;
+pub use reference::ReferenceSoC;
+pub use soc::{SoC, Exit, Config};
+pub use isa::Extension;
+pub use bus::Bus;
+pub use mem::Mem;
+pub use exception::Exception;
+pub use stats::Stats;
+pub use error::MurError;
\ No newline at end of file
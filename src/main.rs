@@ -1,66 +1,528 @@
-use std::{path::PathBuf, fs::File, io::Read};
+use std::{path::{Path, PathBuf}, fs::File, io::Read};
 
 use clap::Parser;
-use dart::DartSoC;
-
-use crate::{isa::print_register_table, zeus::ZeusSoC, kronos::KronosSoC, atlas::AtlasSoC};
-
-mod mem;
-mod bus;
-mod isa;
-mod exception;
-mod dart;
-mod zeus;
-mod kronos;
-mod atlas;
-mod stats;
+use mur::{dart::DartSoC, gdbstub, isa::{register_table, diff_regs, diff_table, format_regs, parse_regs, disasm, parse_march_arg, Exts}, bus::{RAM_BASE, FINISHER_BASE, FINISHER_SIZE, parse_watch_arg, parse_addr_arg, parse_dump_arg, parse_entry_arg, parse_text_check_arg, TextCheck, Bus}, device::{Htif, HTIF_SIZE, Finisher}, zeus::ZeusSoC, kronos::KronosSoC, atlas::AtlasSoC, hermes::HermesSoC, gemini::GeminiSoC, reference::{ReferenceSoC, lockstep}, soc::{SoC, Exit, Snapshot, dep_dot}, stats::{Stats, comparison}, csr::{parse_csr_arg, csr_table, MISA, MISA_A, MISA_C, MISA_F, MISA_M}, bench::{bench, bench_with, BenchReport}, replay::{recorder, format_log, parse_log, replay}, error::MurError};
 
 #[derive(clap::Parser)]
 struct Args {
     path: PathBuf,
     #[arg(long, default_value="all")]
-    soc: String
+    soc: String,
+    /// Stop the run after this many cycles
+    #[arg(long)]
+    timeout_cycles: Option<usize>,
+    /// Stop the run after this many instructions retire
+    #[arg(long)]
+    max_insns: Option<usize>,
+    /// Stop when the program loops this many times without changing a
+    /// register, as with a branch to itself
+    #[arg(long)]
+    livelock: Option<usize>,
+    /// Extra cycles each load and store takes on dart
+    #[arg(long, default_value_t = 0)]
+    mem_latency: usize,
+    /// Set a CSR before running, e.g. --csr mtvec=0x80001000
+    #[arg(long, value_parser = parse_csr_arg)]
+    csr: Vec<(u64, u64)>,
+    /// Print the named CSRs after the run
+    #[arg(long)]
+    dump_csr: bool,
+    /// Disassemble the binary instead of running it
+    #[arg(long)]
+    disasm: bool,
+    /// Report host wall-clock time and emulated MIPS
+    #[arg(long)]
+    bench: bool,
+    /// Print a line for every executed instruction
+    #[arg(long)]
+    trace: bool,
+    /// Leave out per-instruction output, keeping the final tables
+    #[arg(long, conflicts_with = "trace")]
+    quiet: bool,
+    /// Register width, 32 or 64
+    #[arg(long, default_value_t = 64)]
+    xlen: u32,
+    /// Register width and extensions to decode, e.g. rv64imac_zbb. The
+    /// rest raise illegal instruction. Everything is enabled by default.
+    #[arg(long, value_parser = parse_march_arg, conflicts_with = "xlen")]
+    march: Option<(u32, Exts)>,
+    /// Report format, "table" or "json"
+    #[arg(long, default_value="table")]
+    format: String,
+    /// Wait for a GDB connection on this port instead of running
+    #[arg(long)]
+    gdb: Option<u16>,
+    /// Report stores to a range, e.g. --watch 0x80001000:4
+    #[arg(long, value_parser = parse_watch_arg)]
+    watch: Vec<(u64, u64)>,
+    /// Write the memory between --sig-begin and --sig-end to this file at exit
+    #[arg(long, requires_all = ["sig_begin", "sig_end"])]
+    signature: Option<PathBuf>,
+    /// Start of the signature region (begin_signature)
+    #[arg(long, value_parser = parse_addr_arg)]
+    sig_begin: Option<u64>,
+    /// End of the signature region (end_signature)
+    #[arg(long, value_parser = parse_addr_arg)]
+    sig_end: Option<u64>,
+    /// Address of the riscv-tests tohost symbol, ends the run when written
+    #[arg(long, value_parser = parse_addr_arg)]
+    tohost: Option<u64>,
+    /// Write memory to a file at exit, e.g. --dump-mem ram.bin:0x80000000:4096
+    #[arg(long, value_parser = parse_dump_arg)]
+    dump_mem: Vec<(PathBuf, u64, u64)>,
+    /// Restore registers, CSRs and RAM from a snapshot file before running
+    #[arg(long)]
+    restore: Option<PathBuf>,
+    /// Save registers, CSRs and RAM to a snapshot file at exit
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+    /// Write the scheduled dependency graph as Graphviz DOT (kronos, atlas)
+    #[arg(long)]
+    dep_graph: Option<PathBuf>,
+    /// Run alongside the functional reference model and report the first
+    /// instruction where the registers or pc differ
+    #[arg(long)]
+    diff: bool,
+    /// Start executing at this address instead of the start of RAM
+    #[arg(long, value_parser = parse_entry_arg, default_value_t = RAM_BASE)]
+    entry: u64,
+    /// Set up argc, argv and envp on the stack as for a Unix process entry,
+    /// with the binary's path as argv[0]
+    #[arg(long)]
+    init_stack: bool,
+    /// Append an argument to argv, for --init-stack
+    #[arg(long, requires = "init_stack")]
+    arg: Vec<String>,
+    /// Add a KEY=VALUE environment string to envp, for --init-stack
+    #[arg(long, requires = "init_stack")]
+    env: Vec<String>,
+    /// Print the most executed pcs at exit, 10 unless a count is given
+    #[arg(long, num_args = 0..=1, default_missing_value = "10")]
+    profile: Option<usize>,
+    /// Report add, sub and addi instructions whose result overflowed as a
+    /// signed value
+    #[arg(long)]
+    record_overflow: bool,
+    /// Map a SiFive test finisher at 0x100000, as used by QEMU virt
+    /// bare-metal tests
+    #[arg(long)]
+    finisher: bool,
+    /// Check stores into the loaded program: "off", "warn" to report them,
+    /// or "strict" to fault
+    #[arg(long, value_parser = parse_text_check_arg, default_value = "off")]
+    text_check: TextCheck,
+    /// Fetch from a separate instruction memory, so stores only reach the
+    /// data memory. Both start out holding the program.
+    #[arg(long)]
+    harvard: bool,
+    /// Log the pc and word of every retired instruction to this file
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Re-run a log written by --record and report the first instruction
+    /// that differs from it
+    #[arg(long, conflicts_with_all = ["record", "diff", "gdb"])]
+    replay: Option<PathBuf>,
+    /// List the registers that changed since the last --reg-diff run of
+    /// the same binary, whose registers are kept in <path>.regs
+    #[arg(long)]
+    reg_diff: bool
+}
+
+/// Every model, in the order --soc all reports them
+const SOCS: [&str; 6] = ["dart", "zeus", "kronos", "atlas", "hermes", "gemini"];
+
+fn new_soc(soc: &str, bin: Vec<u8>, entry: u64) -> Result<(&'static str, Box<dyn SoC>), MurError> {
+    Ok(match soc {
+        "dart" => ("Dart", Box::new(DartSoC::with_entry(bin, entry))),
+        "zeus" => ("Zeus", Box::new(ZeusSoC::with_entry(bin, entry))),
+        "kronos" => ("Kronos", Box::new(KronosSoC::with_entry(bin, entry))),
+        "atlas" => ("Atlas", Box::new(AtlasSoC::with_entry(bin, entry))),
+        "hermes" => ("Hermes", Box::new(HermesSoC::with_entry(bin, entry))),
+        "gemini" => ("Gemini", Box::new(GeminiSoC::with_entry(bin, entry))),
+        _ => return Err(MurError::UnknownSoc(soc.to_string()))
+    })
+}
+
+/// Applies the command line configuration to a freshly built SoC. Only
+/// one model should echo the tohost console when several run.
+fn setup(cpu: &mut dyn SoC, args: &Args, console: bool) -> Result<(), MurError> {
+    // first, while memory holds nothing but the loaded image. The image
+    // carries data as well as code, so both memories get a copy.
+    if args.harvard {
+        let bus = cpu.bus();
+        let image = match (bus.text, bus.ram()) {
+            (Some((start, end)), Some(ram)) => ram[(start - bus.base) as usize..=(end - bus.base) as usize].to_vec(),
+            _ => Vec::new(),
+        };
+        *cpu.bus_mut() = Bus::harvard(image.clone(), image);
+    }
+    cpu.config_mut().timeout_cycles = args.timeout_cycles;
+    cpu.config_mut().max_insns = args.max_insns;
+    cpu.config_mut().livelock = args.livelock;
+    cpu.config_mut().mem_latency = args.mem_latency;
+    cpu.config_mut().trace = args.trace;
+    cpu.config_mut().record_overflow = args.record_overflow;
+    let (xlen, exts) = args.march.unwrap_or((args.xlen, Exts::ALL));
+    cpu.csr().set_xlen(xlen);
+    cpu.config_mut().enabled_exts = exts;
+    // misa reports the same extensions, which also decides jump alignment
+    let misa = cpu.csr().load(MISA) & !(MISA_M | MISA_A | MISA_F | MISA_C);
+    cpu.csr().store(MISA, misa | exts.misa());
+    for (addr, value) in &args.csr {
+        cpu.csr().store(*addr, *value);
+    }
+    if args.init_stack {
+        let argv: Vec<String> = std::iter::once(args.path.display().to_string()).chain(args.arg.iter().cloned()).collect();
+        cpu.init_stack(&argv, &args.env).map_err(|e| MurError::Config(format!("stack setup failed: {:?}", e)))?;
+    }
+    if let Some(path) = &args.restore {
+        let snapshot = Snapshot::from_bytes(&std::fs::read(path)?)
+            .ok_or(MurError::Decode(format!("{} is not a valid snapshot", path.display())))?;
+        if snapshot.ram.as_ref().map(|ram| ram.len() as u64) != cpu.bus().ram().map(|ram| ram.len() as u64) {
+            return Err(MurError::Config(format!("{} was taken with a different RAM size", path.display())))
+        }
+        cpu.restore(&snapshot);
+    }
+    if let Some(addr) = args.tohost {
+        let out: Box<dyn std::io::Write> = if console { Box::new(std::io::stdout()) } else { Box::new(std::io::sink()) };
+        cpu.bus_mut().map(addr, HTIF_SIZE, Box::new(Htif::new(out)));
+    }
+    cpu.bus_mut().text_check = args.text_check;
+    if args.finisher {
+        cpu.bus_mut().map(FINISHER_BASE, FINISHER_SIZE, Box::new(Finisher::default()));
+    }
+    for (addr, size) in &args.watch {
+        cpu.bus_mut().add_watchpoint(*addr, *size);
+    }
+    Ok(())
+}
+
+/// Runs every model on the same binary and compares their stats
+fn run_all(args: &Args, bin: Vec<u8>) -> Result<(), MurError> {
+    if args.diff || args.gdb.is_some() || args.signature.is_some() || !args.dump_mem.is_empty()
+        || args.snapshot.is_some() || args.dep_graph.is_some() || args.record.is_some() || args.replay.is_some()
+        || args.reg_diff {
+        return Err(MurError::Config("--soc all cannot be combined with --diff, --gdb, --replay or per-model outputs".to_string()))
+    }
+    let mut runs = Vec::new();
+    for (i, soc) in SOCS.iter().enumerate() {
+        let (name, mut cpu) = new_soc(soc, bin.clone(), args.entry).unwrap();
+        setup(cpu.as_mut(), args, i == 0)?;
+        let ex = cpu.execute();
+        runs.push((name, ex, cpu));
+    }
+    if args.format == "json" {
+        let reports: Vec<String> = runs.iter_mut()
+            .map(|(name, ex, cpu)| format!("\"{}\":{}", name.to_lowercase(), cpu.json_report(*ex)))
+            .collect();
+        println!("{{{}}}", reports.join(","));
+    } else {
+        let rows: Vec<(&str, &Stats)> = runs.iter().map(|(name, _, cpu)| (*name, cpu.stats())).collect();
+        print!("{}", comparison(&rows));
+    }
+    if let Some((_, ex, _)) = runs.iter().find(|(_, ex, _)| ex.code() != 0) {
+        std::process::exit(ex.code());
+    }
+    Ok(())
+}
+
+/// The human-readable end of run report. --quiet drops the lines listing
+/// individual instructions, such as watchpoint hits, but keeps the tables.
+fn table_report(cpu: &mut dyn SoC, name: &str, ex: Exit, args: &Args, bench: &BenchReport) -> String {
+    let mut out = format!("{} exited with {}\n", name, ex);
+    if let Some(report) = cpu.fault_report() {
+        out += &report;
+    }
+    let xlen = cpu.csr().xlen();
+    out += &format!("{}\n", register_table(cpu.regfile(), xlen));
+    out += &format!("{}\n", cpu.stats());
+    out += &format!("{}\n", cpu.mix());
+    if let Some(n) = args.profile {
+        out += &cpu.hot_spots(n);
+    }
+    if !args.quiet {
+        for (pc, ex) in &cpu.stats().exceptions {
+            out += &format!("{:#010x}: skipped {:?}\n", pc, ex);
+        }
+        for hit in &cpu.bus().watch_hits {
+            out += &format!("{}\n", hit);
+        }
+        for (pc, addr) in &cpu.bus().text_writes {
+            out += &format!("{:#010x}: store into the program at {:#010x}\n", pc, addr);
+        }
+        for pc in &cpu.stats().overflows {
+            out += &format!("{:#010x}: signed overflow\n", pc);
+        }
+    }
+    if args.bench {
+        out += &bench.to_string();
+    }
+    if args.dump_csr {
+        out += &format!("{}\n", csr_table(cpu.csr()));
+    }
+    out
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    let mut file = File::open(args.path)?;
+/// Where --reg-diff keeps the registers of the last run of a binary
+fn regs_sidecar(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".regs");
+    PathBuf::from(sidecar)
+}
+
+/// Compares the registers with those saved by the previous --reg-diff run
+/// of the binary at path, then saves them for the next run
+fn reg_diff_report(path: &Path, regs: &[u64; 32]) -> Result<String, MurError> {
+    let sidecar = regs_sidecar(path);
+    let previous = match std::fs::read_to_string(&sidecar) {
+        Ok(text) => parse_regs(&text).ok(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into())
+    };
+    let out = match previous {
+        Some(previous) => {
+            let diffs = diff_regs(&previous, regs);
+            if diffs.is_empty() {
+                "No registers changed since the last run\n".to_string()
+            } else {
+                format!("{}\n", diff_table(&diffs, "Last run", "This run"))
+            }
+        },
+        None => format!("No registers from a previous run in {}\n", sidecar.display()),
+    };
+    std::fs::write(&sidecar, format_regs(regs))?;
+    Ok(out)
+}
+
+fn main() {
+    if let Err(e) = run(&Args::parse()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Runs the simulator as the command line asks
+fn run(args: &Args) -> Result<(), MurError> {
+    let mut file = File::open(&args.path)?;
     let mut bin = Vec::new();
     file.read_to_end(&mut bin)?;
 
-    match args.soc.as_str() {
-        "dart" => {
-            let mut cpu = DartSoC::new(bin);
-            let ex = cpu.execute();
-            println!("Dart exited with exception {:?}", ex);
-            print_register_table(&cpu.regs);
-            println!("{}", cpu.stats);
-            Ok(())
-        },
-        "zeus" => {
-            let mut cpu = ZeusSoC::new(bin);
-            let ex = cpu.execute();
-            println!("Zeus exited with exception {:?}", ex);
-            print_register_table(&cpu.regs);
-            println!("{}", cpu.stats);
-            Ok(())
-        },
-        "kronos" => {
-            let mut cpu = KronosSoC::new(bin);
-            let ex = cpu.execute();
-            println!("Kronos exited with exception {:?}", ex);
-            print_register_table(&cpu.regs);
-            println!("{}", cpu.stats);
-            Ok(())
-        },
-        "atlas" => {
-            let mut cpu = AtlasSoC::new(bin);
-            let ex = cpu.execute();
-            println!("Atlas exited with exception {:?}", ex);
-            print_register_table(&cpu.regs);
-            println!("{}", cpu.stats);
-            Ok(())
-        },
-        _ => Err(format!("Unknown SoC type {}", args.soc).into())
+    if args.disasm {
+        let xlen = args.march.map_or(args.xlen, |(xlen, _)| xlen);
+        print!("{}", disasm(&bin, RAM_BASE, xlen == 64));
+        return Ok(())
+    }
+
+    if args.format != "table" && args.format != "json" {
+        return Err(MurError::Config(format!("Unknown format {}", args.format)))
+    }
+    if args.xlen != 32 && args.xlen != 64 {
+        return Err(MurError::Config(format!("Unsupported XLEN {}", args.xlen)))
+    }
+
+    if args.soc == "all" {
+        return run_all(args, bin)
+    }
+
+    let mut reference = args.diff.then(|| ReferenceSoC::with_entry(bin.clone(), args.entry));
+    let (name, mut cpu) = new_soc(&args.soc, bin, args.entry)?;
+    setup(cpu.as_mut(), args, true)?;
+
+    if let Some(reference) = &mut reference {
+        // the model under test already echoes the console
+        setup(reference, args, false)?;
+        match lockstep(cpu.as_mut(), reference, args.timeout_cycles) {
+            Ok(steps) => println!("{} matched the reference for {} instructions", name, steps),
+            Err(div) => {
+                println!("{} diverged from the reference at {:#010x} after {} instructions", name, div.pc, div.steps);
+                println!("Reference pc {:#010x}, {} pc {:#010x}", div.ref_pc, name, div.dut_pc);
+                let diffs = diff_regs(&div.ref_regs, &div.dut_regs);
+                if diffs.is_empty() {
+                    println!("The registers match");
+                } else {
+                    println!("{}", diff_table(&diffs, "Reference", name));
+                }
+                for (i, a, b) in diff_regs(&div.ref_fregs, &div.dut_fregs) {
+                    println!("f{}: reference {:#x}, {} {:#x}", i, a, name, b);
+                }
+                if div.ref_store != div.dut_store {
+                    let store = |s: Option<(u64, u64, u64)>| s.map_or("none".to_string(), |(addr, size, value)| format!("{:#x} to {:#010x} ({} bytes)", value, addr, size));
+                    println!("Reference stored {}, {} stored {}", store(div.ref_store), name, store(div.dut_store));
+                }
+                std::process::exit(1);
+            }
+        }
+        return Ok(())
+    }
+
+    if let Some(path) = &args.replay {
+        let log = parse_log(&std::fs::read_to_string(path)?).map_err(MurError::Decode)?;
+        match replay(cpu.as_mut(), &log) {
+            Ok(()) => println!("{} replayed {} instructions from {}", name, log.len(), path.display()),
+            Err(mismatch) => {
+                println!("{} diverged from {}: {}", name, path.display(), mismatch);
+                std::process::exit(1);
+            }
+        }
+        return Ok(())
+    }
+
+    if let Some(port) = args.gdb {
+        let listener = gdbstub::listen(port)?;
+        println!("Waiting for GDB on port {}", port);
+        gdbstub::serve(&listener, cpu.as_mut())?;
+        return Ok(())
+    }
+
+    let mut log = args.record.is_some().then(Vec::new);
+    let (ex, report) = match &mut log {
+        Some(log) => bench_with(cpu.as_mut(), &mut recorder(log)),
+        None => bench(cpu.as_mut()),
+    };
+    if args.format == "json" {
+        println!("{}", cpu.json_report(ex));
+    } else {
+        print!("{}", table_report(cpu.as_mut(), name, ex, args, &report));
+    }
+    if args.reg_diff {
+        let diff = reg_diff_report(&args.path, cpu.regfile())?;
+        // keep stdout a single JSON object
+        if args.format == "json" {
+            eprint!("{}", diff);
+        } else {
+            print!("{}", diff);
+        }
     }
+    if let (Some(path), Some(begin), Some(end)) = (&args.signature, args.sig_begin, args.sig_end) {
+        let sig = cpu.bus().dump_words(begin, end).map_err(|e| MurError::Config(format!("signature region unmapped: {:?}", e)))?;
+        std::fs::write(path, sig)?;
+    }
+    for (path, addr, len) in &args.dump_mem {
+        let data = cpu.bus().read_bytes(*addr, *len).map_err(|e| MurError::Config(format!("dump region unmapped: {:?}", e)))?;
+        std::fs::write(path, data)?;
+    }
+    if let Some(path) = &args.dep_graph {
+        let nodes = cpu.dep_graph().ok_or(MurError::Config(format!("{} does not record dependencies", name)))?;
+        std::fs::write(path, dep_dot(&nodes))?;
+    }
+    if let (Some(path), Some(log)) = (&args.record, &log) {
+        std::fs::write(path, format_log(log))?;
+    }
+    if let Some(path) = &args.snapshot {
+        std::fs::write(path, cpu.snapshot().to_bytes())?;
+    }
+    if ex.code() != 0 {
+        std::process::exit(ex.code());
+    }
+    Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use mur::{bench::bench, isa::to_bin, stats::{Stats, comparison}, error::MurError};
+    use super::{Args, SOCS, new_soc, setup, table_report, run, reg_diff_report, regs_sidecar};
+
+    #[test]
+    fn reg_diff_between_runs() {
+        let path = std::env::temp_dir().join(format!("mur-reg-diff-{}.bin", std::process::id()));
+        let run_twice = [
+            to_bin(&[
+                0x00100513, // addi a0, zero, 1
+                0x00200593, // addi a1, zero, 2
+            ]),
+            to_bin(&[
+                0x00100513, // addi a0, zero, 1
+                0x00300593, // addi a1, zero, 3
+                0x00400613, // addi a2, zero, 4
+            ]),
+        ];
+        let reports: Vec<String> = run_twice.into_iter().map(|bin| {
+            let (_, mut cpu) = new_soc("dart", bin, mur::bus::RAM_BASE).unwrap();
+            cpu.execute();
+            reg_diff_report(&path, cpu.regfile()).unwrap()
+        }).collect();
+        std::fs::remove_file(regs_sidecar(&path)).unwrap();
+        assert!(reports[0].starts_with("No registers from a previous run"), "{}", reports[0]);
+        let diff = &reports[1];
+        assert!(diff.contains("| a1       | 0x2      | 0x3      |"), "{}", diff);
+        assert!(diff.contains("| a2       | 0x0      | 0x4      |"), "{}", diff);
+        assert_eq!(diff.lines().filter(|l| l.starts_with("| ")).count(), 3, "{}", diff);
+    }
+
+    #[test]
+    fn unknown_soc_error() {
+        let args = Args::parse_from(["mur", concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"), "--soc", "pentium"]);
+        assert!(matches!(run(&args), Err(MurError::UnknownSoc(soc)) if soc == "pentium"));
+        // a missing program is reported before the model is looked up
+        let args = Args::parse_from(["mur", "/nonexistent/prog.bin", "--soc", "pentium"]);
+        assert!(matches!(run(&args), Err(MurError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn compare_all_models() {
+        let bin = to_bin(&[
+            0x00000513, // addi a0, zero, 0
+            0x00500593, // addi a1, zero, 5
+            0x00b50533, // add a0, a0, a1
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ce3, // bne a1, zero, -8
+        ]);
+        let mut cpus: Vec<_> = SOCS.iter()
+            .map(|soc| new_soc(soc, bin.clone(), mur::bus::RAM_BASE).unwrap())
+            .collect();
+        for (_, cpu) in &mut cpus {
+            cpu.execute();
+        }
+        let rows: Vec<(&str, &Stats)> = cpus.iter().map(|(name, cpu)| (*name, cpu.stats())).collect();
+        let table = comparison(&rows);
+        for name in ["Dart", "Zeus", "Kronos", "Atlas"] {
+            let row = table.lines().find(|l| l.contains(name)).unwrap();
+            // every model retires the same 17 instructions
+            assert!(row.contains(" 17 "), "{}", row);
+        }
+    }
+
+    #[test]
+    fn quiet_keeps_tables() {
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x02a00313, // addi t1, zero, 42
+            0x1062b023, // sd t1, 256(t0)
+        ]);
+        let report = |extra: &[&str]| {
+            let args = Args::parse_from(["mur", "prog.bin", "--watch", "0x80000100:8"].iter().chain(extra));
+            let (name, mut cpu) = new_soc("dart", bin.clone(), mur::bus::RAM_BASE).unwrap();
+            setup(cpu.as_mut(), &args, false).unwrap();
+            let (ex, bench) = bench(cpu.as_mut());
+            table_report(cpu.as_mut(), name, ex, &args, &bench)
+        };
+        let hit = "store to 0x80000100 changed 0x0 -> 0x2a";
+        let loud = report(&[]);
+        assert!(loud.contains(hit), "{}", loud);
+        let quiet = report(&["--quiet"]);
+        assert!(!quiet.contains(hit), "{}", quiet);
+        assert!(quiet.contains("Dart exited with"));
+        assert!(quiet.contains("| Retired "), "{}", quiet);
+        assert!(quiet.contains("| t1 "), "{}", quiet);
+    }
+
+    #[test]
+    fn harvard_flag() {
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x0002a423, // sw zero, 8(t0)
+            0x00200593, // addi a1, zero, 2
+        ]);
+        let a1 = |extra: &[&str]| {
+            let args = Args::parse_from(["mur", "prog.bin"].iter().chain(extra));
+            let (_, mut cpu) = new_soc("dart", bin.clone(), mur::bus::RAM_BASE).unwrap();
+            setup(cpu.as_mut(), &args, false).unwrap();
+            cpu.execute();
+            cpu.regfile()[11]
+        };
+        // the store overwrites the addi unless it only reaches data memory
+        assert_eq!(a1(&[]), 0);
+        assert_eq!(a1(&["--harvard"]), 2);
+    }
+}
\ No newline at end of file
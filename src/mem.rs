@@ -1,9 +1,19 @@
 
 
+/// Byte order of multi-byte accesses
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
 pub struct Mem {
-    mem: Vec<u8>
+    mem: Vec<u8>,
+    pub endian: Endian
 }
 
+#[derive(Copy, Clone)]
 pub struct Bits {
     size: u64
 }
@@ -13,22 +23,139 @@ pub const B16: Bits = Bits {size: 2 };
 pub const B32: Bits = Bits {size: 4 };
 pub const B64: Bits = Bits {size: 8 };
 
+impl Bits {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
 impl Mem {
     pub fn new(mem: Vec<u8>) -> Self {
-        Self { mem }
+        Self::with_endian(mem, Endian::Little)
+    }
+
+    pub fn with_endian(mem: Vec<u8>, endian: Endian) -> Self {
+        Self { mem, endian }
+    }
+
+    /// Position of byte i of an access within the value
+    fn shift(&self, i: u64, bits: Bits) -> u64 {
+        match self.endian {
+            Endian::Little => i * 8,
+            Endian::Big => (bits.size - 1 - i) * 8,
+        }
     }
 
     pub fn load(&self, addr: u64, bits: Bits) -> u64 {
+        // naturally aligned accesses read the whole value in one go
+        if bits.size > 1 && addr.is_multiple_of(bits.size) {
+            let a = addr as usize;
+            let little = self.endian == Endian::Little;
+            match bits.size {
+                2 => {
+                    let b = self.mem[a..a + 2].try_into().unwrap();
+                    return if little { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) } as u64
+                },
+                4 => {
+                    let b = self.mem[a..a + 4].try_into().unwrap();
+                    return if little { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) } as u64
+                },
+                8 => {
+                    let b = self.mem[a..a + 8].try_into().unwrap();
+                    return if little { u64::from_le_bytes(b) } else { u64::from_be_bytes(b) }
+                },
+                _ => ()
+            }
+        }
+        self.load_bytes(addr, bits)
+    }
+
+    fn load_bytes(&self, addr: u64, bits: Bits) -> u64 {
         (0..bits.size)
-            .map(|i| (self.mem[(addr + i) as usize] as u64) << (i * 8))
+            .map(|i| (self.mem[(addr + i) as usize] as u64) << self.shift(i, bits))
             .reduce(|a, b| a | b)
             .unwrap_or(0)
     }
 
+    pub fn bytes(&self) -> &[u8] {
+        &self.mem
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
+
     pub fn store(&mut self, addr: u64, bits: Bits, value: u64) {
+        if bits.size > 1 && addr.is_multiple_of(bits.size) {
+            let a = addr as usize;
+            let little = self.endian == Endian::Little;
+            match bits.size {
+                2 => {
+                    let b = if little { (value as u16).to_le_bytes() } else { (value as u16).to_be_bytes() };
+                    return self.mem[a..a + 2].copy_from_slice(&b)
+                },
+                4 => {
+                    let b = if little { (value as u32).to_le_bytes() } else { (value as u32).to_be_bytes() };
+                    return self.mem[a..a + 4].copy_from_slice(&b)
+                },
+                8 => {
+                    let b = if little { value.to_le_bytes() } else { value.to_be_bytes() };
+                    return self.mem[a..a + 8].copy_from_slice(&b)
+                },
+                _ => ()
+            }
+        }
+        self.store_bytes(addr, bits, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, bits: Bits, value: u64) {
         (0..bits.size).for_each(|i| {
-            let offset = 8 * i as usize;
+            let offset = self.shift(i, bits);
             self.mem[(addr + i) as usize] = ((value >> offset) & 0xff) as u8;
         })
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mem, Endian, B8, B16, B32, B64};
+
+    #[test]
+    fn byte_order() {
+        let mut little = Mem::new(vec![0; 8]);
+        little.store(0, B64, 0x1122334455667788);
+        assert_eq!(little.bytes(), [0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(little.load(0, B8), 0x88);
+        assert_eq!(little.load(0, B16), 0x7788);
+        assert_eq!(little.load(0, B64), 0x1122334455667788);
+
+        let mut big = Mem::with_endian(vec![0; 8], Endian::Big);
+        big.store(0, B64, 0x1122334455667788);
+        assert_eq!(big.bytes(), [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]);
+        assert_eq!(big.load(0, B8), 0x11);
+        assert_eq!(big.load(0, B16), 0x1122);
+        assert_eq!(big.load(0, B64), 0x1122334455667788);
+    }
+
+    #[test]
+    fn fast_path_matches_byte_loop() {
+        for endian in [Endian::Little, Endian::Big] {
+            let pattern: Vec<u8> = (0..64).map(|i| (i * 37 + 11) as u8).collect();
+            let mem = Mem::with_endian(pattern.clone(), endian);
+            for addr in 0..56 {
+                for bits in [B8, B16, B32, B64] {
+                    assert_eq!(mem.load(addr, bits), mem.load_bytes(addr, bits));
+                }
+            }
+            for addr in 0..56 {
+                for bits in [B8, B16, B32, B64] {
+                    let mut fast = Mem::with_endian(pattern.clone(), endian);
+                    let mut slow = Mem::with_endian(pattern.clone(), endian);
+                    fast.store(addr, bits, 0x0123456789abcdef);
+                    slow.store_bytes(addr, bits, 0x0123456789abcdef);
+                    assert_eq!(fast.bytes(), slow.bytes());
+                }
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,1014 @@
+use std::mem::discriminant;
+
+use crate::{bus::Bus, stats::{Stats, InsMix, Profile}, isa::Exts, rvc::expand, exception::Exception, soc::{SoC, Config, ExecObserver}, mem::{B8, B16, B32, B64}, csr::{Csr, Privilege, read_only, MISA, MISA_C, MSTATUS, MSTATUS_MPP, MSTATUS_MIE, MSTATUS_MPIE, SSTATUS, SSTATUS_SPP, SSTATUS_SIE, SSTATUS_SPIE, MEPC, SEPC, FFLAGS, FRM, FFLAGS_NV, FFLAGS_DZ, FFLAGS_OF, FFLAGS_UF, FFLAGS_NX}};
+
+/*
+A plain functional interpreter used as the golden model for --diff. It
+decodes each instruction from its fields and executes it here, sharing none
+of the per-extension code the models run, so a bug in that code shows up
+as a divergence. Only the expansion of compressed instructions is borrowed,
+from rvc::expand; memory, CSRs and traps go through the same Bus, Csr and
+SoC::step as the models.
+
+It keeps the models' conventions where they choose: float arithmetic rounds
+to nearest, ties to even, whatever the rounding mode, and the fused
+multiply-adds are illegal. Every instruction takes one cycle and tracing is
+not supported, so the only thing that can differ from the models under test
+is architectural state.
+*/
+
+/// The NaN every float operation producing a NaN returns
+const CANONICAL_NAN: u32 = 0x7fc0_0000;
+
+pub struct ReferenceSoC {
+    pub regs: [u64; 32],
+    /// Float registers, holding NaN-boxed singles
+    pub fregs: [u64; 32],
+    pub pc: u64,
+    pub bus: Bus,
+    pub csr: Csr,
+    pub stats: Stats,
+    /// Executed instructions by mnemonic
+    pub mix: InsMix,
+    /// Executions of each pc
+    pub profile: Profile,
+    pub config: Config,
+    pub observer: Option<Box<dyn ExecObserver>>
+}
+
+type Result = std::result::Result<(), Exception>;
+
+fn rd(ins: u32) -> usize {
+    ((ins >> 7) & 0x1f) as usize
+}
+
+fn rs1(ins: u32) -> usize {
+    ((ins >> 15) & 0x1f) as usize
+}
+
+fn rs2(ins: u32) -> usize {
+    ((ins >> 20) & 0x1f) as usize
+}
+
+/// The immediates, sign-extended to 64 bits
+fn imm_i(ins: u32) -> u64 {
+    (ins as i32 >> 20) as u64
+}
+
+fn imm_s(ins: u32) -> u64 {
+    ((ins as i32 >> 25) << 5 | ((ins >> 7) & 0x1f) as i32) as u64
+}
+
+fn imm_b(ins: u32) -> u64 {
+    let low = ((ins >> 7) & 1) << 11 | ((ins >> 25) & 0x3f) << 5 | ((ins >> 8) & 0xf) << 1;
+    ((ins as i32 >> 31) << 12 | low as i32) as u64
+}
+
+fn imm_u(ins: u32) -> u64 {
+    (ins & 0xffff_f000) as i32 as u64
+}
+
+fn imm_j(ins: u32) -> u64 {
+    let low = ins & 0xff000 | ((ins >> 20) & 1) << 11 | ((ins >> 21) & 0x3ff) << 1;
+    ((ins as i32 >> 31) << 20 | low as i32) as u64
+}
+
+/// Names the instruction `ins` encodes, or None if it is illegal at this
+/// XLEN with these extensions
+fn mnemonic(ins: u32, rv64: bool, exts: Exts) -> Option<&'static str> {
+    let (funct3, funct7, rs2) = ((ins >> 12) & 0b111, ins >> 25, rs2(ins));
+    let (m, a, f, zbb) = (exts.has(Exts::M), exts.has(Exts::A), exts.has(Exts::F), exts.has(Exts::ZBB));
+    let name = match ins & 0x7f {
+        0b0110111 => "lui",
+        0b0010111 => "auipc",
+        0b1101111 => "jal",
+        0b1100111 if funct3 == 0 => "jalr",
+        0b1100011 => match funct3 {
+            0b000 => "beq",
+            0b001 => "bne",
+            0b100 => "blt",
+            0b101 => "bge",
+            0b110 => "bltu",
+            0b111 => "bgeu",
+            _ => return None
+        },
+        0b0000011 => match funct3 {
+            0b000 => "lb",
+            0b001 => "lh",
+            0b010 => "lw",
+            0b100 => "lbu",
+            0b101 => "lhu",
+            0b110 if rv64 => "lwu",
+            0b011 if rv64 => "ld",
+            _ => return None
+        },
+        0b0100011 => match funct3 {
+            0b000 => "sb",
+            0b001 => "sh",
+            0b010 => "sw",
+            0b011 if rv64 => "sd",
+            _ => return None
+        },
+        0b0010011 => match (funct3, funct7) {
+            // bit 25 is shamt[5], which RV32 does not have
+            (0b001 | 0b101, _) if !rv64 && funct7 & 1 != 0 => return None,
+            (0b000, _) => "addi",
+            (0b010, _) => "slti",
+            (0b011, _) => "sltiu",
+            (0b100, _) => "xori",
+            (0b110, _) => "ori",
+            (0b111, _) => "andi",
+            (0b001, 0b0000000 | 0b0000001) => "slli",
+            (0b101, 0b0000000 | 0b0000001) => "srli",
+            (0b101, 0b0100000 | 0b0100001) => "srai",
+            (0b101, 0b0110000 | 0b0110001) if zbb => "rori",
+            (0b001, 0b0110000) if zbb => match rs2 {
+                0b00000 => "clz",
+                0b00001 => "ctz",
+                0b00010 => "cpop",
+                0b00100 => "sext.b",
+                0b00101 => "sext.h",
+                _ => return None
+            },
+            _ => return None
+        },
+        0b0110011 => match (funct7, funct3) {
+            (0b0000000, _) => ["add", "sll", "slt", "sltu", "xor", "srl", "or", "and"][funct3 as usize],
+            (0b0100000, 0b000) => "sub",
+            (0b0100000, 0b101) => "sra",
+            (0b0000001, _) if m => ["mul", "mulh", "mulhsu", "mulhu", "div", "divu", "rem", "remu"][funct3 as usize],
+            (0b0100000, 0b100) if zbb => "xnor",
+            (0b0100000, 0b110) if zbb => "orn",
+            (0b0100000, 0b111) if zbb => "andn",
+            (0b0000101, 0b100) if zbb => "min",
+            (0b0000101, 0b101) if zbb => "minu",
+            (0b0000101, 0b110) if zbb => "max",
+            (0b0000101, 0b111) if zbb => "maxu",
+            (0b0110000, 0b001) if zbb => "rol",
+            (0b0110000, 0b101) if zbb => "ror",
+            _ => return None
+        },
+        0b0011011 if rv64 => match (funct3, funct7) {
+            (0b000, _) => "addiw",
+            (0b001, 0b0000000) => "slliw",
+            (0b101, 0b0000000) => "srliw",
+            (0b101, 0b0100000) => "sraiw",
+            (0b101, 0b0110000) if zbb => "roriw",
+            (0b001, 0b0110000) if zbb => match rs2 {
+                0b00000 => "clzw",
+                0b00001 => "ctzw",
+                0b00010 => "cpopw",
+                _ => return None
+            },
+            _ => return None
+        },
+        0b0111011 if rv64 => match (funct7, funct3) {
+            (0b0000000, 0b000) => "addw",
+            (0b0100000, 0b000) => "subw",
+            (0b0000000, 0b001) => "sllw",
+            (0b0000000, 0b101) => "srlw",
+            (0b0100000, 0b101) => "sraw",
+            (0b0000001, 0b000) if m => "mulw",
+            (0b0000001, 0b100) if m => "divw",
+            (0b0000001, 0b101) if m => "divuw",
+            (0b0000001, 0b110) if m => "remw",
+            (0b0000001, 0b111) if m => "remuw",
+            (0b0110000, 0b001) if zbb => "rolw",
+            (0b0110000, 0b101) if zbb => "rorw",
+            _ => return None
+        },
+        0b0101111 if a && (funct3 == 0b010 || (funct3 == 0b011 && rv64)) => {
+            // the low two bits of funct7 are the aq/rl ordering hints
+            let names = match funct7 >> 2 {
+                0b00010 if rs2 == 0 => ["lr.w", "lr.d"],
+                0b00011 => ["sc.w", "sc.d"],
+                0b00001 => ["amoswap.w", "amoswap.d"],
+                0b00000 => ["amoadd.w", "amoadd.d"],
+                0b00100 => ["amoxor.w", "amoxor.d"],
+                0b01100 => ["amoand.w", "amoand.d"],
+                0b01000 => ["amoor.w", "amoor.d"],
+                0b10000 => ["amomin.w", "amomin.d"],
+                0b10100 => ["amomax.w", "amomax.d"],
+                0b11000 => ["amominu.w", "amominu.d"],
+                0b11100 => ["amomaxu.w", "amomaxu.d"],
+                _ => return None
+            };
+            names[(funct3 - 0b010) as usize]
+        },
+        0b0001111 => match funct3 {
+            0b000 => "fence",
+            0b001 => "fence.i",
+            _ => return None
+        },
+        0b1110011 => match funct3 {
+            0b001 => "csrrw",
+            0b010 => "csrrs",
+            0b011 => "csrrc",
+            0b101 => "csrrwi",
+            0b110 => "csrrsi",
+            0b111 => "csrrci",
+            _ => match ins {
+                0x00000073 => "ecall",
+                0x00100073 => "ebreak",
+                0x30200073 => "mret",
+                0x10200073 => "sret",
+                0x10500073 => "wfi",
+                _ if funct7 == 0b0001001 && funct3 == 0 && rd(ins) == 0 => "sfence.vma",
+                _ => return None
+            }
+        },
+        0b0000111 if f && funct3 == 0b010 => "flw",
+        0b0100111 if f && funct3 == 0b010 => "fsw",
+        0b1010011 if f => {
+            // 0b101 and 0b110 are reserved rounding modes
+            let rm = funct3 <= 0b100 || funct3 == 0b111;
+            match (funct7, funct3, rs2) {
+                (0b0000000, _, _) if rm => "fadd.s",
+                (0b0000100, _, _) if rm => "fsub.s",
+                (0b0001000, _, _) if rm => "fmul.s",
+                (0b0001100, _, _) if rm => "fdiv.s",
+                (0b0101100, _, 0) if rm => "fsqrt.s",
+                (0b0010000, 0b000, _) => "fsgnj.s",
+                (0b0010000, 0b001, _) => "fsgnjn.s",
+                (0b0010000, 0b010, _) => "fsgnjx.s",
+                (0b0010100, 0b000, _) => "fmin.s",
+                (0b0010100, 0b001, _) => "fmax.s",
+                (0b1010000, 0b010, _) => "feq.s",
+                (0b1010000, 0b001, _) => "flt.s",
+                (0b1010000, 0b000, _) => "fle.s",
+                (0b1100000, _, 0) if rm => "fcvt.w.s",
+                (0b1100000, _, 1) if rm => "fcvt.wu.s",
+                (0b1101000, _, 0) if rm => "fcvt.s.w",
+                (0b1101000, _, 1) if rm => "fcvt.s.wu",
+                (0b1110000, 0b000, 0) => "fmv.x.w",
+                (0b1110000, 0b001, 0) => "fclass.s",
+                (0b1111000, 0b000, 0) => "fmv.w.x",
+                _ => return None
+            }
+        },
+        _ => return None
+    };
+    Some(name)
+}
+
+/// The single in a float register, or the canonical NaN if it is not
+/// NaN-boxed
+fn unbox(reg: u64) -> f32 {
+    f32::from_bits(if reg >> 32 == 0xffff_ffff { reg as u32 } else { CANONICAL_NAN })
+}
+
+fn nan_box(bits: u32) -> u64 {
+    0xffff_ffff_0000_0000 | bits as u64
+}
+
+fn is_signalling(value: f32) -> bool {
+    value.is_nan() && value.to_bits() & (1 << 22) == 0
+}
+
+/// Rounds `value`, an integer held exactly, to a single in rounding mode
+/// `rm`, along with whether that was inexact
+fn int_to_single(value: i64, rm: u64) -> (f32, bool) {
+    let negative = value < 0;
+    let magnitude = value.unsigned_abs();
+    let width = 64 - magnitude.leading_zeros();
+    if width <= 24 {
+        let single = magnitude as f32;
+        return (if negative { -single } else { single }, false)
+    }
+    // keep the top 24 bits and round on the ones shifted out
+    let shift = width - 24;
+    let (kept, dropped) = (magnitude >> shift, magnitude & ((1 << shift) - 1));
+    let half = 1 << (shift - 1);
+    let up = match rm {
+        0b001 => false,
+        0b010 => negative && dropped != 0,
+        0b011 => !negative && dropped != 0,
+        0b100 => dropped >= half,
+        _ => dropped > half || (dropped == half && kept & 1 == 1),
+    };
+    let single = ((kept + up as u64) << shift) as f32;
+    (if negative { -single } else { single }, dropped != 0)
+}
+
+/// The fclass.s bit for `value`
+fn classify(value: f32) -> u64 {
+    let negative = value.is_sign_negative();
+    let bit = match value {
+        _ if is_signalling(value) => 8,
+        _ if value.is_nan() => 9,
+        _ if value.is_infinite() => if negative { 0 } else { 7 },
+        _ if value == 0.0 => if negative { 3 } else { 4 },
+        _ if value.is_subnormal() => if negative { 2 } else { 5 },
+        _ => if negative { 1 } else { 6 },
+    };
+    1 << bit
+}
+
+impl ReferenceSoC {
+    fn write(&mut self, rd: usize, value: u64) {
+        if rd != 0 {
+            self.regs[rd] = value;
+        }
+    }
+
+    fn raise(&mut self, flags: u64) {
+        self.csr.store(FFLAGS, self.csr.load(FFLAGS) | flags);
+    }
+
+    /// A taken jump or branch to `target`, which has to be aligned to 2
+    /// bytes when misa has C and to 4 otherwise
+    fn jump(&self, target: u64) -> std::result::Result<u64, Exception> {
+        let align = if self.csr.load(MISA) & MISA_C != 0 { 2 } else { 4 };
+        if !target.is_multiple_of(align) {
+            return Err(Exception::InstructionAddrMisaligned(target))
+        }
+        Ok(target)
+    }
+
+    /// The rounding mode in the rm field of `ins`, looking in frm for the
+    /// dynamic mode, which is illegal if frm holds a reserved one
+    fn rounding_mode(&self, ins: u32) -> std::result::Result<u64, Exception> {
+        let rm = match (ins >> 12) & 0b111 {
+            0b111 => self.csr.load(FRM),
+            rm => rm as u64,
+        };
+        if rm > 0b100 {
+            return Err(Exception::IllegalInstruction(ins as u64))
+        }
+        Ok(rm)
+    }
+
+    /// fadd.s, fsub.s, fmul.s, fdiv.s and fsqrt.s, returning the NaN-boxed
+    /// result and raising its flags. Tininess is judged after rounding.
+    fn float_arith(&mut self, name: &str, x: f32, y: f32) -> u64 {
+        let operands: &[f32] = if name == "fsqrt.s" { &[x] } else { &[x, y] };
+        let result = match name {
+            "fadd.s" => x + y,
+            "fsub.s" => x - y,
+            "fmul.s" => x * y,
+            "fdiv.s" => x / y,
+            _ => x.sqrt(),
+        };
+        let mut flags = 0;
+        if operands.iter().any(|v| is_signalling(*v)) || (result.is_nan() && !operands.iter().any(|v| v.is_nan())) {
+            flags |= FFLAGS_NV;
+        }
+        if result.is_nan() {
+            self.raise(flags);
+            return nan_box(CANONICAL_NAN)
+        }
+        if operands.iter().all(|v| v.is_finite()) {
+            if name == "fdiv.s" && y == 0.0 {
+                flags |= FFLAGS_DZ;
+            } else if result.is_infinite() {
+                flags |= FFLAGS_OF | FFLAGS_NX;
+            } else {
+                // every check is exact in f64: the product of two singles
+                // needs 48 bits, and so does the difference of a sum and
+                // its larger operand
+                let exact = match name {
+                    "fadd.s" | "fsub.s" => {
+                        let y = if name == "fsub.s" { -y } else { y };
+                        let (big, small) = if x.abs() >= y.abs() { (x, y) } else { (y, x) };
+                        result as f64 - big as f64 == small as f64
+                    },
+                    "fmul.s" => x as f64 * y as f64 == result as f64,
+                    "fdiv.s" => result as f64 * y as f64 == x as f64,
+                    _ => result as f64 * result as f64 == x as f64,
+                };
+                if !exact {
+                    flags |= FFLAGS_NX;
+                    if result.abs() < f32::MIN_POSITIVE {
+                        flags |= FFLAGS_UF;
+                    }
+                }
+            }
+        }
+        self.raise(flags);
+        nan_box(result.to_bits())
+    }
+
+    /// Executes `ins`, named `name`, which is `len` bytes long, and returns
+    /// the next pc
+    fn execute(&mut self, name: &'static str, ins: u32, len: u64) -> std::result::Result<u64, Exception> {
+        let pc = self.pc;
+        let next = pc.wrapping_add(len);
+        let rd = rd(ins);
+        let (a, b) = (self.regs[rs1(ins)], self.regs[rs2(ins)]);
+        let (fa, fb) = (self.fregs[rs1(ins)], self.fregs[rs2(ins)]);
+        let xlen = self.csr.xlen();
+        // reads a register as a signed XLEN-bit value
+        let signed = |value: u64| if xlen == 32 { value as i32 as i64 } else { value as i64 };
+        let xmask = if xlen == 32 { 0xffff_ffff } else { u64::MAX };
+        let shamt = |value: u64| (value & (xlen as u64 - 1)) as u32;
+        let (load_addr, store_addr) = (a.wrapping_add(imm_i(ins)), a.wrapping_add(imm_s(ins)));
+        let value = match name {
+            "lui" => imm_u(ins),
+            "auipc" => pc.wrapping_add(imm_u(ins)),
+            "jal" | "jalr" => {
+                let target = if name == "jal" { pc.wrapping_add(imm_j(ins)) } else { a.wrapping_add(imm_i(ins)) & !1 };
+                let target = self.jump(target)?;
+                self.write(rd, next);
+                return Ok(target)
+            },
+            "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" => {
+                let taken = match name {
+                    "beq" => a == b,
+                    "bne" => a != b,
+                    "blt" => signed(a) < signed(b),
+                    "bge" => signed(a) >= signed(b),
+                    "bltu" => a < b,
+                    _ => a >= b,
+                };
+                return if taken { self.jump(pc.wrapping_add(imm_b(ins))) } else { Ok(next) }
+            },
+            "lb" => self.bus.load(load_addr, B8)? as i8 as u64,
+            "lh" => self.bus.load(load_addr, B16)? as i16 as u64,
+            "lw" => self.bus.load(load_addr, B32)? as i32 as u64,
+            "lbu" => self.bus.load(load_addr, B8)?,
+            "lhu" => self.bus.load(load_addr, B16)?,
+            "lwu" => self.bus.load(load_addr, B32)?,
+            "ld" => self.bus.load(load_addr, B64)?,
+            "sb" | "sh" | "sw" | "sd" => {
+                let (bits, mask) = match name {
+                    "sb" => (B8, 0xff),
+                    "sh" => (B16, 0xffff),
+                    "sw" => (B32, 0xffff_ffff),
+                    _ => (B64, u64::MAX),
+                };
+                self.bus.store(store_addr, bits, b & mask)?;
+                return Ok(next)
+            },
+            "addi" => a.wrapping_add(imm_i(ins)),
+            "slti" => (signed(a) < signed(imm_i(ins))) as u64,
+            "sltiu" => (a & xmask < imm_i(ins) & xmask) as u64,
+            "xori" => a ^ imm_i(ins),
+            "ori" => a | imm_i(ins),
+            "andi" => a & imm_i(ins),
+            "slli" => a << ((ins >> 20) & 0x3f),
+            "srli" => a >> ((ins >> 20) & 0x3f),
+            "srai" => (signed(a) >> ((ins >> 20) & 0x3f)) as u64,
+            "add" => a.wrapping_add(b),
+            "sub" => a.wrapping_sub(b),
+            "sll" => a << shamt(b),
+            "slt" => (signed(a) < signed(b)) as u64,
+            "sltu" => (a < b) as u64,
+            "xor" => a ^ b,
+            "srl" => a >> shamt(b),
+            "sra" => (signed(a) >> shamt(b)) as u64,
+            "or" => a | b,
+            "and" => a & b,
+            "addiw" => a.wrapping_add(imm_i(ins)) as i32 as u64,
+            "slliw" => ((a as u32) << ((ins >> 20) & 0x1f)) as i32 as u64,
+            "srliw" => ((a as u32) >> ((ins >> 20) & 0x1f)) as i32 as u64,
+            "sraiw" => ((a as i32) >> ((ins >> 20) & 0x1f)) as u64,
+            "addw" => a.wrapping_add(b) as i32 as u64,
+            "subw" => a.wrapping_sub(b) as i32 as u64,
+            "sllw" => ((a as u32) << (b & 0x1f)) as i32 as u64,
+            "srlw" => ((a as u32) >> (b & 0x1f)) as i32 as u64,
+            "sraw" => ((a as i32) >> (b & 0x1f)) as u64,
+            "mul" => a.wrapping_mul(b),
+            "mulh" => ((signed(a) as i128 * signed(b) as i128) >> xlen) as u64,
+            "mulhsu" => ((signed(a) as i128 * b as i128) >> xlen) as u64,
+            "mulhu" => ((a as u128 * b as u128) >> xlen) as u64,
+            // division by zero gives all ones and leaves the remainder as
+            // the dividend, and the overflowing MIN / -1 gives MIN
+            "div" => if b == 0 { u64::MAX } else { signed(a).wrapping_div(signed(b)) as u64 },
+            "divu" => a.checked_div(b).unwrap_or(u64::MAX),
+            "rem" => if b == 0 { a } else { signed(a).wrapping_rem(signed(b)) as u64 },
+            "remu" => if b == 0 { a } else { a % b },
+            "mulw" => (a as i32).wrapping_mul(b as i32) as u64,
+            "divw" => if b as i32 == 0 { u64::MAX } else { (a as i32).wrapping_div(b as i32) as u64 },
+            "divuw" => (a as u32).checked_div(b as u32).map_or(u64::MAX, |q| q as i32 as u64),
+            "remw" => if b as i32 == 0 { a as i32 as u64 } else { (a as i32).wrapping_rem(b as i32) as u64 },
+            "remuw" => if b as u32 == 0 { a as i32 as u64 } else { ((a as u32) % (b as u32)) as i32 as u64 },
+            "lr.w" | "lr.d" => {
+                let (bits, size) = if name == "lr.w" { (B32, 4) } else { (B64, 8) };
+                if !a.is_multiple_of(size) {
+                    return Err(Exception::LoadAccessMisaligned(a))
+                }
+                let value = self.bus.load(a, bits)?;
+                self.bus.reservation = Some(a);
+                if size == 4 { value as i32 as u64 } else { value }
+            },
+            "sc.w" | "sc.d" => {
+                let (bits, size, mask) = if name == "sc.w" { (B32, 4, 0xffff_ffff) } else { (B64, 8, u64::MAX) };
+                if !a.is_multiple_of(size) {
+                    return Err(Exception::StoreAMOAddrMisaligned(a))
+                }
+                // the reservation is used up whether or not it matches
+                if self.bus.reservation.take() == Some(a) {
+                    self.bus.store(a, bits, b & mask)?;
+                    0
+                } else {
+                    1
+                }
+            },
+            _ if name.starts_with("amo") => {
+                let word = name.ends_with(".w");
+                let (bits, size, mask) = if word { (B32, 4, 0xffff_ffff) } else { (B64, 8, u64::MAX) };
+                if !a.is_multiple_of(size) {
+                    return Err(Exception::StoreAMOAddrMisaligned(a))
+                }
+                // words are compared sign-extended, which keeps both their
+                // signed and their unsigned order
+                let old = self.bus.load(a, bits)?;
+                let (old, src) = if word { (old as i32 as u64, b as i32 as u64) } else { (old, b) };
+                let new = match &name[..name.len() - 2] {
+                    "amoswap" => src,
+                    "amoadd" => old.wrapping_add(src),
+                    "amoxor" => old ^ src,
+                    "amoand" => old & src,
+                    "amoor" => old | src,
+                    "amomin" => if (old as i64) < src as i64 { old } else { src },
+                    "amomax" => if (old as i64) > src as i64 { old } else { src },
+                    "amominu" => old.min(src),
+                    _ => old.max(src),
+                };
+                self.bus.store(a, bits, new & mask)?;
+                old
+            },
+            "andn" => a & !b,
+            "orn" => a | !b,
+            "xnor" => !(a ^ b),
+            "clz" => if xlen == 32 { (a as u32).leading_zeros() as u64 } else { a.leading_zeros() as u64 },
+            "ctz" => if xlen == 32 { (a as u32).trailing_zeros() as u64 } else { a.trailing_zeros() as u64 },
+            "cpop" => a.count_ones() as u64,
+            "min" => if signed(a) < signed(b) { a } else { b },
+            "max" => if signed(a) > signed(b) { a } else { b },
+            "minu" => a.min(b),
+            "maxu" => a.max(b),
+            "sext.b" => a as i8 as u64,
+            "sext.h" => a as i16 as u64,
+            "rol" | "ror" | "rori" => {
+                let amount = if name == "rori" { shamt((ins >> 20) as u64) } else { shamt(b) };
+                let amount = if name == "rol" { amount } else { (xlen - amount) % xlen };
+                if xlen == 32 { (a as u32).rotate_left(amount) as u64 } else { a.rotate_left(amount) }
+            },
+            "clzw" => (a as u32).leading_zeros() as u64,
+            "ctzw" => (a as u32).trailing_zeros() as u64,
+            "cpopw" => (a as u32).count_ones() as u64,
+            "rolw" => (a as u32).rotate_left((b & 0x1f) as u32) as i32 as u64,
+            "rorw" => (a as u32).rotate_right((b & 0x1f) as u32) as i32 as u64,
+            "roriw" => (a as u32).rotate_right((ins >> 20) & 0x1f) as i32 as u64,
+            // memory is always coherent and instructions are fetched
+            // straight from the bus, so neither fence has work to do
+            "fence" | "fence.i" => return Ok(next),
+            "ecall" => return Err(match self.csr.privilege {
+                Privilege::U => Exception::EnvironmentCallFromUMode(pc),
+                Privilege::S => Exception::EnvironmentCallFromSMode(pc),
+                Privilege::M => Exception::EnvironmentCallFromMMode(pc),
+            }),
+            "ebreak" => return Err(Exception::Breakpoint(pc)),
+            "mret" => {
+                if self.csr.privilege != Privilege::M {
+                    return Err(Exception::IllegalInstruction(ins as u64))
+                }
+                let mstatus = self.csr.load(MSTATUS);
+                self.csr.privilege = Privilege::from_bits((mstatus & MSTATUS_MPP) >> 11);
+                // MIE = MPIE, MPIE = 1, MPP = U
+                let mie = if mstatus & MSTATUS_MPIE != 0 { MSTATUS_MIE } else { 0 };
+                self.csr.store(MSTATUS, mstatus & !(MSTATUS_MIE | MSTATUS_MPP) | mie | MSTATUS_MPIE);
+                return Ok(self.csr.load(MEPC))
+            },
+            "sret" => {
+                if self.csr.privilege == Privilege::U {
+                    return Err(Exception::IllegalInstruction(ins as u64))
+                }
+                let sstatus = self.csr.load(SSTATUS);
+                self.csr.privilege = if sstatus & SSTATUS_SPP != 0 { Privilege::S } else { Privilege::U };
+                let sie = if sstatus & SSTATUS_SPIE != 0 { SSTATUS_SIE } else { 0 };
+                self.csr.store(SSTATUS, sstatus & !(SSTATUS_SIE | SSTATUS_SPP) | sie | SSTATUS_SPIE);
+                return Ok(self.csr.load(SEPC))
+            },
+            // no interrupt can arrive while an instruction runs, so wfi
+            // may return at once
+            "wfi" => return Ok(next),
+            "sfence.vma" => {
+                if self.csr.privilege == Privilege::U {
+                    return Err(Exception::IllegalInstruction(ins as u64))
+                }
+                self.bus.flush_tlb();
+                return Ok(next)
+            },
+            "csrrw" | "csrrs" | "csrrc" | "csrrwi" | "csrrsi" | "csrrci" => {
+                let addr = (ins >> 20) as u64;
+                let src = if name.ends_with('i') { rs1(ins) as u64 } else { a };
+                // csrrs and csrrc with x0 or a zero immediate only read
+                let writes = name.starts_with("csrrw") || rs1(ins) != 0;
+                if !self.csr.accessible(addr) || (writes && read_only(addr)) {
+                    return Err(Exception::IllegalInstruction(ins as u64))
+                }
+                let old = self.csr.load(addr);
+                if writes {
+                    let new = match &name[..5] {
+                        "csrrw" => src,
+                        "csrrs" => old | src,
+                        _ => old & !src,
+                    };
+                    self.csr.store(addr, new);
+                }
+                old
+            },
+            "flw" => {
+                self.fregs[rd] = nan_box(self.bus.load(load_addr, B32)? as u32);
+                return Ok(next)
+            },
+            "fsw" => {
+                self.bus.store(store_addr, B32, fb & 0xffff_ffff)?;
+                return Ok(next)
+            },
+            "fadd.s" | "fsub.s" | "fmul.s" | "fdiv.s" | "fsqrt.s" => {
+                self.rounding_mode(ins)?;
+                self.fregs[rd] = self.float_arith(name, unbox(fa), unbox(fb));
+                return Ok(next)
+            },
+            "fsgnj.s" | "fsgnjn.s" | "fsgnjx.s" | "fmin.s" | "fmax.s" => {
+                let (x, y) = (unbox(fa), unbox(fb));
+                let sign = 1 << 31;
+                let bits = match name {
+                    "fsgnj.s" => x.to_bits() & !sign | y.to_bits() & sign,
+                    "fsgnjn.s" => x.to_bits() & !sign | !y.to_bits() & sign,
+                    "fsgnjx.s" => x.to_bits() ^ y.to_bits() & sign,
+                    _ => {
+                        if is_signalling(x) || is_signalling(y) {
+                            self.raise(FFLAGS_NV);
+                        }
+                        let max = name == "fmax.s";
+                        // a NaN loses to a number, and -0 is below +0
+                        let pick_x = match (x.is_nan(), y.is_nan()) {
+                            (true, true) => None,
+                            (true, false) => Some(false),
+                            (false, true) => Some(true),
+                            _ if x == y => Some(x.is_sign_negative() != max),
+                            _ => Some((x < y) != max),
+                        };
+                        match pick_x {
+                            Some(true) => x.to_bits(),
+                            Some(false) => y.to_bits(),
+                            None => CANONICAL_NAN,
+                        }
+                    },
+                };
+                self.fregs[rd] = nan_box(bits);
+                return Ok(next)
+            },
+            "feq.s" | "flt.s" | "fle.s" => {
+                let (x, y) = (unbox(fa), unbox(fb));
+                // feq.s is a quiet comparison, only signalling NaNs are invalid
+                let invalid = if name == "feq.s" { is_signalling(x) || is_signalling(y) } else { x.is_nan() || y.is_nan() };
+                if invalid {
+                    self.raise(FFLAGS_NV);
+                }
+                match name {
+                    "feq.s" => (x == y) as u64,
+                    "flt.s" => (x < y) as u64,
+                    _ => (x <= y) as u64,
+                }
+            },
+            "fcvt.w.s" | "fcvt.wu.s" => {
+                let rm = self.rounding_mode(ins)?;
+                let value = unbox(fa) as f64;
+                let rounded = match rm {
+                    0b001 => value.trunc(),
+                    0b010 => value.floor(),
+                    0b011 => value.ceil(),
+                    0b100 => value.round(),
+                    _ => value.round_ties_even(),
+                };
+                let (min, max) = if name == "fcvt.wu.s" { (0.0, u32::MAX as f64) } else { (i32::MIN as f64, i32::MAX as f64) };
+                // NaN and values out of range saturate
+                let result = if value.is_nan() || rounded > max {
+                    self.raise(FFLAGS_NV);
+                    max
+                } else if rounded < min {
+                    self.raise(FFLAGS_NV);
+                    min
+                } else {
+                    if rounded != value {
+                        self.raise(FFLAGS_NX);
+                    }
+                    rounded
+                };
+                // the 32-bit result is sign-extended either way
+                if name == "fcvt.wu.s" { result as u32 as i32 as u64 } else { result as i32 as u64 }
+            },
+            "fcvt.s.w" | "fcvt.s.wu" => {
+                let rm = self.rounding_mode(ins)?;
+                let value = if name == "fcvt.s.w" { a as i32 as i64 } else { a as u32 as i64 };
+                let (single, inexact) = int_to_single(value, rm);
+                if inexact {
+                    self.raise(FFLAGS_NX);
+                }
+                self.fregs[rd] = nan_box(single.to_bits());
+                return Ok(next)
+            },
+            // the moves copy the bits as they are, NaN-boxed or not
+            "fmv.x.w" => fa as u32 as i32 as u64,
+            "fmv.w.x" => {
+                self.fregs[rd] = nan_box(a as u32);
+                return Ok(next)
+            },
+            "fclass.s" => classify(unbox(fa)),
+            _ => unreachable!("{} has no semantics", name),
+        };
+        self.write(rd, value);
+        Ok(next)
+    }
+}
+
+impl SoC for ReferenceSoC {
+    fn with_entry(bin: Vec<u8>, entry: u64) -> Self {
+        let bus = Bus::new(bin);
+        let mut regs = [0_u64; 32];
+        regs[2] = bus.base + bus.size - 1;
+        let pc = entry;
+        let csr = Csr::new();
+        let stats = Stats::new();
+        let mix = InsMix::new();
+        let profile = Profile::new();
+        Self { regs, fregs: [0; 32], pc, bus, csr, stats, mix, profile, config: Config { entry, ..Config::default() }, observer: None }
+    }
+
+    fn pipeline(&mut self) -> Result {
+        let pc = self.pc;
+        let fetched = self.bus.fetch(pc)?;
+        let rv64 = self.csr.xlen() == 64;
+        let exts = self.config.enabled_exts;
+        // the low two bits are 0b11 for every 32-bit instruction
+        let (word, ins, len) = if fetched & 0b11 == 0b11 {
+            (fetched, fetched, 4)
+        } else if exts.has(Exts::C) {
+            let word = fetched & 0xffff;
+            (word, expand(word as u16, rv64)?, 2)
+        } else {
+            return Err(Exception::IllegalInstruction((fetched & 0xffff) as u64))
+        };
+        let name = mnemonic(ins, rv64, exts).ok_or(Exception::IllegalInstruction(word as u64))?;
+        let memory = matches!(ins & 0x7f, 0b0000011 | 0b0100011 | 0b0101111 | 0b0000111 | 0b0100111);
+        if memory {
+            self.stats.mem_ops += 1;
+        } else {
+            self.stats.alu_ops += 1;
+        }
+        let overflow = self.config.record_overflow && match name {
+            "addi" | "add" | "sub" => {
+                let xlen = self.csr.xlen();
+                let wide = |value: u64| if xlen == 32 { value as i32 as i128 } else { value as i64 as i128 };
+                let (x, y) = (wide(self.regs[rs1(ins)]), if name == "addi" { wide(imm_i(ins)) } else { wide(self.regs[rs2(ins)]) });
+                let result = if name == "sub" { x - y } else { x + y };
+                result != wide(result as u64)
+            },
+            _ => false,
+        };
+        self.bus.watch_pc = pc;
+        self.pc = self.execute(name, ins, len)?;
+        if overflow {
+            self.stats.overflows.push(pc);
+        }
+        self.mix.record(name);
+        self.profile.record(pc);
+        self.stats.retired += 1;
+        if self.csr.xlen() == 32 {
+            self.regs.iter_mut().for_each(|r| *r &= 0xffff_ffff);
+            self.pc &= 0xffff_ffff;
+        }
+        if let Some(observer) = &mut self.observer {
+            observer.on_commit(pc, word, &self.regs);
+        }
+        Ok(())
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn pc_mut(&mut self) -> &mut u64 {
+        &mut self.pc
+    }
+
+    fn regfile(&self) -> &[u64; 32] {
+        &self.regs
+    }
+
+    fn regfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.regs
+    }
+
+    fn fregfile(&self) -> &[u64; 32] {
+        &self.fregs
+    }
+
+    fn fregfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.fregs
+    }
+
+    fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    fn csr(&mut self) -> &mut Csr {
+        &mut self.csr
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    fn mix(&self) -> &InsMix {
+        &self.mix
+    }
+
+    fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    fn reset(&mut self, bin: Vec<u8>) {
+        self.bus.reload(&bin);
+        self.regs = [0; 32];
+        self.fregs = [0; 32];
+        self.regs[2] = self.bus.base + self.bus.size - 1;
+        self.pc = self.config.entry;
+        // keep the XLEN and extensions the run was configured with
+        let misa = self.csr.load(MISA);
+        self.csr = Csr::new();
+        self.csr.store(MISA, misa);
+        self.stats = Stats::new();
+        self.mix = InsMix::new();
+        self.profile = Profile::new();
+    }
+}
+
+/// The first point at which a model and the reference disagreed
+pub struct Divergence {
+    /// Pc of the instruction both models had just run
+    pub pc: u64,
+    /// Instructions run before the divergence
+    pub steps: usize,
+    pub dut_pc: u64,
+    pub dut_regs: [u64; 32],
+    pub dut_fregs: [u64; 32],
+    /// Physical address, size and value of the store the instruction made
+    pub dut_store: Option<(u64, u64, u64)>,
+    pub ref_pc: u64,
+    pub ref_regs: [u64; 32],
+    pub ref_fregs: [u64; 32],
+    pub ref_store: Option<(u64, u64, u64)>,
+}
+
+/// The store `bus` has made since it had made `before` of them
+fn store_since(bus: &Bus, before: usize) -> Option<(u64, u64, u64)> {
+    bus.last_store.filter(|_| bus.stores != before)
+}
+
+/// Steps `dut` and `reference` one instruction at a time until both stop,
+/// comparing pc, registers and the stores made after every step. Returns
+/// the number of instructions run, or the first divergence.
+pub fn lockstep(dut: &mut dyn SoC, reference: &mut ReferenceSoC, max_steps: Option<usize>) -> std::result::Result<usize, Box<Divergence>> {
+    let mut steps = 0;
+    while max_steps.is_none_or(|max| steps < max) {
+        let pc = reference.pc;
+        let (ref_stores, dut_stores) = (reference.bus.stores, dut.bus().stores);
+        let ref_exit = reference.step();
+        let dut_exit = dut.step();
+        steps += 1;
+        let same_outcome = match (&ref_exit, &dut_exit) {
+            (Ok(_), Ok(_)) => true,
+            (Err(a), Err(b)) => discriminant(a) == discriminant(b),
+            _ => false,
+        };
+        let dut_pc = *dut.pc_mut();
+        let (ref_store, dut_store) = (store_since(&reference.bus, ref_stores), store_since(dut.bus(), dut_stores));
+        if !same_outcome || dut_pc != reference.pc || *dut.regfile() != reference.regs
+            || *dut.fregfile() != reference.fregs || dut_store != ref_store {
+            return Err(Box::new(Divergence {
+                pc, steps, dut_pc, dut_regs: *dut.regfile(), dut_fregs: *dut.fregfile(), dut_store,
+                ref_pc: reference.pc, ref_regs: reference.regs, ref_fregs: reference.fregs, ref_store,
+            }))
+        }
+        if ref_exit.is_err() {
+            break
+        }
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::to_bin, kronos::KronosSoC, dart::DartSoC, bus::RAM_BASE, mem::B32, soc::SoC};
+    use super::{ReferenceSoC, lockstep};
+
+    const PROGRAM: [u32; 4] = [
+        0x00100513, // addi a0, zero, 1
+        0x00200593, // addi a1, zero, 2
+        0x00b50633, // add a2, a0, a1
+        0x00c606b3, // add a3, a2, a2
+    ];
+
+    #[test]
+    fn models_agree() {
+        let mut dut = KronosSoC::new(to_bin(&PROGRAM));
+        let mut reference = ReferenceSoC::new(to_bin(&PROGRAM));
+        // the four instructions and the illegal instruction after them
+        assert_eq!(lockstep(&mut dut, &mut reference, None).ok(), Some(5));
+    }
+
+    // one or more instructions from each extension, a compressed pair and
+    // a taken jump and branch
+    const EXTENSIONS: [u32; 27] = [
+        0xff900513, // addi a0, zero, -7
+        0x00300593, // addi a1, zero, 3
+        0x02b50633, // mul a2, a0, a1
+        0x02b546b3, // div a3, a0, a1
+        0x02b57733, // remu a4, a0, a1
+        0x02b517b3, // mulh a5, a0, a1
+        0x00000297, // auipc t0, 0
+        0x10028293, // addi t0, t0, 256
+        0x00a2b023, // sd a0, 0(t0)
+        0x00b2b82f, // amoadd.d a6, a1, (t0)
+        0x1002a8af, // lr.w a7, (t0)
+        0x18b2a92f, // sc.w s2, a1, (t0)
+        0x60051993, // clz s3, a0
+        0x60b55a33, // ror s4, a0, a1
+        0x60461a93, // sext.b s5, a2
+        0xd0057053, // fcvt.s.w ft0, a0
+        0xd005f0d3, // fcvt.s.w ft1, a1
+        0x18107153, // fdiv.s ft2, ft0, ft1
+        0xc0011b53, // fcvt.w.s s6, ft2, rtz
+        0xe0011bd3, // fclass.s s7, ft2
+        0x00102c73, // csrrs s8, fflags, zero
+        0x00010505, // c.addi a0, 1; c.nop
+        0x00a50463, // beq a0, a0, 8
+        0x00100c93, // addi s9, zero, 1
+        0x008000ef, // jal ra, 8
+        0x00100d13, // addi s10, zero, 1
+        0x0162a223, // sw s6, 4(t0)
+    ];
+
+    #[test]
+    fn models_agree_across_extensions() {
+        // 27 words, one holding two instructions, two skipped, and the
+        // illegal instruction after them
+        let mut dut = DartSoC::new(to_bin(&EXTENSIONS));
+        let mut reference = ReferenceSoC::new(to_bin(&EXTENSIONS));
+        assert_eq!(lockstep(&mut dut, &mut reference, None).ok(), Some(27));
+        let mut dut = KronosSoC::new(to_bin(&EXTENSIONS));
+        let mut reference = ReferenceSoC::new(to_bin(&EXTENSIONS));
+        assert_eq!(lockstep(&mut dut, &mut reference, None).ok(), Some(27));
+        let regs = reference.regs;
+        assert_eq!(regs[12], -21_i64 as u64);
+        assert_eq!(regs[13], -2_i64 as u64);
+        assert_eq!(regs[16], -7_i64 as u64);
+        assert_eq!(regs[18], 0);
+        assert_eq!(regs[22], -2_i64 as u64);
+        assert_eq!(regs[24], 1);
+        assert_eq!((regs[25], regs[26]), (0, 0));
+    }
+
+    #[test]
+    fn perturbed_instruction_diverges() {
+        let mut dut = KronosSoC::new(to_bin(&PROGRAM));
+        // turn the first add into a sub
+        dut.bus_mut().store(RAM_BASE + 8, B32, 0x40b50633).unwrap();
+        let mut reference = ReferenceSoC::new(to_bin(&PROGRAM));
+        let Err(div) = lockstep(&mut dut, &mut reference, None) else {
+            panic!("divergence not detected")
+        };
+        assert_eq!(div.pc, RAM_BASE + 8);
+        assert_eq!(div.steps, 3);
+        assert_eq!(div.ref_regs[12], 3);
+        assert_eq!(div.dut_regs[12], -1_i64 as u64);
+    }
+
+    #[test]
+    fn misdirected_store_diverges() {
+        let program = [
+            0x00100513, // addi a0, zero, 1
+            0x00000297, // auipc t0, 0
+            0x04a2a023, // sw a0, 64(t0)
+        ];
+        let mut dut = KronosSoC::new(to_bin(&program));
+        // same registers afterwards, but a different word in memory
+        dut.bus_mut().store(RAM_BASE + 8, B32, 0x04a2a223).unwrap(); // sw a0, 68(t0)
+        let mut reference = ReferenceSoC::new(to_bin(&program));
+        let Err(div) = lockstep(&mut dut, &mut reference, None) else {
+            panic!("divergence not detected")
+        };
+        assert_eq!(div.pc, RAM_BASE + 8);
+        assert_eq!(div.dut_regs, div.ref_regs);
+        assert_eq!(div.ref_store, Some((RAM_BASE + 68, 4, 1)));
+        assert_eq!(div.dut_store, Some((RAM_BASE + 72, 4, 1)));
+    }
+}
\ No newline at end of file
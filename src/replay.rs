@@ -0,0 +1,120 @@
+use std::fmt::Display;
+
+use crate::{bus::Bus, soc::{SoC, StepOutcome, fetch_word}, csr::parse_u64};
+
+/// One retired instruction of a recorded run, written as a line of the log
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LogEntry {
+    pub pc: u64,
+    /// The instruction word, only the low half for a compressed instruction
+    pub word: u32,
+}
+
+impl Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010x} {:#010x}", self.pc, self.word)
+    }
+}
+
+/// Returns an execute_with callback that appends every retired instruction
+/// to `log`
+pub fn recorder(log: &mut Vec<LogEntry>) -> impl FnMut(&Bus, StepOutcome) + '_ {
+    |bus, step| {
+        if let Some(word) = fetch_word(bus, step.pc) {
+            log.push(LogEntry { pc: step.pc, word });
+        }
+    }
+}
+
+/// Writes a log in the newline-delimited `pc word` format read by parse_log
+pub fn format_log(log: &[LogEntry]) -> String {
+    log.iter().map(|entry| format!("{}\n", entry)).collect()
+}
+
+/// Parses a log written by --record, e.g. `0x80000000 0x00000513`
+pub fn parse_log(text: &str) -> Result<Vec<LogEntry>, String> {
+    text.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let mut fields = line.split_whitespace().map(parse_u64);
+            match (fields.next().flatten(), fields.next().flatten(), fields.next()) {
+                (Some(pc), Some(word), None) if word <= u32::MAX as u64 => Ok(LogEntry { pc, word: word as u32 }),
+                _ => Err(format!("line {}: expected \"pc word\", found \"{}\"", i + 1, line)),
+            }
+        })
+        .collect()
+}
+
+/// The first point at which a replayed run left the recorded one
+#[derive(Debug)]
+pub struct Mismatch {
+    /// Instructions that matched before it
+    pub index: usize,
+    pub expected: LogEntry,
+    /// What retired instead, None if the SoC stopped first
+    pub found: Option<LogEntry>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.found {
+            Some(found) => write!(f, "instruction {} retired {}, the log has {}", self.index, found, self.expected),
+            None => write!(f, "stopped before instruction {}, the log has {}", self.index, self.expected),
+        }
+    }
+}
+
+/// Steps `cpu` through a recorded run, checking that each instruction to
+/// retire has the pc and word of the next log entry. Traps are replayed as
+/// they happen since only retired instructions are logged.
+pub fn replay(cpu: &mut dyn SoC, log: &[LogEntry]) -> Result<(), Box<Mismatch>> {
+    for (index, expected) in log.iter().enumerate() {
+        let found = loop {
+            match cpu.step() {
+                Ok(step) if step.retired => break fetch_word(cpu.bus(), step.pc).map(|word| LogEntry { pc: step.pc, word }),
+                Ok(_) => continue,
+                Err(_) => break None,
+            }
+        };
+        if found != Some(*expected) {
+            return Err(Box::new(Mismatch { index, expected: *expected, found }))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dart::DartSoC, isa::to_bin, soc::SoC};
+    use super::{LogEntry, recorder, format_log, parse_log, replay};
+
+    const PROGRAM: [u32; 5] = [
+        0x00000513, // addi a0, zero, 0
+        0x00500593, // addi a1, zero, 5
+        0x00b50533, // add a0, a0, a1
+        0xfff58593, // addi a1, a1, -1
+        0xfe059ce3, // bne a1, zero, -8
+    ];
+
+    #[test]
+    fn record_and_replay() {
+        let mut recorded = DartSoC::new(to_bin(&PROGRAM));
+        let mut log = Vec::new();
+        recorded.execute_with(&mut recorder(&mut log));
+        assert_eq!(log.len(), 17);
+        assert_eq!(log[2], LogEntry { pc: 0x8000_0008, word: 0x00b50533 });
+        let log = parse_log(&format_log(&log)).unwrap();
+
+        let mut replayed = DartSoC::new(to_bin(&PROGRAM));
+        replay(&mut replayed, &log).unwrap();
+        assert_eq!(replayed.regs, recorded.regs);
+        assert_eq!(replayed.pc, recorded.pc);
+
+        // one fewer loop iteration leaves the recorded path at the branch
+        let mut program = PROGRAM;
+        program[1] = 0x00400593; // addi a1, zero, 4
+        let mismatch = replay(&mut DartSoC::new(to_bin(&program)), &log).unwrap_err();
+        assert_eq!(mismatch.index, 1);
+        assert_eq!(mismatch.found, Some(LogEntry { pc: 0x8000_0004, word: 0x00400593 }));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,576 @@
+use std::fmt::Display;
+
+use crate::{exception::Exception, bus::Bus, csr::Csr, mem::{B32, B64}, isa::{Extension, opcode, funct3, funct7, rd, rs1, rs2, reg_name, write_reg}};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Rv32a {
+    LrW { rd: u64, rs1: u64, rs2: u64 },
+    ScW { rd: u64, rs1: u64, rs2: u64 },
+    AmoswapW { rd: u64, rs1: u64, rs2: u64 },
+    AmoaddW { rd: u64, rs1: u64, rs2: u64 },
+    AmoxorW { rd: u64, rs1: u64, rs2: u64 },
+    AmoandW { rd: u64, rs1: u64, rs2: u64 },
+    AmoorW { rd: u64, rs1: u64, rs2: u64 },
+    AmominW { rd: u64, rs1: u64, rs2: u64 },
+    AmomaxW { rd: u64, rs1: u64, rs2: u64 },
+    AmominuW { rd: u64, rs1: u64, rs2: u64 },
+    AmomaxuW { rd: u64, rs1: u64, rs2: u64 },
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Rv64a {
+    LrD { rd: u64, rs1: u64, rs2: u64 },
+    ScD { rd: u64, rs1: u64, rs2: u64 },
+    AmoswapD { rd: u64, rs1: u64, rs2: u64 },
+    AmoaddD { rd: u64, rs1: u64, rs2: u64 },
+    AmoxorD { rd: u64, rs1: u64, rs2: u64 },
+    AmoandD { rd: u64, rs1: u64, rs2: u64 },
+    AmoorD { rd: u64, rs1: u64, rs2: u64 },
+    AmominD { rd: u64, rs1: u64, rs2: u64 },
+    AmomaxD { rd: u64, rs1: u64, rs2: u64 },
+    AmominuD { rd: u64, rs1: u64, rs2: u64 },
+    AmomaxuD { rd: u64, rs1: u64, rs2: u64 },
+}
+
+/// Atomically replaces the word at addr with op(old), returning the old
+/// value sign-extended
+fn amo_w(bus: &mut Bus, addr: u64, op: impl Fn(u32) -> u32) -> Result<u64, Exception> {
+    if !addr.is_multiple_of(4) {
+        return Err(Exception::StoreAMOAddrMisaligned(addr))
+    }
+    let old = bus.load(addr, B32)? as u32;
+    bus.store(addr, B32, op(old) as u64)?;
+    Ok(old as i32 as i64 as u64)
+}
+
+/// Atomically replaces the doubleword at addr with op(old), returning the
+/// old value
+fn amo_d(bus: &mut Bus, addr: u64, op: impl Fn(u64) -> u64) -> Result<u64, Exception> {
+    if !addr.is_multiple_of(8) {
+        return Err(Exception::StoreAMOAddrMisaligned(addr))
+    }
+    let old = bus.load(addr, B64)?;
+    bus.store(addr, B64, op(old))?;
+    Ok(old)
+}
+
+impl Extension for Rv32a {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        let opcode = opcode(ins);
+        let funct3 = funct3(ins);
+        // the low two bits of funct7 are the aq/rl ordering hints, which
+        // have no effect on a single hart
+        let funct5 = funct7(ins) >> 2;
+
+        let rd = rd(ins) as u64;
+        let rs1 = rs1(ins) as u64;
+        let rs2 = rs2(ins) as u64;
+
+        match (funct5, funct3, opcode) {
+            (0b00010, 0b010, 0b0101111) if rs2 == 0 => Ok(Self::LrW { rd, rs1, rs2 }),
+            (0b00011, 0b010, 0b0101111) => Ok(Self::ScW { rd, rs1, rs2 }),
+            (0b00001, 0b010, 0b0101111) => Ok(Self::AmoswapW { rd, rs1, rs2 }),
+            (0b00000, 0b010, 0b0101111) => Ok(Self::AmoaddW { rd, rs1, rs2 }),
+            (0b00100, 0b010, 0b0101111) => Ok(Self::AmoxorW { rd, rs1, rs2 }),
+            (0b01100, 0b010, 0b0101111) => Ok(Self::AmoandW { rd, rs1, rs2 }),
+            (0b01000, 0b010, 0b0101111) => Ok(Self::AmoorW { rd, rs1, rs2 }),
+            (0b10000, 0b010, 0b0101111) => Ok(Self::AmominW { rd, rs1, rs2 }),
+            (0b10100, 0b010, 0b0101111) => Ok(Self::AmomaxW { rd, rs1, rs2 }),
+            (0b11000, 0b010, 0b0101111) => Ok(Self::AmominuW { rd, rs1, rs2 }),
+            (0b11100, 0b010, 0b0101111) => Ok(Self::AmomaxuW { rd, rs1, rs2 }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        match self {
+            Rv32a::LrW { rd, rs1, rs2 } => Self::LrW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::ScW { rd, rs1, rs2 } => Self::ScW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmoswapW { rd, rs1, rs2 } => Self::AmoswapW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmoaddW { rd, rs1, rs2 } => Self::AmoaddW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmoxorW { rd, rs1, rs2 } => Self::AmoxorW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmoandW { rd, rs1, rs2 } => Self::AmoandW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmoorW { rd, rs1, rs2 } => Self::AmoorW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmominW { rd, rs1, rs2 } => Self::AmominW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmomaxW { rd, rs1, rs2 } => Self::AmomaxW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmominuW { rd, rs1, rs2 } => Self::AmominuW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32a::AmomaxuW { rd, rs1, rs2 } => Self::AmomaxuW { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+        }
+    }
+
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], _csr: &mut Csr, bus: &mut Bus) -> Result<u64, Exception> {
+        match self {
+            Rv32a::LrW { rd, rs1, .. } => {
+                if !rs1.is_multiple_of(4) {
+                    return Err(Exception::LoadAccessMisaligned(rs1))
+                }
+                write_reg(regs, rd, bus.load(rs1, B32)? as i32 as i64 as u64);
+                bus.reservation = Some(rs1);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::ScW { rd, rs1, rs2 } => {
+                if !rs1.is_multiple_of(4) {
+                    return Err(Exception::StoreAMOAddrMisaligned(rs1))
+                }
+                if bus.reservation.take() == Some(rs1) {
+                    bus.store(rs1, B32, rs2 & 0xffffffff)?;
+                    write_reg(regs, rd, 0);
+                } else {
+                    write_reg(regs, rd, 1);
+                }
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmoswapW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |_| rs2 as u32)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmoaddW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |old| old.wrapping_add(rs2 as u32))?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmoxorW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |old| old ^ rs2 as u32)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmoandW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |old| old & rs2 as u32)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmoorW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |old| old | rs2 as u32)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmominW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |old| (old as i32).min(rs2 as i32) as u32)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmomaxW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |old| (old as i32).max(rs2 as i32) as u32)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmominuW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |old| old.min(rs2 as u32))?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32a::AmomaxuW { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_w(bus, rs1, |old| old.max(rs2 as u32))?);
+                Ok(pc.wrapping_add(len))
+            },
+        }
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            Rv32a::LrW { rs1, .. } => vec![*rs1],
+            Rv32a::ScW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmoswapW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmoaddW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmoxorW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmoandW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmoorW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmominW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmomaxW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmominuW { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32a::AmomaxuW { rs1, rs2, .. } => vec![*rs1, *rs2],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        match self {
+            Rv32a::LrW { rd, .. } => Some(*rd),
+            Rv32a::ScW { rd, .. } => Some(*rd),
+            Rv32a::AmoswapW { rd, .. } => Some(*rd),
+            Rv32a::AmoaddW { rd, .. } => Some(*rd),
+            Rv32a::AmoxorW { rd, .. } => Some(*rd),
+            Rv32a::AmoandW { rd, .. } => Some(*rd),
+            Rv32a::AmoorW { rd, .. } => Some(*rd),
+            Rv32a::AmominW { rd, .. } => Some(*rd),
+            Rv32a::AmomaxW { rd, .. } => Some(*rd),
+            Rv32a::AmominuW { rd, .. } => Some(*rd),
+            Rv32a::AmomaxuW { rd, .. } => Some(*rd),
+        }
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        match self {
+            Rv32a::ScW { .. } => None,
+            Rv32a::LrW { rs1, .. } |
+            Rv32a::AmoswapW { rs1, .. } |
+            Rv32a::AmoaddW { rs1, .. } |
+            Rv32a::AmoxorW { rs1, .. } |
+            Rv32a::AmoandW { rs1, .. } |
+            Rv32a::AmoorW { rs1, .. } |
+            Rv32a::AmominW { rs1, .. } |
+            Rv32a::AmomaxW { rs1, .. } |
+            Rv32a::AmominuW { rs1, .. } |
+            Rv32a::AmomaxuW { rs1, .. } => Some(*rs1),
+        }
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        match self {
+            Rv32a::LrW { .. } => None,
+            Rv32a::ScW { rs1, .. } |
+            Rv32a::AmoswapW { rs1, .. } |
+            Rv32a::AmoaddW { rs1, .. } |
+            Rv32a::AmoxorW { rs1, .. } |
+            Rv32a::AmoandW { rs1, .. } |
+            Rv32a::AmoorW { rs1, .. } |
+            Rv32a::AmominW { rs1, .. } |
+            Rv32a::AmomaxW { rs1, .. } |
+            Rv32a::AmominuW { rs1, .. } |
+            Rv32a::AmomaxuW { rs1, .. } => Some(*rs1),
+        }
+    }
+
+    fn is_ld(&self) -> bool {
+        !matches!(self, Rv32a::ScW { .. })
+    }
+
+    fn is_st(&self) -> bool {
+        !matches!(self, Rv32a::LrW { .. })
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Rv32a::LrW { rd, rs1, .. } => format!("lr.w {}, ({})", reg_name(*rd), reg_name(*rs1)),
+            Rv32a::ScW { rd, rs1, rs2 } => format!("sc.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmoswapW { rd, rs1, rs2 } => format!("amoswap.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmoaddW { rd, rs1, rs2 } => format!("amoadd.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmoxorW { rd, rs1, rs2 } => format!("amoxor.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmoandW { rd, rs1, rs2 } => format!("amoand.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmoorW { rd, rs1, rs2 } => format!("amoor.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmominW { rd, rs1, rs2 } => format!("amomin.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmomaxW { rd, rs1, rs2 } => format!("amomax.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmominuW { rd, rs1, rs2 } => format!("amominu.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv32a::AmomaxuW { rd, rs1, rs2 } => format!("amomaxu.w {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Rv32a::LrW { .. } => "lr.w",
+            Rv32a::ScW { .. } => "sc.w",
+            Rv32a::AmoswapW { .. } => "amoswap.w",
+            Rv32a::AmoaddW { .. } => "amoadd.w",
+            Rv32a::AmoxorW { .. } => "amoxor.w",
+            Rv32a::AmoandW { .. } => "amoand.w",
+            Rv32a::AmoorW { .. } => "amoor.w",
+            Rv32a::AmominW { .. } => "amomin.w",
+            Rv32a::AmomaxW { .. } => "amomax.w",
+            Rv32a::AmominuW { .. } => "amominu.w",
+            Rv32a::AmomaxuW { .. } => "amomaxu.w",
+        }
+    }
+}
+
+impl Extension for Rv64a {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        let opcode = opcode(ins);
+        let funct3 = funct3(ins);
+        let funct5 = funct7(ins) >> 2;
+
+        let rd = rd(ins) as u64;
+        let rs1 = rs1(ins) as u64;
+        let rs2 = rs2(ins) as u64;
+
+        match (funct5, funct3, opcode) {
+            (0b00010, 0b011, 0b0101111) if rs2 == 0 => Ok(Self::LrD { rd, rs1, rs2 }),
+            (0b00011, 0b011, 0b0101111) => Ok(Self::ScD { rd, rs1, rs2 }),
+            (0b00001, 0b011, 0b0101111) => Ok(Self::AmoswapD { rd, rs1, rs2 }),
+            (0b00000, 0b011, 0b0101111) => Ok(Self::AmoaddD { rd, rs1, rs2 }),
+            (0b00100, 0b011, 0b0101111) => Ok(Self::AmoxorD { rd, rs1, rs2 }),
+            (0b01100, 0b011, 0b0101111) => Ok(Self::AmoandD { rd, rs1, rs2 }),
+            (0b01000, 0b011, 0b0101111) => Ok(Self::AmoorD { rd, rs1, rs2 }),
+            (0b10000, 0b011, 0b0101111) => Ok(Self::AmominD { rd, rs1, rs2 }),
+            (0b10100, 0b011, 0b0101111) => Ok(Self::AmomaxD { rd, rs1, rs2 }),
+            (0b11000, 0b011, 0b0101111) => Ok(Self::AmominuD { rd, rs1, rs2 }),
+            (0b11100, 0b011, 0b0101111) => Ok(Self::AmomaxuD { rd, rs1, rs2 }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        match self {
+            Rv64a::LrD { rd, rs1, rs2 } => Self::LrD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::ScD { rd, rs1, rs2 } => Self::ScD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmoswapD { rd, rs1, rs2 } => Self::AmoswapD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmoaddD { rd, rs1, rs2 } => Self::AmoaddD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmoxorD { rd, rs1, rs2 } => Self::AmoxorD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmoandD { rd, rs1, rs2 } => Self::AmoandD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmoorD { rd, rs1, rs2 } => Self::AmoorD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmominD { rd, rs1, rs2 } => Self::AmominD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmomaxD { rd, rs1, rs2 } => Self::AmomaxD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmominuD { rd, rs1, rs2 } => Self::AmominuD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64a::AmomaxuD { rd, rs1, rs2 } => Self::AmomaxuD { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+        }
+    }
+
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], _csr: &mut Csr, bus: &mut Bus) -> Result<u64, Exception> {
+        match self {
+            Rv64a::LrD { rd, rs1, .. } => {
+                if !rs1.is_multiple_of(8) {
+                    return Err(Exception::LoadAccessMisaligned(rs1))
+                }
+                write_reg(regs, rd, bus.load(rs1, B64)?);
+                bus.reservation = Some(rs1);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::ScD { rd, rs1, rs2 } => {
+                if !rs1.is_multiple_of(8) {
+                    return Err(Exception::StoreAMOAddrMisaligned(rs1))
+                }
+                if bus.reservation.take() == Some(rs1) {
+                    bus.store(rs1, B64, rs2)?;
+                    write_reg(regs, rd, 0);
+                } else {
+                    write_reg(regs, rd, 1);
+                }
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmoswapD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |_| rs2)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmoaddD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |old| old.wrapping_add(rs2))?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmoxorD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |old| old ^ rs2)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmoandD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |old| old & rs2)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmoorD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |old| old | rs2)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmominD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |old| (old as i64).min(rs2 as i64) as u64)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmomaxD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |old| (old as i64).max(rs2 as i64) as u64)?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmominuD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |old| old.min(rs2))?);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64a::AmomaxuD { rd, rs1, rs2 } => {
+                write_reg(regs, rd, amo_d(bus, rs1, |old| old.max(rs2))?);
+                Ok(pc.wrapping_add(len))
+            },
+        }
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            Rv64a::LrD { rs1, .. } => vec![*rs1],
+            Rv64a::ScD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmoswapD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmoaddD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmoxorD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmoandD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmoorD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmominD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmomaxD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmominuD { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64a::AmomaxuD { rs1, rs2, .. } => vec![*rs1, *rs2],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        match self {
+            Rv64a::LrD { rd, .. } => Some(*rd),
+            Rv64a::ScD { rd, .. } => Some(*rd),
+            Rv64a::AmoswapD { rd, .. } => Some(*rd),
+            Rv64a::AmoaddD { rd, .. } => Some(*rd),
+            Rv64a::AmoxorD { rd, .. } => Some(*rd),
+            Rv64a::AmoandD { rd, .. } => Some(*rd),
+            Rv64a::AmoorD { rd, .. } => Some(*rd),
+            Rv64a::AmominD { rd, .. } => Some(*rd),
+            Rv64a::AmomaxD { rd, .. } => Some(*rd),
+            Rv64a::AmominuD { rd, .. } => Some(*rd),
+            Rv64a::AmomaxuD { rd, .. } => Some(*rd),
+        }
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        match self {
+            Rv64a::ScD { .. } => None,
+            Rv64a::LrD { rs1, .. } |
+            Rv64a::AmoswapD { rs1, .. } |
+            Rv64a::AmoaddD { rs1, .. } |
+            Rv64a::AmoxorD { rs1, .. } |
+            Rv64a::AmoandD { rs1, .. } |
+            Rv64a::AmoorD { rs1, .. } |
+            Rv64a::AmominD { rs1, .. } |
+            Rv64a::AmomaxD { rs1, .. } |
+            Rv64a::AmominuD { rs1, .. } |
+            Rv64a::AmomaxuD { rs1, .. } => Some(*rs1),
+        }
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        match self {
+            Rv64a::LrD { .. } => None,
+            Rv64a::ScD { rs1, .. } |
+            Rv64a::AmoswapD { rs1, .. } |
+            Rv64a::AmoaddD { rs1, .. } |
+            Rv64a::AmoxorD { rs1, .. } |
+            Rv64a::AmoandD { rs1, .. } |
+            Rv64a::AmoorD { rs1, .. } |
+            Rv64a::AmominD { rs1, .. } |
+            Rv64a::AmomaxD { rs1, .. } |
+            Rv64a::AmominuD { rs1, .. } |
+            Rv64a::AmomaxuD { rs1, .. } => Some(*rs1),
+        }
+    }
+
+    fn is_ld(&self) -> bool {
+        !matches!(self, Rv64a::ScD { .. })
+    }
+
+    fn is_st(&self) -> bool {
+        !matches!(self, Rv64a::LrD { .. })
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Rv64a::LrD { rd, rs1, .. } => format!("lr.d {}, ({})", reg_name(*rd), reg_name(*rs1)),
+            Rv64a::ScD { rd, rs1, rs2 } => format!("sc.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmoswapD { rd, rs1, rs2 } => format!("amoswap.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmoaddD { rd, rs1, rs2 } => format!("amoadd.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmoxorD { rd, rs1, rs2 } => format!("amoxor.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmoandD { rd, rs1, rs2 } => format!("amoand.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmoorD { rd, rs1, rs2 } => format!("amoor.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmominD { rd, rs1, rs2 } => format!("amomin.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmomaxD { rd, rs1, rs2 } => format!("amomax.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmominuD { rd, rs1, rs2 } => format!("amominu.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+            Rv64a::AmomaxuD { rd, rs1, rs2 } => format!("amomaxu.d {}, {}, ({})", reg_name(*rd), reg_name(*rs2), reg_name(*rs1)),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Rv64a::LrD { .. } => "lr.d",
+            Rv64a::ScD { .. } => "sc.d",
+            Rv64a::AmoswapD { .. } => "amoswap.d",
+            Rv64a::AmoaddD { .. } => "amoadd.d",
+            Rv64a::AmoxorD { .. } => "amoxor.d",
+            Rv64a::AmoandD { .. } => "amoand.d",
+            Rv64a::AmoorD { .. } => "amoor.d",
+            Rv64a::AmominD { .. } => "amomin.d",
+            Rv64a::AmomaxD { .. } => "amomax.d",
+            Rv64a::AmominuD { .. } => "amominu.d",
+            Rv64a::AmomaxuD { .. } => "amomaxu.d",
+        }
+    }
+}
+
+impl Display for Rv32a {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rv32a::LrW { rd, rs1, .. } => write!(f, "lr.w rd={}, rs1={}", rd, rs1),
+            Rv32a::ScW { rd, rs1, rs2 } => write!(f, "sc.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmoswapW { rd, rs1, rs2 } => write!(f, "amoswap.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmoaddW { rd, rs1, rs2 } => write!(f, "amoadd.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmoxorW { rd, rs1, rs2 } => write!(f, "amoxor.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmoandW { rd, rs1, rs2 } => write!(f, "amoand.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmoorW { rd, rs1, rs2 } => write!(f, "amoor.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmominW { rd, rs1, rs2 } => write!(f, "amomin.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmomaxW { rd, rs1, rs2 } => write!(f, "amomax.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmominuW { rd, rs1, rs2 } => write!(f, "amominu.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32a::AmomaxuW { rd, rs1, rs2 } => write!(f, "amomaxu.w rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+        }
+    }
+}
+
+impl Display for Rv64a {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rv64a::LrD { rd, rs1, .. } => write!(f, "lr.d rd={}, rs1={}", rd, rs1),
+            Rv64a::ScD { rd, rs1, rs2 } => write!(f, "sc.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmoswapD { rd, rs1, rs2 } => write!(f, "amoswap.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmoaddD { rd, rs1, rs2 } => write!(f, "amoadd.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmoxorD { rd, rs1, rs2 } => write!(f, "amoxor.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmoandD { rd, rs1, rs2 } => write!(f, "amoand.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmoorD { rd, rs1, rs2 } => write!(f, "amoor.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmominD { rd, rs1, rs2 } => write!(f, "amomin.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmomaxD { rd, rs1, rs2 } => write!(f, "amomax.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmominuD { rd, rs1, rs2 } => write!(f, "amominu.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64a::AmomaxuD { rd, rs1, rs2 } => write!(f, "amomaxu.d rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::Extension, bus::{Bus, RAM_BASE}, csr::Csr, mem::{B32, B64}};
+    use super::{Rv32a, Rv64a};
+
+    #[test]
+    fn amoadd_w() {
+        let mut regs = [0_u64; 32];
+        regs[11] = 3;
+        regs[12] = RAM_BASE;
+        let (mut csr, mut bus) = (Csr::new(), Bus::new(vec![]));
+        bus.store(RAM_BASE, B32, 5).unwrap();
+        let ins = Rv32a::id(0x00b6252f).unwrap(); // amoadd.w a0, a1, (a2)
+        assert_eq!(ins, Rv32a::AmoaddW { rd: 10, rs1: 12, rs2: 11 });
+        ins.ex(&regs, &[0; 32]).wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[10], 5);
+        assert_eq!(bus.load(RAM_BASE, B32).unwrap(), 8);
+    }
+
+    #[test]
+    fn lr_sc_w() {
+        let mut regs = [0_u64; 32];
+        let (mut csr, mut bus) = (Csr::new(), Bus::new(vec![]));
+        bus.store(RAM_BASE, B32, 7).unwrap();
+        Rv32a::LrW { rd: 10, rs1: RAM_BASE, rs2: 0 }.wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[10], 7);
+        // the reservation is held, so the store goes through
+        Rv32a::ScW { rd: 11, rs1: RAM_BASE, rs2: 9 }.wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[11], 0);
+        assert_eq!(bus.load(RAM_BASE, B32).unwrap(), 9);
+        // sc cleared the reservation, so a second one fails
+        Rv32a::ScW { rd: 11, rs1: RAM_BASE, rs2: 1 }.wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[11], 1);
+        assert_eq!(bus.load(RAM_BASE, B32).unwrap(), 9);
+    }
+
+    #[test]
+    fn amoadd_d() {
+        let mut regs = [0_u64; 32];
+        regs[11] = 1;
+        regs[12] = RAM_BASE;
+        let (mut csr, mut bus) = (Csr::new(), Bus::new(vec![]));
+        bus.store(RAM_BASE, B64, 0x1_ffff_ffff).unwrap();
+        let ins = Rv64a::id(0x00b6352f).unwrap(); // amoadd.d a0, a1, (a2)
+        assert_eq!(ins, Rv64a::AmoaddD { rd: 10, rs1: 12, rs2: 11 });
+        ins.ex(&regs, &[0; 32]).wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[10], 0x1_ffff_ffff);
+        assert_eq!(bus.load(RAM_BASE, B64).unwrap(), 0x2_0000_0000);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,501 @@
+use std::fmt::Display;
+
+use crate::{exception::Exception, bus::Bus, csr::Csr, isa::{Extension, opcode, funct3, funct7, rd, rs1, rs2, reg_name, signed, shamt, write_reg}};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Zbb {
+    Andn { rd: u64, rs1: u64, rs2: u64 },
+    Orn { rd: u64, rs1: u64, rs2: u64 },
+    Xnor { rd: u64, rs1: u64, rs2: u64 },
+    Clz { rd: u64, rs1: u64 },
+    Ctz { rd: u64, rs1: u64 },
+    Cpop { rd: u64, rs1: u64 },
+    Min { rd: u64, rs1: u64, rs2: u64 },
+    Minu { rd: u64, rs1: u64, rs2: u64 },
+    Max { rd: u64, rs1: u64, rs2: u64 },
+    Maxu { rd: u64, rs1: u64, rs2: u64 },
+    SextB { rd: u64, rs1: u64 },
+    SextH { rd: u64, rs1: u64 },
+    Rol { rd: u64, rs1: u64, rs2: u64 },
+    Ror { rd: u64, rs1: u64, rs2: u64 },
+    Rori { rd: u64, rs1: u64, shamt: u32 },
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Zbb64 {
+    Clzw { rd: u64, rs1: u64 },
+    Ctzw { rd: u64, rs1: u64 },
+    Cpopw { rd: u64, rs1: u64 },
+    Rolw { rd: u64, rs1: u64, rs2: u64 },
+    Rorw { rd: u64, rs1: u64, rs2: u64 },
+    Roriw { rd: u64, rs1: u64, shamt: u32 },
+}
+
+impl Extension for Zbb {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        let opcode = opcode(ins);
+        let funct3 = funct3(ins);
+        let funct7 = funct7(ins);
+
+        let rd = rd(ins) as u64;
+        let rs1 = rs1(ins) as u64;
+        let rs2 = rs2(ins) as u64;
+
+        match (funct7, funct3, opcode) {
+            (0b0100000, 0b111, 0b0110011) => Ok(Self::Andn { rd, rs1, rs2 }),
+            (0b0100000, 0b110, 0b0110011) => Ok(Self::Orn { rd, rs1, rs2 }),
+            (0b0100000, 0b100, 0b0110011) => Ok(Self::Xnor { rd, rs1, rs2 }),
+            (0b0000101, 0b100, 0b0110011) => Ok(Self::Min { rd, rs1, rs2 }),
+            (0b0000101, 0b101, 0b0110011) => Ok(Self::Minu { rd, rs1, rs2 }),
+            (0b0000101, 0b110, 0b0110011) => Ok(Self::Max { rd, rs1, rs2 }),
+            (0b0000101, 0b111, 0b0110011) => Ok(Self::Maxu { rd, rs1, rs2 }),
+            (0b0110000, 0b001, 0b0110011) => Ok(Self::Rol { rd, rs1, rs2 }),
+            (0b0110000, 0b101, 0b0110011) => Ok(Self::Ror { rd, rs1, rs2 }),
+            // the unary ops are told apart by the rs2 field
+            (0b0110000, 0b001, 0b0010011) if rs2 == 0b00000 => Ok(Self::Clz { rd, rs1 }),
+            (0b0110000, 0b001, 0b0010011) if rs2 == 0b00001 => Ok(Self::Ctz { rd, rs1 }),
+            (0b0110000, 0b001, 0b0010011) if rs2 == 0b00010 => Ok(Self::Cpop { rd, rs1 }),
+            (0b0110000, 0b001, 0b0010011) if rs2 == 0b00100 => Ok(Self::SextB { rd, rs1 }),
+            (0b0110000, 0b001, 0b0010011) if rs2 == 0b00101 => Ok(Self::SextH { rd, rs1 }),
+            // RV64 widens shamt to 6 bits, taking the low bit of funct7
+            (f, 0b101, 0b0010011) if f >> 1 == 0b011000 => Ok(Self::Rori { rd, rs1, shamt: (ins >> 20) & 0x3f }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        match self {
+            Zbb::Andn { rd, rs1, rs2 } => Self::Andn { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::Orn { rd, rs1, rs2 } => Self::Orn { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::Xnor { rd, rs1, rs2 } => Self::Xnor { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::Clz { rd, rs1 } => Self::Clz { rd, rs1: regs[rs1 as usize] },
+            Zbb::Ctz { rd, rs1 } => Self::Ctz { rd, rs1: regs[rs1 as usize] },
+            Zbb::Cpop { rd, rs1 } => Self::Cpop { rd, rs1: regs[rs1 as usize] },
+            Zbb::Min { rd, rs1, rs2 } => Self::Min { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::Minu { rd, rs1, rs2 } => Self::Minu { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::Max { rd, rs1, rs2 } => Self::Max { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::Maxu { rd, rs1, rs2 } => Self::Maxu { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::SextB { rd, rs1 } => Self::SextB { rd, rs1: regs[rs1 as usize] },
+            Zbb::SextH { rd, rs1 } => Self::SextH { rd, rs1: regs[rs1 as usize] },
+            Zbb::Rol { rd, rs1, rs2 } => Self::Rol { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::Ror { rd, rs1, rs2 } => Self::Ror { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb::Rori { rd, rs1, shamt } => Self::Rori { rd, rs1: regs[rs1 as usize], shamt },
+        }
+    }
+
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], csr: &mut Csr, _bus: &mut Bus) -> Result<u64, Exception> {
+        let rv32 = csr.xlen() == 32;
+        match self {
+            Zbb::Andn { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rs1 & !rs2);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Orn { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rs1 | !rs2);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Xnor { rd, rs1, rs2 } => {
+                write_reg(regs, rd, !(rs1 ^ rs2));
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Clz { rd, rs1 } => {
+                write_reg(regs, rd, if rv32 { (rs1 as u32).leading_zeros() } else { rs1.leading_zeros() } as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Ctz { rd, rs1 } => {
+                write_reg(regs, rd, if rv32 { (rs1 as u32).trailing_zeros() } else { rs1.trailing_zeros() } as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Cpop { rd, rs1 } => {
+                write_reg(regs, rd, if rv32 { (rs1 as u32).count_ones() } else { rs1.count_ones() } as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Min { rd, rs1, rs2 } => {
+                write_reg(regs, rd, if signed(rs1, csr) < signed(rs2, csr) { rs1 } else { rs2 });
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Minu { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rs1.min(rs2));
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Max { rd, rs1, rs2 } => {
+                write_reg(regs, rd, if signed(rs1, csr) < signed(rs2, csr) { rs2 } else { rs1 });
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Maxu { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rs1.max(rs2));
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::SextB { rd, rs1 } => {
+                write_reg(regs, rd, rs1 as i8 as i64 as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::SextH { rd, rs1 } => {
+                write_reg(regs, rd, rs1 as i16 as i64 as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Rol { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rotate_left(rs1, shamt(rs2, csr), rv32));
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Ror { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rotate_right(rs1, shamt(rs2, csr), rv32));
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb::Rori { rd, rs1, shamt: amount } => {
+                write_reg(regs, rd, rotate_right(rs1, shamt(amount as u64, csr), rv32));
+                Ok(pc.wrapping_add(len))
+            },
+        }
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            Zbb::Andn { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::Orn { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::Xnor { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::Clz { rs1, .. } => vec![*rs1],
+            Zbb::Ctz { rs1, .. } => vec![*rs1],
+            Zbb::Cpop { rs1, .. } => vec![*rs1],
+            Zbb::Min { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::Minu { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::Max { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::Maxu { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::SextB { rs1, .. } => vec![*rs1],
+            Zbb::SextH { rs1, .. } => vec![*rs1],
+            Zbb::Rol { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::Ror { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb::Rori { rs1, .. } => vec![*rs1],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        match self {
+            Zbb::Andn { rd, .. } => Some(*rd),
+            Zbb::Orn { rd, .. } => Some(*rd),
+            Zbb::Xnor { rd, .. } => Some(*rd),
+            Zbb::Clz { rd, .. } => Some(*rd),
+            Zbb::Ctz { rd, .. } => Some(*rd),
+            Zbb::Cpop { rd, .. } => Some(*rd),
+            Zbb::Min { rd, .. } => Some(*rd),
+            Zbb::Minu { rd, .. } => Some(*rd),
+            Zbb::Max { rd, .. } => Some(*rd),
+            Zbb::Maxu { rd, .. } => Some(*rd),
+            Zbb::SextB { rd, .. } => Some(*rd),
+            Zbb::SextH { rd, .. } => Some(*rd),
+            Zbb::Rol { rd, .. } => Some(*rd),
+            Zbb::Ror { rd, .. } => Some(*rd),
+            Zbb::Rori { rd, .. } => Some(*rd),
+        }
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_ld(&self) -> bool {
+        false
+    }
+
+    fn is_st(&self) -> bool {
+        false
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Zbb::Andn { rd, rs1, rs2 } => format!("andn {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::Orn { rd, rs1, rs2 } => format!("orn {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::Xnor { rd, rs1, rs2 } => format!("xnor {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::Clz { rd, rs1 } => format!("clz {}, {}", reg_name(*rd), reg_name(*rs1)),
+            Zbb::Ctz { rd, rs1 } => format!("ctz {}, {}", reg_name(*rd), reg_name(*rs1)),
+            Zbb::Cpop { rd, rs1 } => format!("cpop {}, {}", reg_name(*rd), reg_name(*rs1)),
+            Zbb::Min { rd, rs1, rs2 } => format!("min {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::Minu { rd, rs1, rs2 } => format!("minu {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::Max { rd, rs1, rs2 } => format!("max {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::Maxu { rd, rs1, rs2 } => format!("maxu {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::SextB { rd, rs1 } => format!("sext.b {}, {}", reg_name(*rd), reg_name(*rs1)),
+            Zbb::SextH { rd, rs1 } => format!("sext.h {}, {}", reg_name(*rd), reg_name(*rs1)),
+            Zbb::Rol { rd, rs1, rs2 } => format!("rol {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::Ror { rd, rs1, rs2 } => format!("ror {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb::Rori { rd, rs1, shamt } => format!("rori {}, {}, {}", reg_name(*rd), reg_name(*rs1), shamt),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Zbb::Andn { .. } => "andn",
+            Zbb::Orn { .. } => "orn",
+            Zbb::Xnor { .. } => "xnor",
+            Zbb::Clz { .. } => "clz",
+            Zbb::Ctz { .. } => "ctz",
+            Zbb::Cpop { .. } => "cpop",
+            Zbb::Min { .. } => "min",
+            Zbb::Minu { .. } => "minu",
+            Zbb::Max { .. } => "max",
+            Zbb::Maxu { .. } => "maxu",
+            Zbb::SextB { .. } => "sext.b",
+            Zbb::SextH { .. } => "sext.h",
+            Zbb::Rol { .. } => "rol",
+            Zbb::Ror { .. } => "ror",
+            Zbb::Rori { .. } => "rori",
+        }
+    }
+}
+
+impl Extension for Zbb64 {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        let opcode = opcode(ins);
+        let funct3 = funct3(ins);
+        let funct7 = funct7(ins);
+
+        let rd = rd(ins) as u64;
+        let rs1 = rs1(ins) as u64;
+        let rs2 = rs2(ins) as u64;
+
+        match (funct7, funct3, opcode) {
+            (0b0110000, 0b001, 0b0011011) if rs2 == 0b00000 => Ok(Self::Clzw { rd, rs1 }),
+            (0b0110000, 0b001, 0b0011011) if rs2 == 0b00001 => Ok(Self::Ctzw { rd, rs1 }),
+            (0b0110000, 0b001, 0b0011011) if rs2 == 0b00010 => Ok(Self::Cpopw { rd, rs1 }),
+            (0b0110000, 0b001, 0b0111011) => Ok(Self::Rolw { rd, rs1, rs2 }),
+            (0b0110000, 0b101, 0b0111011) => Ok(Self::Rorw { rd, rs1, rs2 }),
+            (0b0110000, 0b101, 0b0011011) => Ok(Self::Roriw { rd, rs1, shamt: (ins >> 20) & 0x1f }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        match self {
+            Zbb64::Clzw { rd, rs1 } => Self::Clzw { rd, rs1: regs[rs1 as usize] },
+            Zbb64::Ctzw { rd, rs1 } => Self::Ctzw { rd, rs1: regs[rs1 as usize] },
+            Zbb64::Cpopw { rd, rs1 } => Self::Cpopw { rd, rs1: regs[rs1 as usize] },
+            Zbb64::Rolw { rd, rs1, rs2 } => Self::Rolw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb64::Rorw { rd, rs1, rs2 } => Self::Rorw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Zbb64::Roriw { rd, rs1, shamt } => Self::Roriw { rd, rs1: regs[rs1 as usize], shamt },
+        }
+    }
+
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], _csr: &mut Csr, _bus: &mut Bus) -> Result<u64, Exception> {
+        match self {
+            Zbb64::Clzw { rd, rs1 } => {
+                write_reg(regs, rd, (rs1 as u32).leading_zeros() as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb64::Ctzw { rd, rs1 } => {
+                write_reg(regs, rd, (rs1 as u32).trailing_zeros() as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb64::Cpopw { rd, rs1 } => {
+                write_reg(regs, rd, (rs1 as u32).count_ones() as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb64::Rolw { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rotate_left(rs1, (rs2 & 0x1f) as u32, true) as i32 as i64 as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb64::Rorw { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rotate_right(rs1, (rs2 & 0x1f) as u32, true) as i32 as i64 as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Zbb64::Roriw { rd, rs1, shamt } => {
+                write_reg(regs, rd, rotate_right(rs1, shamt, true) as i32 as i64 as u64);
+                Ok(pc.wrapping_add(len))
+            },
+        }
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            Zbb64::Clzw { rs1, .. } => vec![*rs1],
+            Zbb64::Ctzw { rs1, .. } => vec![*rs1],
+            Zbb64::Cpopw { rs1, .. } => vec![*rs1],
+            Zbb64::Rolw { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb64::Rorw { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Zbb64::Roriw { rs1, .. } => vec![*rs1],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        match self {
+            Zbb64::Clzw { rd, .. } => Some(*rd),
+            Zbb64::Ctzw { rd, .. } => Some(*rd),
+            Zbb64::Cpopw { rd, .. } => Some(*rd),
+            Zbb64::Rolw { rd, .. } => Some(*rd),
+            Zbb64::Rorw { rd, .. } => Some(*rd),
+            Zbb64::Roriw { rd, .. } => Some(*rd),
+        }
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_ld(&self) -> bool {
+        false
+    }
+
+    fn is_st(&self) -> bool {
+        false
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Zbb64::Clzw { rd, rs1 } => format!("clzw {}, {}", reg_name(*rd), reg_name(*rs1)),
+            Zbb64::Ctzw { rd, rs1 } => format!("ctzw {}, {}", reg_name(*rd), reg_name(*rs1)),
+            Zbb64::Cpopw { rd, rs1 } => format!("cpopw {}, {}", reg_name(*rd), reg_name(*rs1)),
+            Zbb64::Rolw { rd, rs1, rs2 } => format!("rolw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb64::Rorw { rd, rs1, rs2 } => format!("rorw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Zbb64::Roriw { rd, rs1, shamt } => format!("roriw {}, {}, {}", reg_name(*rd), reg_name(*rs1), shamt),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Zbb64::Clzw { .. } => "clzw",
+            Zbb64::Ctzw { .. } => "ctzw",
+            Zbb64::Cpopw { .. } => "cpopw",
+            Zbb64::Rolw { .. } => "rolw",
+            Zbb64::Rorw { .. } => "rorw",
+            Zbb64::Roriw { .. } => "roriw",
+        }
+    }
+}
+
+/// Rotates within the low 32 bits when `word` is set
+fn rotate_left(value: u64, amount: u32, word: bool) -> u64 {
+    if word { (value as u32).rotate_left(amount) as u64 } else { value.rotate_left(amount) }
+}
+
+fn rotate_right(value: u64, amount: u32, word: bool) -> u64 {
+    if word { (value as u32).rotate_right(amount) as u64 } else { value.rotate_right(amount) }
+}
+
+impl Display for Zbb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Zbb::Andn { rd, rs1, rs2 } => write!(f, "andn rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::Orn { rd, rs1, rs2 } => write!(f, "orn rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::Xnor { rd, rs1, rs2 } => write!(f, "xnor rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::Clz { rd, rs1 } => write!(f, "clz rd={}, rs1={}", rd, rs1),
+            Zbb::Ctz { rd, rs1 } => write!(f, "ctz rd={}, rs1={}", rd, rs1),
+            Zbb::Cpop { rd, rs1 } => write!(f, "cpop rd={}, rs1={}", rd, rs1),
+            Zbb::Min { rd, rs1, rs2 } => write!(f, "min rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::Minu { rd, rs1, rs2 } => write!(f, "minu rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::Max { rd, rs1, rs2 } => write!(f, "max rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::Maxu { rd, rs1, rs2 } => write!(f, "maxu rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::SextB { rd, rs1 } => write!(f, "sext.b rd={}, rs1={}", rd, rs1),
+            Zbb::SextH { rd, rs1 } => write!(f, "sext.h rd={}, rs1={}", rd, rs1),
+            Zbb::Rol { rd, rs1, rs2 } => write!(f, "rol rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::Ror { rd, rs1, rs2 } => write!(f, "ror rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb::Rori { rd, rs1, shamt } => write!(f, "rori rd={}, rs1={}, shamt={}", rd, rs1, shamt),
+        }
+    }
+}
+
+impl Display for Zbb64 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Zbb64::Clzw { rd, rs1 } => write!(f, "clzw rd={}, rs1={}", rd, rs1),
+            Zbb64::Ctzw { rd, rs1 } => write!(f, "ctzw rd={}, rs1={}", rd, rs1),
+            Zbb64::Cpopw { rd, rs1 } => write!(f, "cpopw rd={}, rs1={}", rd, rs1),
+            Zbb64::Rolw { rd, rs1, rs2 } => write!(f, "rolw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb64::Rorw { rd, rs1, rs2 } => write!(f, "rorw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Zbb64::Roriw { rd, rs1, shamt } => write!(f, "roriw rd={}, rs1={}, shamt={}", rd, rs1, shamt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::Extension, bus::Bus, csr::Csr};
+    use super::{Zbb, Zbb64};
+
+    fn run<E: Extension>(word: u32, regs: &mut [u64; 32], csr: &mut Csr) {
+        let ins = E::id(word).unwrap().ex(regs, &[0; 32]);
+        ins.wr(0, 4, regs, &mut [0; 32], csr, &mut Bus::new(vec![])).unwrap();
+    }
+
+    #[test]
+    fn bit_counts() {
+        let mut regs = [0_u64; 32];
+        let mut csr = Csr::new();
+        regs[11] = 0x0000_0100_f0f0_0000;
+        run::<Zbb>(0x60059513, &mut regs, &mut csr); // clz a0, a1
+        assert_eq!(regs[10], 23);
+        run::<Zbb>(0x60159513, &mut regs, &mut csr); // ctz a0, a1
+        assert_eq!(regs[10], 20);
+        run::<Zbb>(0x60259513, &mut regs, &mut csr); // cpop a0, a1
+        assert_eq!(regs[10], 9);
+        run::<Zbb64>(0x6005951b, &mut regs, &mut csr); // clzw a0, a1
+        assert_eq!(regs[10], 0);
+        regs[11] = 0;
+        run::<Zbb>(0x60059513, &mut regs, &mut csr); // clz a0, a1
+        assert_eq!(regs[10], 64);
+
+        csr.set_xlen(32);
+        regs[11] = 0x0000_f0f0;
+        run::<Zbb>(0x60059513, &mut regs, &mut csr); // clz a0, a1
+        assert_eq!(regs[10], 16);
+    }
+
+    #[test]
+    fn rotates() {
+        let mut regs = [0_u64; 32];
+        let mut csr = Csr::new();
+        regs[11] = 0x8000_0000_0000_0001;
+        regs[12] = 4;
+        run::<Zbb>(0x60c59533, &mut regs, &mut csr); // rol a0, a1, a2
+        assert_eq!(regs[10], 0x18);
+        run::<Zbb>(0x60c5d533, &mut regs, &mut csr); // ror a0, a1, a2
+        assert_eq!(regs[10], 0x1800_0000_0000_0000);
+        run::<Zbb>(0x6015d513, &mut regs, &mut csr); // rori a0, a1, 1
+        assert_eq!(regs[10], 0xc000_0000_0000_0000);
+        run::<Zbb64>(0x60c5953b, &mut regs, &mut csr); // rolw a0, a1, a2
+        assert_eq!(regs[10], 0x10);
+
+        csr.set_xlen(32);
+        regs[11] = 0x8000_0001;
+        run::<Zbb>(0x60c59533, &mut regs, &mut csr); // rol a0, a1, a2
+        assert_eq!(regs[10], 0x18);
+    }
+
+    #[test]
+    fn logic_and_minmax() {
+        let mut regs = [0_u64; 32];
+        let mut csr = Csr::new();
+        regs[11] = -5_i64 as u64;
+        regs[12] = 3;
+        run::<Zbb>(0x0ac5c533, &mut regs, &mut csr); // min a0, a1, a2
+        assert_eq!(regs[10], -5_i64 as u64);
+        run::<Zbb>(0x0ac5d533, &mut regs, &mut csr); // minu a0, a1, a2
+        assert_eq!(regs[10], 3);
+        run::<Zbb>(0x40c5f533, &mut regs, &mut csr); // andn a0, a1, a2
+        assert_eq!(regs[10], -5_i64 as u64 & !3);
+        regs[11] = 0x80;
+        run::<Zbb>(0x60459513, &mut regs, &mut csr); // sext.b a0, a1
+        assert_eq!(regs[10], 0xffff_ffff_ffff_ff80);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,239 @@
+use crate::exception::Exception;
+
+/*
+RVC decoder: each 16-bit instruction is expanded to the 32-bit encoding of
+its base-ISA equivalent, which then goes through the normal decoders. Only
+the integer subset is supported; the compressed float loads/stores decode
+as illegal.
+*/
+
+/// Size in bytes of the instruction whose low half is ins
+pub fn ins_len(ins: u32) -> u64 {
+    if is_compressed(ins) { 2 } else { 4 }
+}
+
+pub fn is_compressed(ins: u32) -> bool {
+    ins & 0b11 != 0b11
+}
+
+/// Expands a compressed instruction to its 32-bit equivalent. A few
+/// encodings differ between RV32C and RV64C, rv64 picks which applies.
+pub fn expand(ins: u16, rv64: bool) -> Result<u32, Exception> {
+    let illegal = Err(Exception::IllegalInstruction(ins as u64));
+    let ins = ins as u32;
+    let funct3 = bits(ins, 15, 13);
+    // full register fields
+    let rd = bits(ins, 11, 7);
+    let rs2 = bits(ins, 6, 2);
+    // 3-bit fields addressing x8-x15
+    let rd_ = bits(ins, 4, 2) + 8;
+    let rs1_ = bits(ins, 9, 7) + 8;
+
+    match (ins & 0b11, funct3) {
+        // c.addi4spn
+        (0b00, 0b000) => {
+            let imm = bits(ins, 12, 11) << 4 | bits(ins, 10, 7) << 6 | bits(ins, 6, 6) << 2 | bits(ins, 5, 5) << 3;
+            if imm == 0 { return illegal }
+            Ok(i_type(imm, 2, 0b000, rd_, 0b0010011))
+        },
+        // c.lw
+        (0b00, 0b010) => Ok(i_type(lw_imm(ins), rs1_, 0b010, rd_, 0b0000011)),
+        // c.ld
+        (0b00, 0b011) => Ok(i_type(ld_imm(ins), rs1_, 0b011, rd_, 0b0000011)),
+        // c.sw
+        (0b00, 0b110) => Ok(s_type(lw_imm(ins), rd_, rs1_, 0b010)),
+        // c.sd
+        (0b00, 0b111) => Ok(s_type(ld_imm(ins), rd_, rs1_, 0b011)),
+        // c.addi (c.nop when rd is zero)
+        (0b01, 0b000) => Ok(i_type(ci_imm(ins), rd, 0b000, rd, 0b0010011)),
+        // c.jal, RV32 only
+        (0b01, 0b001) if !rv64 => Ok(j_type(cj_imm(ins), 1)),
+        // c.addiw
+        (0b01, 0b001) => {
+            if rd == 0 { return illegal }
+            Ok(i_type(ci_imm(ins), rd, 0b000, rd, 0b0011011))
+        },
+        // c.li
+        (0b01, 0b010) => Ok(i_type(ci_imm(ins), 0, 0b000, rd, 0b0010011)),
+        // c.addi16sp
+        (0b01, 0b011) if rd == 2 => {
+            let imm = sext(bits(ins, 12, 12) << 9 | bits(ins, 6, 6) << 4 | bits(ins, 5, 5) << 6
+                | bits(ins, 4, 3) << 7 | bits(ins, 2, 2) << 5, 10);
+            if imm == 0 { return illegal }
+            Ok(i_type(imm, 2, 0b000, 2, 0b0010011))
+        },
+        // c.lui
+        (0b01, 0b011) => {
+            let imm = sext(bits(ins, 12, 12) << 17 | bits(ins, 6, 2) << 12, 18);
+            if imm == 0 || rd == 0 { return illegal }
+            Ok(imm & 0xfffff000 | rd << 7 | 0b0110111)
+        },
+        (0b01, 0b100) => {
+            let shamt = bits(ins, 12, 12) << 5 | bits(ins, 6, 2);
+            // shamt[5] is reserved in RV32
+            if bits(ins, 11, 11) == 0 && shamt >= 32 && !rv64 { return illegal }
+            match (bits(ins, 11, 10), bits(ins, 12, 12), bits(ins, 6, 5)) {
+                // c.srli, c.srai
+                (0b00, _, _) => Ok(i_type(shamt, rs1_, 0b101, rs1_, 0b0010011)),
+                (0b01, _, _) => Ok(i_type(0b0100000 << 5 | shamt, rs1_, 0b101, rs1_, 0b0010011)),
+                // c.andi
+                (0b10, _, _) => Ok(i_type(ci_imm(ins), rs1_, 0b111, rs1_, 0b0010011)),
+                // c.sub, c.xor, c.or, c.and
+                (0b11, 0, 0b00) => Ok(r_type(0b0100000, rd_, rs1_, 0b000, rs1_, 0b0110011)),
+                (0b11, 0, 0b01) => Ok(r_type(0, rd_, rs1_, 0b100, rs1_, 0b0110011)),
+                (0b11, 0, 0b10) => Ok(r_type(0, rd_, rs1_, 0b110, rs1_, 0b0110011)),
+                (0b11, 0, 0b11) => Ok(r_type(0, rd_, rs1_, 0b111, rs1_, 0b0110011)),
+                // c.subw, c.addw
+                (0b11, 1, 0b00) => Ok(r_type(0b0100000, rd_, rs1_, 0b000, rs1_, 0b0111011)),
+                (0b11, 1, 0b01) => Ok(r_type(0, rd_, rs1_, 0b000, rs1_, 0b0111011)),
+                _ => illegal
+            }
+        },
+        // c.j
+        (0b01, 0b101) => Ok(j_type(cj_imm(ins), 0)),
+        // c.beqz, c.bnez
+        (0b01, 0b110) => Ok(b_type(cb_imm(ins), 0, rs1_, 0b000)),
+        (0b01, 0b111) => Ok(b_type(cb_imm(ins), 0, rs1_, 0b001)),
+        // c.slli
+        (0b10, 0b000) => {
+            let shamt = bits(ins, 12, 12) << 5 | bits(ins, 6, 2);
+            if shamt >= 32 && !rv64 { return illegal }
+            Ok(i_type(shamt, rd, 0b001, rd, 0b0010011))
+        },
+        // c.lwsp
+        (0b10, 0b010) => {
+            if rd == 0 { return illegal }
+            let imm = bits(ins, 12, 12) << 5 | bits(ins, 6, 4) << 2 | bits(ins, 3, 2) << 6;
+            Ok(i_type(imm, 2, 0b010, rd, 0b0000011))
+        },
+        // c.ldsp
+        (0b10, 0b011) => {
+            if rd == 0 { return illegal }
+            let imm = bits(ins, 12, 12) << 5 | bits(ins, 6, 5) << 3 | bits(ins, 4, 2) << 6;
+            Ok(i_type(imm, 2, 0b011, rd, 0b0000011))
+        },
+        (0b10, 0b100) => match (bits(ins, 12, 12), rd, rs2) {
+            (0, 0, 0) => illegal,
+            // c.jr
+            (0, _, 0) => Ok(i_type(0, rd, 0b000, 0, 0b1100111)),
+            // c.mv
+            (0, _, _) => Ok(r_type(0, rs2, 0, 0b000, rd, 0b0110011)),
+            // c.ebreak
+            (1, 0, 0) => Ok(0x00100073),
+            // c.jalr
+            (1, _, 0) => Ok(i_type(0, rd, 0b000, 1, 0b1100111)),
+            // c.add
+            _ => Ok(r_type(0, rs2, rd, 0b000, rd, 0b0110011)),
+        },
+        // c.swsp
+        (0b10, 0b110) => {
+            let imm = bits(ins, 12, 9) << 2 | bits(ins, 8, 7) << 6;
+            Ok(s_type(imm, rs2, 2, 0b010))
+        },
+        // c.sdsp
+        (0b10, 0b111) => {
+            let imm = bits(ins, 12, 10) << 3 | bits(ins, 9, 7) << 6;
+            Ok(s_type(imm, rs2, 2, 0b011))
+        },
+        _ => illegal
+    }
+}
+
+/// Extracts ins[hi:lo]
+fn bits(ins: u32, hi: u32, lo: u32) -> u32 {
+    (ins >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+/// Sign-extends the low width bits of value
+fn sext(value: u32, width: u32) -> u32 {
+    (((value << (32 - width)) as i32) >> (32 - width)) as u32
+}
+
+/// 6-bit signed immediate of c.addi, c.li, c.andi
+fn ci_imm(ins: u32) -> u32 {
+    sext(bits(ins, 12, 12) << 5 | bits(ins, 6, 2), 6)
+}
+
+fn lw_imm(ins: u32) -> u32 {
+    bits(ins, 12, 10) << 3 | bits(ins, 6, 6) << 2 | bits(ins, 5, 5) << 6
+}
+
+fn ld_imm(ins: u32) -> u32 {
+    bits(ins, 12, 10) << 3 | bits(ins, 6, 5) << 6
+}
+
+fn cj_imm(ins: u32) -> u32 {
+    sext(bits(ins, 12, 12) << 11 | bits(ins, 11, 11) << 4 | bits(ins, 10, 9) << 8
+        | bits(ins, 8, 8) << 10 | bits(ins, 7, 7) << 6 | bits(ins, 6, 6) << 7
+        | bits(ins, 5, 3) << 1 | bits(ins, 2, 2) << 5, 12)
+}
+
+fn cb_imm(ins: u32) -> u32 {
+    sext(bits(ins, 12, 12) << 8 | bits(ins, 11, 10) << 3 | bits(ins, 6, 5) << 6
+        | bits(ins, 4, 3) << 1 | bits(ins, 2, 2) << 5, 9)
+}
+
+fn i_type(imm: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (imm & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn s_type(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    bits(imm, 11, 5) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | bits(imm, 4, 0) << 7 | 0b0100011
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn b_type(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    bits(imm, 12, 12) << 31 | bits(imm, 10, 5) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12
+        | bits(imm, 4, 1) << 8 | bits(imm, 11, 11) << 7 | 0b1100011
+}
+
+fn j_type(imm: u32, rd: u32) -> u32 {
+    bits(imm, 20, 20) << 31 | bits(imm, 10, 1) << 21 | bits(imm, 11, 11) << 20
+        | bits(imm, 19, 12) << 12 | rd << 7 | 0b1101111
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::{Extension, Rv32i, Rv64i};
+    use super::expand;
+
+    #[test]
+    fn c_addi() {
+        // c.addi a0, 1
+        let ins = expand(0x0505, true).unwrap();
+        assert_eq!(ins, 0x00150513); // addi a0, a0, 1
+        assert_eq!(Rv32i::id(ins).unwrap(), Rv32i::Addi { rd: 10, rs1: 10, imm: 1 });
+    }
+
+    #[test]
+    fn c_ld() {
+        // c.ld a0, 8(a1)
+        let ins = expand(0x6588, true).unwrap();
+        assert_eq!(ins, 0x0085b503); // ld a0, 8(a1)
+        assert_eq!(Rv64i::id(ins).unwrap(), Rv64i::Ld { rd: 10, rs1: 11, imm: 8 });
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(expand(0xbfe5, true).unwrap(), 0xff9ff06f); // c.j -8 => jal zero, -8
+        assert_eq!(expand(0xdc75, true).unwrap(), 0xfe040ee3); // c.beqz s0, -4 => beq s0, zero, -4
+        assert_eq!(expand(0x9582, true).unwrap(), 0x000580e7); // c.jalr a1 => jalr ra, 0(a1)
+        assert_eq!(expand(0x8082, true).unwrap(), 0x00008067); // c.jr ra => jalr zero, 0(ra)
+        assert!(expand(0x0000, true).is_err());
+    }
+
+    #[test]
+    fn rv32c() {
+        // c.jal 4 in RV32, c.addiw zero, 4 (reserved) in RV64
+        assert_eq!(expand(0x2011, false).unwrap(), 0x004000ef); // jal ra, 4
+        assert!(expand(0x2011, true).is_err());
+        // c.slli a0, 33 and c.srli s0, 33 need shamt[5]
+        assert_eq!(expand(0x1506, true).unwrap(), 0x02151513); // slli a0, a0, 33
+        assert!(expand(0x1506, false).is_err());
+        assert!(expand(0x9005, false).is_err());
+        assert_eq!(expand(0x8005, false).unwrap(), 0x00145413); // srli s0, s0, 1
+    }
+}
\ No newline at end of file
@@ -0,0 +1,676 @@
+use std::fmt::Display;
+
+use crate::{exception::Exception, bus::Bus, csr::{Csr, FCSR, FRM, FFLAGS_NV, FFLAGS_DZ, FFLAGS_OF, FFLAGS_UF, FFLAGS_NX}, isa::{Extension, FuncUnit, FREG_BASE, opcode, funct3, funct7, rd, rs1, rs2, i_imm, s_imm, reg_name, write_reg}, mem::B32, rvm::{MUL_LATENCY, DIV_LATENCY}};
+
+/*
+Single-precision floats on Rust f32. Arithmetic rounds to nearest, ties to
+even, whatever the rounding mode; the mode is honoured by the conversions.
+The exception flags describe that rounding, with tininess judged on the
+rounded result. The fused multiply-adds are not modelled and decode as
+illegal.
+*/
+
+/// Rounding modes, as encoded in the rm field and frm
+pub const RM_RNE: u32 = 0b000;
+pub const RM_RTZ: u32 = 0b001;
+pub const RM_RDN: u32 = 0b010;
+pub const RM_RUP: u32 = 0b011;
+pub const RM_RMM: u32 = 0b100;
+/// Take the rounding mode from frm
+pub const RM_DYN: u32 = 0b111;
+
+/// The NaN every operation producing a NaN returns
+pub const CANONICAL_NAN: u32 = 0x7fc0_0000;
+
+const FRVABI: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7",
+    "fs0", "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5",
+    "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7",
+    "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Rv32f {
+    Flw { rd: u64, rs1: u64, imm: u64 },
+    Fsw { rs1: u64, rs2: u64, imm: u64 },
+    FaddS { rd: u64, rs1: u64, rs2: u64, rm: u32 },
+    FsubS { rd: u64, rs1: u64, rs2: u64, rm: u32 },
+    FmulS { rd: u64, rs1: u64, rs2: u64, rm: u32 },
+    FdivS { rd: u64, rs1: u64, rs2: u64, rm: u32 },
+    FsqrtS { rd: u64, rs1: u64, rm: u32 },
+    FsgnjS { rd: u64, rs1: u64, rs2: u64 },
+    FsgnjnS { rd: u64, rs1: u64, rs2: u64 },
+    FsgnjxS { rd: u64, rs1: u64, rs2: u64 },
+    FminS { rd: u64, rs1: u64, rs2: u64 },
+    FmaxS { rd: u64, rs1: u64, rs2: u64 },
+    FeqS { rd: u64, rs1: u64, rs2: u64 },
+    FltS { rd: u64, rs1: u64, rs2: u64 },
+    FleS { rd: u64, rs1: u64, rs2: u64 },
+    FcvtWS { rd: u64, rs1: u64, rm: u32 },
+    FcvtWuS { rd: u64, rs1: u64, rm: u32 },
+    FcvtSW { rd: u64, rs1: u64, rm: u32 },
+    FcvtSWu { rd: u64, rs1: u64, rm: u32 },
+    FmvXW { rd: u64, rs1: u64 },
+    FmvWX { rd: u64, rs1: u64 },
+    FclassS { rd: u64, rs1: u64 },
+}
+
+/// Widens a single to a float register, NaN-boxed as on harts with wider
+/// float registers
+pub fn box_f32(value: f32) -> u64 {
+    0xffff_ffff_0000_0000 | value.to_bits() as u64
+}
+
+/// Reads a single from a float register. A value that is not NaN-boxed
+/// reads as the canonical NaN.
+pub fn unbox_f32(reg: u64) -> f32 {
+    if reg >> 32 == 0xffff_ffff {
+        f32::from_bits(reg as u32)
+    } else {
+        f32::from_bits(CANONICAL_NAN)
+    }
+}
+
+pub fn freg_name(reg: u64) -> &'static str {
+    FRVABI[reg as usize]
+}
+
+fn is_snan(value: f32) -> bool {
+    value.is_nan() && value.to_bits() & 0x0040_0000 == 0
+}
+
+fn raise(csr: &mut Csr, flags: u64) {
+    csr.store(FCSR, csr.load(FCSR) | flags);
+}
+
+/// The rounding mode an instruction uses, looking up frm for RM_DYN. A
+/// reserved mode in frm makes the instruction illegal.
+fn rounding(rm: u32, csr: &Csr) -> Result<u32, Exception> {
+    let rm = if rm == RM_DYN { csr.load(FRM) as u32 } else { rm };
+    if rm > RM_RMM {
+        // the word is no longer at hand, and mtval may be zero
+        return Err(Exception::IllegalInstruction(0))
+    }
+    Ok(rm)
+}
+
+/// Finishes an arithmetic result and raises its flags. inexact says
+/// whether a finite result was rounded. An operation on numbers that gives
+/// NaN, or any operation on a signalling NaN, is invalid, and every NaN
+/// result is the canonical one.
+fn arith(csr: &mut Csr, operands: &[f32], result: f32, inexact: bool) -> u64 {
+    if operands.iter().any(|v| is_snan(*v)) || (result.is_nan() && !operands.iter().any(|v| v.is_nan())) {
+        raise(csr, FFLAGS_NV);
+    }
+    if result.is_nan() {
+        return box_f32(f32::from_bits(CANONICAL_NAN))
+    }
+    // operations on infinities are exact
+    if !operands.iter().all(|v| v.is_finite()) {
+        return box_f32(result)
+    }
+    if result.is_infinite() {
+        // x / 0 is a division by zero rather than an overflow
+        if operands.iter().all(|v| *v != 0.0) {
+            raise(csr, FFLAGS_OF | FFLAGS_NX);
+        }
+    } else if inexact && result.abs() < f32::MIN_POSITIVE {
+        raise(csr, FFLAGS_UF | FFLAGS_NX);
+    } else if inexact {
+        raise(csr, FFLAGS_NX);
+    }
+    box_f32(result)
+}
+
+/// Whether a + b was rounded to get sum, by recovering the rounding error
+/// exactly (Knuth's two-sum)
+fn sum_inexact(a: f32, b: f32, sum: f32) -> bool {
+    let b_part = sum - a;
+    let error = (a - (sum - b_part)) + (b - b_part);
+    error != 0.0
+}
+
+/// Whether the product of two singles differs from result. The 48-bit
+/// product is exact in an f64, which also covers division and square root
+/// checked by multiplying back.
+fn product_differs(a: f32, b: f32, result: f32) -> bool {
+    a as f64 * b as f64 != result as f64
+}
+
+/// fmin.s and fmax.s: a NaN operand gives way to a number, and -0 is
+/// below +0
+fn min_max(csr: &mut Csr, a: f32, b: f32, max: bool) -> u64 {
+    if is_snan(a) || is_snan(b) {
+        raise(csr, FFLAGS_NV);
+    }
+    let result = match (a.is_nan(), b.is_nan()) {
+        (true, true) => f32::from_bits(CANONICAL_NAN),
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b => if max == a.is_sign_positive() { a } else { b },
+        _ => if (a > b) == max { a } else { b },
+    };
+    box_f32(result)
+}
+
+/// Rounds to an integral value in the given mode
+fn round(value: f32, rm: u32) -> f32 {
+    match rm {
+        RM_RTZ => value.trunc(),
+        RM_RDN => value.floor(),
+        RM_RUP => value.ceil(),
+        RM_RMM => value.round(),
+        _ => value.round_ties_even(),
+    }
+}
+
+/// fcvt.w.s and fcvt.wu.s: out of range values and NaN saturate, and the
+/// 32-bit result is sign-extended
+fn to_int(csr: &mut Csr, value: f32, rm: u32, unsigned: bool) -> u64 {
+    let (min, max) = if unsigned { (0.0, u32::MAX as f64) } else { (i32::MIN as f64, i32::MAX as f64) };
+    let rounded = round(value, rm) as f64;
+    let result = if value.is_nan() || rounded > max {
+        raise(csr, FFLAGS_NV);
+        max
+    } else if rounded < min {
+        raise(csr, FFLAGS_NV);
+        min
+    } else {
+        if rounded != value as f64 {
+            raise(csr, FFLAGS_NX);
+        }
+        rounded
+    };
+    if unsigned { result as u32 as i32 as u64 } else { result as i32 as u64 }
+}
+
+/// fcvt.s.w and fcvt.s.wu: narrows an integer, which f64 holds exactly,
+/// to a single in the given mode
+fn from_int(csr: &mut Csr, exact: f64, rm: u32) -> u64 {
+    let nearest = exact as f32;
+    if nearest as f64 == exact {
+        return box_f32(nearest)
+    }
+    raise(csr, FFLAGS_NX);
+    let below = if (nearest as f64) < exact { nearest } else { nearest.next_down() };
+    let above = if (nearest as f64) > exact { nearest } else { nearest.next_up() };
+    let result = match rm {
+        RM_RTZ => if exact > 0.0 { below } else { above },
+        RM_RDN => below,
+        RM_RUP => above,
+        RM_RMM if exact - below as f64 == above as f64 - exact => if exact > 0.0 { above } else { below },
+        _ => nearest,
+    };
+    box_f32(result)
+}
+
+/// The fclass.s mask: -inf, negative normal, negative subnormal, -0, +0,
+/// positive subnormal, positive normal, +inf, signalling NaN, quiet NaN
+fn class(value: f32) -> u64 {
+    let bit = if value.is_nan() {
+        if is_snan(value) { 8 } else { 9 }
+    } else {
+        let negative = value.is_sign_negative();
+        let offset = if value.is_infinite() {
+            0
+        } else if value.is_normal() {
+            1
+        } else if value != 0.0 {
+            2
+        } else {
+            3
+        };
+        if negative { offset } else { 7 - offset }
+    };
+    1 << bit
+}
+
+/// The assembler suffix for a rounding mode, empty for the default
+fn rm_suffix(rm: u32) -> &'static str {
+    match rm {
+        RM_RNE => ", rne",
+        RM_RTZ => ", rtz",
+        RM_RDN => ", rdn",
+        RM_RUP => ", rup",
+        RM_RMM => ", rmm",
+        _ => "",
+    }
+}
+
+impl Extension for Rv32f {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        let opcode = opcode(ins);
+        let funct3 = funct3(ins);
+        let funct7 = funct7(ins);
+
+        let rd = rd(ins) as u64;
+        let rs1 = rs1(ins) as u64;
+        let rs2 = rs2(ins) as u64;
+        // 0b101 and 0b110 are reserved rounding modes
+        let rm = funct3;
+        let valid_rm = rm <= RM_RMM || rm == RM_DYN;
+
+        match (funct7, funct3, opcode) {
+            (_, 0b010, 0b0000111) => Ok(Self::Flw { rd, rs1, imm: i_imm(ins) }),
+            (_, 0b010, 0b0100111) => Ok(Self::Fsw { rs1, rs2, imm: s_imm(ins) }),
+            (0b0000000, _, 0b1010011) if valid_rm => Ok(Self::FaddS { rd, rs1, rs2, rm }),
+            (0b0000100, _, 0b1010011) if valid_rm => Ok(Self::FsubS { rd, rs1, rs2, rm }),
+            (0b0001000, _, 0b1010011) if valid_rm => Ok(Self::FmulS { rd, rs1, rs2, rm }),
+            (0b0001100, _, 0b1010011) if valid_rm => Ok(Self::FdivS { rd, rs1, rs2, rm }),
+            (0b0101100, _, 0b1010011) if valid_rm && rs2 == 0 => Ok(Self::FsqrtS { rd, rs1, rm }),
+            (0b0010000, 0b000, 0b1010011) => Ok(Self::FsgnjS { rd, rs1, rs2 }),
+            (0b0010000, 0b001, 0b1010011) => Ok(Self::FsgnjnS { rd, rs1, rs2 }),
+            (0b0010000, 0b010, 0b1010011) => Ok(Self::FsgnjxS { rd, rs1, rs2 }),
+            (0b0010100, 0b000, 0b1010011) => Ok(Self::FminS { rd, rs1, rs2 }),
+            (0b0010100, 0b001, 0b1010011) => Ok(Self::FmaxS { rd, rs1, rs2 }),
+            (0b1010000, 0b010, 0b1010011) => Ok(Self::FeqS { rd, rs1, rs2 }),
+            (0b1010000, 0b001, 0b1010011) => Ok(Self::FltS { rd, rs1, rs2 }),
+            (0b1010000, 0b000, 0b1010011) => Ok(Self::FleS { rd, rs1, rs2 }),
+            (0b1100000, _, 0b1010011) if valid_rm && rs2 == 0 => Ok(Self::FcvtWS { rd, rs1, rm }),
+            (0b1100000, _, 0b1010011) if valid_rm && rs2 == 1 => Ok(Self::FcvtWuS { rd, rs1, rm }),
+            (0b1101000, _, 0b1010011) if valid_rm && rs2 == 0 => Ok(Self::FcvtSW { rd, rs1, rm }),
+            (0b1101000, _, 0b1010011) if valid_rm && rs2 == 1 => Ok(Self::FcvtSWu { rd, rs1, rm }),
+            (0b1110000, 0b000, 0b1010011) if rs2 == 0 => Ok(Self::FmvXW { rd, rs1 }),
+            (0b1110000, 0b001, 0b1010011) if rs2 == 0 => Ok(Self::FclassS { rd, rs1 }),
+            (0b1111000, 0b000, 0b1010011) if rs2 == 0 => Ok(Self::FmvWX { rd, rs1 }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, regs: &[u64; 32], fregs: &[u64; 32]) -> Self {
+        match self {
+            Rv32f::Flw { rd, rs1, imm } => Self::Flw { rd, rs1: regs[rs1 as usize], imm },
+            Rv32f::Fsw { rs1, rs2, imm } => Self::Fsw { rs1: regs[rs1 as usize], rs2: fregs[rs2 as usize], imm },
+            Rv32f::FaddS { rd, rs1, rs2, rm } => Self::FaddS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize], rm },
+            Rv32f::FsubS { rd, rs1, rs2, rm } => Self::FsubS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize], rm },
+            Rv32f::FmulS { rd, rs1, rs2, rm } => Self::FmulS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize], rm },
+            Rv32f::FdivS { rd, rs1, rs2, rm } => Self::FdivS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize], rm },
+            Rv32f::FsqrtS { rd, rs1, rm } => Self::FsqrtS { rd, rs1: fregs[rs1 as usize], rm },
+            Rv32f::FsgnjS { rd, rs1, rs2 } => Self::FsgnjS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize] },
+            Rv32f::FsgnjnS { rd, rs1, rs2 } => Self::FsgnjnS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize] },
+            Rv32f::FsgnjxS { rd, rs1, rs2 } => Self::FsgnjxS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize] },
+            Rv32f::FminS { rd, rs1, rs2 } => Self::FminS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize] },
+            Rv32f::FmaxS { rd, rs1, rs2 } => Self::FmaxS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize] },
+            Rv32f::FeqS { rd, rs1, rs2 } => Self::FeqS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize] },
+            Rv32f::FltS { rd, rs1, rs2 } => Self::FltS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize] },
+            Rv32f::FleS { rd, rs1, rs2 } => Self::FleS { rd, rs1: fregs[rs1 as usize], rs2: fregs[rs2 as usize] },
+            Rv32f::FcvtWS { rd, rs1, rm } => Self::FcvtWS { rd, rs1: fregs[rs1 as usize], rm },
+            Rv32f::FcvtWuS { rd, rs1, rm } => Self::FcvtWuS { rd, rs1: fregs[rs1 as usize], rm },
+            Rv32f::FcvtSW { rd, rs1, rm } => Self::FcvtSW { rd, rs1: regs[rs1 as usize], rm },
+            Rv32f::FcvtSWu { rd, rs1, rm } => Self::FcvtSWu { rd, rs1: regs[rs1 as usize], rm },
+            Rv32f::FmvXW { rd, rs1 } => Self::FmvXW { rd, rs1: fregs[rs1 as usize] },
+            Rv32f::FmvWX { rd, rs1 } => Self::FmvWX { rd, rs1: regs[rs1 as usize] },
+            Rv32f::FclassS { rd, rs1 } => Self::FclassS { rd, rs1: fregs[rs1 as usize] },
+        }
+    }
+
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], fregs: &mut [u64; 32], csr: &mut Csr, bus: &mut Bus) -> Result<u64, Exception> {
+        match self {
+            Rv32f::Flw { rd, rs1, imm } => {
+                let addr = rs1.wrapping_add(imm);
+                fregs[rd as usize] = box_f32(f32::from_bits(bus.load(addr, B32)? as u32));
+            },
+            Rv32f::Fsw { rs1, rs2, imm } => {
+                let addr = rs1.wrapping_add(imm);
+                bus.store(addr, B32, rs2 & 0xffff_ffff)?;
+            },
+            Rv32f::FaddS { rd, rs1, rs2, rm } => {
+                rounding(rm, csr)?;
+                let (a, b) = (unbox_f32(rs1), unbox_f32(rs2));
+                let sum = a + b;
+                fregs[rd as usize] = arith(csr, &[a, b], sum, sum_inexact(a, b, sum));
+            },
+            Rv32f::FsubS { rd, rs1, rs2, rm } => {
+                rounding(rm, csr)?;
+                let (a, b) = (unbox_f32(rs1), unbox_f32(rs2));
+                let difference = a - b;
+                fregs[rd as usize] = arith(csr, &[a, b], difference, sum_inexact(a, -b, difference));
+            },
+            Rv32f::FmulS { rd, rs1, rs2, rm } => {
+                rounding(rm, csr)?;
+                let (a, b) = (unbox_f32(rs1), unbox_f32(rs2));
+                let product = a * b;
+                fregs[rd as usize] = arith(csr, &[a, b], product, product_differs(a, b, product));
+            },
+            Rv32f::FdivS { rd, rs1, rs2, rm } => {
+                rounding(rm, csr)?;
+                let (a, b) = (unbox_f32(rs1), unbox_f32(rs2));
+                if b == 0.0 && a.is_finite() && a != 0.0 {
+                    raise(csr, FFLAGS_DZ);
+                }
+                let quotient = a / b;
+                fregs[rd as usize] = arith(csr, &[a, b], quotient, product_differs(quotient, b, a));
+            },
+            Rv32f::FsqrtS { rd, rs1, rm } => {
+                rounding(rm, csr)?;
+                let a = unbox_f32(rs1);
+                let root = a.sqrt();
+                fregs[rd as usize] = arith(csr, &[a], root, product_differs(root, root, a));
+            },
+            Rv32f::FsgnjS { rd, rs1, rs2 } => {
+                let (a, b) = (unbox_f32(rs1).to_bits(), unbox_f32(rs2).to_bits());
+                fregs[rd as usize] = box_f32(f32::from_bits((a & 0x7fff_ffff) | (b & 0x8000_0000)));
+            },
+            Rv32f::FsgnjnS { rd, rs1, rs2 } => {
+                let (a, b) = (unbox_f32(rs1).to_bits(), unbox_f32(rs2).to_bits());
+                fregs[rd as usize] = box_f32(f32::from_bits((a & 0x7fff_ffff) | (!b & 0x8000_0000)));
+            },
+            Rv32f::FsgnjxS { rd, rs1, rs2 } => {
+                let (a, b) = (unbox_f32(rs1).to_bits(), unbox_f32(rs2).to_bits());
+                fregs[rd as usize] = box_f32(f32::from_bits(a ^ (b & 0x8000_0000)));
+            },
+            Rv32f::FminS { rd, rs1, rs2 } => {
+                fregs[rd as usize] = min_max(csr, unbox_f32(rs1), unbox_f32(rs2), false);
+            },
+            Rv32f::FmaxS { rd, rs1, rs2 } => {
+                fregs[rd as usize] = min_max(csr, unbox_f32(rs1), unbox_f32(rs2), true);
+            },
+            Rv32f::FeqS { rd, rs1, rs2 } => {
+                // a quiet comparison, only signalling NaNs are invalid
+                let (a, b) = (unbox_f32(rs1), unbox_f32(rs2));
+                if is_snan(a) || is_snan(b) {
+                    raise(csr, FFLAGS_NV);
+                }
+                write_reg(regs, rd, (a == b) as u64);
+            },
+            Rv32f::FltS { rd, rs1, rs2 } => {
+                let (a, b) = (unbox_f32(rs1), unbox_f32(rs2));
+                if a.is_nan() || b.is_nan() {
+                    raise(csr, FFLAGS_NV);
+                }
+                write_reg(regs, rd, (a < b) as u64);
+            },
+            Rv32f::FleS { rd, rs1, rs2 } => {
+                let (a, b) = (unbox_f32(rs1), unbox_f32(rs2));
+                if a.is_nan() || b.is_nan() {
+                    raise(csr, FFLAGS_NV);
+                }
+                write_reg(regs, rd, (a <= b) as u64);
+            },
+            Rv32f::FcvtWS { rd, rs1, rm } => {
+                let rm = rounding(rm, csr)?;
+                let value = to_int(csr, unbox_f32(rs1), rm, false);
+                write_reg(regs, rd, value);
+            },
+            Rv32f::FcvtWuS { rd, rs1, rm } => {
+                let rm = rounding(rm, csr)?;
+                let value = to_int(csr, unbox_f32(rs1), rm, true);
+                write_reg(regs, rd, value);
+            },
+            Rv32f::FcvtSW { rd, rs1, rm } => {
+                let rm = rounding(rm, csr)?;
+                fregs[rd as usize] = from_int(csr, rs1 as i32 as f64, rm);
+            },
+            Rv32f::FcvtSWu { rd, rs1, rm } => {
+                let rm = rounding(rm, csr)?;
+                fregs[rd as usize] = from_int(csr, rs1 as u32 as f64, rm);
+            },
+            // the moves copy the bits as they are, NaN-boxed or not
+            Rv32f::FmvXW { rd, rs1 } => write_reg(regs, rd, rs1 as u32 as i32 as u64),
+            Rv32f::FmvWX { rd, rs1 } => fregs[rd as usize] = box_f32(f32::from_bits(rs1 as u32)),
+            Rv32f::FclassS { rd, rs1 } => write_reg(regs, rd, class(unbox_f32(rs1))),
+        }
+        Ok(pc.wrapping_add(len))
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            Rv32f::Flw { rs1, .. } |
+            Rv32f::FcvtSW { rs1, .. } |
+            Rv32f::FcvtSWu { rs1, .. } |
+            Rv32f::FmvWX { rs1, .. } => vec![*rs1],
+            Rv32f::Fsw { rs1, rs2, .. } => vec![*rs1, FREG_BASE + rs2],
+            Rv32f::FaddS { rs1, rs2, .. } |
+            Rv32f::FsubS { rs1, rs2, .. } |
+            Rv32f::FmulS { rs1, rs2, .. } |
+            Rv32f::FdivS { rs1, rs2, .. } |
+            Rv32f::FsgnjS { rs1, rs2, .. } |
+            Rv32f::FsgnjnS { rs1, rs2, .. } |
+            Rv32f::FsgnjxS { rs1, rs2, .. } |
+            Rv32f::FminS { rs1, rs2, .. } |
+            Rv32f::FmaxS { rs1, rs2, .. } |
+            Rv32f::FeqS { rs1, rs2, .. } |
+            Rv32f::FltS { rs1, rs2, .. } |
+            Rv32f::FleS { rs1, rs2, .. } => vec![FREG_BASE + rs1, FREG_BASE + rs2],
+            Rv32f::FsqrtS { rs1, .. } |
+            Rv32f::FcvtWS { rs1, .. } |
+            Rv32f::FcvtWuS { rs1, .. } |
+            Rv32f::FmvXW { rs1, .. } |
+            Rv32f::FclassS { rs1, .. } => vec![FREG_BASE + rs1],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        match self {
+            Rv32f::Fsw { .. } => None,
+            Rv32f::FeqS { rd, .. } |
+            Rv32f::FltS { rd, .. } |
+            Rv32f::FleS { rd, .. } |
+            Rv32f::FcvtWS { rd, .. } |
+            Rv32f::FcvtWuS { rd, .. } |
+            Rv32f::FmvXW { rd, .. } |
+            Rv32f::FclassS { rd, .. } => Some(*rd),
+            Rv32f::Flw { rd, .. } |
+            Rv32f::FaddS { rd, .. } |
+            Rv32f::FsubS { rd, .. } |
+            Rv32f::FmulS { rd, .. } |
+            Rv32f::FdivS { rd, .. } |
+            Rv32f::FsqrtS { rd, .. } |
+            Rv32f::FsgnjS { rd, .. } |
+            Rv32f::FsgnjnS { rd, .. } |
+            Rv32f::FsgnjxS { rd, .. } |
+            Rv32f::FminS { rd, .. } |
+            Rv32f::FmaxS { rd, .. } |
+            Rv32f::FcvtSW { rd, .. } |
+            Rv32f::FcvtSWu { rd, .. } |
+            Rv32f::FmvWX { rd, .. } => Some(FREG_BASE + rd),
+        }
+    }
+
+    fn imm(&self) -> Option<i64> {
+        match self {
+            Rv32f::Flw { imm, .. } |
+            Rv32f::Fsw { imm, .. } => Some(*imm as i64),
+            _ => None
+        }
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        match self {
+            Rv32f::Flw { rs1, imm, .. } => Some(rs1.wrapping_add(*imm)),
+            _ => None
+        }
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        match self {
+            Rv32f::Fsw { rs1, imm, .. } => Some(rs1.wrapping_add(*imm)),
+            _ => None
+        }
+    }
+
+    fn is_ld(&self) -> bool {
+        matches!(self, Rv32f::Flw { .. })
+    }
+
+    fn is_st(&self) -> bool {
+        matches!(self, Rv32f::Fsw { .. })
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn latency(&self) -> usize {
+        match self {
+            Rv32f::Flw { .. } => 2,
+            Rv32f::FmulS { .. } => MUL_LATENCY,
+            Rv32f::FdivS { .. } |
+            Rv32f::FsqrtS { .. } => DIV_LATENCY,
+            _ => 1
+        }
+    }
+
+    fn func_unit(&self) -> FuncUnit {
+        match self {
+            Rv32f::Flw { .. } |
+            Rv32f::Fsw { .. } => FuncUnit::Mem,
+            Rv32f::FmulS { .. } => FuncUnit::Mul,
+            Rv32f::FdivS { .. } |
+            Rv32f::FsqrtS { .. } => FuncUnit::Div,
+            _ => FuncUnit::Alu
+        }
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Rv32f::Flw { rd, rs1, imm } => format!("flw {}, {}({})", freg_name(*rd), *imm as i64, reg_name(*rs1)),
+            Rv32f::Fsw { rs1, rs2, imm } => format!("fsw {}, {}({})", freg_name(*rs2), *imm as i64, reg_name(*rs1)),
+            Rv32f::FaddS { rd, rs1, rs2, rm } => format!("fadd.s {}, {}, {}{}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2), rm_suffix(*rm)),
+            Rv32f::FsubS { rd, rs1, rs2, rm } => format!("fsub.s {}, {}, {}{}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2), rm_suffix(*rm)),
+            Rv32f::FmulS { rd, rs1, rs2, rm } => format!("fmul.s {}, {}, {}{}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2), rm_suffix(*rm)),
+            Rv32f::FdivS { rd, rs1, rs2, rm } => format!("fdiv.s {}, {}, {}{}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2), rm_suffix(*rm)),
+            Rv32f::FsqrtS { rd, rs1, rm } => format!("fsqrt.s {}, {}{}", freg_name(*rd), freg_name(*rs1), rm_suffix(*rm)),
+            Rv32f::FsgnjS { rd, rs1, rs2 } => format!("fsgnj.s {}, {}, {}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2)),
+            Rv32f::FsgnjnS { rd, rs1, rs2 } => format!("fsgnjn.s {}, {}, {}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2)),
+            Rv32f::FsgnjxS { rd, rs1, rs2 } => format!("fsgnjx.s {}, {}, {}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2)),
+            Rv32f::FminS { rd, rs1, rs2 } => format!("fmin.s {}, {}, {}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2)),
+            Rv32f::FmaxS { rd, rs1, rs2 } => format!("fmax.s {}, {}, {}", freg_name(*rd), freg_name(*rs1), freg_name(*rs2)),
+            Rv32f::FeqS { rd, rs1, rs2 } => format!("feq.s {}, {}, {}", reg_name(*rd), freg_name(*rs1), freg_name(*rs2)),
+            Rv32f::FltS { rd, rs1, rs2 } => format!("flt.s {}, {}, {}", reg_name(*rd), freg_name(*rs1), freg_name(*rs2)),
+            Rv32f::FleS { rd, rs1, rs2 } => format!("fle.s {}, {}, {}", reg_name(*rd), freg_name(*rs1), freg_name(*rs2)),
+            Rv32f::FcvtWS { rd, rs1, rm } => format!("fcvt.w.s {}, {}{}", reg_name(*rd), freg_name(*rs1), rm_suffix(*rm)),
+            Rv32f::FcvtWuS { rd, rs1, rm } => format!("fcvt.wu.s {}, {}{}", reg_name(*rd), freg_name(*rs1), rm_suffix(*rm)),
+            Rv32f::FcvtSW { rd, rs1, rm } => format!("fcvt.s.w {}, {}{}", freg_name(*rd), reg_name(*rs1), rm_suffix(*rm)),
+            Rv32f::FcvtSWu { rd, rs1, rm } => format!("fcvt.s.wu {}, {}{}", freg_name(*rd), reg_name(*rs1), rm_suffix(*rm)),
+            Rv32f::FmvXW { rd, rs1 } => format!("fmv.x.w {}, {}", reg_name(*rd), freg_name(*rs1)),
+            Rv32f::FmvWX { rd, rs1 } => format!("fmv.w.x {}, {}", freg_name(*rd), reg_name(*rs1)),
+            Rv32f::FclassS { rd, rs1 } => format!("fclass.s {}, {}", reg_name(*rd), freg_name(*rs1)),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Rv32f::Flw { .. } => "flw",
+            Rv32f::Fsw { .. } => "fsw",
+            Rv32f::FaddS { .. } => "fadd.s",
+            Rv32f::FsubS { .. } => "fsub.s",
+            Rv32f::FmulS { .. } => "fmul.s",
+            Rv32f::FdivS { .. } => "fdiv.s",
+            Rv32f::FsqrtS { .. } => "fsqrt.s",
+            Rv32f::FsgnjS { .. } => "fsgnj.s",
+            Rv32f::FsgnjnS { .. } => "fsgnjn.s",
+            Rv32f::FsgnjxS { .. } => "fsgnjx.s",
+            Rv32f::FminS { .. } => "fmin.s",
+            Rv32f::FmaxS { .. } => "fmax.s",
+            Rv32f::FeqS { .. } => "feq.s",
+            Rv32f::FltS { .. } => "flt.s",
+            Rv32f::FleS { .. } => "fle.s",
+            Rv32f::FcvtWS { .. } => "fcvt.w.s",
+            Rv32f::FcvtWuS { .. } => "fcvt.wu.s",
+            Rv32f::FcvtSW { .. } => "fcvt.s.w",
+            Rv32f::FcvtSWu { .. } => "fcvt.s.wu",
+            Rv32f::FmvXW { .. } => "fmv.x.w",
+            Rv32f::FmvWX { .. } => "fmv.w.x",
+            Rv32f::FclassS { .. } => "fclass.s",
+        }
+    }
+}
+
+impl Display for Rv32f {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rv32f::Flw { rd, rs1, imm } => write!(f, "flw rd={}, offset(rs1)={}({})", rd, imm, rs1),
+            Rv32f::Fsw { rs1, rs2, imm } => write!(f, "fsw rs2={}, offset(rs1)={}({})", rs2, imm, rs1),
+            Rv32f::FaddS { rd, rs1, rs2, rm } => write!(f, "fadd.s rd={}, rs1={}, rs2={}, rm={}", rd, rs1, rs2, rm),
+            Rv32f::FsubS { rd, rs1, rs2, rm } => write!(f, "fsub.s rd={}, rs1={}, rs2={}, rm={}", rd, rs1, rs2, rm),
+            Rv32f::FmulS { rd, rs1, rs2, rm } => write!(f, "fmul.s rd={}, rs1={}, rs2={}, rm={}", rd, rs1, rs2, rm),
+            Rv32f::FdivS { rd, rs1, rs2, rm } => write!(f, "fdiv.s rd={}, rs1={}, rs2={}, rm={}", rd, rs1, rs2, rm),
+            Rv32f::FsqrtS { rd, rs1, rm } => write!(f, "fsqrt.s rd={}, rs1={}, rm={}", rd, rs1, rm),
+            Rv32f::FsgnjS { rd, rs1, rs2 } => write!(f, "fsgnj.s rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32f::FsgnjnS { rd, rs1, rs2 } => write!(f, "fsgnjn.s rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32f::FsgnjxS { rd, rs1, rs2 } => write!(f, "fsgnjx.s rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32f::FminS { rd, rs1, rs2 } => write!(f, "fmin.s rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32f::FmaxS { rd, rs1, rs2 } => write!(f, "fmax.s rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32f::FeqS { rd, rs1, rs2 } => write!(f, "feq.s rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32f::FltS { rd, rs1, rs2 } => write!(f, "flt.s rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32f::FleS { rd, rs1, rs2 } => write!(f, "fle.s rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32f::FcvtWS { rd, rs1, rm } => write!(f, "fcvt.w.s rd={}, rs1={}, rm={}", rd, rs1, rm),
+            Rv32f::FcvtWuS { rd, rs1, rm } => write!(f, "fcvt.wu.s rd={}, rs1={}, rm={}", rd, rs1, rm),
+            Rv32f::FcvtSW { rd, rs1, rm } => write!(f, "fcvt.s.w rd={}, rs1={}, rm={}", rd, rs1, rm),
+            Rv32f::FcvtSWu { rd, rs1, rm } => write!(f, "fcvt.s.wu rd={}, rs1={}, rm={}", rd, rs1, rm),
+            Rv32f::FmvXW { rd, rs1 } => write!(f, "fmv.x.w rd={}, rs1={}", rd, rs1),
+            Rv32f::FmvWX { rd, rs1 } => write!(f, "fmv.w.x rd={}, rs1={}", rd, rs1),
+            Rv32f::FclassS { rd, rs1 } => write!(f, "fclass.s rd={}, rs1={}", rd, rs1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::Extension, bus::Bus, csr::{Csr, FFLAGS, FRM, FFLAGS_DZ, FFLAGS_NV, FFLAGS_NX, FFLAGS_OF, FFLAGS_UF}, dart::DartSoC, soc::{SoC, Exit}, exception::Exception};
+    use super::{Rv32f, box_f32, unbox_f32, CANONICAL_NAN, RM_DYN, RM_RTZ};
+
+    #[test]
+    fn fadd_through_the_pipeline() {
+        let bin: Vec<u8> = [
+            0x3fc00537_u32, // lui a0, 0x3fc00 (1.5)
+            0x401005b7,     // lui a1, 0x40100 (2.25)
+            0xf0050053,     // fmv.w.x ft0, a0
+            0xf00580d3,     // fmv.w.x ft1, a1
+            0x00107153,     // fadd.s ft2, ft0, ft1
+            0xe0010653,     // fmv.x.w a2, ft2
+        ].iter().flat_map(|i| i.to_le_bytes()).collect();
+        let mut cpu = DartSoC::new(bin);
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(unbox_f32(cpu.fregs[2]), 3.75);
+        assert_eq!(cpu.regs[12], 3.75_f32.to_bits() as u64);
+    }
+
+    #[test]
+    fn flags_and_conversions() {
+        let (mut regs, mut fregs) = ([0_u64; 32], [0_u64; 32]);
+        let (mut csr, mut bus) = (Csr::new(), Bus::new(vec![]));
+        let (one, zero) = (box_f32(1.0), box_f32(0.0));
+        Rv32f::FdivS { rd: 1, rs1: one, rs2: zero, rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(unbox_f32(fregs[1]), f32::INFINITY);
+        assert_eq!(csr.load(FFLAGS), FFLAGS_DZ);
+        Rv32f::FsqrtS { rd: 1, rs1: box_f32(-1.0), rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(fregs[1] as u32, CANONICAL_NAN);
+        assert_eq!(csr.load(FFLAGS), FFLAGS_DZ | FFLAGS_NV);
+        csr.store(FFLAGS, 0);
+        Rv32f::FaddS { rd: 1, rs1: box_f32(1.5), rs2: box_f32(2.25), rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        Rv32f::FdivS { rd: 1, rs1: one, rs2: box_f32(f32::INFINITY), rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(csr.load(FFLAGS), 0);
+        Rv32f::FaddS { rd: 1, rs1: one, rs2: box_f32(1e-10), rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(csr.load(FFLAGS), FFLAGS_NX);
+        Rv32f::FmulS { rd: 1, rs1: box_f32(f32::MAX), rs2: box_f32(2.0), rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(csr.load(FFLAGS), FFLAGS_OF | FFLAGS_NX);
+        csr.store(FFLAGS, 0);
+        // halving the smallest normal is exact, a third of it is not
+        Rv32f::FmulS { rd: 1, rs1: box_f32(f32::MIN_POSITIVE), rs2: box_f32(0.5), rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(csr.load(FFLAGS), 0);
+        Rv32f::FdivS { rd: 1, rs1: box_f32(f32::MIN_POSITIVE), rs2: box_f32(3.0), rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(csr.load(FFLAGS), FFLAGS_UF | FFLAGS_NX);
+        csr.store(FFLAGS, 0);
+        Rv32f::FsqrtS { rd: 1, rs1: box_f32(2.0), rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(csr.load(FFLAGS), FFLAGS_NX);
+        Rv32f::FcvtWS { rd: 1, rs1: box_f32(-2.5), rm: RM_RTZ }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[1], -2_i64 as u64);
+        Rv32f::FcvtWuS { rd: 1, rs1: box_f32(-2.5), rm: RM_RTZ }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[1], 0);
+        // an unboxed value reads as the canonical NaN, fcvt.w.s saturates it
+        Rv32f::FcvtWS { rd: 1, rs1: 1.0_f32.to_bits() as u64, rm: RM_RTZ }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[1], i32::MAX as u64);
+        Rv32f::FmaxS { rd: 1, rs1: box_f32(-0.0), rs2: zero }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).unwrap();
+        assert_eq!(fregs[1], zero);
+        // frm holding a reserved mode makes dynamic rounding illegal
+        csr.store(FRM, 0b101);
+        assert!(Rv32f::FaddS { rd: 1, rs1: one, rs2: one, rm: RM_DYN }.wr(0, 4, &mut regs, &mut fregs, &mut csr, &mut bus).is_err());
+        assert!(Rv32f::id(0x00105153).is_err());
+    }
+}
\ No newline at end of file
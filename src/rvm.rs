@@ -0,0 +1,380 @@
+use std::fmt::Display;
+
+use crate::{exception::Exception, bus::Bus, csr::Csr, isa::{Extension, FuncUnit, opcode, funct3, funct7, rd, rs1, rs2, reg_name, signed, write_reg}};
+
+pub const MUL_LATENCY: usize = 3;
+pub const DIV_LATENCY: usize = 20;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Rv32m {
+    Mul { rd: u64, rs1: u64, rs2: u64 },
+    Mulh { rd: u64, rs1: u64, rs2: u64 },
+    Mulhsu { rd: u64, rs1: u64, rs2: u64 },
+    Mulhu { rd: u64, rs1: u64, rs2: u64 },
+    Div { rd: u64, rs1: u64, rs2: u64 },
+    Divu { rd: u64, rs1: u64, rs2: u64 },
+    Rem { rd: u64, rs1: u64, rs2: u64 },
+    Remu { rd: u64, rs1: u64, rs2: u64 },
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Rv64m {
+    Mulw { rd: u64, rs1: u64, rs2: u64 },
+    Divw { rd: u64, rs1: u64, rs2: u64 },
+    Divuw { rd: u64, rs1: u64, rs2: u64 },
+    Remw { rd: u64, rs1: u64, rs2: u64 },
+    Remuw { rd: u64, rs1: u64, rs2: u64 },
+}
+
+impl Extension for Rv32m {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        let opcode = opcode(ins);
+        let funct3 = funct3(ins);
+        let funct7 = funct7(ins);
+
+        let rd = rd(ins) as u64;
+        let rs1 = rs1(ins) as u64;
+        let rs2 = rs2(ins) as u64;
+
+        match (funct7, funct3, opcode) {
+            (0b0000001, 0b000, 0b0110011) => Ok(Self::Mul { rd, rs1, rs2 }),
+            (0b0000001, 0b001, 0b0110011) => Ok(Self::Mulh { rd, rs1, rs2 }),
+            (0b0000001, 0b010, 0b0110011) => Ok(Self::Mulhsu { rd, rs1, rs2 }),
+            (0b0000001, 0b011, 0b0110011) => Ok(Self::Mulhu { rd, rs1, rs2 }),
+            (0b0000001, 0b100, 0b0110011) => Ok(Self::Div { rd, rs1, rs2 }),
+            (0b0000001, 0b101, 0b0110011) => Ok(Self::Divu { rd, rs1, rs2 }),
+            (0b0000001, 0b110, 0b0110011) => Ok(Self::Rem { rd, rs1, rs2 }),
+            (0b0000001, 0b111, 0b0110011) => Ok(Self::Remu { rd, rs1, rs2 }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        match self {
+            Rv32m::Mul { rd, rs1, rs2 } => Self::Mul { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32m::Mulh { rd, rs1, rs2 } => Self::Mulh { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32m::Mulhsu { rd, rs1, rs2 } => Self::Mulhsu { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32m::Mulhu { rd, rs1, rs2 } => Self::Mulhu { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32m::Div { rd, rs1, rs2 } => Self::Div { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32m::Divu { rd, rs1, rs2 } => Self::Divu { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32m::Rem { rd, rs1, rs2 } => Self::Rem { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv32m::Remu { rd, rs1, rs2 } => Self::Remu { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+        }
+    }
+
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], csr: &mut Csr, _bus: &mut Bus) -> Result<u64, Exception> {
+        match self {
+            Rv32m::Mul { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rs1.wrapping_mul(rs2));
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32m::Mulh { rd, rs1, rs2 } => {
+                write_reg(regs, rd, ((signed(rs1, csr) as i128 * signed(rs2, csr) as i128) >> csr.xlen()) as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32m::Mulhsu { rd, rs1, rs2 } => {
+                write_reg(regs, rd, ((signed(rs1, csr) as i128).wrapping_mul(rs2 as i128) >> csr.xlen()) as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32m::Mulhu { rd, rs1, rs2 } => {
+                write_reg(regs, rd, ((rs1 as u128 * rs2 as u128) >> csr.xlen()) as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32m::Div { rd, rs1, rs2 } => {
+                write_reg(regs, rd, if rs2 == 0 { u64::MAX } else { signed(rs1, csr).wrapping_div(signed(rs2, csr)) as u64 });
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32m::Divu { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rs1.checked_div(rs2).unwrap_or(u64::MAX));
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32m::Rem { rd, rs1, rs2 } => {
+                write_reg(regs, rd, if rs2 == 0 { rs1 } else { signed(rs1, csr).wrapping_rem(signed(rs2, csr)) as u64 });
+                Ok(pc.wrapping_add(len))
+            },
+            Rv32m::Remu { rd, rs1, rs2 } => {
+                write_reg(regs, rd, rs1.checked_rem(rs2).unwrap_or(rs1));
+                Ok(pc.wrapping_add(len))
+            },
+        }
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            Rv32m::Mul { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32m::Mulh { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32m::Mulhsu { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32m::Mulhu { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32m::Div { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32m::Divu { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32m::Rem { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv32m::Remu { rs1, rs2, .. } => vec![*rs1, *rs2],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        match self {
+            Rv32m::Mul { rd, .. } => Some(*rd),
+            Rv32m::Mulh { rd, .. } => Some(*rd),
+            Rv32m::Mulhsu { rd, .. } => Some(*rd),
+            Rv32m::Mulhu { rd, .. } => Some(*rd),
+            Rv32m::Div { rd, .. } => Some(*rd),
+            Rv32m::Divu { rd, .. } => Some(*rd),
+            Rv32m::Rem { rd, .. } => Some(*rd),
+            Rv32m::Remu { rd, .. } => Some(*rd),
+        }
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_ld(&self) -> bool {
+        false
+    }
+
+    fn is_st(&self) -> bool {
+        false
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn latency(&self) -> usize {
+        match self {
+            Rv32m::Mul { .. } |
+            Rv32m::Mulh { .. } |
+            Rv32m::Mulhsu { .. } |
+            Rv32m::Mulhu { .. } => MUL_LATENCY,
+            _ => DIV_LATENCY
+        }
+    }
+
+    fn func_unit(&self) -> FuncUnit {
+        match self {
+            Rv32m::Mul { .. } |
+            Rv32m::Mulh { .. } |
+            Rv32m::Mulhsu { .. } |
+            Rv32m::Mulhu { .. } => FuncUnit::Mul,
+            _ => FuncUnit::Div
+        }
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Rv32m::Mul { rd, rs1, rs2 } => format!("mul {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32m::Mulh { rd, rs1, rs2 } => format!("mulh {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32m::Mulhsu { rd, rs1, rs2 } => format!("mulhsu {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32m::Mulhu { rd, rs1, rs2 } => format!("mulhu {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32m::Div { rd, rs1, rs2 } => format!("div {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32m::Divu { rd, rs1, rs2 } => format!("divu {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32m::Rem { rd, rs1, rs2 } => format!("rem {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv32m::Remu { rd, rs1, rs2 } => format!("remu {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Rv32m::Mul { .. } => "mul",
+            Rv32m::Mulh { .. } => "mulh",
+            Rv32m::Mulhsu { .. } => "mulhsu",
+            Rv32m::Mulhu { .. } => "mulhu",
+            Rv32m::Div { .. } => "div",
+            Rv32m::Divu { .. } => "divu",
+            Rv32m::Rem { .. } => "rem",
+            Rv32m::Remu { .. } => "remu",
+        }
+    }
+}
+
+impl Extension for Rv64m {
+    fn id(ins: u32) -> Result<Self, Exception> {
+        let opcode = opcode(ins);
+        let funct3 = funct3(ins);
+        let funct7 = funct7(ins);
+
+        let rd = rd(ins) as u64;
+        let rs1 = rs1(ins) as u64;
+        let rs2 = rs2(ins) as u64;
+
+        match (funct7, funct3, opcode) {
+            (0b0000001, 0b000, 0b0111011) => Ok(Self::Mulw { rd, rs1, rs2 }),
+            (0b0000001, 0b100, 0b0111011) => Ok(Self::Divw { rd, rs1, rs2 }),
+            (0b0000001, 0b101, 0b0111011) => Ok(Self::Divuw { rd, rs1, rs2 }),
+            (0b0000001, 0b110, 0b0111011) => Ok(Self::Remw { rd, rs1, rs2 }),
+            (0b0000001, 0b111, 0b0111011) => Ok(Self::Remuw { rd, rs1, rs2 }),
+            _ => Err(Exception::IllegalInstruction(ins as u64))
+        }
+    }
+
+    fn ex(self, regs: &[u64; 32], _fregs: &[u64; 32]) -> Self {
+        match self {
+            Rv64m::Mulw { rd, rs1, rs2 } => Self::Mulw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64m::Divw { rd, rs1, rs2 } => Self::Divw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64m::Divuw { rd, rs1, rs2 } => Self::Divuw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64m::Remw { rd, rs1, rs2 } => Self::Remw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+            Rv64m::Remuw { rd, rs1, rs2 } => Self::Remuw { rd, rs1: regs[rs1 as usize], rs2: regs[rs2 as usize] },
+        }
+    }
+
+    fn wr(self, pc: u64, len: u64, regs: &mut [u64; 32], _fregs: &mut [u64; 32], _csr: &mut Csr, _bus: &mut Bus) -> Result<u64, Exception> {
+        match self {
+            Rv64m::Mulw { rd, rs1, rs2 } => {
+                write_reg(regs, rd, (rs1 as i32).wrapping_mul(rs2 as i32) as i64 as u64);
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64m::Divw { rd, rs1, rs2 } => {
+                write_reg(regs, rd, if rs2 as i32 == 0 { u64::MAX } else { (rs1 as i32).wrapping_div(rs2 as i32) as i64 as u64 });
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64m::Divuw { rd, rs1, rs2 } => {
+                write_reg(regs, rd, (rs1 as u32).checked_div(rs2 as u32).map_or(u64::MAX, |q| q as i32 as i64 as u64));
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64m::Remw { rd, rs1, rs2 } => {
+                write_reg(regs, rd, if rs2 as i32 == 0 { rs1 as i32 as i64 as u64 } else { (rs1 as i32).wrapping_rem(rs2 as i32) as i64 as u64 });
+                Ok(pc.wrapping_add(len))
+            },
+            Rv64m::Remuw { rd, rs1, rs2 } => {
+                write_reg(regs, rd, (rs1 as u32).checked_rem(rs2 as u32).unwrap_or(rs1 as u32) as i32 as i64 as u64);
+                Ok(pc.wrapping_add(len))
+            },
+        }
+    }
+
+    fn src_regs(&self) -> Vec<u64> {
+        match self {
+            Rv64m::Mulw { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64m::Divw { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64m::Divuw { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64m::Remw { rs1, rs2, .. } => vec![*rs1, *rs2],
+            Rv64m::Remuw { rs1, rs2, .. } => vec![*rs1, *rs2],
+        }
+    }
+
+    fn dst_reg(&self) -> Option<u64> {
+        match self {
+            Rv64m::Mulw { rd, .. } => Some(*rd),
+            Rv64m::Divw { rd, .. } => Some(*rd),
+            Rv64m::Divuw { rd, .. } => Some(*rd),
+            Rv64m::Remw { rd, .. } => Some(*rd),
+            Rv64m::Remuw { rd, .. } => Some(*rd),
+        }
+    }
+
+    fn src_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn dst_mem_addr(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_ld(&self) -> bool {
+        false
+    }
+
+    fn is_st(&self) -> bool {
+        false
+    }
+
+    fn is_br(&self) -> bool {
+        false
+    }
+
+    fn is_jmp(&self) -> bool {
+        false
+    }
+
+    fn latency(&self) -> usize {
+        match self {
+            Rv64m::Mulw { .. } => MUL_LATENCY,
+            _ => DIV_LATENCY
+        }
+    }
+
+    fn func_unit(&self) -> FuncUnit {
+        match self {
+            Rv64m::Mulw { .. } => FuncUnit::Mul,
+            _ => FuncUnit::Div
+        }
+    }
+
+    fn asm(&self, _pc: u64) -> String {
+        match self {
+            Rv64m::Mulw { rd, rs1, rs2 } => format!("mulw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv64m::Divw { rd, rs1, rs2 } => format!("divw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv64m::Divuw { rd, rs1, rs2 } => format!("divuw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv64m::Remw { rd, rs1, rs2 } => format!("remw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+            Rv64m::Remuw { rd, rs1, rs2 } => format!("remuw {}, {}, {}", reg_name(*rd), reg_name(*rs1), reg_name(*rs2)),
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Rv64m::Mulw { .. } => "mulw",
+            Rv64m::Divw { .. } => "divw",
+            Rv64m::Divuw { .. } => "divuw",
+            Rv64m::Remw { .. } => "remw",
+            Rv64m::Remuw { .. } => "remuw",
+        }
+    }
+}
+
+impl Display for Rv32m {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rv32m::Mul { rd, rs1, rs2 } => write!(f, "mul rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32m::Mulh { rd, rs1, rs2 } => write!(f, "mulh rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32m::Mulhsu { rd, rs1, rs2 } => write!(f, "mulhsu rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32m::Mulhu { rd, rs1, rs2 } => write!(f, "mulhu rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32m::Div { rd, rs1, rs2 } => write!(f, "div rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32m::Divu { rd, rs1, rs2 } => write!(f, "divu rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32m::Rem { rd, rs1, rs2 } => write!(f, "rem rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv32m::Remu { rd, rs1, rs2 } => write!(f, "remu rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+        }
+    }
+}
+
+impl Display for Rv64m {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rv64m::Mulw { rd, rs1, rs2 } => write!(f, "mulw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64m::Divw { rd, rs1, rs2 } => write!(f, "divw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64m::Divuw { rd, rs1, rs2 } => write!(f, "divuw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64m::Remw { rd, rs1, rs2 } => write!(f, "remw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+            Rv64m::Remuw { rd, rs1, rs2 } => write!(f, "remuw rd={}, rs1={}, rs2={}", rd, rs1, rs2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{isa::Extension, bus::Bus, csr::Csr};
+    use super::{Rv32m, Rv64m};
+
+    #[test]
+    fn div_corner_cases() {
+        let mut regs = [0_u64; 32];
+        let (mut csr, mut bus) = (Csr::new(), Bus::new(vec![]));
+        Rv32m::Div { rd: 1, rs1: 7, rs2: 0 }.wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[1], u64::MAX);
+        Rv32m::Div { rd: 1, rs1: i64::MIN as u64, rs2: -1_i64 as u64 }.wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[1], i64::MIN as u64);
+        Rv32m::Rem { rd: 1, rs1: 7, rs2: 0 }.wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[1], 7);
+        Rv32m::Mulhu { rd: 1, rs1: u64::MAX, rs2: 2 }.wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[1], 1);
+        Rv64m::Divw { rd: 1, rs1: -7_i64 as u64, rs2: 2 }.wr(0, 4, &mut regs, &mut [0; 32], &mut csr, &mut bus).unwrap();
+        assert_eq!(regs[1], -3_i64 as u64);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,1364 @@
+use std::{fmt::Display, io::Write};
+
+use tabled::{builder::Builder, settings::Style};
+
+use crate::{exception::Exception, decode::{decode, Decoded, DecodeCache}, csr::{Csr, MISA, Privilege, CSR_COUNT, IRQ_M_EXT, IRQ_M_SOFT, IRQ_M_TIMER, MTVEC, MEPC, MCAUSE, MTVAL, MSTATUS, MSTATUS_MPP, MSTATUS_MIE, MSTATUS_MPIE, MIE, MIP, SATP, SSTATUS_SUM, CYCLE, TIME, INSTRET}, stats::{Stats, InsMix, Profile}, bus::{Bus, RAM_BASE}, device::Halt, isa::{Extension, reg_name, dep_reg_name, FREG_BASE, disasm_ins, decode_fields, Exts}, mem::{B8, B16, B32, B64}, rvc::{ins_len, is_compressed, expand}};
+
+/// Reason a SoC stopped executing
+#[derive(Debug, Copy, Clone)]
+pub enum Exit {
+    Exception(Exception),
+    Timeout,
+    /// The guest signalled completion through the assertion device
+    Done,
+    /// The guest stored a failing value to the assertion device
+    AssertFailed(u64),
+    /// The guest asked to exit with a status through tohost
+    Status(u64),
+    /// The retired instruction budget ran out
+    InsnLimit,
+    /// The pc kept revisiting recent instructions without the registers
+    /// changing, at the given pc
+    Livelock(u64),
+}
+
+impl Exit {
+    /// Process exit code for this outcome. Only a clean finish gives 0, and
+    /// a run cut short by a limit gives 124 as timeout(1) does.
+    pub fn code(&self) -> i32 {
+        match self {
+            Exit::Done => 0,
+            Exit::AssertFailed(_) => 1,
+            // the shell only sees the low byte, which must not read as a pass
+            Exit::Status(code) if code & 0xff == 0 && *code != 0 => 1,
+            Exit::Status(code) => *code as i32,
+            // an unhandled ecall is how test programs signal the end
+            Exit::Exception(ex) if is_ecall(*ex) => 0,
+            Exit::Exception(_) => 2,
+            Exit::Timeout | Exit::InsnLimit | Exit::Livelock(_) => 124,
+        }
+    }
+
+    pub fn json(&self) -> String {
+        match self {
+            Exit::Exception(ex) => format!("{{\"reason\":\"exception\",\"name\":\"{}\",\"value\":{}}}", ex.name(), ex.value()),
+            Exit::Timeout => "{\"reason\":\"timeout\"}".to_string(),
+            Exit::InsnLimit => "{\"reason\":\"insn_limit\"}".to_string(),
+            Exit::Livelock(pc) => format!("{{\"reason\":\"livelock\",\"pc\":{}}}", pc),
+            Exit::Done => "{\"reason\":\"done\"}".to_string(),
+            Exit::AssertFailed(value) => format!("{{\"reason\":\"assert_failed\",\"value\":{}}}", value),
+            Exit::Status(code) => format!("{{\"reason\":\"status\",\"code\":{}}}", code),
+        }
+    }
+}
+
+impl From<Halt> for Exit {
+    fn from(halt: Halt) -> Self {
+        match halt {
+            Halt::Done => Exit::Done,
+            Halt::AssertFailed(value) => Exit::AssertFailed(value),
+            Halt::Exit(code) => Exit::Status(code),
+        }
+    }
+}
+
+impl Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exit::Exception(ex) => write!(f, "{:?}", ex),
+            Exit::Timeout => write!(f, "timeout"),
+            Exit::InsnLimit => write!(f, "instruction limit"),
+            Exit::Livelock(pc) => write!(f, "livelock, no progress looping at {:#010x}", pc),
+            Exit::Done => write!(f, "done"),
+            Exit::AssertFailed(value) => write!(f, "assertion failure (value {:#x})", value),
+            Exit::Status(code) => write!(f, "exit code {}", code),
+        }
+    }
+}
+
+/// The instruction run by a single step
+#[derive(Debug, Copy, Clone)]
+pub struct StepOutcome {
+    /// Address the instruction was fetched from
+    pub pc: u64,
+    /// Whether it completed, rather than trapping or being skipped
+    pub retired: bool,
+}
+
+/// Architectural state of a SoC, taken by SoC::snapshot
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snapshot {
+    pub regs: [u64; 32],
+    pub fregs: [u64; 32],
+    pub pc: u64,
+    pub csr: Csr,
+    /// Contents of RAM, if it is still plain memory
+    pub ram: Option<Vec<u8>>,
+}
+
+impl Snapshot {
+    /// Flattens the snapshot into little-endian words: the integer and
+    /// float registers, pc, all 4096 stored CSRs, the privilege level and
+    /// pending interrupts, then a RAM length (u64::MAX if absent) and the
+    /// RAM
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let csrs = (0..CSR_COUNT).map(|addr| self.csr.load_raw(addr));
+        let ram_len = self.ram.as_ref().map_or(u64::MAX, |ram| ram.len() as u64);
+        let mut bytes: Vec<u8> = self.regs.iter().copied()
+            .chain(self.fregs.iter().copied())
+            .chain(std::iter::once(self.pc))
+            .chain(csrs)
+            .chain([self.csr.privilege as u64, self.csr.pending_interrupts, ram_len])
+            .flat_map(u64::to_le_bytes)
+            .collect();
+        if let Some(ram) = &self.ram {
+            bytes.extend_from_slice(ram);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Snapshot> {
+        let header = (32 + 32 + 1 + CSR_COUNT as usize + 3) * 8;
+        let (words, ram) = (bytes.get(..header)?, &bytes[header..]);
+        let mut words = words.chunks(8).map(|w| u64::from_le_bytes(w.try_into().unwrap()));
+        let mut regs = [0; 32];
+        for reg in regs.iter_mut() {
+            *reg = words.next()?;
+        }
+        let mut fregs = [0; 32];
+        for reg in fregs.iter_mut() {
+            *reg = words.next()?;
+        }
+        let pc = words.next()?;
+        let mut csr = Csr::new();
+        for addr in 0..CSR_COUNT {
+            csr.store_raw(addr, words.next()?);
+        }
+        csr.privilege = match words.next()? {
+            0 => Privilege::U,
+            1 => Privilege::S,
+            3 => Privilege::M,
+            _ => return None,
+        };
+        csr.pending_interrupts = words.next()?;
+        let ram = match words.next()? {
+            u64::MAX if ram.is_empty() => None,
+            len if len == ram.len() as u64 => Some(ram.to_vec()),
+            _ => return None,
+        };
+        Some(Snapshot { regs, fregs, pc, csr, ram })
+    }
+}
+
+/// An executed instruction in a dependency graph
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepNode {
+    pub pc: u64,
+    /// Cycle the scheduler issued the instruction in
+    pub cycle: usize,
+    /// Indices of the earlier instructions that produced its operands
+    pub deps: Vec<usize>,
+}
+
+/// Renders a dependency graph as Graphviz DOT, with edges running from
+/// producer to consumer
+pub fn dep_dot(nodes: &[DepNode]) -> String {
+    let mut dot = String::from("digraph deps {\n");
+    for (i, node) in nodes.iter().enumerate() {
+        dot += &format!("    n{} [label=\"{}: {:#010x}\\ncycle {}\"];\n", i, i, node.pc, node.cycle);
+        for dep in &node.deps {
+            dot += &format!("    n{} -> n{};\n", dep, i);
+        }
+    }
+    dot += "}\n";
+    dot
+}
+
+/// Run options shared by all SoCs
+#[derive(Default, Copy, Clone)]
+pub struct Config {
+    /// Stop the run once Stats::cycles reaches this, memory latency and
+    /// page walks included. The OoO models count a cycle per instruction
+    /// until calc_stats works out the real figure
+    pub timeout_cycles: Option<usize>,
+    /// Stop the run once this many instructions have retired, which unlike
+    /// cycles is the same point in the program for every model
+    pub max_insns: Option<usize>,
+    /// Write a line to the SoC's trace writer for each executed instruction
+    pub trace: bool,
+    /// Stop once instructions in the recent window have been revisited
+    /// this many times with no change to the registers or memory
+    pub livelock: Option<usize>,
+    /// Extensions the pipeline decodes, the rest raise illegal instruction
+    pub enabled_exts: Exts,
+    /// Log the pc of every add, sub and addi whose result overflows as a
+    /// signed XLEN value in Stats::overflows
+    pub record_overflow: bool,
+    /// Extra cycles Dart charges for each load or store that completes, to
+    /// model a memory slower than the single-cycle default
+    pub mem_latency: usize,
+    /// Where execution starts, again after a reset
+    pub entry: u64,
+}
+
+/// Receives every instruction a SoC commits, for tools built on top of the
+/// models such as coverage
+pub trait ExecObserver {
+    /// Called after write-back with the instruction's pc and word, the low
+    /// half only if it is compressed, and the updated registers
+    fn on_commit(&mut self, pc: u64, ins: u32, regs: &[u64; 32]);
+}
+
+/// Writes a Spike-style trace line for an executed instruction, e.g.
+/// `0x80000004 (0x02a00f93) addi t6, zero, 42  t6=0x2a`
+pub fn write_trace(out: &mut dyn Write, pc: u64, word: u32, asm: &str, dst: Option<u64>, regs: &[u64; 32], fregs: &[u64; 32]) {
+    // tracing is best effort and never stops the run
+    let _ = match dst {
+        Some(rd) if rd >= FREG_BASE => writeln!(out, "{:#010x} ({:#010x}) {}  {}={:#x}", pc, word, asm, dep_reg_name(rd), fregs[(rd - FREG_BASE) as usize]),
+        Some(rd) if rd != 0 => writeln!(out, "{:#010x} ({:#010x}) {}  {}={:#x}", pc, word, asm, reg_name(rd), regs[rd as usize]),
+        _ => writeln!(out, "{:#010x} ({:#010x}) {}", pc, word, asm),
+    };
+}
+
+/// Fetches the instruction at pc and decodes it at the current XLEN,
+/// through `cache` when the model keeps one. Returns the word, only the
+/// low half if it is compressed, along with the decoded instruction.
+pub fn fetch(bus: &mut Bus, csr: &Csr, pc: u64, exts: Exts, cache: Option<&mut DecodeCache>) -> Result<(u32, Decoded), Exception> {
+    let fetched = bus.fetch(pc)?;
+    // the RV64-only encodings are illegal in 32-bit mode
+    let rv64 = csr.xlen() == 64;
+    match cache {
+        Some(cache) => cache.decode(pc, fetched, rv64, exts),
+        None => {
+            let word = if is_compressed(fetched) { fetched & 0xffff } else { fetched };
+            Ok((word, decode(word, rv64, exts)?))
+        }
+    }
+}
+
+/// The state every model keeps, borrowed field by field so that executing
+/// and committing an instruction is written once for all of them
+pub struct Hart<'a> {
+    pub regs: &'a mut [u64; 32],
+    pub fregs: &'a mut [u64; 32],
+    pub pc: &'a mut u64,
+    pub bus: &'a mut Bus,
+    pub csr: &'a mut Csr,
+    pub stats: &'a mut Stats,
+    pub mix: &'a mut InsMix,
+    pub profile: &'a mut Profile,
+    pub config: &'a Config,
+    /// Destination for the execution trace when config.trace is set
+    pub trace_out: &'a mut Box<dyn Write>,
+    /// Called with every instruction that commits, when set
+    pub observer: &'a mut Option<Box<dyn ExecObserver>>,
+}
+
+impl Hart<'_> {
+    /// Executes an instruction and writes it back, then does the
+    /// bookkeeping that does not depend on timing: the op counts, mix,
+    /// profile, trace and observer. The models time it around this.
+    pub fn commit<O: Extension>(&mut self, word: u32, i: O) -> Result<(), Exception> {
+        let pc = *self.pc;
+        let mnemonic = i.mnemonic();
+        let trace = self.config.trace.then(|| (i.asm(pc), i.dst_reg()));
+        let ins_ex = i.ex(self.regs, self.fregs);
+        if ins_ex.is_ld() || ins_ex.is_st() {
+            self.stats.mem_ops += 1;
+        } else {
+            self.stats.alu_ops += 1;
+        }
+        let overflow = self.config.record_overflow && ins_ex.overflows(self.csr);
+        self.bus.watch_pc = pc;
+        *self.pc = ins_ex.wr(pc, ins_len(word), self.regs, self.fregs, self.csr, self.bus)?;
+        if overflow {
+            self.stats.overflows.push(pc);
+        }
+        self.mix.record(mnemonic);
+        self.profile.record(pc);
+        if self.csr.xlen() == 32 {
+            self.regs.iter_mut().for_each(|r| *r &= 0xffff_ffff);
+            *self.pc &= 0xffff_ffff;
+        }
+        if let Some((asm, dst)) = trace {
+            write_trace(self.trace_out, pc, word, &asm, dst, self.regs, self.fregs);
+        }
+        if let Some(observer) = self.observer {
+            observer.on_commit(pc, word, self.regs);
+        }
+        Ok(())
+    }
+
+    /// The part of SoC::reset every model shares
+    pub fn reset(&mut self, bin: &[u8]) {
+        self.bus.reload(bin);
+        *self.regs = [0; 32];
+        *self.fregs = [0; 32];
+        self.regs[2] = self.bus.base + self.bus.size - 1;
+        *self.pc = self.config.entry;
+        // keep the XLEN and extensions the run was configured with
+        let misa = self.csr.load(MISA);
+        *self.csr = Csr::new();
+        self.csr.store(MISA, misa);
+        *self.stats = Stats::new();
+        *self.mix = InsMix::new();
+        *self.profile = Profile::new();
+    }
+}
+
+/// A model's handling of a decoded instruction, generic over the
+/// extension that decoded it
+pub trait Datapath {
+    fn datapath<O: Extension>(&mut self, word: u32, i: O) -> Result<(), Exception>;
+
+    /// Passes `ins` to datapath as the extension's own type
+    fn dispatch(&mut self, word: u32, ins: Decoded) -> Result<(), Exception> {
+        match ins {
+            Decoded::Rv32i(ins) => self.datapath(word, ins),
+            Decoded::Rv64i(ins) => self.datapath(word, ins),
+            Decoded::Zbb(ins) => self.datapath(word, ins),
+            Decoded::Zbb64(ins) => self.datapath(word, ins),
+            Decoded::Rv32m(ins) => self.datapath(word, ins),
+            Decoded::Rv64m(ins) => self.datapath(word, ins),
+            Decoded::Rv32a(ins) => self.datapath(word, ins),
+            Decoded::Rv64a(ins) => self.datapath(word, ins),
+            Decoded::Rv32f(ins) => self.datapath(word, ins),
+            Decoded::Zicsr(ins) => self.datapath(word, ins),
+            Decoded::Zifencei(ins) => self.datapath(word, ins),
+            Decoded::System(ins) => self.datapath(word, ins),
+        }
+    }
+}
+
+fn is_ecall(ex: Exception) -> bool {
+    matches!(ex, Exception::EnvironmentCallFromUMode(_)
+        | Exception::EnvironmentCallFromSMode(_)
+        | Exception::EnvironmentCallFromMMode(_))
+}
+
+/// The raw word at pc and its disassembly, or None if pc is unmapped.
+/// Compressed words are shown with their RV64 expansion.
+fn fetch_asm(bus: &Bus, pc: u64) -> Option<(String, String)> {
+    let word = fetch_word(bus, pc)?;
+    let (raw, asm) = if is_compressed(word) {
+        (format!("{:04x}", word), expand(word as u16, true).ok().and_then(|ins| disasm_ins(ins, pc)))
+    } else {
+        (format!("{:08x}", word), disasm_ins(word, pc))
+    };
+    Some((raw, asm.unwrap_or("<undecodable>".to_string())))
+}
+
+/// The instruction at `pc`, only the low half of the word if it is
+/// compressed
+pub fn fetch_word(bus: &Bus, pc: u64) -> Option<u32> {
+    // a compressed instruction at the very end of RAM has no full word
+    let word = bus.peek_fetch(pc, B32).or_else(|_| bus.peek_fetch(pc, B16)).ok()? as u32;
+    Some(if is_compressed(word) { word & 0xffff } else { word })
+}
+
+/// One line of the fault report
+fn describe_ins(bus: &Bus, pc: u64) -> String {
+    match fetch_asm(bus, pc) {
+        Some((raw, asm)) => format!("{:#010x}: {}  {}", pc, raw, asm),
+        None => format!("{:#010x}: <unmapped>", pc),
+    }
+}
+
+pub trait SoC {
+    /// Starts execution at `entry` rather than the first byte of the program
+    fn with_entry(bin: Vec<u8>, entry: u64) -> Self where Self: Sized;
+
+    fn new(bin: Vec<u8>) -> Self where Self: Sized {
+        Self::with_entry(bin, RAM_BASE)
+    }
+
+    /// Runs a single instruction through the model
+    fn pipeline(&mut self) -> Result<(), Exception>;
+
+    fn config(&self) -> &Config;
+
+    fn config_mut(&mut self) -> &mut Config;
+
+    fn pc_mut(&mut self) -> &mut u64;
+
+    fn regfile(&self) -> &[u64; 32];
+
+    fn regfile_mut(&mut self) -> &mut [u64; 32];
+
+    /// The float registers, NaN-boxed singles
+    fn fregfile(&self) -> &[u64; 32];
+
+    fn fregfile_mut(&mut self) -> &mut [u64; 32];
+
+    fn bus(&self) -> &Bus;
+
+    fn bus_mut(&mut self) -> &mut Bus;
+
+    fn csr(&mut self) -> &mut Csr;
+
+    fn stats(&self) -> &Stats;
+
+    fn stats_mut(&mut self) -> &mut Stats;
+
+    fn mix(&self) -> &InsMix;
+
+    fn profile(&self) -> &Profile;
+
+    /// Loads a new program into the existing RAM and puts the registers,
+    /// pc, CSRs and stats back to how construction left them, keeping the
+    /// config, the entry point and the XLEN and extensions in misa.
+    /// Cheaper than building a new SoC, which allocates all of RAM again.
+    fn reset(&mut self, bin: Vec<u8>);
+
+    /// Captures the registers, pc, CSRs and RAM
+    fn snapshot(&mut self) -> Snapshot {
+        Snapshot {
+            regs: *self.regfile(),
+            fregs: *self.fregfile(),
+            pc: *self.pc_mut(),
+            csr: self.csr().clone(),
+            ram: self.bus().ram().map(|ram| ram.to_vec()),
+        }
+    }
+
+    /// Puts the SoC back into a state taken by snapshot, which must have
+    /// the same RAM size. Devices other than RAM keep their current state.
+    fn restore(&mut self, snapshot: &Snapshot) {
+        *self.regfile_mut() = snapshot.regs;
+        *self.fregfile_mut() = snapshot.fregs;
+        *self.pc_mut() = snapshot.pc;
+        *self.csr() = snapshot.csr.clone();
+        if let (Some(ram), Some(saved)) = (self.bus_mut().ram_mut(), &snapshot.ram) {
+            ram.copy_from_slice(saved);
+        }
+        self.bus_mut().reservation = None;
+    }
+
+    /// Lays out argc, argv and envp below the top of RAM as the psABI
+    /// expects at process entry, and points sp at argc
+    fn init_stack(&mut self, argv: &[String], envp: &[String]) -> Result<(), Exception> {
+        let (word, bits) = if self.csr().xlen() == 32 { (4, B32) } else { (8, B64) };
+        let bus = self.bus_mut();
+        let mut sp = bus.base + bus.size;
+        // the strings sit at the very top, the vectors point back up at them
+        let mut ptrs = Vec::new();
+        for s in argv.iter().chain(envp) {
+            sp -= s.len() as u64 + 1;
+            for (i, b) in s.bytes().chain([0]).enumerate() {
+                bus.store(sp + i as u64, B8, b as u64)?;
+            }
+            ptrs.push(sp);
+        }
+        let (argv_ptrs, envp_ptrs) = ptrs.split_at(argv.len());
+        // argc, argv, NULL, envp, NULL, then an empty auxv (AT_NULL)
+        let mut block = vec![argv.len() as u64];
+        block.extend(argv_ptrs);
+        block.push(0);
+        block.extend(envp_ptrs);
+        block.push(0);
+        block.extend([0, 0]);
+        sp = (sp - block.len() as u64 * word) & !0xf;
+        for (i, value) in block.iter().enumerate() {
+            bus.store(sp + i as u64 * word, bits, *value)?;
+        }
+        self.regfile_mut()[2] = sp;
+        Ok(())
+    }
+
+    /// Derives the final stats once execution has stopped
+    fn calc_stats(&mut self) {}
+
+    /// The scheduled instructions and their dependencies, for the models
+    /// that record them, available after calc_stats
+    fn dep_graph(&self) -> Option<Vec<DepNode>> {
+        None
+    }
+
+    /// Takes a trap into the handler at mtvec, recording the faulting pc in
+    /// mepc and the cause in mcause/mtval
+    fn trap(&mut self, ex: Exception) {
+        let tval = if is_ecall(ex) { 0 } else { *ex.value() };
+        self.enter_handler(ex.code(), tval);
+    }
+
+    /// Marks the interrupt with the given cause, e.g. IRQ_M_TIMER, pending.
+    /// It is taken before the next instruction once mie and mstatus.MIE
+    /// enable it, and stays pending until cleared.
+    fn raise_interrupt(&mut self, cause: u64) {
+        self.csr().pending_interrupts |= 1 << cause;
+    }
+
+    fn clear_interrupt(&mut self, cause: u64) {
+        self.csr().pending_interrupts &= !(1 << cause);
+    }
+
+    /// The highest priority pending interrupt that mie enables. M-mode
+    /// interrupts are always enabled below M-mode and follow mstatus.MIE
+    /// in it.
+    fn enabled_interrupt(&mut self) -> Option<u64> {
+        let csr = self.csr();
+        if csr.privilege == Privilege::M && csr.load(MSTATUS) & MSTATUS_MIE == 0 {
+            return None
+        }
+        let pending = csr.load(MIP) & csr.load(MIE);
+        // external, then software, then timer, as the spec orders them
+        [IRQ_M_EXT, IRQ_M_SOFT, IRQ_M_TIMER].into_iter()
+            .chain(0..64)
+            .find(|cause| pending & (1 << cause) != 0)
+    }
+
+    /// Records the trap cause and vectors to mtvec, with mepc pointing at
+    /// the instruction that was interrupted or raised the exception
+    fn enter_handler(&mut self, cause: u64, tval: u64) {
+        let pc = *self.pc_mut();
+        let csr = self.csr();
+        csr.store(MEPC, pc);
+        csr.store(MCAUSE, cause);
+        csr.store(MTVAL, tval);
+        // every trap is taken into M-mode, remembering where it came from
+        // and pushing MIE onto the interrupt enable stack
+        let mstatus = csr.load(MSTATUS);
+        let mpie = if mstatus & MSTATUS_MIE != 0 { MSTATUS_MPIE } else { 0 };
+        let mstatus = mstatus & !(MSTATUS_MPP | MSTATUS_MIE | MSTATUS_MPIE);
+        csr.store(MSTATUS, mstatus | mpie | (csr.privilege as u64) << 11);
+        csr.privilege = Privilege::M;
+        // only direct mode is supported, the mode bits are ignored
+        let handler = csr.load(MTVEC) & !0b11;
+        *self.pc_mut() = handler;
+    }
+
+    /// Runs exactly one instruction, vectoring to mtvec if it raises an
+    /// exception and a handler is installed. Returns the reason to stop if
+    /// the run cannot continue.
+    fn step(&mut self) -> Result<StepOutcome, Exit> {
+        let pc = *self.pc_mut();
+        // an interrupt takes the place of the instruction at pc, which runs
+        // again after the handler returns
+        if let Some(cause) = self.enabled_interrupt() {
+            let interrupt = 1 << (self.csr().xlen() - 1);
+            self.enter_handler(interrupt | cause, 0);
+            return Ok(StepOutcome { pc, retired: false })
+        }
+        // M-mode accesses are never translated
+        let csr = self.csr();
+        let satp = if csr.privilege < Privilege::M { csr.load(SATP) } else { 0 };
+        let (privilege, sum) = (csr.privilege, csr.load(MSTATUS) & SSTATUS_SUM != 0);
+        let bus = self.bus_mut();
+        bus.satp = satp;
+        bus.privilege = privilege;
+        bus.sum = sum;
+        let cycles = self.stats().cycles as u64;
+        let csr = self.csr();
+        let cycle = cycles.max(csr.load(CYCLE) + 1);
+        csr.store(CYCLE, cycle);
+        csr.store(TIME, cycle);
+        // every instruction takes at least a cycle, the OoO models replace
+        // the count with the one they model in calc_stats
+        self.stats_mut().cycles += 1;
+        let retired = self.pipeline();
+        let bus = self.bus();
+        let (hits, misses, walk) = (bus.tlb_hits, bus.tlb_misses, bus.page_walk_cycles);
+        let stats = self.stats_mut();
+        stats.tlb_hits = hits;
+        stats.tlb_misses = misses;
+        // the hart waits out every page table read, the OoO models add
+        // the total again in calc_stats
+        stats.cycles += walk.saturating_sub(stats.page_walk_cycles);
+        stats.page_walk_cycles = walk;
+        match retired {
+            Ok(_) => {
+                let csr = self.csr();
+                csr.store(INSTRET, csr.load(INSTRET) + 1);
+                self.stats_mut().push_recent(pc);
+                if let Some(halt) = self.bus().halt() {
+                    return Err(halt.into())
+                }
+            },
+            // without a handler installed there is nowhere to vector to
+            Err(ex) => if self.csr().load(MTVEC) != 0 {
+                self.trap(ex);
+            } else if ex.is_fatal() || is_ecall(ex) {
+                // an unhandled ecall is how test programs signal the end
+                if ex.is_fatal() {
+                    self.stats_mut().fault = Some((pc, ex));
+                }
+                return Err(Exit::Exception(ex))
+            } else {
+                // record the exception and step over the instruction rather
+                // than retrying it forever
+                self.stats_mut().exceptions.push((pc, ex));
+                let word = self.bus().peek_fetch(pc, B16).map_err(Exit::Exception)?;
+                *self.pc_mut() = pc.wrapping_add(ins_len(word as u32));
+            },
+        }
+        Ok(StepOutcome { pc, retired: retired.is_ok() })
+    }
+
+    /// The instruction that raised the fatal exception ending the run, and
+    /// the ones that retired just before it
+    fn fault_report(&self) -> Option<String> {
+        let (pc, ex) = self.stats().fault?;
+        let mut out = format!("{:?} at {:#010x}\n", ex, pc);
+        out += &format!("  {}\n", describe_ins(self.bus(), pc));
+        // show what the decoder saw, to tell a bad word from a missing one
+        if let Exception::IllegalInstruction(word) = ex {
+            for line in decode_fields(word as u32).to_string().lines() {
+                out += &format!("  {}\n", line);
+            }
+        }
+        out += "Last retired:\n";
+        for pc in &self.stats().recent {
+            out += &format!("  {}\n", describe_ins(self.bus(), *pc));
+        }
+        Some(out)
+    }
+
+    /// The n most executed pcs with their counts and disassembly
+    fn hot_spots(&self, n: usize) -> String {
+        let mut table = Builder::new();
+        table.set_header(["PC", "Count", "Word", "Instruction"]);
+        for (pc, count) in self.profile().hottest(n) {
+            let (raw, asm) = fetch_asm(self.bus(), pc).unwrap_or_default();
+            table.push_record([format!("{:#010x}", pc), format!("{}", count), raw, asm]);
+        }
+        let table = table.build()
+            .with(Style::ascii_rounded())
+            .to_string();
+        format!("{}\n", table)
+    }
+
+    /// The outcome of a run as a single JSON object, for scripts
+    fn json_report(&mut self, exit: Exit) -> String {
+        let pc = *self.pc_mut();
+        let regs: Vec<String> = self.regfile().iter().map(|r| r.to_string()).collect();
+        format!(
+            "{{\"exit\":{},\"pc\":{},\"regs\":[{}],\"stats\":{}}}",
+            exit.json(), pc, regs.join(","), self.stats().json()
+        )
+    }
+
+    fn execute(&mut self) -> Exit {
+        self.execute_with(&mut |_, _| {})
+    }
+
+    /// Runs like execute, handing each instruction that retires to
+    /// `on_retire` along with the bus it ran against
+    fn execute_with(&mut self, on_retire: &mut dyn FnMut(&Bus, StepOutcome)) -> Exit {
+        let mut retired = 0;
+        // revisits of a recent pc since the registers or memory last changed
+        let mut spins = 0;
+        let mut state = (*self.regfile(), *self.fregfile(), self.bus().stores);
+        loop {
+            if self.config().timeout_cycles.is_some_and(|max| self.stats().cycles >= max) {
+                self.calc_stats();
+                return Exit::Timeout
+            }
+            if self.config().max_insns.is_some_and(|max| retired >= max) {
+                self.calc_stats();
+                return Exit::InsnLimit
+            }
+            match self.step() {
+                Ok(outcome) => if outcome.retired {
+                    retired += 1;
+                    on_retire(self.bus(), outcome);
+                    if let Some(limit) = self.config().livelock {
+                        let now = (*self.regfile(), *self.fregfile(), self.bus().stores);
+                        if now != state {
+                            state = now;
+                            spins = 0;
+                        } else if self.stats().recent.iter().rev().skip(1).any(|pc| *pc == outcome.pc) {
+                            spins += 1;
+                            if spins > limit {
+                                self.calc_stats();
+                                return Exit::Livelock(outcome.pc)
+                            }
+                        }
+                    }
+                },
+                Err(exit) => {
+                    self.calc_stats();
+                    return exit
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dart::DartSoC, zeus::ZeusSoC, kronos::KronosSoC, atlas::AtlasSoC, hermes::HermesSoC, gemini::GeminiSoC, isa::to_bin, device::{SharedBuf, Finisher}, bus::{Bus, FINISHER_BASE, FINISHER_SIZE}, csr::{Privilege, MCAUSE, MEPC, MSTATUS, MSTATUS_MPP, MSTATUS_MIE, MSTATUS_MPIE, MTVEC, MIP, IRQ_M_TIMER}, bus::RAM_BASE, exception::Exception, mem::B64, rvf::unbox_f32};
+    use std::{cell::Cell, rc::Rc};
+    use super::{SoC, Exit, Snapshot, ExecObserver};
+
+    // jal x0, 0
+    const SELF_LOOP: u32 = 0x0000006f;
+
+    #[test]
+    fn timeout_all_models() {
+        let bin = to_bin(&[SELF_LOOP]);
+
+        let mut dart = DartSoC::new(bin.clone());
+        dart.config.timeout_cycles = Some(50);
+        assert!(matches!(dart.execute(), Exit::Timeout));
+        assert_eq!(dart.stats.cycles, 50);
+        assert_eq!(dart.stats.alu_ops, 50);
+
+        let mut zeus = ZeusSoC::new(bin.clone());
+        zeus.config.timeout_cycles = Some(50);
+        assert!(matches!(zeus.execute(), Exit::Timeout));
+        assert_eq!(zeus.stats.alu_ops, 50);
+
+        let mut kronos = KronosSoC::new(bin.clone());
+        kronos.config.timeout_cycles = Some(50);
+        assert!(matches!(kronos.execute(), Exit::Timeout));
+        assert_eq!(kronos.stats.alu_ops, 50);
+
+        let mut atlas = AtlasSoC::new(bin);
+        atlas.config.timeout_cycles = Some(50);
+        assert!(matches!(atlas.execute(), Exit::Timeout));
+        assert_eq!(atlas.stats.alu_ops, 50);
+    }
+
+    #[test]
+    fn timeout_counts_memory_latency() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x0002a503, // lw a0, 0(t0)
+            0xffdff06f, // jal zero, -4
+        ]));
+        cpu.config.mem_latency = 8;
+        cpu.config.timeout_cycles = Some(50);
+        assert!(matches!(cpu.execute(), Exit::Timeout));
+        assert_eq!(cpu.stats.cycles, 50);
+        // the auipc, four trips round the loop at 9 + 1 cycles and the lw
+        // that takes the count to 50
+        assert_eq!(cpu.stats.retired, 10);
+    }
+
+    #[test]
+    fn livelock_all_models() {
+        let cpus: [Box<dyn SoC>; 4] = [
+            Box::new(DartSoC::new(to_bin(&[0x00000063]))), // beq zero, zero, 0
+            Box::new(ZeusSoC::new(to_bin(&[0x00000063]))),
+            Box::new(KronosSoC::new(to_bin(&[0x00000063]))),
+            Box::new(AtlasSoC::new(to_bin(&[0x00000063]))),
+        ];
+        for mut cpu in cpus {
+            cpu.config_mut().livelock = Some(100);
+            assert!(matches!(cpu.execute(), Exit::Livelock(RAM_BASE)));
+            // the first trip has nothing to revisit
+            assert_eq!(cpu.stats().retired, 102);
+        }
+
+        // a loop that counts down is making progress
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x0c800593, // addi a1, zero, 200
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ee3, // bne a1, zero, -4
+        ]));
+        cpu.config.livelock = Some(100);
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0))));
+
+        // as are loops that only change the float registers or memory
+        for bin in [
+            to_bin(&[
+                0x00100293, // addi t0, zero, 1
+                0xd002f153, // fcvt.s.w ft2, t0
+                0xd00070d3, // fcvt.s.w ft1, zero
+                0x0020f0d3, // fadd.s ft1, ft1, ft2
+                0xffdff06f, // jal zero, -4
+            ]),
+            to_bin(&[
+                0x00000297, // auipc t0, 0
+                0x0402a023, // sw zero, 64(t0)
+                0xffdff06f, // jal zero, -4
+            ]),
+        ] {
+            let mut cpu = DartSoC::new(bin);
+            cpu.config.livelock = Some(100);
+            cpu.config.max_insns = Some(1000);
+            assert!(matches!(cpu.execute(), Exit::InsnLimit));
+        }
+    }
+
+    struct Counter(Rc<Cell<usize>>);
+
+    impl ExecObserver for Counter {
+        fn on_commit(&mut self, _pc: u64, _ins: u32, _regs: &[u64; 32]) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn observer_sees_every_commit() {
+        let bin = to_bin(&[
+            0x00000513, // addi a0, zero, 0
+            0x00500593, // addi a1, zero, 5
+            0x00b50533, // add a0, a0, a1
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ce3, // bne a1, zero, -8
+        ]);
+        let count = Rc::new(Cell::new(0));
+        let mut dart = DartSoC::new(bin.clone());
+        dart.observer = Some(Box::new(Counter(count.clone())));
+        dart.execute();
+        assert_eq!(count.get(), dart.stats.retired);
+
+        let count = Rc::new(Cell::new(0));
+        let mut zeus = ZeusSoC::new(bin.clone());
+        zeus.observer = Some(Box::new(Counter(count.clone())));
+        zeus.execute();
+        assert_eq!(count.get(), zeus.stats.retired);
+
+        let count = Rc::new(Cell::new(0));
+        let mut kronos = KronosSoC::new(bin.clone());
+        kronos.observer = Some(Box::new(Counter(count.clone())));
+        kronos.execute();
+        assert_eq!(count.get(), kronos.stats.retired);
+
+        let count = Rc::new(Cell::new(0));
+        let mut atlas = AtlasSoC::new(bin);
+        atlas.observer = Some(Box::new(Counter(count.clone())));
+        atlas.execute();
+        assert_eq!(count.get(), atlas.stats.retired);
+        assert_eq!(count.get(), 17);
+    }
+
+    #[test]
+    fn assert_device() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x001102b7, // lui t0, 0x110
+            0x02a00313, // addi t1, zero, 42
+            0x0062b023, // sd t1, 0(t0)
+        ]));
+        let exit = cpu.execute();
+        assert!(matches!(exit, Exit::AssertFailed(42)));
+        assert_ne!(exit.code(), 0);
+        assert_eq!(exit.to_string(), "assertion failure (value 0x2a)");
+
+        let mut cpu = ZeusSoC::new(to_bin(&[
+            0x001102b7, // lui t0, 0x110
+            0x0002b423, // sd zero, 8(t0)
+        ]));
+        let exit = cpu.execute();
+        assert!(matches!(exit, Exit::Done));
+        assert_eq!(exit.code(), 0);
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(Exit::Status(256).code(), 1);
+        assert_eq!(Exit::Status(257).code(), 257);
+        assert_eq!(Exit::Status(0).code(), 0);
+        assert_eq!(Exit::Exception(Exception::EnvironmentCallFromMMode(0)).code(), 0);
+        assert_eq!(Exit::Exception(Exception::IllegalInstruction(0)).code(), 2);
+        for exit in [Exit::Timeout, Exit::InsnLimit, Exit::Livelock(RAM_BASE)] {
+            assert_eq!(exit.code(), 124);
+        }
+    }
+
+    #[test]
+    fn finisher_device() {
+        let bin = |fail: bool| to_bin(&[
+            0x001002b7, // lui t0, 0x100
+            if fail { 0x00033337 } else { 0x00005337 }, // lui t1, 0x33 / 0x5
+            if fail { 0x33330313 } else { 0x55530313 }, // addi t1, t1, 0x333 / 0x555
+            0x0062a023, // sw t1, 0(t0)
+        ]);
+        let mut cpu = DartSoC::new(bin(false));
+        cpu.bus.map(FINISHER_BASE, FINISHER_SIZE, Box::<Finisher>::default());
+        let exit = cpu.execute();
+        assert!(matches!(exit, Exit::Done));
+        assert_eq!(exit.code(), 0);
+        assert_eq!(cpu.stats.retired, 4);
+
+        let mut cpu = KronosSoC::new(bin(true));
+        cpu.bus.map(FINISHER_BASE, FINISHER_SIZE, Box::<Finisher>::default());
+        let exit = cpu.execute();
+        assert!(matches!(exit, Exit::Status(3)));
+        assert_eq!(exit.code(), 3);
+
+        // unmapped rather than taken for a failed assertion
+        let mut cpu = DartSoC::new(bin(false));
+        let exit = cpu.execute();
+        assert!(matches!(exit, Exit::Exception(Exception::StoreAMOAccessFault(FINISHER_BASE))));
+    }
+
+    #[test]
+    fn max_insns_all_models() {
+        let bin = to_bin(&[
+            0x00000513, // addi a0, zero, 0
+            0x00500593, // addi a1, zero, 5
+            0x00b50533, // add a0, a0, a1
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ce3, // bne a1, zero, -8
+        ]);
+        let cpus: [Box<dyn SoC>; 4] = [
+            Box::new(DartSoC::new(bin.clone())),
+            Box::new(ZeusSoC::new(bin.clone())),
+            Box::new(KronosSoC::new(bin.clone())),
+            Box::new(AtlasSoC::new(bin)),
+        ];
+        for mut cpu in cpus {
+            cpu.config_mut().max_insns = Some(10);
+            let exit = cpu.execute();
+            assert!(matches!(exit, Exit::InsnLimit));
+            assert_eq!(cpu.stats().retired, 10);
+            // two addis, then two and two thirds trips round the loop
+            assert_eq!(*cpu.pc_mut(), RAM_BASE + 16);
+            assert_eq!(cpu.regfile()[10], 5 + 4 + 3);
+        }
+    }
+
+    #[test]
+    fn trace_addi() {
+        let buf = SharedBuf::default();
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x02a00f93, // addi t6, zero, 42
+        ]));
+        cpu.config.trace = true;
+        cpu.trace_out = Box::new(buf.clone());
+        cpu.execute();
+        assert_eq!(buf.contents(), "0x80000000 (0x02a00f93) addi t6, zero, 42  t6=0x2a\n");
+    }
+
+    #[test]
+    fn ecall_trap_and_mret() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x01c28293, // addi t0, t0, 28
+            0x30529073, // csrrw zero, mtvec, t0
+            0x00000073, // ecall
+            0x00200593, // addi a1, zero, 2
+            0x00110337, // lui t1, 0x110
+            0x00033423, // sd zero, 8(t1)
+            // handler
+            0x00100513, // addi a0, zero, 1
+            0x341023f3, // csrrs t2, mepc, zero
+            0x00438393, // addi t2, t2, 4
+            0x34139073, // csrrw zero, mepc, t2
+            0x30200073, // mret
+        ]));
+        assert!(matches!(cpu.execute(), Exit::Done));
+        assert_eq!(cpu.regs[10], 1);
+        assert_eq!(cpu.regs[11], 2);
+        assert_eq!(cpu.csr.load(MCAUSE), 11);
+    }
+
+    #[test]
+    fn rdinstret_counts_loop() {
+        let bin = to_bin(&[
+            0xc0202573, // rdinstret a0
+            0x00500593, // addi a1, zero, 5
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ee3, // bne a1, zero, -4
+            0xc0202673, // rdinstret a2
+            0xc00026f3, // rdcycle a3
+        ]);
+        let cpus: [Box<dyn SoC>; 3] = [
+            Box::new(DartSoC::new(bin.clone())),
+            Box::new(KronosSoC::new(bin.clone())),
+            Box::new(ZeusSoC::new(bin)),
+        ];
+        for mut cpu in cpus {
+            cpu.execute();
+            let regs = cpu.regfile();
+            // the first rdinstret and the addi, then five trips round the loop
+            assert_eq!(regs[12] - regs[10], 2 + 2 * 5);
+            assert!(regs[13] >= regs[12]);
+        }
+    }
+
+    #[test]
+    fn read_only_csr_writes() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0xc0002573, // rdcycle a0
+            0xf14065f3, // csrrsi a1, mhartid, 0
+            0xf142a673, // csrrs a2, mhartid, t0
+        ]));
+        // reads are fine, a write is illegal even if it changes nothing
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.stats.fault.map(|(pc, _)| pc), Some(RAM_BASE + 8));
+        assert_eq!(cpu.regs[10], 1);
+
+        let mut cpu = DartSoC::new(to_bin(&[
+            0xc020d073, // csrrwi zero, instret, 1
+        ]));
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.stats.fault.map(|(pc, _)| pc), Some(RAM_BASE));
+    }
+
+    #[test]
+    fn mret_reenables_interrupts() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x02028293, // addi t0, t0, 32
+            0x30529073, // csrrw zero, mtvec, t0
+            0x30046073, // csrrsi zero, mstatus, 8
+            0x00000073, // ecall
+            0x300026f3, // csrrs a3, mstatus, zero
+            0x00110337, // lui t1, 0x110
+            0x00033423, // sd zero, 8(t1)
+            // handler
+            0x30002673, // csrrs a2, mstatus, zero
+            0x341023f3, // csrrs t2, mepc, zero
+            0x00438393, // addi t2, t2, 4
+            0x34139073, // csrrw zero, mepc, t2
+            0x30200073, // mret
+        ]));
+        assert!(matches!(cpu.execute(), Exit::Done));
+        // the trap stacked MIE into MPIE and disabled interrupts
+        assert_eq!(cpu.regs[12] & (MSTATUS_MIE | MSTATUS_MPIE), MSTATUS_MPIE);
+        // mret came back after the ecall with MIE restored
+        assert_eq!(cpu.regs[13] & (MSTATUS_MIE | MSTATUS_MPIE), MSTATUS_MIE | MSTATUS_MPIE);
+        assert_eq!(cpu.csr.privilege, Privilege::M);
+    }
+
+    #[test]
+    fn timer_interrupt_vectors_to_mtvec() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x02028293, // addi t0, t0, 32
+            0x30529073, // csrrw zero, mtvec, t0
+            0x08000313, // addi t1, zero, 128
+            0x30432073, // csrrs zero, mie, t1
+            0x00100513, // addi a0, zero, 1
+            0x30046073, // csrrsi zero, mstatus, 8
+            0x00200593, // addi a1, zero, 2
+            // handler
+            0x30433073, // csrrc zero, mie, t1
+            0x30200073, // mret
+        ]));
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+        cpu.raise_interrupt(IRQ_M_TIMER);
+        assert_eq!(cpu.csr.load(MIP), 1 << IRQ_M_TIMER);
+        // held off until mstatus.MIE is set
+        assert!(cpu.step().unwrap().retired);
+        assert!(cpu.step().unwrap().retired);
+        let outcome = cpu.step().unwrap();
+        assert!(!outcome.retired);
+        assert_eq!(cpu.pc, RAM_BASE + 32);
+        assert_eq!(cpu.csr.load(MEPC), RAM_BASE + 28);
+        assert_eq!(cpu.csr.load(MCAUSE), 1 << 63 | IRQ_M_TIMER);
+        assert_eq!(cpu.csr.load(MSTATUS) & (MSTATUS_MIE | MSTATUS_MPIE), MSTATUS_MPIE);
+        // the handler masks the timer and returns to the interrupted addi
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, RAM_BASE + 28);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs[10], 1);
+        assert_eq!(cpu.regs[11], 2);
+        assert_eq!(cpu.csr.load(MIP), 1 << IRQ_M_TIMER);
+        cpu.clear_interrupt(IRQ_M_TIMER);
+        assert_eq!(cpu.csr.load(MIP), 0);
+    }
+
+    #[test]
+    fn wfi_with_pending_timer_traps() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x02028293, // addi t0, t0, 32
+            0x30529073, // csrrw zero, mtvec, t0
+            0x08000313, // addi t1, zero, 128
+            0x30432073, // csrrs zero, mie, t1
+            0x30046073, // csrrsi zero, mstatus, 8
+            0x10500073, // wfi
+            0x00100513, // addi a0, zero, 1
+            // handler
+            0x30433073, // csrrc zero, mie, t1
+            0x30200073, // mret
+        ]));
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.pc, RAM_BASE + 24);
+        cpu.raise_interrupt(IRQ_M_TIMER);
+        // the interrupt is taken instead of waiting in the wfi
+        let outcome = cpu.step().unwrap();
+        assert!(!outcome.retired);
+        assert_eq!(cpu.pc, RAM_BASE + 32);
+        assert_eq!(cpu.csr.load(MEPC), RAM_BASE + 24);
+        assert_eq!(cpu.csr.load(MCAUSE), 1 << 63 | IRQ_M_TIMER);
+        // back from the handler the wfi completes and execution carries on
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, RAM_BASE + 24);
+        assert!(cpu.step().unwrap().retired);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs[10], 1);
+        assert_eq!(cpu.csr.load(MSTATUS) & MSTATUS_MIE, MSTATUS_MIE);
+    }
+
+    #[test]
+    fn reading_mip_does_not_latch_pending() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x34402573, // csrrs a0, mip, zero
+            0x344025f3, // csrrs a1, mip, zero
+            0x34416673, // csrrsi a2, mip, 2
+        ]));
+        cpu.raise_interrupt(IRQ_M_TIMER);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs[10], 1 << IRQ_M_TIMER);
+        // nor is the pending bit saved as mip
+        let restored = Snapshot::from_bytes(&cpu.snapshot().to_bytes()).unwrap();
+        assert_eq!(restored.csr.load_raw(MIP), 0);
+        cpu.clear_interrupt(IRQ_M_TIMER);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs[11], 0);
+        assert_eq!(cpu.csr.load(MIP), 0);
+        // a write sets only the bits the hart owns
+        cpu.raise_interrupt(IRQ_M_TIMER);
+        cpu.step().unwrap();
+        cpu.clear_interrupt(IRQ_M_TIMER);
+        assert_eq!(cpu.csr.load(MIP), 2);
+    }
+
+    #[test]
+    fn machine_csr_from_user_mode() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x30002573, // csrrs a0, mstatus, zero
+            0x00100613, // addi a2, zero, 1
+            // handler
+            0x00700593, // addi a1, zero, 7
+            0x00110337, // lui t1, 0x110
+            0x00033423, // sd zero, 8(t1)
+        ]));
+        cpu.csr.store(MTVEC, RAM_BASE + 8);
+        cpu.csr.privilege = Privilege::U;
+        assert!(matches!(cpu.execute(), Exit::Done));
+        assert_eq!(cpu.regs[11], 7);
+        assert_eq!(cpu.regs[12], 0);
+        assert_eq!(cpu.csr.load(MCAUSE), 2);
+        assert_eq!(cpu.csr.load(MEPC), RAM_BASE);
+        // the trap raised the hart to M-mode and recorded U in MPP
+        assert_eq!(cpu.csr.privilege, Privilege::M);
+        assert_eq!(cpu.csr.load(MSTATUS) & MSTATUS_MPP, 0);
+    }
+
+    #[test]
+    fn ecall_cause_follows_privilege() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x10002573, // csrrs a0, sstatus, zero
+            0x00000073, // ecall
+        ]));
+        cpu.csr.privilege = Privilege::S;
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::EnvironmentCallFromSMode(0x8000_0004))));
+
+        let mut cpu = DartSoC::new(to_bin(&[0x30200073])); // mret
+        cpu.csr.privilege = Privilege::U;
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::IllegalInstruction(0x30200073))));
+    }
+
+    #[test]
+    fn json_report() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x02a00513, // addi a0, zero, 42
+        ]));
+        let exit = cpu.execute();
+        let json = cpu.json_report(exit);
+        assert!(json.starts_with("{\"exit\":{\"reason\":\"exception\",\"name\":\"IllegalInstruction\",\"value\":0},"));
+        let regs = json.split("\"regs\":[").nth(1).unwrap().split(']').next().unwrap();
+        let regs: Vec<u64> = regs.split(',').map(|r| r.parse().unwrap()).collect();
+        assert_eq!(regs.len(), 32);
+        assert_eq!(regs[10], 42);
+        assert!(json.ends_with("\"stats\":{\"cycles\":2,\"stalls\":0,\"mem_stalls\":0,\"raw_stalls\":0,\"branch_stalls\":0,\"alu_ops\":1,\"mem_ops\":0,\"retired\":1,\"rob_full_stalls\":0,\"rename_stalls\":0,\"dual_issue_cycles\":0,\"single_issue_cycles\":0,\"structural_stalls\":0,\"tlb_hits\":0,\"tlb_misses\":0,\"page_walk_cycles\":0}}"));
+    }
+
+    #[test]
+    fn retired_straight_line() {
+        let bin = to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00200593, // addi a1, zero, 2
+            0x00b50633, // add a2, a0, a1
+            0x00c62023, // sw a2, 0(a2)
+        ]);
+        // the store faults, so only the first three retire
+        let mut dart = DartSoC::new(bin.clone());
+        dart.execute();
+        assert_eq!(dart.stats.retired, 3);
+
+        let mut kronos = KronosSoC::new(bin);
+        kronos.execute();
+        assert_eq!(kronos.stats.retired, 3);
+        assert_eq!(kronos.stats.ipc(), 3.0 / kronos.stats.cycles as f64);
+    }
+
+    #[test]
+    fn step_two_addis() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00200593, // addi a1, zero, 2
+        ]));
+        assert_eq!(cpu.step().unwrap().pc, RAM_BASE);
+        assert_eq!(cpu.step().unwrap().pc, RAM_BASE + 4);
+        assert_eq!(cpu.pc, RAM_BASE + 8);
+        assert_eq!(cpu.regs[11], 2);
+        assert!(matches!(cpu.step(), Err(Exit::Exception(Exception::IllegalInstruction(0)))));
+    }
+
+    #[test]
+    fn ecall_without_handler_stops() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00000073, // ecall
+        ]));
+        assert!(matches!(cpu.execute(), Exit::Exception(Exception::EnvironmentCallFromMMode(0x8000_0004))));
+        assert_eq!(cpu.regs[10], 1);
+    }
+
+    #[test]
+    fn skipped_exception_recorded() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00100073, // ebreak
+            0x00200593, // addi a1, zero, 2
+        ]));
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(cpu.regs[11], 2);
+        assert!(matches!(cpu.stats.exceptions[..], [(0x8000_0004, Exception::Breakpoint(0x8000_0004))]));
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x00100513, // addi a0, zero, 1
+            0x10a2a023, // sw a0, 256(t0)
+            0x00150513, // addi a0, a0, 1
+            0x10a2a023, // sw a0, 256(t0)
+            0x34051073, // csrrw zero, mscratch, a0
+        ]);
+        let mut cpu = DartSoC::new(vec![]);
+        cpu.bus = Bus::with_layout(bin, RAM_BASE, 4096);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        let snapshot = cpu.snapshot();
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_ne!(cpu.snapshot(), snapshot);
+        cpu.restore(&snapshot);
+        assert_eq!(cpu.snapshot(), snapshot);
+        assert_eq!(cpu.pc, RAM_BASE + 12);
+        assert_eq!(cpu.regs[10], 1);
+
+        let bytes = snapshot.to_bytes();
+        assert_eq!(Snapshot::from_bytes(&bytes), Some(snapshot));
+        assert_eq!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn snapshot_keeps_privilege() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x01028293, // addi t0, t0, 16
+            0x34129073, // csrrw zero, mepc, t0
+            0x30200073, // mret
+            0x00100513, // addi a0, zero, 1
+        ]));
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.csr.privilege, Privilege::U);
+        cpu.raise_interrupt(IRQ_M_TIMER);
+        let bytes = cpu.snapshot().to_bytes();
+
+        let mut restored = DartSoC::new(vec![]);
+        restored.restore(&Snapshot::from_bytes(&bytes).unwrap());
+        assert_eq!(restored.csr.privilege, Privilege::U);
+        assert_eq!(restored.csr.pending_interrupts, 1 << IRQ_M_TIMER);
+        assert_eq!(restored.pc, RAM_BASE + 16);
+    }
+
+    #[test]
+    fn snapshot_keeps_fregs() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x3fc00537, // lui a0, 0x3fc00
+            0xf00500d3, // fmv.w.x ft1, a0
+        ]));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let bytes = cpu.snapshot().to_bytes();
+
+        let mut restored = DartSoC::new(vec![]);
+        restored.restore(&Snapshot::from_bytes(&bytes).unwrap());
+        assert_eq!(unbox_f32(restored.fregs[1]), 1.5);
+    }
+
+    #[test]
+    fn init_stack_argv() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00013503, // ld a0, 0(sp)
+            0x00813583, // ld a1, 8(sp)
+            0x0005c603, // lbu a2, 0(a1)
+            0x01013683, // ld a3, 16(sp)
+            0x0015c703, // lbu a4, 1(a1)
+        ]));
+        let argv = ["prog".to_string(), "-v".to_string()];
+        cpu.init_stack(&argv, &["HOME=/".to_string()]).unwrap();
+        assert_eq!(cpu.regs[2] % 16, 0);
+        cpu.execute();
+        assert_eq!(cpu.regs[10], 2);
+        assert_eq!(cpu.regs[12], b'p' as u64);
+        assert_eq!(cpu.regs[14], b'r' as u64);
+        // the strings are pushed downwards, so "-v\0" sits just below argv[0]
+        assert_eq!(cpu.regs[13] + 3, cpu.regs[11]);
+        let envp = cpu.bus.load(cpu.regs[2] + 32, B64).unwrap();
+        assert_eq!(cpu.bus.read_bytes(envp, 7).unwrap(), b"HOME=/\0");
+    }
+
+    #[test]
+    fn fault_report() {
+        let mut cpu = DartSoC::new(to_bin(&[
+            0x00100513, // addi a0, zero, 1
+            0x00200593, // addi a1, zero, 2
+            0xffffffff,
+        ]));
+        let ex = cpu.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0xffffffff))));
+        assert!(matches!(cpu.stats.fault, Some((pc, Exception::IllegalInstruction(_))) if pc == RAM_BASE + 8));
+        assert_eq!(cpu.fault_report().unwrap(), concat!(
+            "IllegalInstruction(4294967295) at 0x80000008\n",
+            "  0x80000008: ffffffff  <undecodable>\n",
+            "  opcode 0b1111111 funct3 0b111 funct7 0b1111111 rd 31 rs1 31 rs2 31\n",
+            "  i_imm -1 s_imm -1 b_imm -2 u_imm -4096 j_imm -2\n",
+            "Last retired:\n",
+            "  0x80000000: 00100513  addi a0, zero, 1\n",
+            "  0x80000004: 00200593  addi a1, zero, 2\n",
+        ));
+    }
+    #[test]
+    fn resumed_run_counts_once() {
+        let bin = to_bin(&[
+            0x00000297, // auipc t0, 0
+            0x00500593, // addi a1, zero, 5
+            0x0002a503, // lw a0, 0(t0)
+            0x0042a603, // lw a2, 4(t0)
+            0x02b54533, // div a0, a0, a1
+            0xfff58593, // addi a1, a1, -1
+            0xfe0598e3, // bne a1, zero, -16
+        ]);
+        let check = |whole: &mut dyn SoC, resumed: &mut dyn SoC| {
+            whole.execute();
+            resumed.config_mut().max_insns = Some(10);
+            assert!(matches!(resumed.execute(), Exit::InsnLimit));
+            resumed.config_mut().max_insns = None;
+            resumed.execute();
+            let (a, b) = (whole.stats(), resumed.stats());
+            assert_eq!(a.cycles, b.cycles);
+            assert_eq!(a.dual_issue_cycles, b.dual_issue_cycles);
+            assert_eq!(a.single_issue_cycles, b.single_issue_cycles);
+            assert_eq!(a.structural_stalls, b.structural_stalls);
+            assert_eq!(a.rob_full_stalls, b.rob_full_stalls);
+            assert_eq!(a.rename_stalls, b.rename_stalls);
+            assert_eq!(a.stalls, b.stalls);
+        };
+        check(&mut GeminiSoC::new(bin.clone()), &mut GeminiSoC::new(bin.clone()));
+        check(&mut KronosSoC::new(bin.clone()), &mut KronosSoC::new(bin.clone()));
+        check(&mut HermesSoC::new(bin.clone()), &mut HermesSoC::new(bin));
+    }
+}
\ No newline at end of file
@@ -1,13 +1,59 @@
-use std::fmt::Display;
+use std::{collections::{HashMap, VecDeque}, fmt::Display};
 
 use tabled::{builder::Builder, settings::Style};
 
-#[derive(Copy, Clone)]
+use crate::exception::Exception;
+
+/// Number of retired instructions kept for the fatal-exception report
+pub const RECENT_LEN: usize = 8;
+
+#[derive(Clone)]
 pub struct Stats {
     pub cycles: usize,
     pub stalls: usize,
+    /// Stalls behind a load or store that was free to issue
+    pub mem_stalls: usize,
+    /// Stalls behind an instruction waiting on a source register
+    pub raw_stalls: usize,
+    /// Stalls behind a branch or jump that was free to issue
+    pub branch_stalls: usize,
     pub alu_ops: usize,
-    pub mem_ops: usize
+    pub mem_ops: usize,
+    /// Instructions that completed without raising an exception
+    pub retired: usize,
+    /// Cycles renaming stopped because the reorder buffer was full
+    pub rob_full_stalls: usize,
+    /// Cycles renaming stopped because no physical register was free
+    pub rename_stalls: usize,
+    /// Cycles in which two instructions issued together
+    pub dual_issue_cycles: usize,
+    /// Cycles in which only one instruction issued
+    pub single_issue_cycles: usize,
+    /// Times a ready instruction could not issue because every port of its
+    /// functional unit was taken
+    pub structural_stalls: usize,
+    /// Exceptions that were skipped over because no handler was installed,
+    /// with the pc of the instruction that raised them
+    pub exceptions: Vec<(u64, Exception)>,
+    /// The pcs of the last RECENT_LEN instructions to retire, oldest first
+    pub recent: VecDeque<u64>,
+    /// The fatal exception that ended the run and the pc that raised it
+    pub fault: Option<(u64, Exception)>,
+    /// Translations found in the TLB
+    pub tlb_hits: usize,
+    /// Translations that had to walk the page tables
+    pub tlb_misses: usize,
+    /// Cycles spent walking the page tables, one per level read
+    pub page_walk_cycles: usize,
+    /// Pcs of overflowing arithmetic under Config::record_overflow, oldest
+    /// first
+    pub overflows: Vec<u64>
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Stats {
@@ -15,9 +61,52 @@ impl Stats {
         Self {
             cycles: 0,
             stalls: 0,
+            mem_stalls: 0,
+            raw_stalls: 0,
+            branch_stalls: 0,
             alu_ops: 0,
             mem_ops: 0,
+            retired: 0,
+            rob_full_stalls: 0,
+            rename_stalls: 0,
+            dual_issue_cycles: 0,
+            single_issue_cycles: 0,
+            structural_stalls: 0,
+            exceptions: Vec::new(),
+            recent: VecDeque::with_capacity(RECENT_LEN),
+            fault: None,
+            tlb_hits: 0,
+            tlb_misses: 0,
+            page_walk_cycles: 0,
+            overflows: Vec::new(),
+        }
+    }
+
+    /// Remembers pc as the most recently retired instruction
+    pub fn push_recent(&mut self, pc: u64) {
+        if self.recent.len() == RECENT_LEN {
+            self.recent.pop_front();
         }
+        self.recent.push_back(pc);
+    }
+
+    /// Cycles per retired instruction
+    pub fn cpi(&self) -> f64 {
+        if self.retired == 0 { 0.0 } else { self.cycles as f64 / self.retired as f64 }
+    }
+
+    /// Retired instructions per cycle
+    pub fn ipc(&self) -> f64 {
+        if self.cycles == 0 { 0.0 } else { self.retired as f64 / self.cycles as f64 }
+    }
+
+    pub fn json(&self) -> String {
+        format!(
+            "{{\"cycles\":{},\"stalls\":{},\"mem_stalls\":{},\"raw_stalls\":{},\"branch_stalls\":{},\"alu_ops\":{},\"mem_ops\":{},\"retired\":{},\"rob_full_stalls\":{},\"rename_stalls\":{},\"dual_issue_cycles\":{},\"single_issue_cycles\":{},\"structural_stalls\":{},\"tlb_hits\":{},\"tlb_misses\":{},\"page_walk_cycles\":{}}}",
+            self.cycles, self.stalls, self.mem_stalls, self.raw_stalls, self.branch_stalls, self.alu_ops, self.mem_ops, self.retired, self.rob_full_stalls, self.rename_stalls,
+            self.dual_issue_cycles, self.single_issue_cycles, self.structural_stalls,
+            self.tlb_hits, self.tlb_misses, self.page_walk_cycles
+        )
     }
 }
 
@@ -27,8 +116,23 @@ impl Display for Stats {
         table.set_header(["Stat", "Value"]);
         table.push_record(["Cycles", &format!("{}", self.cycles)]);
         table.push_record(["Stalls", &format!("{}", self.stalls)]);
+        table.push_record(["  Memory", &format!("{}", self.mem_stalls)]);
+        table.push_record(["  RAW", &format!("{}", self.raw_stalls)]);
+        table.push_record(["  Branch", &format!("{}", self.branch_stalls)]);
         table.push_record(["ALU ops", &format!("{}", self.alu_ops)]);
         table.push_record(["Mem ops", &format!("{}", self.mem_ops)]);
+        table.push_record(["Retired", &format!("{}", self.retired)]);
+        table.push_record(["CPI", &format!("{:.3}", self.cpi())]);
+        table.push_record(["IPC", &format!("{:.3}", self.ipc())]);
+        table.push_record(["ROB full stalls", &format!("{}", self.rob_full_stalls)]);
+        table.push_record(["Rename stalls", &format!("{}", self.rename_stalls)]);
+        table.push_record(["Dual-issue cycles", &format!("{}", self.dual_issue_cycles)]);
+        table.push_record(["Single-issue cycles", &format!("{}", self.single_issue_cycles)]);
+        table.push_record(["Structural stalls", &format!("{}", self.structural_stalls)]);
+        table.push_record(["TLB hits", &format!("{}", self.tlb_hits)]);
+        table.push_record(["TLB misses", &format!("{}", self.tlb_misses)]);
+        table.push_record(["Page walk cycles", &format!("{}", self.page_walk_cycles)]);
+        table.push_record(["Skipped exceptions", &format!("{}", self.exceptions.len())]);
         let table = table.build()
             .with(Style::ascii_rounded())
             .to_string();
@@ -36,3 +140,95 @@ impl Display for Stats {
         Ok(())
     }
 }
+
+/// Side-by-side cycles, stalls and IPC of several models that ran the
+/// same program
+pub fn comparison(rows: &[(&str, &Stats)]) -> String {
+    let mut table = Builder::new();
+    table.set_header(["Model", "Cycles", "Stalls", "Retired", "IPC"]);
+    for (name, stats) in rows {
+        table.push_record([
+            name.to_string(),
+            format!("{}", stats.cycles),
+            format!("{}", stats.stalls),
+            format!("{}", stats.retired),
+            format!("{:.3}", stats.ipc()),
+        ]);
+    }
+    let table = table.build()
+        .with(Style::ascii_rounded())
+        .to_string();
+    format!("{}\n", table)
+}
+
+/// Count of executed instructions per mnemonic
+pub struct InsMix {
+    counts: HashMap<&'static str, usize>
+}
+
+impl Default for InsMix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InsMix {
+    pub fn new() -> Self {
+        Self { counts: HashMap::new() }
+    }
+
+    pub fn record(&mut self, mnemonic: &'static str) {
+        *self.counts.entry(mnemonic).or_insert(0) += 1;
+    }
+
+    pub fn count(&self, mnemonic: &str) -> usize {
+        self.counts.get(mnemonic).copied().unwrap_or(0)
+    }
+}
+
+impl Display for InsMix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // most frequent first, ties in alphabetical order
+        let mut counts: Vec<_> = self.counts.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let mut table = Builder::new();
+        table.set_header(["Instruction", "Count"]);
+        for (mnemonic, count) in counts {
+            table.push_record([*mnemonic, &format!("{}", count)]);
+        }
+        let table = table.build()
+            .with(Style::ascii_rounded())
+            .to_string();
+        writeln!(f, "{}", table)?;
+        Ok(())
+    }
+}
+
+/// Count of executions per pc
+pub struct Profile {
+    counts: HashMap<u64, usize>
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self { counts: HashMap::new() }
+    }
+
+    pub fn record(&mut self, pc: u64) {
+        *self.counts.entry(pc).or_insert(0) += 1;
+    }
+
+    /// The n most executed pcs, most frequent first, ties by address
+    pub fn hottest(&self, n: usize) -> Vec<(u64, usize)> {
+        let mut counts: Vec<(u64, usize)> = self.counts.iter().map(|(pc, count)| (*pc, *count)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(n);
+        counts
+    }
+}
\ No newline at end of file
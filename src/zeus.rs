@@ -1,6 +1,6 @@
-use std::fmt::Display;
+use std::io::Write;
 
-use crate::{bus::{Bus, RAM_END, RAM_BASE}, stats::Stats, mem::B64, isa::{Rv32i, Extension, Rv64i}, exception::Exception};
+use crate::{bus::Bus, stats::{Stats, InsMix, Profile}, isa::Extension, exception::Exception, soc::{SoC, Config, ExecObserver, Hart, Datapath, fetch}, csr::Csr};
 
 /*
 An out-of-order, infinite-fetch, infinite-issue single-stage processor
@@ -9,69 +9,145 @@ An out-of-order, infinite-fetch, infinite-issue single-stage processor
 struct HistItem {
     src_regs: Vec<u64>,
     dst_reg: Option<u64>,
-    blocking: bool
+    blocking: bool,
+    branch: bool
 }
 
 pub struct ZeusSoC {
     pub regs: [u64; 32],
+    /// Float registers, holding NaN-boxed singles
+    pub fregs: [u64; 32],
     pub pc: u64,
     pub bus: Bus,
+    pub csr: Csr,
     pub stats: Stats,
+    /// Executed instructions by mnemonic
+    pub mix: InsMix,
+    /// Executions of each pc
+    pub profile: Profile,
+    pub config: Config,
+    pub trace_out: Box<dyn Write>,
+    pub observer: Option<Box<dyn ExecObserver>>,
     hist: Vec<HistItem>
 }
 
 type Result = std::result::Result<(), Exception>;
 
 impl ZeusSoC {
-    pub fn new(bin: Vec<u8>) -> Self {
-        let mut regs = [0_u64; 32];
-        regs[2] = RAM_END;
-        let pc = RAM_BASE;
-        let bus = Bus::new(bin);
-        let stats = Stats::new();
-        let hist = Vec::new();
-        Self { regs, pc, bus, stats, hist }
+    fn hart(&mut self) -> Hart<'_> {
+        Hart {
+            regs: &mut self.regs, fregs: &mut self.fregs, pc: &mut self.pc, bus: &mut self.bus, csr: &mut self.csr,
+            stats: &mut self.stats, mix: &mut self.mix, profile: &mut self.profile, config: &self.config,
+            trace_out: &mut self.trace_out, observer: &mut self.observer
+        }
     }
 
-    pub fn pipeline(&mut self) -> Result {
-        let ins = self.bus.load(self.pc, B64)? as u32;
-        if let Ok(ins) = Rv32i::id(ins) {
-            self.datapath(ins)
-        } else if let Ok(ins) = Rv64i::id(ins) {
-            self.datapath(ins)
-        } else {
-            Err(Exception::IllegalInstruction(ins as u64))
-        }
+    fn intersect<'a, T: PartialEq>(a: &'a [T], b: &'a [T]) -> Vec<&'a T> {
+        a.iter()
+            .filter(|item| b.contains(&item))
+            .collect()
     }
+}
 
-    pub fn datapath<O: Extension + Display>(&mut self, i: O) -> Result {
+impl Datapath for ZeusSoC {
+    fn datapath<O: Extension>(&mut self, word: u32, i: O) -> Result {
         let record = HistItem { 
             src_regs: i.src_regs(), 
             dst_reg: i.dst_reg(), 
-            blocking: i.is_br() || i.is_jmp() || i.is_ld() || i.is_st()
+            blocking: i.is_br() || i.is_jmp() || i.is_ld() || i.is_st(),
+            branch: i.is_br() || i.is_jmp()
         };
-        let ins_ex = i.ex(&self.regs);
-        if ins_ex.is_ld() || ins_ex.is_st() {
-            self.stats.mem_ops += 1;
-        } else {
-            self.stats.alu_ops += 1;
-        }
-        self.regs[0] = 0;
-        self.pc = ins_ex.wr(self.pc, &mut self.regs, &mut self.bus)?;
-        self.regs[0] = 0;
+        self.hart().commit(word, i)?;
         self.hist.push(record);
         Ok(())
     }
+}
 
-    fn intersect<'a, T: PartialEq>(a: &'a [T], b: &'a [T]) -> Vec<&'a T> {
-        a.iter()
-            .filter(|item| b.contains(&item))
-            .collect()
+impl SoC for ZeusSoC {
+    fn with_entry(bin: Vec<u8>, entry: u64) -> Self {
+        let bus = Bus::new(bin);
+        let mut regs = [0_u64; 32];
+        regs[2] = bus.base + bus.size - 1;
+        let pc = entry;
+        let csr = Csr::new();
+        let stats = Stats::new();
+        let mix = InsMix::new();
+        let profile = Profile::new();
+        let hist = Vec::new();
+        Self { regs, fregs: [0; 32], pc, bus, csr, stats, mix, profile, config: Config { entry, ..Config::default() }, trace_out: Box::new(std::io::stdout()), observer: None, hist }
+    }
+
+    fn pipeline(&mut self) -> Result {
+        let (word, ins) = fetch(&mut self.bus, &self.csr, self.pc, self.config.enabled_exts, None)?;
+        self.dispatch(word, ins)
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn pc_mut(&mut self) -> &mut u64 {
+        &mut self.pc
+    }
+
+    fn regfile(&self) -> &[u64; 32] {
+        &self.regs
+    }
+
+    fn regfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.regs
+    }
+
+    fn fregfile(&self) -> &[u64; 32] {
+        &self.fregs
+    }
+
+    fn fregfile_mut(&mut self) -> &mut [u64; 32] {
+        &mut self.fregs
+    }
+
+    fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    fn csr(&mut self) -> &mut Csr {
+        &mut self.csr
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    fn mix(&self) -> &InsMix {
+        &self.mix
+    }
+
+    fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    fn reset(&mut self, bin: Vec<u8>) {
+        self.hart().reset(&bin);
+        self.hist.clear();
     }
 
     fn calc_stats(&mut self) {
+        // only instructions that completed are recorded in the history
+        self.stats.retired = self.hist.len();
         let mut cycles = 0;
-        let mut stalls = 0;
+        let (mut mem_stalls, mut raw_stalls, mut branch_stalls) = (0, 0, 0);
         // 1. starting from the top of the hist:
         // 2. an instruction is executed if all src regs are available
         // 3. the instructions's dst regs are then added to the occupied list
@@ -85,7 +161,8 @@ impl ZeusSoC {
             let iter = executed.iter_mut().enumerate()
                 .filter(|(_, done)| !**done);
             for (i, done) in iter {
-                if Self::intersect(&self.hist[i].src_regs, &occupied_regs).is_empty() {
+                let raw = !Self::intersect(&self.hist[i].src_regs, &occupied_regs).is_empty();
+                if !raw {
                     // we can execute this op
                     *done = true;
                 }
@@ -93,29 +170,52 @@ impl ZeusSoC {
                     occupied_regs.push(dst);
                 }
                 if self.hist[i].blocking {
-                    stalls += 1;
+                    if raw {
+                        raw_stalls += 1;
+                    } else if self.hist[i].branch {
+                        branch_stalls += 1;
+                    } else {
+                        mem_stalls += 1;
+                    }
                     continue 'cycle;
                 }
             }
             if executed.iter().all(|e| *e) {
-                self.stats.cycles = cycles;
-                self.stats.stalls = stalls;
+                self.stats.cycles = cycles + self.stats.page_walk_cycles;
+                self.stats.stalls = mem_stalls + raw_stalls + branch_stalls;
+                self.stats.mem_stalls = mem_stalls;
+                self.stats.raw_stalls = raw_stalls;
+                self.stats.branch_stalls = branch_stalls;
                 break;
             }
         }
     }
+}
 
-    pub fn execute(&mut self) -> Exception {
-        loop {
-            // execute instruction, add dst registers to dependents
-            // don't execute beyond branch
-            match self.pipeline() {
-                Ok(_) => {},
-                Err(ex) => if ex.is_fatal() {
-                    self.calc_stats();
-                    return ex
-                },
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use crate::{dart::DartSoC, isa::to_bin, soc::{SoC, Exit}, exception::Exception};
+    use super::ZeusSoC;
+
+    #[test]
+    fn add_loop() {
+        let bin = to_bin(&[
+            0x00000513, // addi a0, zero, 0
+            0x00500593, // addi a1, zero, 5
+            0x00b50533, // add a0, a0, a1
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ce3, // bne a1, zero, -8
+        ]);
+        let mut zeus = ZeusSoC::new(bin.clone());
+        let ex = zeus.execute();
+        assert!(matches!(ex, Exit::Exception(Exception::IllegalInstruction(0))));
+        assert_eq!(zeus.regs[10], 15);
+        assert_eq!(zeus.regs[11], 0);
+
+        let mut dart = DartSoC::new(bin);
+        dart.execute();
+        assert_eq!(zeus.regs, dart.regs);
+        assert_eq!(zeus.pc, dart.pc);
+        assert_eq!(zeus.stats.alu_ops, dart.stats.alu_ops);
     }
 }
\ No newline at end of file